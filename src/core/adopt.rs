@@ -0,0 +1,97 @@
+use crate::core::hash::matches;
+use crate::core::node::Meta::FILE;
+use crate::core::store::{AddOptions, Store};
+use crate::core::util::{same_device, same_file, same_ownership};
+use anyhow::{anyhow, bail};
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// adopt的结果
+#[derive(Debug, Default)]
+pub struct AdoptReport {
+    pub name: String,
+    /// 替换为仓库对象硬链接的文件，相对于根目录
+    pub replaced: Vec<PathBuf>,
+    /// 权限或属主与对象不同而没有替换的文件，相对于根目录
+    pub skipped: Vec<PathBuf>,
+}
+
+impl Store {
+    /// 接管与仓库在同一文件系统上的目录，文件只做硬链接不复制，
+    /// `replace` 为真时将内容与已有对象相同的文件替换为对象的硬链接
+    pub fn adopt(
+        &mut self,
+        path: &Path,
+        opts: &AddOptions,
+        replace: bool,
+    ) -> anyhow::Result<AdoptReport> {
+        if !path.exists() {
+            bail!("{:?} not exists", path);
+        }
+        self.check_source(path)?;
+        for dir in self.object_dirs() {
+            if dir.exists() && !same_device(path, &dir) {
+                bail!(
+                    "{:?} is not on the same filesystem as {:?}, use add instead",
+                    path,
+                    dir
+                );
+            }
+        }
+        let name = match &opts.name {
+            Some(name) => name.clone(),
+            None => path
+                .file_name()
+                .ok_or(anyhow!("invalid path {:?}", path))?
+                .to_string_lossy()
+                .to_string(),
+        };
+        self.add_with(path, opts)?;
+        let mut report = AdoptReport {
+            name,
+            ..Default::default()
+        };
+        if !replace {
+            return Ok(report);
+        }
+        let root = self.entry(&report.name)?;
+        let mut files = Vec::new();
+        root.walk(&mut |rel, node| {
+            if let FILE(hash) = &node.meta {
+                files.push((rel.to_path_buf(), hash.clone()));
+            }
+        });
+        for (rel, hash) in files {
+            // 根节点是文件时 `join("")` 会在末尾加上分隔符
+            let file = if rel.as_os_str().is_empty() {
+                path.to_path_buf()
+            } else {
+                path.join(&rel)
+            };
+            let object = self.object_path(&hash);
+            // add后被修改的文件不替换
+            if file.is_symlink() || same_file(&file, &object) || !matches(&file, &hash)? {
+                continue;
+            }
+            // 硬链接后文件会变成对象的权限和属主
+            if !same_ownership(&file, &object) {
+                warn!(
+                    "{:?} has a different mode or owner than {}, not replaced",
+                    file, hash
+                );
+                report.skipped.push(rel);
+                continue;
+            }
+            let tmp = file.with_file_name(format!(
+                ".{}.hbx-adopt",
+                file.file_name().unwrap_or_default().to_string_lossy()
+            ));
+            fs::hard_link(&object, &tmp)?;
+            fs::rename(&tmp, &file)?;
+            info!("replace {:?} with a link to {}", file, hash);
+            report.replaced.push(rel);
+        }
+        Ok(report)
+    }
+}
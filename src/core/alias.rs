@@ -0,0 +1,88 @@
+use crate::core::node::Node;
+use crate::core::store::Store;
+use anyhow::{anyhow, bail};
+use log::info;
+
+impl Store {
+    /// 带有别名的条目，有同名的条目时别名不生效
+    pub fn alias_target(&self, alias: &str) -> Option<&Node> {
+        if !self.versions(alias).is_empty() {
+            return None;
+        }
+        self.entries()
+            .into_iter()
+            .find(|n| n.aliases.iter().any(|a| a == alias))
+    }
+
+    /// 所有别名和对应的条目
+    pub fn entry_aliases(&self) -> Vec<(&str, &Node)> {
+        let mut ans: Vec<(&str, &Node)> = self
+            .entries()
+            .into_iter()
+            .flat_map(|n| n.aliases.iter().map(move |a| (a.as_str(), n)))
+            .collect();
+        ans.sort_by_key(|(a, _)| *a);
+        ans
+    }
+
+    /// 名称没有被条目或别名占用
+    fn check_free(&self, name: &str) -> anyhow::Result<()> {
+        self.settings().name_rules.validate(name)?;
+        if !self.versions(name).is_empty() {
+            bail!("{} already exists", name);
+        }
+        if let Some((_, n)) = self.entry_aliases().into_iter().find(|(a, _)| *a == name) {
+            bail!("{} is already an alias of {}", name, n.label());
+        }
+        Ok(())
+    }
+
+    /// 给条目加上别名，`spec` 可以带版本，别名指向这个版本
+    pub fn add_alias(&mut self, spec: &str, alias: &str) -> anyhow::Result<()> {
+        self.check_free(alias)?;
+        let mut root = self
+            .take_entry(&self.resolve(spec)?.clone())
+            .ok_or(anyhow!("{} not exists", spec))?;
+        info!("alias {} -> {}", alias, root.label());
+        root.aliases.push(alias.to_string());
+        root.aliases.sort();
+        self.insert_entry(root)
+    }
+
+    /// 删除别名，别名不存在时返回false
+    pub fn remove_alias(&mut self, alias: &str) -> anyhow::Result<bool> {
+        let Some(key) = self
+            .entries()
+            .into_iter()
+            .find(|n| n.aliases.iter().any(|a| a == alias))
+            .cloned()
+        else {
+            return Ok(false);
+        };
+        if let Some(mut root) = self.take_entry(&key) {
+            root.aliases.retain(|a| a != alias);
+            self.insert_entry(root)?;
+        }
+        Ok(true)
+    }
+
+    /// 修改条目的名称，所有版本和别名一起改名，不复制对象
+    pub fn rename(&mut self, old: &str, new: &str) -> anyhow::Result<()> {
+        if old == new {
+            return Ok(());
+        }
+        let keys: Vec<Node> = self.versions(old).into_iter().cloned().collect();
+        if keys.is_empty() {
+            bail!("{} not exists", old);
+        }
+        self.check_free(new)?;
+        for key in keys {
+            if let Some(mut root) = self.take_entry(&key) {
+                info!("rename {} -> {}", root.label(), new);
+                root.name = new.to_string();
+                self.insert_entry(root)?;
+            }
+        }
+        Ok(())
+    }
+}
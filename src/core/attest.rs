@@ -0,0 +1,169 @@
+use crate::core::hash::HashAlgorithm;
+use crate::core::store::Store;
+use crate::core::util::now;
+use crate::{ATTEST_KEY_NAME, STORE_ID_NAME};
+use anyhow::{anyhow, bail};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// 某一时刻仓库内容的声明
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Statement {
+    /// 仓库标识
+    pub store: String,
+    /// 索引的哈希
+    pub index: String,
+    /// 每个条目（`name@version`）的内容哈希
+    pub entries: BTreeMap<String, String>,
+    pub time: u64,
+}
+
+/// 签名后的声明，可以交给审计方归档
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Attestation {
+    pub statement: Statement,
+    /// ed25519公钥，base64
+    pub key: String,
+    /// 对 `statement` 的json的签名，base64
+    pub signature: String,
+}
+
+/// 仓库与之前的attestation相比的变化
+#[derive(Debug, Default, Serialize)]
+pub struct AttestDiff {
+    /// 是否为同一个仓库
+    pub same_store: bool,
+    /// 是否由本仓库的密钥签名
+    pub own_key: bool,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl AttestDiff {
+    pub fn unchanged(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Attestation {
+    /// 检查签名，不检查签名的是哪个密钥
+    pub fn check(&self) -> anyhow::Result<()> {
+        let key: [u8; 32] = decode(&self.key)?
+            .try_into()
+            .map_err(|_| anyhow!("invalid attestation key"))?;
+        let signature: [u8; 64] = decode(&self.signature)?
+            .try_into()
+            .map_err(|_| anyhow!("invalid attestation signature"))?;
+        VerifyingKey::from_bytes(&key)?
+            .verify(
+                serde_json::to_string(&self.statement)?.as_bytes(),
+                &Signature::from_bytes(&signature),
+            )
+            .map_err(|_| anyhow!("attestation signature does not match its statement"))
+    }
+}
+
+impl Store {
+    /// 仓库标识，不存在时生成
+    pub fn store_id(&self) -> anyhow::Result<String> {
+        let bytes = read_or_create(&self.path().join(STORE_ID_NAME), 16)?;
+        Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// 签名用的密钥，不存在时生成
+    fn attest_key(&self) -> anyhow::Result<SigningKey> {
+        let bytes: [u8; 32] = read_or_create(&self.path().join(ATTEST_KEY_NAME), 32)?
+            .try_into()
+            .map_err(|_| anyhow!("invalid {}", ATTEST_KEY_NAME))?;
+        Ok(SigningKey::from_bytes(&bytes))
+    }
+
+    /// 当前仓库内容的声明
+    pub fn statement(&self) -> anyhow::Result<Statement> {
+        let entries = self.entries();
+        let mut hasher = HashAlgorithm::Blake3.hasher();
+        hasher.update(serde_json::to_string(&entries)?.as_bytes());
+        Ok(Statement {
+            store: self.store_id()?,
+            index: hasher.finalize(),
+            entries: entries
+                .iter()
+                .map(|n| (n.label(), n.content_id()))
+                .collect(),
+            time: now(),
+        })
+    }
+
+    /// 生成并签名当前仓库内容的声明
+    pub fn attest(&self) -> anyhow::Result<Attestation> {
+        let statement = self.statement()?;
+        let key = self.attest_key()?;
+        let signature = key.sign(serde_json::to_string(&statement)?.as_bytes());
+        Ok(Attestation {
+            statement,
+            key: STANDARD.encode(key.verifying_key().as_bytes()),
+            signature: STANDARD.encode(signature.to_bytes()),
+        })
+    }
+
+    /// 检查签名并列出之后变化的条目
+    pub fn verify_attestation(&self, old: &Attestation) -> anyhow::Result<AttestDiff> {
+        old.check()?;
+        let key = self.attest_key()?;
+        let now = self.statement()?;
+        let mut diff = AttestDiff {
+            same_store: now.store == old.statement.store,
+            own_key: STANDARD.encode(key.verifying_key().as_bytes()) == old.key,
+            ..Default::default()
+        };
+        for (label, id) in &now.entries {
+            match old.statement.entries.get(label) {
+                None => diff.added.push(label.clone()),
+                Some(x) if x != id => diff.changed.push(label.clone()),
+                _ => {}
+            }
+        }
+        diff.removed = old
+            .statement
+            .entries
+            .keys()
+            .filter(|k| !now.entries.contains_key(*k))
+            .cloned()
+            .collect();
+        Ok(diff)
+    }
+}
+
+fn decode(s: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(STANDARD.decode(s)?)
+}
+
+/// 读取随机生成的文件，不存在时生成 `len` 字节
+fn read_or_create(path: &Path, len: usize) -> anyhow::Result<Vec<u8>> {
+    match fs::read(path) {
+        Ok(bytes) if bytes.len() == len => return Ok(bytes),
+        Ok(_) => bail!("{:?} is broken", path),
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        Err(_) => {}
+    }
+    let mut bytes = vec![0; len];
+    getrandom::getrandom(&mut bytes).map_err(|e| anyhow!("{}", e))?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    match options.open(path) {
+        Ok(mut f) => std::io::Write::write_all(&mut f, &bytes)?,
+        // 其他进程同时生成了，使用先生成的
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(fs::read(path)?),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(bytes)
+}
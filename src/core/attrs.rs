@@ -0,0 +1,386 @@
+#[cfg(unix)]
+use crate::core::util::file_mode;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// add时记录的文件属性，get时还原
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Attrs {
+    /// windows的文件属性，只记录只读和隐藏
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub windows: Option<u32>,
+    /// windows的DACL，SDDL格式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acl: Option<String>,
+    /// unix上普通文件的权限位
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// 修改时间，unix纳秒，get时指定 `--preserve-times` 才还原
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    /// unix上的属主，get时指定 `--preserve-owner` 才还原
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// unix上的扩展属性，值为base64，如 `security.capability` 和SELinux标签
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+}
+
+impl Attrs {
+    pub fn is_empty(&self) -> bool {
+        self == &Attrs::default()
+    }
+}
+
+/// 记录文件的属性，`acl` 为true时同时记录DACL，`skip_xattrs` 中命名空间的扩展属性不记录，
+/// 没有需要记录的属性时为空
+pub fn capture(path: &Path, acl: bool, skip_xattrs: &[String]) -> anyhow::Result<Option<Attrs>> {
+    let meta = path.metadata()?;
+    let mut attrs = Attrs {
+        mtime: mtime_ns(&meta),
+        ..Default::default()
+    };
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        let bits = path.symlink_metadata()?.file_attributes() & windows::KEPT;
+        attrs.windows = (bits != 0).then_some(bits);
+        if acl {
+            attrs.acl = Some(windows::read_acl(path)?);
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let _ = acl;
+        attrs.mode = meta.is_file().then(|| file_mode(&meta));
+        attrs.uid = Some(meta.uid());
+        attrs.gid = Some(meta.gid());
+        attrs.xattrs = read_xattrs(path, skip_xattrs)?;
+    }
+    #[cfg(not(unix))]
+    let _ = skip_xattrs;
+    #[cfg(not(any(windows, unix)))]
+    let _ = acl;
+    Ok((!attrs.is_empty()).then_some(attrs))
+}
+
+/// 修改时间，unix纳秒
+pub fn mtime_ns(meta: &Metadata) -> Option<i64> {
+    let t = meta.modified().ok()?;
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => i64::try_from(d.as_nanos()).ok(),
+        Err(e) => i64::try_from(e.duration().as_nanos()).ok().map(|n| -n),
+    }
+}
+
+/// 扩展属性是否属于被跳过的命名空间，如 `security.selinux` 属于 `security`
+fn skipped(name: &str, skip: &[String]) -> bool {
+    skip.iter().any(|ns| {
+        name.strip_prefix(ns.as_str())
+            .is_some_and(|r| r.starts_with('.'))
+    })
+}
+
+/// 读取扩展属性，文件系统不支持时为空
+#[cfg(unix)]
+pub fn read_xattrs(path: &Path, skip: &[String]) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut ans = BTreeMap::new();
+    let names = match xattr::list(path) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(ans),
+        Err(e) => return Err(e.into()),
+    };
+    for name in names {
+        let name = name.to_string_lossy().to_string();
+        if skipped(&name, skip) {
+            continue;
+        }
+        if let Some(value) = xattr::get(path, &name)? {
+            ans.insert(name, STANDARD.encode(value));
+        }
+    }
+    Ok(ans)
+}
+
+/// 还原记录的扩展属性，没有权限设置的属性只打印警告
+pub fn set_xattrs(path: &Path, attrs: &Attrs, skip: &[String]) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    for (name, value) in &attrs.xattrs {
+        if skipped(name, skip) {
+            continue;
+        }
+        let value = STANDARD.decode(value)?;
+        if xattr::get(path, name)?.as_ref() == Some(&value) {
+            continue;
+        }
+        match xattr::set(path, name, &value) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                log::warn!("no permission to set {} on {:?}", name, path)
+            }
+            Err(e) => anyhow::bail!("failed to set {} on {:?}: {}", name, path, e),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, attrs, skip);
+    Ok(())
+}
+
+/// 文件的扩展属性与记录的一致
+pub fn same_xattrs(path: &Path, attrs: &Attrs, skip: &[String]) -> anyhow::Result<bool> {
+    #[cfg(unix)]
+    if !attrs.xattrs.is_empty() {
+        let mut current = read_xattrs(path, skip)?;
+        current.retain(|k, _| attrs.xattrs.contains_key(k));
+        let expected = attrs.xattrs.iter().filter(|(k, _)| !skipped(k, skip));
+        return Ok(current.iter().eq(expected));
+    }
+    let _ = (path, attrs, skip);
+    Ok(true)
+}
+
+/// 当前进程能否修改文件的属主
+pub fn can_chown() -> bool {
+    #[cfg(unix)]
+    return unsafe { libc::geteuid() } == 0;
+    #[cfg(not(unix))]
+    false
+}
+
+/// 文件的属主与记录的一致，没有记录时也认为一致
+pub fn same_owner(meta: &Metadata, attrs: &Attrs) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        attrs.uid.is_none_or(|u| u == meta.uid()) && attrs.gid.is_none_or(|g| g == meta.gid())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (meta, attrs);
+        true
+    }
+}
+
+/// 还原记录的属主，需要在设置权限之前调用，修改属主会清除setuid位
+pub fn set_owner(path: &Path, attrs: &Attrs) -> anyhow::Result<()> {
+    if same_owner(&path.metadata()?, attrs) {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::chown(path, attrs.uid, attrs.gid)
+        .map_err(|e| anyhow::anyhow!("failed to change the owner of {:?}: {}", path, e))?;
+    Ok(())
+}
+
+/// 设置文件或目录的修改时间，不改变访问时间，时间已经一致时不修改
+pub fn set_mtime(path: &Path, ns: i64) -> anyhow::Result<()> {
+    if mtime_ns(&path.metadata()?) == Some(ns) {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let c = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        let times = [
+            libc::timespec {
+                tv_sec: 0,
+                tv_nsec: libc::UTIME_OMIT,
+            },
+            libc::timespec {
+                tv_sec: ns.div_euclid(1_000_000_000) as libc::time_t,
+                tv_nsec: ns.rem_euclid(1_000_000_000) as _,
+            },
+        ];
+        if unsafe { libc::utimensat(libc::AT_FDCWD, c.as_ptr(), times.as_ptr(), 0) } != 0 {
+            anyhow::bail!(
+                "failed to set mtime of {:?}: {}",
+                path,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        use std::time::Duration;
+        let t = match ns >= 0 {
+            true => UNIX_EPOCH + Duration::from_nanos(ns as u64),
+            false => UNIX_EPOCH - Duration::from_nanos(ns.unsigned_abs()),
+        };
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true);
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::OpenOptionsExt;
+            // FILE_WRITE_ATTRIBUTES，目录需要FILE_FLAG_BACKUP_SEMANTICS
+            options.access_mode(0x100).custom_flags(0x02000000);
+        }
+        options.open(path)?.set_modified(t)?;
+    }
+    Ok(())
+}
+
+/// 还原记录的属性，当前系统不支持的属性被忽略
+pub fn apply(path: &Path, attrs: &Attrs) -> anyhow::Result<()> {
+    #[cfg(windows)]
+    {
+        if let Some(acl) = &attrs.acl {
+            windows::write_acl(path, acl)?;
+        }
+        // 只读属性最后设置，否则无法修改DACL
+        if let Some(bits) = attrs.windows {
+            windows::set_attributes(path, bits)?;
+        }
+    }
+    #[cfg(not(windows))]
+    if attrs.windows.is_some() || attrs.acl.is_some() {
+        log::debug!("ignore windows attributes of {:?}", path);
+    }
+    // 权限已经一致时不修改，硬链接的对象可能属于其他用户
+    #[cfg(unix)]
+    if let Some(mode) = attrs.mode {
+        use std::os::unix::fs::PermissionsExt;
+        if file_mode(&path.metadata()?) != mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+mod windows {
+    use anyhow::bail;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::MetadataExt;
+    use std::path::Path;
+    use std::ptr::null_mut;
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertSecurityDescriptorToStringSecurityDescriptorW,
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, GetNamedSecurityInfoW,
+        SetNamedSecurityInfoW, SDDL_REVISION_1, SE_FILE_OBJECT,
+    };
+    use windows_sys::Win32::Security::{
+        GetSecurityDescriptorDacl, ACL, DACL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR,
+    };
+    use windows_sys::Win32::Storage::FileSystem::{
+        SetFileAttributesW, FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_READONLY,
+    };
+
+    /// 记录的属性
+    pub const KEPT: u32 = FILE_ATTRIBUTE_READONLY | FILE_ATTRIBUTE_HIDDEN;
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain(Some(0)).collect()
+    }
+
+    pub fn set_attributes(path: &Path, bits: u32) -> anyhow::Result<()> {
+        let current = path.symlink_metadata()?.file_attributes();
+        let bits = (current & !KEPT) | (bits & KEPT);
+        if unsafe { SetFileAttributesW(wide(path).as_ptr(), bits) } == 0 {
+            bail!(
+                "failed to set attributes of {:?}: {}",
+                path,
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn read_acl(path: &Path) -> anyhow::Result<String> {
+        let mut sd: PSECURITY_DESCRIPTOR = null_mut();
+        let err = unsafe {
+            GetNamedSecurityInfoW(
+                wide(path).as_ptr(),
+                SE_FILE_OBJECT,
+                DACL_SECURITY_INFORMATION,
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                &mut sd,
+            )
+        };
+        if err != 0 {
+            bail!(
+                "failed to read acl of {:?}: {}",
+                path,
+                std::io::Error::from_raw_os_error(err as i32)
+            );
+        }
+        let mut s = null_mut();
+        let ok = unsafe {
+            ConvertSecurityDescriptorToStringSecurityDescriptorW(
+                sd,
+                SDDL_REVISION_1,
+                DACL_SECURITY_INFORMATION,
+                &mut s,
+                null_mut(),
+            )
+        };
+        let res = if ok == 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            let len = (0..).take_while(|&i| unsafe { *s.add(i) } != 0).count();
+            let sddl = String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(s, len) });
+            unsafe { LocalFree(s.cast()) };
+            Ok(sddl)
+        };
+        unsafe { LocalFree(sd) };
+        res.map_err(|e| anyhow::anyhow!("failed to read acl of {:?}: {}", path, e))
+    }
+
+    pub fn write_acl(path: &Path, sddl: &str) -> anyhow::Result<()> {
+        let s: Vec<u16> = sddl.encode_utf16().chain(Some(0)).collect();
+        let mut sd: PSECURITY_DESCRIPTOR = null_mut();
+        let ok = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                s.as_ptr(),
+                SDDL_REVISION_1,
+                &mut sd,
+                null_mut(),
+            )
+        };
+        if ok == 0 {
+            bail!(
+                "invalid acl {:?}: {}",
+                sddl,
+                std::io::Error::last_os_error()
+            );
+        }
+        let mut present = 0;
+        let mut defaulted = 0;
+        let mut dacl: *mut ACL = null_mut();
+        let err = unsafe {
+            if GetSecurityDescriptorDacl(sd, &mut present, &mut dacl, &mut defaulted) == 0 {
+                std::io::Error::last_os_error().raw_os_error().unwrap_or(1) as u32
+            } else {
+                SetNamedSecurityInfoW(
+                    wide(path).as_ptr(),
+                    SE_FILE_OBJECT,
+                    DACL_SECURITY_INFORMATION,
+                    null_mut(),
+                    null_mut(),
+                    dacl,
+                    null_mut(),
+                )
+            }
+        };
+        unsafe { LocalFree(sd) };
+        if err != 0 {
+            bail!(
+                "failed to set acl of {:?}: {}",
+                path,
+                std::io::Error::from_raw_os_error(err as i32)
+            );
+        }
+        Ok(())
+    }
+}
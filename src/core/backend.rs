@@ -0,0 +1,602 @@
+use crate::core::encryption::{decrypt, encrypt, is_encrypted};
+use crate::core::migration::{parse_index, serialize_index};
+use crate::core::node::Meta::FILE;
+use crate::core::node::Node;
+use crate::core::store::Store;
+use crate::{CONFIG_NAME, ENTRIES_DIRECTORY};
+use age::x25519::Identity;
+use anyhow::{anyhow, bail};
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// 索引的存储方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexFormat {
+    /// 整个索引保存为一个json文件
+    #[default]
+    Json,
+    /// 整个索引保存为一个MessagePack文件，比json更小、解析更快
+    Msgpack,
+    /// 每个条目一行的SQLite数据库，保存时只写入变化的条目
+    Sqlite,
+    /// 每个条目一个json文件，保存时只写入变化的条目，列出条目时只读取文件名
+    Entries,
+}
+
+/// 索引的存储
+pub trait IndexBackend {
+    /// 读取所有条目，索引不存在时为空
+    fn load(&self) -> anyhow::Result<Vec<Node>>;
+
+    /// 保存所有条目
+    fn save(&self, entries: &[&Node]) -> anyhow::Result<()>;
+
+    /// 所有条目的名称和版本，不需要解析节点树
+    fn labels(&self) -> anyhow::Result<Vec<(String, Option<String>)>> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .map(|n| (n.name, n.version))
+            .collect())
+    }
+
+    /// 同名条目的所有版本
+    fn find(&self, name: &str) -> anyhow::Result<Vec<Node>> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .filter(|n| n.name == name)
+            .collect())
+    }
+
+    /// 删除一个条目，返回被删除的节点，条目不存在时为空
+    fn remove(&self, name: &str, version: Option<&str>) -> anyhow::Result<Option<Node>> {
+        let mut all = self.load()?;
+        let Some(i) = all
+            .iter()
+            .position(|n| n.name == name && n.version.as_deref() == version)
+        else {
+            return Ok(None);
+        };
+        let node = all.remove(i);
+        self.save(&all.iter().collect::<Vec<_>>())?;
+        Ok(Some(node))
+    }
+
+    /// 引用对象的文件数
+    fn refcount(&self, hash: &str) -> anyhow::Result<usize> {
+        let mut ans = 0;
+        for root in self.load()? {
+            root.walk(&mut |_, n| {
+                if matches!(&n.meta, FILE(x) if x == hash) {
+                    ans += 1;
+                }
+            });
+        }
+        Ok(ans)
+    }
+
+    /// 能按需读取单个条目，打开仓库时不必解析整个索引
+    fn lazy(&self) -> bool {
+        false
+    }
+}
+
+/// 解析json格式的节点
+pub(crate) fn parse_nodes<T: for<'de> Deserialize<'de>>(s: &str) -> anyhow::Result<T> {
+    // 目录层级在add时已经限制，这里不使用serde_json默认的递归深度限制
+    let mut de = serde_json::Deserializer::from_str(s);
+    de.disable_recursion_limit();
+    Ok(T::deserialize(&mut de)?)
+}
+
+/// 解析MessagePack格式的节点
+pub(crate) fn parse_msgpack<T: for<'de> Deserialize<'de>>(data: &[u8]) -> anyhow::Result<T> {
+    let mut de = rmp_serde::Deserializer::from_read_ref(data);
+    de.set_max_depth(usize::MAX);
+    Ok(T::deserialize(&mut de)?)
+}
+
+/// 保存在 `config` 文件中的索引，读取时自动识别json和MessagePack
+pub struct FileIndex {
+    path: PathBuf,
+    /// 开启加密时用于加解密的私钥
+    identity: Option<Identity>,
+    /// 保存为MessagePack
+    binary: bool,
+}
+
+impl FileIndex {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            identity: None,
+            binary: false,
+        }
+    }
+
+    pub fn encrypted(path: PathBuf, identity: Identity) -> Self {
+        Self {
+            identity: Some(identity),
+            ..Self::new(path)
+        }
+    }
+
+    /// 保存时使用MessagePack
+    pub fn binary(self) -> Self {
+        Self {
+            binary: true,
+            ..self
+        }
+    }
+}
+
+impl IndexBackend for FileIndex {
+    fn load(&self) -> anyhow::Result<Vec<Node>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let data = fs::read(&self.path)?;
+        let data = match (is_encrypted(&data), &self.identity) {
+            (false, _) => data,
+            (true, Some(identity)) => decrypt(identity, &data)?,
+            (true, None) => bail!("{:?} is encrypted, set encryption.key_file", self.path),
+        };
+        parse_index(&data)
+    }
+
+    fn save(&self, entries: &[&Node]) -> anyhow::Result<()> {
+        let mut data = serialize_index(entries, self.binary)?;
+        if let Some(identity) = &self.identity {
+            data = encrypt(identity, &data)?;
+        }
+        AtomicFile::new(&self.path, AllowOverwrite).write(|f| f.write_all(&data))?;
+        info!("save path is {}", self.path.display());
+        Ok(())
+    }
+}
+
+/// `entries/` 目录中每个条目一个文件，文件名为 `<名称>[@<版本>].<内容哈希>.json`，
+/// 名称和版本中的特殊字符以 `%XX` 转义，根据文件名中的哈希判断条目是否变化
+pub struct EntriesIndex {
+    dir: PathBuf,
+    /// 切换前的单文件索引，目录不存在时从中导入
+    legacy: PathBuf,
+}
+
+impl EntriesIndex {
+    pub fn new(dir: PathBuf, legacy: PathBuf) -> Self {
+        Self { dir, legacy }
+    }
+
+    /// 目录中的条目文件及其名称和版本
+    fn files(&self) -> anyhow::Result<BTreeMap<PathBuf, (String, Option<String>)>> {
+        let mut ans = BTreeMap::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            // 跳过写入中的临时文件
+            let Some(stem) = file_name.strip_suffix(".json") else {
+                continue;
+            };
+            let Some((label, _digest)) = stem.rsplit_once('.') else {
+                bail!("invalid index file {:?}", path);
+            };
+            let (name, version) = match label.split_once('@') {
+                Some((n, v)) => (unescape(n)?, Some(unescape(v)?)),
+                None => (unescape(label)?, None),
+            };
+            ans.insert(path.clone(), (name, version));
+        }
+        Ok(ans)
+    }
+}
+
+/// 文件名中只保留字母、数字和 `-_.+`
+fn escape(s: &str) -> String {
+    let mut ans = String::new();
+    for b in s.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'+' => {
+                ans.push(b as char)
+            }
+            _ => ans.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    ans
+}
+
+fn unescape(s: &str) -> anyhow::Result<String> {
+    let bytes = s.as_bytes();
+    let mut ans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s
+                .get(i + 1..i + 3)
+                .ok_or(anyhow!("invalid escape in {}", s))?;
+            ans.push(u8::from_str_radix(hex, 16)?);
+            i += 3;
+        } else {
+            ans.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(String::from_utf8(ans)?)
+}
+
+impl IndexBackend for EntriesIndex {
+    fn load(&self) -> anyhow::Result<Vec<Node>> {
+        if !self.dir.exists() {
+            if self.legacy.exists() {
+                info!("import {:?} into {:?}", self.legacy, self.dir);
+            }
+            return FileIndex::new(self.legacy.clone()).load();
+        }
+        let mut ans = Vec::new();
+        for path in self.files()?.into_keys() {
+            ans.push(parse_nodes(&fs::read_to_string(path)?)?);
+        }
+        Ok(ans)
+    }
+
+    fn save(&self, entries: &[&Node]) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut old = self.files()?;
+        let mut changed = 0;
+        for node in entries {
+            let manifest = serde_json::to_string(node)?;
+            let digest = blake3::hash(manifest.as_bytes()).to_hex()[..16].to_string();
+            let mut name = escape(&node.name);
+            if let Some(version) = &node.version {
+                name = format!("{}@{}", name, escape(version));
+            }
+            let path = self.dir.join(format!("{}.{}.json", name, digest));
+            if old.remove(&path).is_some() {
+                continue;
+            }
+            changed += 1;
+            AtomicFile::new(&path, AllowOverwrite).write(|f| f.write_all(manifest.as_bytes()))?;
+        }
+        // 剩下的是被删除或修改前的条目
+        for path in old.keys() {
+            changed += 1;
+            fs::remove_file(path)?;
+        }
+        info!("save {} changed entries to {}", changed, self.dir.display());
+        // 导入后旧的单文件索引不再使用
+        if self.legacy.exists() {
+            let backup = self.legacy.with_extension("json.bak");
+            info!("move {:?} -> {:?}", self.legacy, backup);
+            fs::rename(&self.legacy, backup)?;
+        }
+        Ok(())
+    }
+
+    fn labels(&self) -> anyhow::Result<Vec<(String, Option<String>)>> {
+        if !self.dir.exists() {
+            return FileIndex::new(self.legacy.clone()).labels();
+        }
+        let mut ans: Vec<_> = self.files()?.into_values().collect();
+        ans.sort();
+        Ok(ans)
+    }
+
+    /// 根据文件名筛选，只解析这个名称的文件
+    fn find(&self, name: &str) -> anyhow::Result<Vec<Node>> {
+        if !self.dir.exists() {
+            return FileIndex::new(self.legacy.clone()).find(name);
+        }
+        let mut ans = Vec::new();
+        for (path, (n, _)) in self.files()? {
+            if n == name {
+                ans.push(parse_nodes(&fs::read_to_string(path)?)?);
+            }
+        }
+        Ok(ans)
+    }
+
+    fn remove(&self, name: &str, version: Option<&str>) -> anyhow::Result<Option<Node>> {
+        for (path, (n, v)) in self.files()? {
+            if n == name && v.as_deref() == version {
+                let node = parse_nodes(&fs::read_to_string(&path)?)?;
+                fs::remove_file(&path)?;
+                info!("remove {:?}", path);
+                return Ok(Some(node));
+            }
+        }
+        Ok(None)
+    }
+
+    /// 还没有从旧索引导入时需要完整加载，保存时才会导入
+    fn lazy(&self) -> bool {
+        self.dir.exists()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteIndex;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{parse_nodes, FileIndex, IndexBackend};
+    use crate::core::node::Meta::FILE;
+    use crate::core::node::Node;
+    use log::info;
+    use rusqlite::OptionalExtension;
+    use rusqlite::{params, Connection};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS nodes (
+            name TEXT NOT NULL,
+            version TEXT NOT NULL,
+            added INTEGER NOT NULL,
+            digest TEXT NOT NULL,
+            manifest TEXT NOT NULL,
+            PRIMARY KEY (name, version)
+        );
+        CREATE TABLE IF NOT EXISTS blobs (
+            hash TEXT NOT NULL,
+            name TEXT NOT NULL,
+            version TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS blobs_hash ON blobs (hash);
+        CREATE INDEX IF NOT EXISTS blobs_node ON blobs (name, version);
+        CREATE TABLE IF NOT EXISTS refcounts (
+            hash TEXT PRIMARY KEY,
+            refs INTEGER NOT NULL
+        );
+    ";
+
+    /// SQLite索引，`nodes` 每个条目一行，`blobs` 记录条目引用的对象，`refcounts` 为对象的引用数
+    pub struct SqliteIndex {
+        path: PathBuf,
+        /// 切换前的json索引，数据库不存在时从中导入
+        legacy: PathBuf,
+    }
+
+    impl SqliteIndex {
+        pub fn new(path: PathBuf, legacy: PathBuf) -> Self {
+            Self { path, legacy }
+        }
+
+        fn open(&self) -> anyhow::Result<Connection> {
+            let conn = Connection::open(&self.path)?;
+            conn.execute_batch(SCHEMA)?;
+            Ok(conn)
+        }
+
+        /// 引用对象的数量
+        pub fn refcount(&self, hash: &str) -> anyhow::Result<usize> {
+            // 打开连接会创建数据库，还没有导入时从旧索引中统计
+            if !self.path.exists() {
+                return FileIndex::new(self.legacy.clone()).refcount(hash);
+            }
+            let conn = self.open()?;
+            let refs: Option<i64> = conn
+                .query_row(
+                    "SELECT refs FROM refcounts WHERE hash = ?1",
+                    params![hash],
+                    |r| r.get(0),
+                )
+                .ok();
+            Ok(refs.unwrap_or_default() as usize)
+        }
+    }
+
+    /// 空字符串表示没有版本
+    fn version_key(node: &Node) -> &str {
+        node.version.as_deref().unwrap_or("")
+    }
+
+    fn from_key(v: String) -> Option<String> {
+        (!v.is_empty()).then_some(v)
+    }
+
+    impl IndexBackend for SqliteIndex {
+        fn load(&self) -> anyhow::Result<Vec<Node>> {
+            if !self.path.exists() {
+                if self.legacy.exists() {
+                    info!("import {:?} into {:?}", self.legacy, self.path);
+                }
+                return FileIndex::new(self.legacy.clone()).load();
+            }
+            let conn = self.open()?;
+            let mut stmt = conn.prepare("SELECT manifest FROM nodes ORDER BY name, version")?;
+            let rows = stmt.query_map([], |r| r.get::<_, String>(0))?;
+            let mut ans = Vec::new();
+            for row in rows {
+                ans.push(parse_nodes(&row?)?);
+            }
+            Ok(ans)
+        }
+
+        fn save(&self, entries: &[&Node]) -> anyhow::Result<()> {
+            let mut conn = self.open()?;
+            let tx = conn.transaction()?;
+            let mut old: HashMap<(String, String), String> = HashMap::new();
+            {
+                let mut stmt = tx.prepare("SELECT name, version, digest FROM nodes")?;
+                let rows = stmt.query_map([], |r| Ok(((r.get(0)?, r.get(1)?), r.get(2)?)))?;
+                for row in rows {
+                    let (k, v) = row?;
+                    old.insert(k, v);
+                }
+            }
+            let mut changed = 0;
+            for node in entries {
+                let key = (node.name.clone(), version_key(node).to_string());
+                let manifest = serde_json::to_string(node)?;
+                let digest = blake3::hash(manifest.as_bytes()).to_hex().to_string();
+                if old.remove(&key).as_ref() == Some(&digest) {
+                    continue;
+                }
+                changed += 1;
+                tx.execute(
+                    "INSERT INTO nodes (name, version, added, digest, manifest)
+                     VALUES (?1, ?2, ?3, ?4, ?5)
+                     ON CONFLICT (name, version) DO UPDATE SET
+                     added = excluded.added, digest = excluded.digest, manifest = excluded.manifest",
+                    params![key.0, key.1, node.added() as i64, digest, manifest],
+                )?;
+                tx.execute(
+                    "DELETE FROM blobs WHERE name = ?1 AND version = ?2",
+                    params![key.0, key.1],
+                )?;
+                let mut insert =
+                    tx.prepare("INSERT INTO blobs (hash, name, version) VALUES (?1, ?2, ?3)")?;
+                node.try_walk(&mut |_, n| {
+                    if let FILE(hash) = &n.meta {
+                        insert.execute(params![hash, key.0, key.1])?;
+                    }
+                    Ok(())
+                })?;
+            }
+            // 剩下的是被删除的条目
+            for (name, version) in old.keys() {
+                changed += 1;
+                for table in ["nodes", "blobs"] {
+                    tx.execute(
+                        &format!("DELETE FROM {} WHERE name = ?1 AND version = ?2", table),
+                        params![name, version],
+                    )?;
+                }
+            }
+            if changed > 0 {
+                tx.execute_batch(
+                    "DELETE FROM refcounts;
+                     INSERT INTO refcounts (hash, refs) SELECT hash, count(*) FROM blobs GROUP BY hash;",
+                )?;
+            }
+            tx.commit()?;
+            info!(
+                "save {} changed entries to {}",
+                changed,
+                self.path.display()
+            );
+            // 导入后旧的json索引不再使用
+            if self.legacy.exists() {
+                let backup = self.legacy.with_extension("json.bak");
+                info!("move {:?} -> {:?}", self.legacy, backup);
+                fs::rename(&self.legacy, backup)?;
+            }
+            Ok(())
+        }
+
+        fn labels(&self) -> anyhow::Result<Vec<(String, Option<String>)>> {
+            if !self.path.exists() {
+                return FileIndex::new(self.legacy.clone())
+                    .load()
+                    .map(|v| v.into_iter().map(|n| (n.name, n.version)).collect());
+            }
+            let conn = self.open()?;
+            let mut stmt =
+                conn.prepare("SELECT name, version FROM nodes ORDER BY name, version")?;
+            let rows = stmt.query_map([], |r| Ok((r.get(0)?, from_key(r.get(1)?))))?;
+            Ok(rows.collect::<Result<_, _>>()?)
+        }
+
+        fn find(&self, name: &str) -> anyhow::Result<Vec<Node>> {
+            if !self.path.exists() {
+                return FileIndex::new(self.legacy.clone()).find(name);
+            }
+            let conn = self.open()?;
+            let mut stmt =
+                conn.prepare("SELECT manifest FROM nodes WHERE name = ?1 ORDER BY version")?;
+            let rows = stmt.query_map(params![name], |r| r.get::<_, String>(0))?;
+            let mut ans = Vec::new();
+            for row in rows {
+                ans.push(parse_nodes(&row?)?);
+            }
+            Ok(ans)
+        }
+
+        /// 只删除这个条目的行，并重新统计它引用的对象
+        fn remove(&self, name: &str, version: Option<&str>) -> anyhow::Result<Option<Node>> {
+            let mut conn = self.open()?;
+            let tx = conn.transaction()?;
+            let version = version.unwrap_or("");
+            let manifest: Option<String> = tx
+                .query_row(
+                    "SELECT manifest FROM nodes WHERE name = ?1 AND version = ?2",
+                    params![name, version],
+                    |r| r.get(0),
+                )
+                .optional()?;
+            let Some(manifest) = manifest else {
+                return Ok(None);
+            };
+            let hashes: Vec<String> = tx
+                .prepare("SELECT DISTINCT hash FROM blobs WHERE name = ?1 AND version = ?2")?
+                .query_map(params![name, version], |r| r.get(0))?
+                .collect::<Result<_, _>>()?;
+            for table in ["nodes", "blobs"] {
+                tx.execute(
+                    &format!("DELETE FROM {} WHERE name = ?1 AND version = ?2", table),
+                    params![name, version],
+                )?;
+            }
+            for hash in &hashes {
+                tx.execute("DELETE FROM refcounts WHERE hash = ?1", params![hash])?;
+                tx.execute(
+                    "INSERT INTO refcounts (hash, refs)
+                     SELECT hash, count(*) FROM blobs WHERE hash = ?1 GROUP BY hash",
+                    params![hash],
+                )?;
+            }
+            tx.commit()?;
+            let node: Node = parse_nodes(&manifest)?;
+            info!("remove {} from {}", node.label(), self.path.display());
+            Ok(Some(node))
+        }
+
+        fn refcount(&self, hash: &str) -> anyhow::Result<usize> {
+            SqliteIndex::refcount(self, hash)
+        }
+
+        /// 还没有从旧索引导入时需要完整加载，保存时才会导入
+        fn lazy(&self) -> bool {
+            self.path.exists()
+        }
+    }
+}
+
+impl Store {
+    /// 按配置选择索引的存储
+    pub fn index_backend(&self) -> anyhow::Result<Box<dyn IndexBackend>> {
+        let json = self.path().join(CONFIG_NAME);
+        match (self.settings().index, self.identity()?) {
+            (IndexFormat::Json, None) => Ok(Box::new(FileIndex::new(json))),
+            (IndexFormat::Json, Some(identity)) => {
+                Ok(Box::new(FileIndex::encrypted(json, identity)))
+            }
+            (IndexFormat::Msgpack, None) => Ok(Box::new(FileIndex::new(json).binary())),
+            (IndexFormat::Msgpack, Some(identity)) => {
+                Ok(Box::new(FileIndex::encrypted(json, identity).binary()))
+            }
+            (IndexFormat::Sqlite | IndexFormat::Entries, Some(_)) => {
+                bail!("encryption only supports the json and msgpack index")
+            }
+            (IndexFormat::Entries, None) => Ok(Box::new(EntriesIndex::new(
+                self.path().join(ENTRIES_DIRECTORY),
+                json,
+            ))),
+            #[cfg(feature = "sqlite")]
+            (IndexFormat::Sqlite, None) => Ok(Box::new(SqliteIndex::new(
+                self.path().join(crate::INDEX_DB_NAME),
+                json,
+            ))),
+            #[cfg(not(feature = "sqlite"))]
+            (IndexFormat::Sqlite, None) => bail!("hbx is built without the sqlite feature"),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::core::store::{AddOptions, Store};
+use crate::core::util::{format_time, now};
+use anyhow::{anyhow, bail};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 定时备份任务
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Backup {
+    /// 备份的目录
+    pub path: PathBuf,
+    /// 备份周期，如 hourly、daily、weekly 或 `6h`
+    pub schedule: String,
+    /// 保留的版本数
+    pub keep: usize,
+    /// 上次备份时间，unix秒
+    #[serde(default)]
+    pub last_run: u64,
+}
+
+impl Backup {
+    /// 下次备份时间，unix秒
+    pub fn next_run(&self) -> anyhow::Result<u64> {
+        Ok(self.last_run + parse_schedule(&self.schedule)?.as_secs())
+    }
+}
+
+/// 解析备份周期
+pub fn parse_schedule(s: &str) -> anyhow::Result<Duration> {
+    let secs = match s {
+        "hourly" => 60 * 60,
+        "daily" => 24 * 60 * 60,
+        "weekly" => 7 * 24 * 60 * 60,
+        _ => {
+            return humantime::parse_duration(s)
+                .map_err(|e| anyhow!("invalid schedule {:?}: {}", s, e))
+        }
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+impl Store {
+    /// 添加备份任务，返回条目名称
+    pub fn add_backup(
+        &mut self,
+        path: &Path,
+        schedule: &str,
+        keep: usize,
+    ) -> anyhow::Result<String> {
+        parse_schedule(schedule)?;
+        if keep == 0 {
+            bail!("keep must be at least 1");
+        }
+        let path = path.canonicalize()?;
+        if !path.is_dir() {
+            bail!("{:?} is not a directory", path);
+        }
+        let name = path
+            .file_name()
+            .ok_or(anyhow!("invalid path {:?}", path))?
+            .to_string_lossy()
+            .to_string();
+        let backup = Backup {
+            path,
+            schedule: schedule.to_string(),
+            keep,
+            last_run: 0,
+        };
+        self.settings_mut().backups.insert(name.clone(), backup);
+        Ok(name)
+    }
+
+    /// 执行到期的备份任务并清理超出保留数量的旧版本，返回执行了备份的条目
+    pub fn run_backups(&mut self, force: bool) -> anyhow::Result<Vec<String>> {
+        let now = now();
+        let mut due = Vec::new();
+        for (name, backup) in &self.settings().backups {
+            if force || backup.next_run()? <= now {
+                due.push((name.clone(), backup.clone()));
+            }
+        }
+
+        let mut ans = Vec::new();
+        for (name, backup) in due {
+            if !backup.path.is_dir() {
+                warn!("backup {} skipped, {:?} not exists", name, backup.path);
+                continue;
+            }
+            // 备份的版本号为备份时间
+            let version = format_time(now);
+            if self.entry_version(&name, Some(&version)).is_ok() {
+                info!("backup {}@{} already exists", name, version);
+                continue;
+            }
+            info!("backup {} from {:?}", name, backup.path);
+            let opts = AddOptions {
+                version: Some(version),
+                ..Default::default()
+            };
+            self.add_with(&backup.path, &opts)?;
+            self.prune_versions(&name, backup.keep);
+            if let Some(b) = self.settings_mut().backups.get_mut(&name) {
+                b.last_run = now;
+            }
+            ans.push(name);
+        }
+        Ok(ans)
+    }
+
+    /// 只保留最新的 `keep` 个版本，对象需要clear后才会删除
+    pub fn prune_versions(&mut self, name: &str, keep: usize) -> Vec<Option<String>> {
+        let versions = self.versions(name);
+        let expired: Vec<Option<String>> = versions[..versions.len().saturating_sub(keep)]
+            .iter()
+            .map(|n| n.version.clone())
+            .collect();
+        for version in &expired {
+            info!("expire {}@{}", name, version.as_deref().unwrap_or_default());
+            self.delete_version(name, version.as_deref());
+        }
+        expired
+    }
+
+    /// 最近一个备份任务的到期时间，unix秒
+    pub fn next_backup(&self) -> Option<u64> {
+        self.settings()
+            .backups
+            .values()
+            .filter_map(|b| b.next_run().ok())
+            .min()
+    }
+}
@@ -1,5 +1,15 @@
-use clap::{Parser, Subcommand};
+use crate::core::filter::FileKind;
+use crate::core::names::NameEscape;
+use crate::core::store::Reflink;
+use crate::core::stream::StreamFormat;
+use crate::core::util::parse_size;
+use anyhow::{anyhow, bail};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use log::debug;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -7,39 +17,809 @@ use std::path::PathBuf;
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// wait for a locked store, e.g. 30s, 5m, without a value wait until it is released
+    #[arg(
+        long,
+        global = true,
+        num_args = 0..=1,
+        default_missing_value = "forever",
+        value_parser = parse_wait
+    )]
+    pub wait: Option<Duration>,
+
+    /// fail at once when the store is locked, even if lock_wait is configured
+    #[arg(long, global = true, conflicts_with = "wait")]
+    pub no_wait: bool,
+
+    /// use a store declared in stores.toml instead of HBX_HOME
+    #[arg(long, global = true)]
+    pub store: Option<String>,
+
+    /// print newline-delimited json events (start, progress, file, result, summary) instead of human output
+    #[arg(long, global = true)]
+    pub porcelain: bool,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
+    #[command(disable_version_flag = true)]
     Add {
-        /// the path of the file
-        path: PathBuf,
+        /// the path of the file, or an http(s) URL to download, archives are unpacked
+        #[arg(required_unless_present = "stdin")]
+        path: Option<PathBuf>,
+        /// the expected checksum of the downloaded file, like sha256:<hex>
+        #[arg(long, requires = "path")]
+        checksum: Option<String>,
+        /// read a tar stream from stdin instead of a path, needs --name
+        #[arg(long, conflicts_with = "path", requires = "name")]
+        stdin: bool,
+        /// the format of the stream read with --stdin
+        #[arg(long, value_enum, default_value_t, requires = "stdin")]
+        format: StreamFormat,
+        /// store the entry as this version, e.g. 1.2.3, default a version from the add time when the name exists
+        #[arg(long)]
+        version: Option<String>,
         // #[arg(short, long)]
         // force: bool,
+        /// skip files larger than this size, e.g. 2G
+        #[arg(long, value_parser = parse_size)]
+        max_file_size: Option<u64>,
+        /// skip files smaller than this size, e.g. 1K
+        #[arg(long, value_parser = parse_size)]
+        min_file_size: Option<u64>,
+        /// only store paths up to this many levels below the added directory, deeper ones are skipped
+        #[arg(long)]
+        max_depth: Option<usize>,
+        /// skip special files, split by ','
+        #[arg(long, value_delimiter = ',')]
+        exclude_type: Vec<FileKind>,
+        /// skip paths matching this gitignore pattern, e.g. target/ or *.log, can repeat; .hbxignore files in the tree are also read
+        #[arg(long)]
+        exclude: Vec<String>,
+        /// only store files matching this glob, e.g. '**/*.parquet', can repeat
+        #[arg(long)]
+        include: Vec<String>,
+        /// store the content symlinks point to instead of the links, symlink cycles are refused
+        #[arg(short = 'L', long, visible_alias = "follow-symlinks")]
+        dereference: bool,
+        /// hash stored files again to make sure they did not change during add
+        #[arg(long)]
+        rehash: bool,
+        /// entry name, may have levels like toolchains/gcc, default the file name
+        #[arg(long)]
+        name: Option<String>,
+        /// copy files that cannot be hard linked into the store, default the copy_on_cross_device setting
+        #[arg(long)]
+        copy_on_cross_device: Option<bool>,
+        /// also record the DACLs of files on windows and restore them on get
+        #[arg(long)]
+        acl: bool,
+        /// do not record extended attributes in this namespace, like security or user, can repeat
+        #[arg(long, value_name = "NAMESPACE")]
+        skip_xattr: Vec<String>,
+        /// attach a tag to the entry, can repeat
+        #[arg(long)]
+        tag: Vec<String>,
+    },
+
+    /// shallow clone a git repository at a revision and store the tree without .git
+    AddGit {
+        /// the repository URL or path
+        url: String,
+        /// branch, tag or commit to check out, default the remote HEAD
+        #[arg(long)]
+        rev: Option<String>,
+        /// entry name, default the repository name
+        #[arg(long)]
+        name: Option<String>,
+        /// store the entry as this version
+        #[arg(long)]
+        version: Option<String>,
+        /// attach a tag to the entry, can repeat
+        #[arg(long)]
+        tag: Vec<String>,
+    },
+
+    /// take over a directory on the same filesystem as the store, files are linked, never copied
+    Adopt {
+        /// the directory or file to adopt
+        path: PathBuf,
+        /// entry name, default the file name
+        #[arg(long)]
+        name: Option<String>,
+        /// replace files whose content is already stored with links to the stored objects, files with a different mode or owner are kept
+        #[arg(long)]
+        replace: bool,
+    },
+
+    /// remove a restored tree if it still matches a stored version, the objects stay in the store
+    Release {
+        /// the restored directory or file
+        path: PathBuf,
+        /// entry name, default the file name
+        #[arg(long)]
+        name: Option<String>,
+        /// remove it even when it has local changes
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// encrypt an entry for age recipients, it can only be restored after unseal
+    Seal {
+        /// package name, use name@version to seal a single version
+        name: String,
+        /// age public key of a recipient, e.g. age1...
+        #[arg(short, long, required = true)]
+        recipient: Vec<String>,
+    },
+
+    /// decrypt a sealed entry with an age identity file
+    Unseal {
+        /// package name, use name@version to unseal a single version
+        name: String,
+        /// file with the age secret keys
+        #[arg(short, long)]
+        identity: PathBuf,
     },
 
     Delete {
-        /// package name
+        /// package name, use name@version to delete a single version
         name: String,
     },
 
+    /// rename an entry with all its versions and aliases, objects are not copied
+    Rename {
+        old: String,
+        new: String,
+    },
+
+    /// names that resolve to an entry, like jdk17 for temurin-17.0.9
+    EntryAlias {
+        #[command(subcommand)]
+        command: EntryAliasCommands,
+    },
+
+    /// restore one or more entries, several entries are restored concurrently
     Get {
-        /// name
+        /// package names, name@latest or name@^12 pick the best matching version, name/some/path restores only that path
+        #[arg(required = true)]
+        names: Vec<String>,
+        /// the directory to save files, default the current directory
+        #[arg(short, long)]
+        dst: Option<PathBuf>,
+        /// restore a single entry or path to exactly this path, e.g. -o ./gcc
+        #[arg(short, long, conflicts_with = "dst")]
+        output: Option<PathBuf>,
+        /// restore the version closest to this time, e.g. 2024-05-01
+        #[arg(long)]
+        at: Option<String>,
+        /// restore everything that does not conflict and write the conflicts to this json file
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// replace an existing destination with exactly the stored tree
+        #[arg(long, group = "existing")]
+        overwrite: bool,
+        /// restore into an existing destination and keep files that are already there
+        #[arg(long, group = "existing")]
+        skip_existing: bool,
+        /// restore into an existing destination, replace changed files and keep extra ones
+        #[arg(long, group = "existing")]
+        merge: bool,
+        /// how to escape names that are invalid on windows
+        #[arg(long, value_enum)]
+        name_escape: Option<NameEscape>,
+        /// write directly into the destination instead of renaming a staging directory into place
+        #[arg(long)]
+        no_atomic: bool,
+        /// copy objects that cannot be hard linked into the destination, default the copy_on_cross_device setting
+        #[arg(long)]
+        copy_on_cross_device: Option<bool>,
+        /// copy objects instead of hard linking them, so restored files can be edited without touching the store
+        #[arg(long, conflicts_with = "symlink")]
+        copy: bool,
+        /// restore files as symlinks to the objects in the store, works across filesystems, for read-only use
+        #[arg(long)]
+        symlink: bool,
+        /// restore files as copy-on-write clones sharing storage with the store on btrfs, XFS and APFS
+        #[arg(long, value_enum, default_value_t = Reflink::Never)]
+        reflink: Reflink,
+        /// restore the modification times recorded at add
+        #[arg(long)]
+        preserve_times: bool,
+        /// restore the owner and group recorded at add, needs root
+        #[arg(long)]
+        preserve_owner: bool,
+        /// do not restore extended attributes in this namespace, like security or user, can repeat
+        #[arg(long, value_name = "NAMESPACE")]
+        skip_xattr: Vec<String>,
+        /// only restore files matching this glob, e.g. '**/*.parquet', can repeat
+        #[arg(long)]
+        include: Vec<String>,
+        /// do not restore files matching this glob, e.g. 'raw/**', can repeat
+        #[arg(long)]
+        exclude: Vec<String>,
+        /// hash every object again before restoring and fail listing the corrupt ones
+        #[arg(long)]
+        verify: bool,
+    },
+
+    /// check whether objects are in the store, exit non-zero if any is missing
+    Has {
+        /// object hashes, read from stdin when omitted
+        #[arg(long = "hash")]
+        hashes: Vec<String>,
+    },
+
+    /// show all versions of an entry
+    Versions {
+        /// package name
         name: String,
-        /// the path to save files
-        path: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
 
-    List {},
+    /// check that the objects of an entry exist and match their hashes,
+    /// without a name every object in the store is hashed again
+    Verify {
+        /// package name, default the whole store
+        name: Option<String>,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// show what an entry is made of, by file extension and by top-level directory
+    Info {
+        /// package name
+        name: String,
+        /// number of extensions and directories to show
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    List {
+        /// only show entries under this prefix, e.g. toolchains/
+        prefix: Option<String>,
+        /// also show the file count, size, time, content hash and tags recorded at add
+        #[arg(short, long)]
+        long: bool,
+        /// only show entries with this tag, can repeat to require all of them
+        #[arg(long)]
+        tag: Vec<String>,
+        /// show each name once with its number of versions and the newest version
+        #[arg(short, long, conflicts_with = "long")]
+        count: bool,
+    },
+
+    /// show how much space each entry uses alone and which entries share content
+    DedupReport {
+        /// number of entry pairs to show
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// print the digest of a file or the tree hash of a directory, the store is not changed
+    Hash {
+        path: PathBuf,
+        /// follow symlinks like add -L
+        #[arg(short = 'L', long)]
+        dereference: bool,
+    },
+
+    /// search the text files of an entry by regex without restoring it
+    Grep {
+        pattern: String,
+        name: String,
+        /// only search files whose name or path matches, can be repeated
+        #[arg(long)]
+        include: Vec<String>,
+        /// case insensitive
+        #[arg(short, long)]
+        ignore_case: bool,
+    },
+
+    /// show which entries and paths keep an object, and whether clear would remove it
+    Why {
+        /// object hash
+        #[arg(long)]
+        hash: String,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// find entries on remotes by name, remote catalogs are cached for a while
+    Search {
+        /// substring of the entry name, or a glob like tools/*
+        pattern: String,
+        /// remote names or addresses split by ',', all for every configured remote
+        #[arg(long, value_delimiter = ',', default_value = "all")]
+        remote: Vec<String>,
+        /// fetch the catalogs again instead of using the cache
+        #[arg(long)]
+        refresh: bool,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
 
     About {},
 
+    /// check what the filesystem of a directory supports before big operations:
+    /// hard links from the store, symlinks, path limits, free inodes and space
+    Selfcheck {
+        /// the directory to check, default the current directory
+        path: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
     Clear {},
 
+    /// move objects no entry references any more since they were deleted to the trash, faster than clear
+    Gc {
+        /// only print what would be removed
+        #[arg(long)]
+        dry_run: bool,
+        /// also purge objects that have been in the trash longer than this, e.g. 7d
+        #[arg(long, value_parser = humantime::parse_duration)]
+        expire: Option<std::time::Duration>,
+    },
+
+    /// move objects back from the trash
+    RestoreBlob {
+        /// object hashes, list the trash when empty
+        hashes: Vec<String>,
+    },
+
     Pull {
-        /// package name ,split by ' '
+        /// package name ,split by ' ', a name ending with '/' pulls everything under it, name@^12 picks a version
         names: Vec<String>,
         /// ip or host
         address: String,
+        /// queue the transfer for `hbx queue run` instead of pulling now
+        #[arg(long)]
+        background: bool,
+        /// jobs with a higher priority run first
+        #[arg(long, default_value_t = 0, requires = "background")]
+        priority: i32,
+    },
+
+    /// export an entry as a tar or zip archive
+    Export {
+        /// package name
+        name: String,
+        /// write a zip archive instead of tar
+        #[arg(long)]
+        zip: bool,
+        /// output file, default <name>.tar or <name>.zip
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// keep a directory of hard-linked entries at a stable path, only changed entries are replaced
+    Farm {
+        /// entries to expose, name@^12 picks a version, default the entries already in the directory
+        entries: Vec<String>,
+        /// the target directory
+        #[arg(long)]
+        dst: PathBuf,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// write a consistent snapshot of the whole store (index, settings, objects) as tar
+    ExportStore {
+        /// output file, compressed with zstd when it ends with .zst
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+
+    /// merge a snapshot written by export-store into this store, existing entries are kept
+    ImportStore {
+        /// snapshot file, decompressed with zstd when it ends with .zst
+        input: PathBuf,
+    },
+
+    /// export the index (entries, trees, hashes, sizes) as versioned json for other tools
+    ExportIndex {
+        /// output file, default stdout
+        file: Option<PathBuf>,
+    },
+
+    /// import an index written by export-index, the objects must already be in the store
+    ImportIndex {
+        /// the exported index file
+        file: PathBuf,
+    },
+
+    /// count entries and objects and show how much space deduplication saves
+    Stats {
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+
+    /// list the named stores declared in stores.toml
+    Stores {},
+
+    /// encrypt objects and the index at rest with an age key, existing entries are rewritten
+    Encrypt {
+        /// the age identity file, created when missing, keep it outside the store
+        #[arg(long)]
+        key_file: PathBuf,
+    },
+
+    /// rebuild missing index entries from the manifests saved next to the index
+    Repair {},
+
+    /// move the whole store to another directory
+    Relocate {
+        /// the new store directory
+        path: PathBuf,
+    },
+
+    /// remove a lock left behind by a crashed process
+    Unlock {
+        /// also remove locks held by other hosts
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// write a signed statement of every entry's root hash for auditors
+    Attest {
+        /// output file, default stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[command(subcommand)]
+        command: Option<AttestCommands>,
+    },
+
+    /// scheduled deduplicated backups of directories
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommands,
+    },
+
+    /// pull the entries pinned in project files (hbx.toml) and restore the missing ones
+    Sync {
+        /// project files, default ./hbx.toml
+        files: Vec<PathBuf>,
+        /// only pull into the local store, meant for cron on build agents
+        #[arg(long)]
+        prefetch_only: bool,
+    },
+
+    /// background pull jobs
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommands,
     },
+
+    /// show or set command aliases
+    Alias {
+        /// alias name
+        name: Option<String>,
+        /// the command the alias expands to, quotes are honoured but $VAR, $(...) and backticks are not expanded
+        command: Option<String>,
+        /// remove the alias
+        #[arg(long)]
+        unset: bool,
+    },
+
+    /// show or change settings
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// print completion candidates for the words after `hbx`, used by shell scripts
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        /// the last word is the one being completed
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
+}
+
+/// 解析等待时间，`forever` 表示一直等待
+pub fn parse_wait(s: &str) -> anyhow::Result<Duration> {
+    match s {
+        "forever" => Ok(Duration::MAX),
+        _ => Ok(humantime::parse_duration(s)?),
+    }
+}
+
+/// 命令输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+pub enum BackupCommands {
+    /// back up a directory on a schedule
+    Add {
+        /// the directory to back up
+        path: PathBuf,
+        /// hourly, daily, weekly or a duration like 6h
+        #[arg(long, default_value = "daily")]
+        schedule: String,
+        /// number of versions to keep
+        #[arg(long, default_value_t = 7)]
+        keep: usize,
+    },
+
+    /// stop backing up a directory, stored versions are kept
+    Remove {
+        /// package name
+        name: String,
+    },
+
+    /// show backup jobs
+    List {},
+
+    /// run due backups, meant for cron or a service manager
+    Run {
+        /// back up every job now
+        #[arg(long)]
+        force: bool,
+        /// keep running and back up jobs as they become due
+        #[arg(long)]
+        watch: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AttestCommands {
+    /// check the signature of an attestation and list entries changed since
+    Verify {
+        /// the attestation file
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EntryAliasCommands {
+    /// attach an alias to an entry
+    Add {
+        /// the entry, name@version pins the alias to a version
+        entry: String,
+        alias: String,
+    },
+
+    /// remove an alias
+    Remove { alias: String },
+
+    /// show aliases and the entries they resolve to
+    List {},
+}
+
+#[derive(Subcommand)]
+pub enum QueueCommands {
+    /// show queued pull jobs
+    List {},
+
+    /// remove a job from the queue
+    Cancel {
+        /// job id
+        id: u64,
+    },
+
+    /// run queued jobs by priority, meant for cron or a service manager
+    Run {
+        /// bandwidth limit per second, e.g. 10M
+        #[arg(long, value_parser = parse_size)]
+        limit: Option<u64>,
+        /// keep running and wait for new jobs
+        #[arg(long)]
+        watch: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// print a setting, or all settings
+    Get {
+        /// dotted key, e.g. inline_threshold
+        key: Option<String>,
+    },
+
+    /// change a setting
+    Set {
+        /// dotted key, e.g. alias.up
+        key: String,
+        /// json value or plain string
+        value: String,
+    },
+
+    /// export settings (aliases, remotes, defaults) without the index
+    Export {
+        /// output file, default stdout
+        file: Option<PathBuf>,
+    },
+
+    /// import settings exported from another machine
+    Import {
+        /// the exported settings file
+        file: PathBuf,
+        /// replace current settings instead of merging
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+impl Commands {
+    /// 修改支持 `--format` 的命令的输出格式
+    pub fn set_format(&mut self, value: OutputFormat) {
+        match self {
+            Commands::Versions { format, .. }
+            | Commands::Verify { format, .. }
+            | Commands::Info { format, .. }
+            | Commands::DedupReport { format, .. }
+            | Commands::Stats { format }
+            | Commands::Why { format, .. }
+            | Commands::Search { format, .. }
+            | Commands::Selfcheck { format, .. }
+            | Commands::Farm { format, .. }
+            | Commands::Attest {
+                command: Some(AttestCommands::Verify { format, .. }),
+                ..
+            } => *format = value,
+            _ => {}
+        }
+    }
+
+    /// 只读取少数条目的命令，索引支持按需读取时不必在开始时加载整个索引
+    pub fn lazy_index(&self) -> bool {
+        matches!(
+            self,
+            Commands::List { .. }
+                | Commands::Info { .. }
+                | Commands::Versions { .. }
+                | Commands::Delete { .. }
+        )
+    }
+
+    /// 会修改仓库的命令，执行前需要加锁
+    pub fn lock_name(&self) -> Option<&'static str> {
+        match self {
+            Commands::Add { .. } | Commands::AddGit { .. } => Some("add"),
+            Commands::Adopt { .. } => Some("adopt"),
+            Commands::Delete { .. } => Some("delete"),
+            Commands::Rename { .. } => Some("rename"),
+            Commands::EntryAlias {
+                command: EntryAliasCommands::Add { .. } | EntryAliasCommands::Remove { .. },
+            } => Some("alias"),
+            Commands::Seal { .. } => Some("seal"),
+            Commands::Unseal { .. } => Some("unseal"),
+            Commands::Clear { .. } => Some("clear"),
+            Commands::Gc { dry_run: false, .. } => Some("gc"),
+            Commands::RestoreBlob { hashes } if !hashes.is_empty() => Some("gc"),
+            Commands::Pull { .. } => Some("pull"),
+            Commands::Sync { .. } => Some("sync"),
+            Commands::Queue {
+                command: QueueCommands::Cancel { .. },
+            } => Some("queue"),
+            Commands::Relocate { .. } => Some("relocate"),
+            Commands::Repair { .. } => Some("repair"),
+            Commands::Encrypt { .. } => Some("encrypt"),
+            Commands::Alias {
+                command: Some(_), ..
+            }
+            | Commands::Alias { unset: true, .. } => Some("settings"),
+            Commands::Config {
+                command: ConfigCommands::Set { .. } | ConfigCommands::Import { .. },
+            } => Some("settings"),
+            Commands::Backup {
+                command: BackupCommands::Add { .. } | BackupCommands::Remove { .. },
+            } => Some("settings"),
+            Commands::ImportIndex { .. } => Some("import-index"),
+            Commands::ExportStore { .. } => Some("export-store"),
+            Commands::ImportStore { .. } => Some("import-store"),
+            _ => None,
+        }
+    }
+}
+
+/// 展开命令别名，与git类似，别名不能覆盖内置命令
+pub fn expand_alias(
+    args: Vec<OsString>,
+    alias: &BTreeMap<String, String>,
+) -> anyhow::Result<Vec<OsString>> {
+    // 跳过全局选项和它们的值，如 `--wait 5s`、`--store up`
+    let flags = value_flags();
+    let mut pos = 1;
+    loop {
+        let Some(arg) = args.get(pos).map(|a| a.to_string_lossy()) else {
+            return Ok(args);
+        };
+        if arg == "--" {
+            return Ok(args);
+        }
+        if !arg.starts_with('-') {
+            break;
+        }
+        let has_value = args
+            .get(pos + 1)
+            .is_some_and(|v| !v.to_string_lossy().starts_with('-'));
+        if flags.iter().any(|f| *f == arg) && has_value {
+            pos += 1;
+        }
+        pos += 1;
+    }
+    let name = args[pos].to_string_lossy().to_string();
+    if is_builtin(&name) {
+        return Ok(args);
+    }
+    let value = match alias.get(&name) {
+        None => return Ok(args),
+        Some(v) => v,
+    };
+    let words = split_alias(value)?;
+    if words.is_empty() {
+        bail!("alias {} is empty", name);
+    }
+    debug!("expand alias {} -> {:?}", name, words);
+    let mut ans = args[..pos].to_vec();
+    ans.extend(words.into_iter().map(OsString::from));
+    ans.extend_from_slice(&args[pos + 1..]);
+    Ok(ans)
+}
+
+const STORE_FLAG: &str = "--store";
+
+/// 解析参数前找出 `--store` 指定的仓库，别名在仓库的配置中
+pub fn store_arg(args: &[OsString]) -> Option<String> {
+    let mut iter = args.iter().skip(1).map(|a| a.to_string_lossy());
+    while let Some(a) = iter.next() {
+        if a == "--" {
+            break;
+        }
+        if a == STORE_FLAG {
+            return iter.next().map(|s| s.to_string());
+        }
+        if let Some(v) = a.strip_prefix("--store=") {
+            return Some(v.to_string());
+        }
+    }
+    None
+}
+
+/// 是否为内置子命令
+pub fn is_builtin(name: &str) -> bool {
+    name == "help" || Cli::command().find_subcommand(name).is_some()
+}
+
+/// 带值的全局选项，值不是子命令
+fn value_flags() -> Vec<String> {
+    Cli::command()
+        .get_arguments()
+        .filter(|a| a.is_global_set() && a.get_action().takes_values())
+        .filter_map(|a| a.get_long())
+        .map(|l| format!("--{}", l))
+        .collect()
+}
+
+/// 按shell的规则处理引号和转义，但不做变量、`$(...)` 等任何展开，
+/// 单引号和转义之外的 `$`、反引号直接报错，以免被当成字面值
+pub fn split_alias(value: &str) -> anyhow::Result<Vec<String>> {
+    let (mut single, mut escaped) = (false, false);
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if !single => escaped = true,
+            '\'' => single = !single,
+            '$' | '`' if !single => bail!(
+                "alias {:?} uses shell expansion, which is not supported; quote it with '' to keep it literally",
+                value
+            ),
+            _ => {}
+        }
+    }
+    shlex::split(value).ok_or(anyhow!("unbalanced quotes in alias {:?}", value))
 }
@@ -0,0 +1,117 @@
+use crate::core::cli::Cli;
+use crate::core::node::Meta::DIRECTORY;
+use crate::core::node::Node;
+use crate::core::store::Store;
+use clap::CommandFactory;
+use std::collections::BTreeSet;
+
+/// 动态补全，`words` 为 `hbx` 之后的参数，最后一个为正在输入的词，返回候选项
+pub fn complete(store: &Store, words: &[String]) -> Vec<String> {
+    let (current, prev) = match words.split_last() {
+        None => ("", &[][..]),
+        Some((current, prev)) => (current.as_str(), prev),
+    };
+    let args: Vec<&str> = prev
+        .iter()
+        .map(String::as_str)
+        .filter(|w| !w.starts_with('-'))
+        .collect();
+
+    let cli = Cli::command();
+    let ans: Vec<String> = match args.as_slice() {
+        [] => cli
+            .get_subcommands()
+            .filter(|c| !c.is_hide_set())
+            .map(|c| c.get_name().to_string())
+            .chain(store.settings().alias.keys().cloned())
+            .collect(),
+        [cmd, ..] if current.starts_with('-') => match cli.find_subcommand(cmd) {
+            None => Vec::new(),
+            Some(c) => c
+                .get_arguments()
+                .filter_map(|a| a.get_long())
+                .map(|l| format!("--{}", l))
+                .collect(),
+        },
+        ["get", ..] => entry_names(store, current, true),
+        ["delete" | "versions" | "export"] => entry_names(store, current, false),
+        ["pull", ..] => entry_names(store, current, false)
+            .into_iter()
+            .chain(store.settings().remotes.keys().cloned())
+            .collect(),
+        ["backup", "remove"] => store.settings().backups.keys().cloned().collect(),
+        ["alias"] => store.settings().alias.keys().cloned().collect(),
+        [cmd] => match cli.find_subcommand(cmd) {
+            None => Vec::new(),
+            Some(c) => c
+                .get_subcommands()
+                .map(|c| c.get_name().to_string())
+                .collect(),
+        },
+        _ => Vec::new(),
+    };
+    ans.into_iter()
+        .filter(|x| x.starts_with(current))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// 补全条目名称，`name@` 补全版本，`subpath` 时 `name/` 补全条目内的路径
+fn entry_names(store: &Store, current: &str, subpath: bool) -> Vec<String> {
+    if let Some((name, _)) = current.split_once('@') {
+        return store
+            .versions(name)
+            .iter()
+            .map(|n| n.label())
+            .filter(|l| l.contains('@'))
+            .collect();
+    }
+    // 多级名称中也有 `/`，名称完整匹配某个条目后才补全条目内的路径
+    let entries = store.entries();
+    let root = entries
+        .iter()
+        .filter(|n| current.starts_with(&format!("{}/", n.name)))
+        .max_by_key(|n| n.name.len());
+    match root {
+        Some(root) if subpath => {
+            let rest = &current[root.name.len() + 1..];
+            children(root, rest)
+                .into_iter()
+                .map(|x| format!("{}/{}", root.name, x))
+                .collect()
+        }
+        _ => entries.iter().map(|n| n.name.clone()).collect(),
+    }
+}
+
+/// `rest` 所在目录下的子节点，目录以 `/` 结尾
+fn children(root: &Node, rest: &str) -> Vec<String> {
+    let (dir, _) = rest.rsplit_once('/').unwrap_or(("", rest));
+    let mut node = root;
+    for part in dir.split('/').filter(|p| !p.is_empty()) {
+        let next = match &node.meta {
+            DIRECTORY(vec) => vec.iter().find(|x| x.name == part),
+            _ => None,
+        };
+        match next {
+            None => return Vec::new(),
+            Some(n) => node = n,
+        }
+    }
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    };
+    match &node.meta {
+        DIRECTORY(vec) => vec
+            .iter()
+            .map(|x| match x.meta {
+                DIRECTORY(_) => format!("{}{}/", prefix, x.name),
+                _ => format!("{}{}", prefix, x.name),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,229 @@
+use crate::core::node::Meta::{FILE, INLINE};
+use crate::core::store::Store;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// 单个条目占用的空间
+#[derive(Debug, Serialize)]
+pub struct EntryUsage {
+    /// 带版本号的条目名称
+    pub label: String,
+    /// 引用的对象数
+    pub objects: usize,
+    /// 引用的对象总大小
+    pub total: u64,
+    /// 只被该条目引用的对象大小，即删除该条目后能释放的空间
+    pub unique: u64,
+}
+
+/// 两个条目共享的内容
+#[derive(Debug, Serialize)]
+pub struct SharedPair {
+    pub a: String,
+    pub b: String,
+    /// 共享对象的总大小
+    pub shared: u64,
+}
+
+/// 对象被保留的原因
+#[derive(Debug, Serialize)]
+pub struct ObjectUsage {
+    pub hash: String,
+    /// 对象是否在仓库中
+    pub present: bool,
+    pub size: Option<u64>,
+    /// 引用该对象的条目和文件，为空时下次clear会删除该对象
+    pub retained_by: Vec<Retainer>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Retainer {
+    /// 带版本号的条目名称
+    pub entry: String,
+    /// 相对于条目根目录的路径
+    pub path: PathBuf,
+}
+
+impl ObjectUsage {
+    /// 下次clear是否会删除该对象
+    pub fn collectable(&self) -> bool {
+        self.present && self.retained_by.is_empty()
+    }
+}
+
+/// 仓库的去重情况
+#[derive(Debug, Serialize)]
+pub struct DedupReport {
+    /// 按独占空间从大到小排序
+    pub entries: Vec<EntryUsage>,
+    /// 按共享大小从大到小排序
+    pub pairs: Vec<SharedPair>,
+    /// 所有条目的大小之和
+    pub logical: u64,
+    /// 仓库中被引用的对象实际占用的大小
+    pub physical: u64,
+}
+
+/// 仓库的总体统计
+#[derive(Debug, Serialize)]
+pub struct StoreStats {
+    /// 条目数，每个版本单独计算
+    pub entries: usize,
+    /// 条目中的文件数，重复的文件分别计算
+    pub files: usize,
+    /// 被引用的不同对象数
+    pub blobs: usize,
+    /// 所有文件的大小之和，即不去重时需要的空间
+    pub logical: u64,
+    /// 对象目录中文件实际占用的大小，包括还未清理的对象
+    pub physical: u64,
+    /// 内联在索引中的文件大小
+    pub inline: u64,
+    /// 去重比例，logical / physical
+    pub ratio: f64,
+}
+
+impl Store {
+    /// 统计条目、对象和去重比例，对象按仓库中的大小计算，压缩过的对象为压缩后的大小
+    pub fn stats(&self) -> anyhow::Result<StoreStats> {
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        let (mut files, mut logical, mut inline) = (0, 0, 0);
+        for root in self.entries() {
+            root.try_walk(&mut |_, n| {
+                match &n.meta {
+                    FILE(hash) => {
+                        let size = match sizes.get(hash) {
+                            Some(s) => *s,
+                            None => {
+                                let s = self.object_path(hash).metadata().map_or(0, |m| m.len());
+                                sizes.insert(hash.clone(), s);
+                                s
+                            }
+                        };
+                        files += 1;
+                        logical += size;
+                    }
+                    INLINE(_) => {
+                        let size = n.inline_content()?.map_or(0, |c| c.len() as u64);
+                        files += 1;
+                        logical += size;
+                        inline += size;
+                    }
+                    _ => {}
+                }
+                Ok(())
+            })?;
+        }
+        let mut physical = 0;
+        for (_, path) in self.stored_files()? {
+            physical += path.metadata().map_or(0, |m| m.len());
+        }
+        Ok(StoreStats {
+            entries: self.entries().len(),
+            files,
+            blobs: sizes.len(),
+            logical,
+            physical,
+            inline,
+            ratio: if physical == 0 {
+                1.0
+            } else {
+                logical as f64 / physical as f64
+            },
+        })
+    }
+
+    /// 列出引用对象的所有条目和路径
+    pub fn why(&self, hash: &str) -> ObjectUsage {
+        let mut retained_by = Vec::new();
+        for root in self.entries() {
+            let base = Path::new(root.leaf_name());
+            root.walk(&mut |path, node| {
+                if matches!(&node.meta, FILE(h) if h == hash) {
+                    retained_by.push(Retainer {
+                        entry: root.label(),
+                        path: base.join(path),
+                    });
+                }
+            });
+        }
+        let present = self.has_object(hash);
+        ObjectUsage {
+            hash: hash.to_string(),
+            present,
+            size: present
+                .then(|| self.object_path(hash).metadata().ok().map(|m| m.len()))
+                .flatten(),
+            retained_by,
+        }
+    }
+
+    /// 统计各条目之间共享的内容，不在仓库中的对象按0计算
+    pub fn dedup_report(&self) -> anyhow::Result<DedupReport> {
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        // 对象 -> 引用它的条目
+        let mut owners: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut entries = Vec::new();
+        for root in self.entries() {
+            let label = root.label();
+            let mut objects = BTreeSet::new();
+            root.walk(&mut |_, n| {
+                if let FILE(hash) = &n.meta {
+                    objects.insert(hash.clone());
+                }
+            });
+            for hash in &objects {
+                if !sizes.contains_key(hash) {
+                    let size = match self.object_path(hash).metadata() {
+                        Ok(m) => m.len(),
+                        Err(_) => 0,
+                    };
+                    sizes.insert(hash.clone(), size);
+                }
+                owners
+                    .entry(hash.clone())
+                    .or_default()
+                    .insert(label.clone());
+            }
+            entries.push((label, objects));
+        }
+
+        let mut shared: BTreeMap<(String, String), u64> = BTreeMap::new();
+        for (hash, labels) in &owners {
+            let labels: Vec<&String> = labels.iter().collect();
+            for (i, a) in labels.iter().enumerate() {
+                for b in &labels[i + 1..] {
+                    *shared.entry(((*a).clone(), (*b).clone())).or_default() += sizes[hash];
+                }
+            }
+        }
+
+        let mut usage: Vec<EntryUsage> = entries
+            .into_iter()
+            .map(|(label, objects)| EntryUsage {
+                objects: objects.len(),
+                total: objects.iter().map(|h| sizes[h]).sum(),
+                unique: objects
+                    .iter()
+                    .filter(|h| owners[*h].len() == 1)
+                    .map(|h| sizes[h])
+                    .sum(),
+                label,
+            })
+            .collect();
+        usage.sort_by(|a, b| b.unique.cmp(&a.unique).then(a.label.cmp(&b.label)));
+        let mut pairs: Vec<SharedPair> = shared
+            .into_iter()
+            .map(|((a, b), shared)| SharedPair { a, b, shared })
+            .collect();
+        pairs.sort_by_key(|p| std::cmp::Reverse(p.shared));
+
+        Ok(DedupReport {
+            logical: usage.iter().map(|u| u.total).sum(),
+            physical: sizes.values().sum(),
+            entries: usage,
+            pairs,
+        })
+    }
+}
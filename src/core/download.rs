@@ -0,0 +1,124 @@
+use crate::core::hash::HashAlgorithm;
+use crate::core::store::{AddOptions, Store};
+use crate::core::util::format_size;
+use anyhow::{anyhow, bail};
+use flate2::read::GzDecoder;
+use log::info;
+use std::fs::File;
+use std::io;
+use std::time::Duration;
+use tempfile::{Builder, NamedTempFile};
+
+/// 下载的超时时间，只限制连接和两次读取之间的间隔
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 按URL的扩展名判断下载的内容，不是归档的文件原样保存
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Download {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+    File,
+}
+
+const SUFFIXES: [(&str, Download); 6] = [
+    (".tar", Download::Tar),
+    (".tar.gz", Download::TarGz),
+    (".tgz", Download::TarGz),
+    (".tar.zst", Download::TarZst),
+    (".tzst", Download::TarZst),
+    (".zip", Download::Zip),
+];
+
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// URL路径的最后一段和内容类型，归档去掉扩展名后作为默认的条目名称
+fn url_target(url: &str) -> anyhow::Result<(String, Download)> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let file_name = path
+        .split_once("://")
+        .and_then(|(_, rest)| rest.split_once('/'))
+        .and_then(|(_, path)| path.rsplit('/').next())
+        .filter(|s| !s.is_empty())
+        .ok_or(anyhow!("{} has no file name", url))?;
+    let (suffix, kind) = SUFFIXES
+        .iter()
+        .find(|(s, _)| file_name.len() > s.len() && file_name.ends_with(s))
+        .copied()
+        .unwrap_or(("", Download::File));
+    Ok((
+        file_name[..file_name.len() - suffix.len()].to_string(),
+        kind,
+    ))
+}
+
+/// 解析期望的校验和，格式为 `算法:十六进制`，只有十六进制时为sha256
+fn parse_checksum(spec: &str) -> anyhow::Result<(HashAlgorithm, String)> {
+    let (algorithm, hex) = match spec.split_once(':') {
+        None => (HashAlgorithm::Sha256, spec),
+        Some(("sha256", hex)) => (HashAlgorithm::Sha256, hex),
+        Some(("blake3", hex)) => (HashAlgorithm::Blake3, hex),
+        Some(("md5", hex)) => (HashAlgorithm::Md5, hex),
+        Some((other, _)) => bail!("unsupported checksum algorithm {}", other),
+    };
+    if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("invalid checksum {}", spec);
+    }
+    Ok((algorithm, hex.to_ascii_lowercase()))
+}
+
+impl Store {
+    /// 下载URL并add，下载完成后先检查校验和，归档解包后保存，返回条目名称
+    pub fn add_url(
+        &mut self,
+        url: &str,
+        checksum: Option<&str>,
+        opts: &AddOptions,
+    ) -> anyhow::Result<String> {
+        let expected = checksum.map(parse_checksum).transpose()?;
+        let (default_name, kind) = url_target(url)?;
+        let opts = AddOptions {
+            name: Some(opts.name.clone().unwrap_or(default_name)),
+            ..opts.clone()
+        };
+        let mut tmp = NamedTempFile::new_in(self.tmp_dir()?)?;
+        info!("download {}", url);
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(TIMEOUT)
+            .timeout_read(TIMEOUT)
+            .build();
+        let res = agent.get(url).call()?;
+        let size = io::copy(&mut res.into_reader(), tmp.as_file_mut())?;
+        info!("downloaded {} from {}", format_size(size), url);
+        if let Some((algorithm, hex)) = expected {
+            let actual = algorithm.hash_file(tmp.path())?;
+            // sha256的对象名带有前缀
+            if actual.rsplit('-').next() != Some(hex.as_str()) {
+                bail!(
+                    "checksum mismatch for {}: expected {}, got {}",
+                    url,
+                    hex,
+                    actual
+                );
+            }
+        }
+        let file = File::open(tmp.path())?;
+        match kind {
+            Download::Tar => self.add_tar(file, &opts)?,
+            Download::TarGz => self.add_tar(GzDecoder::new(file), &opts)?,
+            Download::TarZst => self.add_tar(zstd::Decoder::new(file)?, &opts)?,
+            Download::Zip => {
+                let dir = Builder::new()
+                    .prefix(".hbx-unzip-")
+                    .tempdir_in(self.tmp_dir()?)?;
+                zip::ZipArchive::new(file)?.extract(dir.path())?;
+                self.add_with(dir.path(), &opts)?;
+            }
+            Download::File => self.add_with(tmp.path(), &opts)?,
+        }
+        Ok(opts.name.unwrap_or_default())
+    }
+}
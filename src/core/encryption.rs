@@ -0,0 +1,181 @@
+use crate::core::node::Meta::{DIRECTORY, FILE};
+use crate::core::node::Node;
+use crate::core::object_filter::{Age, ObjectFilter, Pipeline};
+use crate::core::store::Store;
+use crate::core::util::ingest;
+use age::secrecy::ExposeSecret;
+use age::x25519::Identity;
+use age::Recipient;
+use anyhow::{anyhow, bail};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// 加密对象使用的过滤器名称
+pub const AGE_FILTER: &str = "age";
+
+/// age加密文件的开头
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+/// 仓库加密的配置，开启后对象内容和索引都加密保存
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Encryption {
+    /// age身份文件，应放在共享目录之外
+    pub key_file: PathBuf,
+}
+
+/// 读取身份文件中的第一个x25519私钥
+pub fn load_identity(path: &Path) -> anyhow::Result<Identity> {
+    let s = fs::read_to_string(path).map_err(|e| anyhow!("failed to read {:?}: {}", path, e))?;
+    s.lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+        .and_then(|l| Identity::from_str(l).ok())
+        .ok_or(anyhow!("no age identity in {:?}", path))
+}
+
+/// 读取身份文件，不存在时生成新的私钥，只有当前用户可读
+pub fn load_or_create_identity(path: &Path) -> anyhow::Result<Identity> {
+    if path.exists() {
+        return load_identity(path);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let identity = Identity::generate();
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    match options.open(path) {
+        Ok(mut f) => {
+            writeln!(f, "# public key: {}", identity.to_public())?;
+            writeln!(f, "{}", identity.to_string().expose_secret())?;
+        }
+        // 其他进程同时生成了，使用先生成的
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => return load_identity(path),
+        Err(e) => return Err(e.into()),
+    }
+    info!("created key {:?}", path);
+    Ok(identity)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(AGE_MAGIC)
+}
+
+pub fn encrypt(identity: &Identity, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let recipient = identity.to_public();
+    let encryptor = age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn Recipient))?;
+    let mut ans = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut ans)?;
+    writer.write_all(data)?;
+    writer.finish()?;
+    Ok(ans)
+}
+
+pub fn decrypt(identity: &Identity, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut reader = age::Decryptor::new_buffered(data)?
+        .decrypt(std::iter::once(identity as &dyn age::Identity))
+        .map_err(|e| anyhow!("failed to decrypt: {}", e))?;
+    let mut ans = Vec::new();
+    reader.read_to_end(&mut ans)?;
+    Ok(ans)
+}
+
+impl Store {
+    /// 加密使用的私钥，未开启加密时为空
+    pub fn identity(&self) -> anyhow::Result<Option<Identity>> {
+        match &self.settings().encryption {
+            None => Ok(None),
+            Some(e) => load_identity(&e.key_file).map(Some),
+        }
+    }
+
+    /// 按名称创建过滤器，加密过滤器使用仓库的私钥
+    pub fn pipeline(&self, names: &[String]) -> anyhow::Result<Pipeline> {
+        let identity = match names.iter().any(|n| n == AGE_FILTER) {
+            true => Some(
+                self.identity()?
+                    .ok_or(anyhow!("the store has no encryption key"))?,
+            ),
+            false => None,
+        };
+        Pipeline::with_identity(names, identity.as_ref())
+    }
+
+    /// 新条目使用的过滤器，开启加密时最后加密
+    pub fn filters_for(&self, name: &str) -> anyhow::Result<Vec<String>> {
+        let mut filters = self.settings().filters_for(name)?;
+        if self.settings().encryption.is_some() {
+            filters.push(AGE_FILTER.to_string());
+        }
+        Ok(filters)
+    }
+
+    /// 开启加密并加密已有的条目，返回重新写入的条目数，原来的明文对象需要通过gc清理
+    pub fn encrypt_store(&mut self, key_file: &Path) -> anyhow::Result<usize> {
+        let key_file = std::path::absolute(key_file)?;
+        if key_file.starts_with(self.path()) {
+            bail!(
+                "{:?} is inside the store, keep the key somewhere else",
+                key_file
+            );
+        }
+        let identity = load_or_create_identity(&key_file)?;
+        if self.settings().index != Default::default() {
+            bail!("encryption only supports the json index");
+        }
+        self.settings_mut().encryption = Some(Encryption { key_file });
+        let age = Age::new(identity);
+        // 多个条目引用同一对象时只加密一次
+        let mut encrypted: HashMap<String, String> = HashMap::new();
+        let mut count = 0;
+        let roots: Vec<_> = self
+            .entries()
+            .into_iter()
+            .filter(|n| !n.filters.iter().any(|f| f == AGE_FILTER))
+            .cloned()
+            .collect();
+        for mut root in roots {
+            self.encrypt_node(&mut root, &age, &mut encrypted)?;
+            root.filters.push(AGE_FILTER.to_string());
+            root.compute_tree();
+            info!("encrypted {}", root.label());
+            self.replace_manifest(root)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// 加密节点树引用的对象，替换为加密后的对象名
+    fn encrypt_node(
+        &self,
+        node: &mut Node,
+        age: &Age,
+        encrypted: &mut HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        match &mut node.meta {
+            FILE(hash) => {
+                if let Some(e) = encrypted.get(hash.as_str()) {
+                    *hash = e.clone();
+                    return Ok(());
+                }
+                let reader = age.encode(Box::new(File::open(self.object_path(hash))?))?;
+                let e = ingest(reader, self)?;
+                encrypted.insert(std::mem::replace(hash, e.clone()), e);
+            }
+            DIRECTORY(vec) => {
+                for x in vec {
+                    self.encrypt_node(x, age, encrypted)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
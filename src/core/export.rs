@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// 导出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Tar,
+    Zip,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Tar => "tar",
+            Format::Zip => "zip",
+        }
+    }
+
+    /// 创建写入到文件的导出器
+    pub fn exporter(&self, file: File) -> Box<dyn Exporter> {
+        match self {
+            Format::Tar => Box::new(TarExporter::new(file)),
+            Format::Zip => Box::new(ZipExporter::new(file)),
+        }
+    }
+}
+
+/// 导出流水线，由 `Store::export` 按节点树顺序调用
+pub trait Exporter {
+    fn dir(&mut self, path: &Path, mode: u32) -> anyhow::Result<()>;
+
+    fn file(
+        &mut self,
+        path: &Path,
+        mode: u32,
+        size: u64,
+        data: &mut dyn Read,
+    ) -> anyhow::Result<()>;
+
+    fn symlink(&mut self, path: &Path, target: &Path) -> anyhow::Result<()>;
+
+    fn finish(&mut self) -> anyhow::Result<()>;
+}
+
+/// 归档中统一使用 `/` 作为分隔符
+fn archive_name(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub struct TarExporter<W: Write> {
+    builder: tar::Builder<W>,
+}
+
+impl<W: Write> TarExporter<W> {
+    pub fn new(w: W) -> Self {
+        let mut builder = tar::Builder::new(w);
+        builder.follow_symlinks(false);
+        Self { builder }
+    }
+}
+
+impl<W: Write> Exporter for TarExporter<W> {
+    fn dir(&mut self, path: &Path, mode: u32) -> anyhow::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(mode);
+        header.set_size(0);
+        self.builder
+            .append_data(&mut header, archive_name(path), std::io::empty())?;
+        Ok(())
+    }
+
+    fn file(
+        &mut self,
+        path: &Path,
+        mode: u32,
+        size: u64,
+        data: &mut dyn Read,
+    ) -> anyhow::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(mode);
+        header.set_size(size);
+        self.builder
+            .append_data(&mut header, archive_name(path), data)?;
+        Ok(())
+    }
+
+    fn symlink(&mut self, path: &Path, target: &Path) -> anyhow::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_mode(0o777);
+        header.set_size(0);
+        self.builder
+            .append_link(&mut header, archive_name(path), target)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.builder.finish()?;
+        Ok(())
+    }
+}
+
+/// zip中的权限和符号链接记录在unix扩展字段中
+pub struct ZipExporter<W: Write + Seek> {
+    writer: ZipWriter<W>,
+}
+
+impl<W: Write + Seek> ZipExporter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            writer: ZipWriter::new(w),
+        }
+    }
+}
+
+impl<W: Write + Seek> Exporter for ZipExporter<W> {
+    fn dir(&mut self, path: &Path, mode: u32) -> anyhow::Result<()> {
+        let options = FileOptions::default().unix_permissions(mode);
+        self.writer.add_directory(archive_name(path), options)?;
+        Ok(())
+    }
+
+    fn file(
+        &mut self,
+        path: &Path,
+        mode: u32,
+        size: u64,
+        data: &mut dyn Read,
+    ) -> anyhow::Result<()> {
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(mode)
+            .large_file(size >= u32::MAX as u64);
+        self.writer.start_file(archive_name(path), options)?;
+        std::io::copy(data, &mut self.writer)?;
+        Ok(())
+    }
+
+    fn symlink(&mut self, path: &Path, target: &Path) -> anyhow::Result<()> {
+        let target = target.to_string_lossy().replace('\\', "/");
+        self.writer
+            .add_symlink(archive_name(path), target, FileOptions::default())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use crate::core::store::{GetOptions, Store};
+use crate::core::util::remove_path;
+use anyhow::bail;
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// 目标目录中记录当前内容的文件
+pub const FARM_STATE_NAME: &str = ".hbx-farm.json";
+
+/// 目标目录中的条目，key为条目名称，也是相对目标目录的路径
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct FarmState {
+    entries: BTreeMap<String, Planted>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Planted {
+    /// 指定条目的方式，如 `gcc@^12`，每次更新时重新解析
+    spec: String,
+    label: String,
+    content: String,
+}
+
+/// farm的结果
+#[derive(Debug, Default, Serialize)]
+pub struct FarmReport {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+fn load_state(path: &Path) -> anyhow::Result<FarmState> {
+    if !path.exists() {
+        return Ok(FarmState::default());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn save_state(path: &Path, state: &FarmState) -> anyhow::Result<()> {
+    let s = serde_json::to_string_pretty(state)?;
+    AtomicFile::new(path, AllowOverwrite).write(|f| f.write_all(s.as_bytes()))?;
+    Ok(())
+}
+
+impl Store {
+    /// 在目标目录中维护一组条目的硬链接视图，只更新内容变化的条目，
+    /// `specs` 为空时按上次的条目更新
+    pub fn farm(
+        &self,
+        specs: &[String],
+        dst: &Path,
+        opts: &GetOptions,
+    ) -> anyhow::Result<FarmReport> {
+        fs::create_dir_all(dst)?;
+        let state_path = dst.join(FARM_STATE_NAME);
+        let mut state = load_state(&state_path)?;
+        let specs: Vec<String> = if specs.is_empty() {
+            state.entries.values().map(|p| p.spec.clone()).collect()
+        } else {
+            specs.to_vec()
+        };
+        let mut wanted = BTreeMap::new();
+        for spec in &specs {
+            let root = self.resolve(spec)?;
+            if wanted.insert(root.name.clone(), (spec, root)).is_some() {
+                bail!("{} is given more than once", root.name);
+            }
+        }
+
+        let mut report = FarmReport::default();
+        let stale: Vec<String> = state
+            .entries
+            .keys()
+            .filter(|k| !wanted.contains_key(*k))
+            .cloned()
+            .collect();
+        for name in stale {
+            let path = dst.join(&name);
+            info!("remove {:?}", path);
+            remove_path(&path)?;
+            remove_empty_parents(&path, dst);
+            state.entries.remove(&name);
+            save_state(&state_path, &state)?;
+            report.removed.push(name);
+        }
+
+        for (name, (spec, root)) in wanted {
+            let content = root.content_id();
+            let old = state.entries.get(&name);
+            if old.is_some_and(|p| p.content == content) && dst.join(&name).exists() {
+                report.unchanged.push(root.label());
+                continue;
+            }
+            let path = dst.join(&name);
+            let parent = path.parent().unwrap_or(dst).to_path_buf();
+            fs::create_dir_all(&parent)?;
+            // 先恢复到临时目录，再替换旧的内容，使用中的路径不会出现半成品
+            let staging = tempfile::Builder::new()
+                .prefix(".hbx-farm-")
+                .tempdir_in(&parent)?;
+            self.get_node(root, Some(staging.path().to_path_buf()), opts)?;
+            let fresh = staging.path().join(root.leaf_name());
+            match old {
+                None => {
+                    if path.symlink_metadata().is_ok() {
+                        bail!("{:?} already exists and is not managed by hbx", path);
+                    }
+                    report.added.push(root.label());
+                }
+                Some(_) => {
+                    let retired = staging.path().join(".old");
+                    if path.symlink_metadata().is_ok() {
+                        fs::rename(&path, &retired)?;
+                    }
+                    report.updated.push(root.label());
+                }
+            }
+            info!("plant {} -> {:?}", root.label(), path);
+            fs::rename(&fresh, &path)?;
+            state.entries.insert(
+                name,
+                Planted {
+                    spec: spec.clone(),
+                    label: root.label(),
+                    content,
+                },
+            );
+            save_state(&state_path, &state)?;
+        }
+        Ok(report)
+    }
+}
+
+/// 删除多级条目名称留下的空目录，不会删除目标目录本身
+fn remove_empty_parents(path: &Path, root: &Path) {
+    let mut cur: Option<PathBuf> = path.parent().map(Path::to_path_buf);
+    while let Some(dir) = cur {
+        if dir == root || fs::remove_dir(&dir).is_err() {
+            break;
+        }
+        cur = dir.parent().map(Path::to_path_buf);
+    }
+}
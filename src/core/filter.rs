@@ -0,0 +1,191 @@
+use crate::core::node::Meta::DIRECTORY;
+use crate::core::node::Node;
+use crate::IGNORE_NAME;
+use clap::ValueEnum;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::fs::Metadata;
+use std::path::Path;
+
+/// 可以被排除的特殊文件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FileKind {
+    Socket,
+    Fifo,
+    Device,
+}
+
+impl FileKind {
+    #[cfg(unix)]
+    fn matches(&self, meta: &Metadata) -> bool {
+        use std::os::unix::fs::FileTypeExt;
+        let t = meta.file_type();
+        match self {
+            FileKind::Socket => t.is_socket(),
+            FileKind::Fifo => t.is_fifo(),
+            FileKind::Device => t.is_block_device() || t.is_char_device(),
+        }
+    }
+
+    #[cfg(windows)]
+    fn matches(&self, _meta: &Metadata) -> bool {
+        false
+    }
+}
+
+/// add时的过滤条件
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    pub max_file_size: Option<u64>,
+    pub min_file_size: Option<u64>,
+    pub exclude_types: Vec<FileKind>,
+    /// 排除的路径，gitignore语法，相对于add的目录
+    pub exclude: Vec<String>,
+    /// 只保存匹配的文件，通配符可以匹配文件名或相对路径，为空时保存所有文件
+    pub include: Vec<String>,
+    /// 只保存这个层级以内的路径，1为add的目录下的直接子节点
+    pub max_depth: Option<usize>,
+}
+
+impl Filter {
+    /// 判断路径是否需要跳过，返回跳过的原因，`follow` 时按符号链接的目标判断
+    pub fn skip(&self, path: &Path, follow: bool) -> anyhow::Result<Option<String>> {
+        let meta = match path.metadata() {
+            Ok(meta) if follow => meta,
+            _ => path.symlink_metadata()?,
+        };
+        if let Some(kind) = self.exclude_types.iter().find(|k| k.matches(&meta)) {
+            return Ok(Some(format!("excluded type {:?}", kind)));
+        }
+        if !meta.is_file() {
+            return Ok(None);
+        }
+        Ok(self.skip_size(meta.len()))
+    }
+
+    /// 按文件大小判断是否需要跳过
+    pub fn skip_size(&self, size: u64) -> Option<String> {
+        if self.max_file_size.is_some_and(|max| size > max) {
+            return Some(format!("size {} exceeds max file size", size));
+        }
+        if self.min_file_size.is_some_and(|min| size < min) {
+            return Some(format!("size {} below min file size", size));
+        }
+        None
+    }
+}
+
+/// add时生效的忽略规则，`--exclude` 优先，其次是从内到外各级目录的 `.hbxignore`
+#[derive(Debug, Default)]
+pub struct Ignores {
+    exclude: Option<Gitignore>,
+    dirs: Vec<Option<Gitignore>>,
+}
+
+impl Ignores {
+    pub fn new(root: &Path, exclude: &[String]) -> anyhow::Result<Self> {
+        let exclude = if exclude.is_empty() {
+            None
+        } else {
+            let mut builder = GitignoreBuilder::new(root);
+            for pattern in exclude {
+                builder.add_line(None, pattern)?;
+            }
+            Some(builder.build()?)
+        };
+        Ok(Self {
+            exclude,
+            dirs: Vec::new(),
+        })
+    }
+
+    /// 进入目录，读取其中的 `.hbxignore`
+    pub fn push(&mut self, dir: &Path) -> anyhow::Result<()> {
+        let file = dir.join(IGNORE_NAME);
+        let rules = if file.is_file() {
+            let mut builder = GitignoreBuilder::new(dir);
+            if let Some(e) = builder.add(&file) {
+                return Err(e.into());
+            }
+            Some(builder.build()?)
+        } else {
+            None
+        };
+        self.dirs.push(rules);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) {
+        self.dirs.pop();
+    }
+
+    /// 路径是否被忽略，内层目录的规则可以用 `!` 重新包含外层忽略的路径
+    pub fn ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if let Some(exclude) = &self.exclude {
+            if exclude.matched(path, is_dir).is_ignore() {
+                return true;
+            }
+        }
+        for rules in self.dirs.iter().rev().flatten() {
+            match rules.matched(path, is_dir) {
+                Match::None => continue,
+                m => return m.is_ignore(),
+            }
+        }
+        false
+    }
+}
+
+/// 按通配符选择文件，通配符可以匹配文件名或相对于条目根目录的路径
+#[derive(Debug, Default)]
+pub struct PathFilter {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> anyhow::Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| glob::Pattern::new(p))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// 文件是否被选中，没有包含规则时选中所有没有被排除的文件
+    pub fn matches(&self, path: &Path) -> bool {
+        let hit = |patterns: &[glob::Pattern]| {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            patterns
+                .iter()
+                .any(|p| p.matches(&file_name) || p.matches_path(path))
+        };
+        (self.include.is_empty() || hit(&self.include)) && !hit(&self.exclude)
+    }
+
+    /// 节点是否需要恢复，目录中有选中的文件时才恢复
+    pub fn selects(&self, rel: &Path, node: &Node) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        if !matches!(node.meta, DIRECTORY(_)) {
+            return self.matches(rel);
+        }
+        let mut any = false;
+        node.walk(&mut |p, n| {
+            if !any && !matches!(n.meta, DIRECTORY(_)) {
+                any = self.matches(&rel.join(p));
+            }
+        });
+        any
+    }
+}
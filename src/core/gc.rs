@@ -0,0 +1,92 @@
+use crate::core::store::Store;
+use crate::core::trash::TrashedObject;
+use crate::core::webhook::EventKind;
+use crate::GARBAGE_NAME;
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// gc的结果
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    /// 移到回收站（`dry_run` 时为将要移动）的对象
+    pub removed: Vec<String>,
+    /// 这些对象的大小
+    pub size: u64,
+    /// 从回收站中清除的过期对象
+    pub expired: Vec<TrashedObject>,
+    /// 清除过期对象释放的空间
+    pub freed: u64,
+}
+
+impl Store {
+    fn garbage_path(&self) -> PathBuf {
+        self.path().join(GARBAGE_NAME)
+    }
+
+    /// 之前保存的等待gc的对象
+    fn saved_garbage(&self) -> anyhow::Result<BTreeSet<String>> {
+        match fs::read_to_string(self.garbage_path()) {
+            Ok(s) => Ok(serde_json::from_str(&s)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeSet::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 把本次引用数降为0的对象合并到列表中
+    pub(crate) fn save_garbage(&self) -> anyhow::Result<()> {
+        self.merge_garbage(self.pending_garbage())
+    }
+
+    pub(crate) fn merge_garbage(&self, hashes: &BTreeSet<String>) -> anyhow::Result<()> {
+        if hashes.is_empty() {
+            return Ok(());
+        }
+        let mut all = self.saved_garbage()?;
+        all.extend(hashes.iter().cloned());
+        let s = serde_json::to_string(&all)?;
+        AtomicFile::new(self.garbage_path(), AllowOverwrite)
+            .write(|f| f.write_all(s.as_bytes()))?;
+        Ok(())
+    }
+
+    /// 把引用数降为0的对象移到回收站，只检查这些对象而不遍历整个仓库，
+    /// 指定 `expire` 时同时清除回收站中超过这个时间的对象
+    pub fn gc(&mut self, dry_run: bool, expire: Option<Duration>) -> anyhow::Result<GcReport> {
+        let mut candidates = self.saved_garbage()?;
+        candidates.extend(self.pending_garbage().iter().cloned());
+        let mut report = GcReport::default();
+        for hash in candidates {
+            // 之后又被add的对象不能删除
+            if self.refcount(&hash)? > 0 || !self.has_object(&hash) {
+                continue;
+            }
+            let path = self.object_path(&hash);
+            report.size += path.metadata()?.len();
+            if !dry_run {
+                self.trash_object(&path, &hash)?;
+            }
+            report.removed.push(hash);
+        }
+        if let Some(age) = expire {
+            report.expired = self.expire_trash(age, dry_run)?;
+            report.freed = report.expired.iter().map(|t| t.size).sum();
+        }
+        if dry_run {
+            return Ok(report);
+        }
+        self.pending_garbage_mut().clear();
+        match fs::remove_file(self.garbage_path()) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        if !report.removed.is_empty() {
+            self.notify(EventKind::Clear, None, report.removed.clone());
+        }
+        Ok(report)
+    }
+}
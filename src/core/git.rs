@@ -0,0 +1,93 @@
+use crate::core::node::Provenance;
+use crate::core::store::{AddOptions, Store};
+use anyhow::{anyhow, bail};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::Builder;
+
+/// 在目录中运行git，返回去掉首尾空白的标准输出
+fn git(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// 仓库URL的最后一段去掉 `.git`，作为默认的条目名称
+fn repo_name(url: &str) -> anyhow::Result<String> {
+    url.trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .map(|s| s.trim_end_matches(".git"))
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .ok_or(anyhow!("{} has no repository name, use --name", url))
+}
+
+impl Store {
+    /// 浅克隆git仓库的指定版本并add，不保存 `.git`，仓库和提交记录在汇总信息中，返回条目名称
+    pub fn add_git(
+        &mut self,
+        url: &str,
+        rev: Option<&str>,
+        opts: &AddOptions,
+    ) -> anyhow::Result<String> {
+        // 以 `-` 开头的值会被git当成选项，如 `--upload-pack=<cmd>` 会执行任意命令
+        for value in [Some(url), rev].into_iter().flatten() {
+            if value.starts_with('-') {
+                bail!("invalid git url or revision {:?}", value);
+            }
+        }
+        let name = match &opts.name {
+            Some(name) => name.clone(),
+            None => repo_name(url)?,
+        };
+        let dir = Builder::new()
+            .prefix(".hbx-git-")
+            .tempdir_in(self.tmp_dir()?)?;
+        let work = dir.path().join("tree");
+        fs::create_dir(&work)?;
+        info!("fetch {} {}", url, rev.unwrap_or("HEAD"));
+        git(&work, &["init", "--quiet"])?;
+        git(&work, &["remote", "add", "--end-of-options", "origin", url])?;
+        git(
+            &work,
+            &[
+                "fetch",
+                "--quiet",
+                "--depth",
+                "1",
+                "--end-of-options",
+                "origin",
+                rev.unwrap_or("HEAD"),
+            ],
+        )?;
+        git(&work, &["checkout", "--quiet", "--detach", "FETCH_HEAD"])?;
+        let commit = git(&work, &["rev-parse", "HEAD"])?;
+        info!("checked out {} at {}", url, commit);
+        fs::remove_dir_all(work.join(".git"))?;
+        let opts = AddOptions {
+            name: Some(name.clone()),
+            provenance: Some(Provenance {
+                origin: url.to_string(),
+                rev: rev.map(String::from),
+                commit: Some(commit),
+            }),
+            ..opts.clone()
+        };
+        self.add_with(&work, &opts)?;
+        Ok(name)
+    }
+}
@@ -0,0 +1,108 @@
+use crate::core::node::Meta::{FILE, INLINE};
+use crate::core::store::Store;
+use anyhow::bail;
+use regex::bytes::Regex;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// 开头这么多字节中有 `\0` 时视为二进制文件
+const BINARY_PROBE: usize = 8 * 1024;
+
+/// 匹配的一行
+#[derive(Debug, Serialize)]
+pub struct GrepMatch {
+    /// 相对于条目根目录的路径
+    pub path: PathBuf,
+    /// 从1开始的行号
+    pub line: usize,
+    pub text: String,
+}
+
+/// grep的结果
+#[derive(Debug, Default, Serialize)]
+pub struct GrepReport {
+    /// 搜索过的文本文件
+    pub files: usize,
+    /// 跳过的二进制文件
+    pub binary: Vec<PathBuf>,
+    pub matches: usize,
+}
+
+impl Store {
+    /// 直接在仓库对象中搜索条目的文本文件，每找到一行调用一次 `f`
+    pub fn grep(
+        &self,
+        name: &str,
+        pattern: &Regex,
+        include: &[String],
+        f: &mut dyn FnMut(&GrepMatch),
+    ) -> anyhow::Result<GrepReport> {
+        let root = self.entry(name)?;
+        if root.sealed.is_some() {
+            bail!("{} is sealed, unseal it first", root.label());
+        }
+        let include = include
+            .iter()
+            .map(|p| glob::Pattern::new(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        let codec = self.pipeline(&root.filters)?;
+        let mut report = GrepReport::default();
+        root.try_walk(&mut |path, node| {
+            // 单个文件的条目使用文件名
+            let path = match path.as_os_str().is_empty() {
+                true => Path::new(root.leaf_name()),
+                false => path,
+            };
+            if !included(&include, path) {
+                return Ok(());
+            }
+            let reader: Box<dyn Read> = match &node.meta {
+                FILE(hash) => codec.decode(Box::new(File::open(self.object_path(hash))?))?,
+                INLINE(_) => Box::new(std::io::Cursor::new(
+                    node.inline_content()?.unwrap_or_default(),
+                )),
+                _ => return Ok(()),
+            };
+            let mut reader = BufReader::with_capacity(BINARY_PROBE, reader);
+            if reader.fill_buf()?.contains(&0) {
+                report.binary.push(path.to_path_buf());
+                return Ok(());
+            }
+            report.files += 1;
+            let mut buf = Vec::new();
+            let mut line = 0;
+            loop {
+                buf.clear();
+                if reader.read_until(b'\n', &mut buf)? == 0 {
+                    break;
+                }
+                line += 1;
+                let text = buf.strip_suffix(b"\n").unwrap_or(&buf);
+                let text = text.strip_suffix(b"\r").unwrap_or(text);
+                if pattern.is_match(text) {
+                    report.matches += 1;
+                    f(&GrepMatch {
+                        path: path.to_path_buf(),
+                        line,
+                        text: String::from_utf8_lossy(text).to_string(),
+                    });
+                }
+            }
+            Ok(())
+        })?;
+        Ok(report)
+    }
+}
+
+/// 没有指定通配符时搜索所有文件，通配符可以匹配文件名或相对路径
+fn included(include: &[glob::Pattern], path: &Path) -> bool {
+    if include.is_empty() {
+        return true;
+    }
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    include
+        .iter()
+        .any(|p| p.matches(&file_name) || p.matches_path(path))
+}
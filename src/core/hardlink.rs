@@ -0,0 +1,102 @@
+use crate::core::node::Node;
+use crate::core::store::{GetOptions, GetReport, LinkMode, Store};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 文件所在的设备和inode
+pub(crate) type InodeKey = (u64, u64);
+
+/// 有多个硬链接的普通文件的设备和inode，不跟随符号链接
+pub(crate) fn inode_key(path: &Path) -> Option<InodeKey> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let meta = path.symlink_metadata().ok()?;
+        if meta.is_file() && meta.nlink() > 1 {
+            return Some((meta.dev(), meta.ino()));
+        }
+    }
+    let _ = path;
+    None
+}
+
+/// 同一个inode的路径分为一组，只保留多于一个路径的组，路径相对于根目录
+pub(crate) fn link_groups(
+    inodes: HashMap<InodeKey, Vec<PathBuf>>,
+    root: &Path,
+) -> Vec<Vec<PathBuf>> {
+    let mut groups: Vec<Vec<PathBuf>> = inodes
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .map(|paths| {
+            let mut paths: Vec<PathBuf> = paths
+                .into_iter()
+                .map(|p| p.strip_prefix(root).map(Path::to_path_buf).unwrap_or(p))
+                .collect();
+            paths.sort();
+            paths
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// 两个路径是否为同一个文件
+fn same_file(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let (Ok(a), Ok(b)) = (a.symlink_metadata(), b.symlink_metadata()) {
+            return (a.dev(), a.ino()) == (b.dev(), b.ino());
+        }
+    }
+    let _ = (a, b);
+    false
+}
+
+impl Store {
+    /// 按add时记录的硬链接组重建硬链接，组内其他路径都链接到第一个路径，冲突和转义的路径不处理，
+    /// 恢复为符号链接时不需要处理
+    pub(crate) fn relink(
+        &self,
+        root: &Node,
+        base: &Path,
+        opts: &GetOptions,
+        report: &GetReport,
+    ) -> anyhow::Result<()> {
+        let Some(summary) = &root.summary else {
+            return Ok(());
+        };
+        if opts.link == LinkMode::Symlink {
+            return Ok(());
+        }
+        let skip = |p: &Path| {
+            report.conflicts.iter().any(|c| c.path == p)
+                || !p.symlink_metadata().is_ok_and(|m| m.is_file())
+        };
+        for group in &summary.hardlinks {
+            let paths: Vec<PathBuf> = group.iter().map(|p| base.join(p)).collect();
+            let Some(leader) = paths.iter().find(|p| !skip(p)) else {
+                continue;
+            };
+            for path in &paths {
+                if path == leader || same_file(leader, path) {
+                    continue;
+                }
+                if skip(path) {
+                    warn!(
+                        "{:?} is not restored from the store, not linked to {:?}",
+                        path, leader
+                    );
+                    continue;
+                }
+                info!("h {:?} -> {:?}", leader, path);
+                fs::remove_file(path)?;
+                fs::hard_link(leader, path)?;
+            }
+        }
+        Ok(())
+    }
+}
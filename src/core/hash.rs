@@ -0,0 +1,158 @@
+use crate::core::util::format_size;
+use log::info;
+use md5::Digest;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
+
+/// 计算哈希时每次读取的大小
+const CHUNK_SIZE: usize = 1 << 20;
+/// 大文件每计算这么多字节输出一次进度
+const PROGRESS_STEP: u64 = 1 << 30;
+
+/// sha256对象名的前缀，与blake3的对象名长度相同，需要区分
+const SHA256_PREFIX: &str = "sha256-";
+
+/// 对象名使用的哈希算法，由对象名可以推断出算法，因此不同算法的对象可以共存
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// 旧版本仓库使用的算法
+    Md5,
+    Sha256,
+    #[default]
+    Blake3,
+    /// 非加密哈希，速度最快，适合可信的本地仓库
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    /// 由对象名推断算法，不是合法的对象名时为空
+    pub fn of(name: &str) -> Option<Self> {
+        let (algorithm, hex) = match name.strip_prefix(SHA256_PREFIX) {
+            Some(hex) if hex.len() == 64 => (HashAlgorithm::Sha256, hex),
+            Some(_) => return None,
+            None => match name.len() {
+                16 => (HashAlgorithm::Xxh3, name),
+                32 => (HashAlgorithm::Md5, name),
+                64 => (HashAlgorithm::Blake3, name),
+                _ => return None,
+            },
+        };
+        hex.bytes()
+            .all(|b| b.is_ascii_hexdigit())
+            .then_some(algorithm)
+    }
+
+    pub fn hasher(&self) -> Box<dyn Hasher> {
+        match self {
+            HashAlgorithm::Md5 => Box::new(Digested(md5::Md5::default(), "")),
+            HashAlgorithm::Sha256 => Box::new(Digested(sha2::Sha256::default(), SHA256_PREFIX)),
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            HashAlgorithm::Xxh3 => Box::new(Xxh3::new()),
+        }
+    }
+
+    /// 计算文件的哈希，大文件定期输出进度
+    pub fn hash_file(&self, path: &Path) -> anyhow::Result<String> {
+        let size = path.metadata()?.len();
+        let mut reported = 0;
+        self.hash_reader(File::open(path)?, &mut |done| {
+            if done - reported >= PROGRESS_STEP {
+                reported = done;
+                info!(
+                    "hashing {:?}: {} / {}",
+                    path,
+                    format_size(done),
+                    format_size(size)
+                );
+            }
+        })
+    }
+
+    /// 按固定大小的块读取并计算哈希，内存占用与文件大小无关，每读取一块后以已读取的字节数调用 `progress`
+    pub fn hash_reader<R: Read>(
+        &self,
+        mut reader: R,
+        progress: &mut dyn FnMut(u64),
+    ) -> anyhow::Result<String> {
+        let mut hasher = self.hasher();
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut done = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            hasher.update(&buf[..n]);
+            done += n as u64;
+            progress(done);
+        }
+        Ok(hasher.finalize())
+    }
+}
+
+/// 增量计算哈希，结果即为对象名
+pub trait Hasher {
+    fn update(&mut self, data: &[u8]);
+
+    fn finalize(self: Box<Self>) -> String;
+}
+
+impl io::Write for dyn Hasher + '_ {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// RustCrypto的摘要算法，对象名为前缀加上十六进制摘要
+struct Digested<D>(D, &'static str);
+
+impl<D: Digest> Hasher for Digested<D> {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(&mut self.0, data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        let digest = self.0.finalize();
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}{}", self.1, hex)
+    }
+}
+
+impl Hasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        blake3::Hasher::finalize(&self).to_hex().to_string()
+    }
+}
+
+impl Hasher for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        Xxh3::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        format!("{:016x}", self.digest())
+    }
+}
+
+/// 按对象名对应的算法重新计算文件哈希，判断内容是否与名称一致
+pub fn matches(path: &Path, name: &str) -> anyhow::Result<bool> {
+    match HashAlgorithm::of(name) {
+        None => Ok(false),
+        Some(algorithm) => Ok(algorithm.hash_file(path)? == name),
+    }
+}
@@ -0,0 +1,100 @@
+use crate::core::hash::HashAlgorithm;
+use crate::core::store::Store;
+use crate::HASH_CACHE_NAME;
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// 文件的哈希缓存，文件的大小、修改时间和inode都没有变化时不再计算哈希
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HashCache {
+    files: HashMap<PathBuf, Cached>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    changed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct Cached {
+    len: u64,
+    mtime: Option<SystemTime>,
+    inode: u64,
+    hash: String,
+}
+
+impl Cached {
+    fn new(meta: &Metadata, hash: &str) -> Self {
+        Self {
+            len: meta.len(),
+            mtime: meta.modified().ok(),
+            inode: inode(meta),
+            hash: hash.to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn inode(meta: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.ino()
+}
+
+#[cfg(windows)]
+fn inode(_meta: &Metadata) -> u64 {
+    0
+}
+
+impl HashCache {
+    /// 读取仓库中的缓存，文件不存在或损坏时为空
+    pub fn open(store: &Store) -> anyhow::Result<Self> {
+        let path = store.path().join(HASH_CACHE_NAME);
+        let mut cache = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                warn!("ignore broken hash cache {:?}: {}", path, e);
+                HashCache::default()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashCache::default(),
+            Err(e) => return Err(e.into()),
+        };
+        cache.path = path;
+        Ok(cache)
+    }
+
+    /// 缓存的哈希，文件已变化或算法不同时为空，`path` 需要是绝对路径
+    pub fn hash(&self, path: &Path, algorithm: HashAlgorithm) -> Option<String> {
+        let cached = self.files.get(path)?;
+        let meta = path.metadata().ok()?;
+        let valid = Cached::new(&meta, &cached.hash) == *cached
+            && HashAlgorithm::of(&cached.hash) == Some(algorithm);
+        valid.then(|| cached.hash.clone())
+    }
+
+    pub fn insert(&mut self, path: &Path, hash: &str) {
+        let Ok(meta) = path.metadata() else {
+            return;
+        };
+        let cached = Cached::new(&meta, hash);
+        if self.files.get(path) != Some(&cached) {
+            self.files.insert(path.to_path_buf(), cached);
+            self.changed = true;
+        }
+    }
+
+    /// 保存缓存，同时清理已经不存在的文件
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if !self.changed || self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        self.files.retain(|p, _| p.exists());
+        let s = serde_json::to_string(self)?;
+        AtomicFile::new(&self.path, AllowOverwrite).write(|f| f.write_all(s.as_bytes()))?;
+        self.changed = false;
+        Ok(())
+    }
+}
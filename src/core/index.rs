@@ -0,0 +1,194 @@
+use crate::core::node::Meta::{DIRECTORY, FILE, INLINE, SYMLINK};
+use crate::core::node::{Node, Summary};
+use crate::core::store::Store;
+use crate::core::util::{format_time, now};
+use anyhow::{anyhow, bail};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// 导出索引的格式名称
+pub const INDEX_SCHEMA: &str = "hbx-index";
+/// 导出索引的格式版本，不兼容的修改需要增加版本号
+pub const INDEX_VERSION: u32 = 1;
+
+/// 供外部工具使用的索引，与内部的config格式无关
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IndexExport {
+    /// 固定为 `hbx-index`
+    pub schema: String,
+    /// 格式版本
+    pub version: u32,
+    /// 导出时间，rfc3339
+    pub generated: String,
+    pub entries: Vec<EntryRecord>,
+}
+
+/// 一个条目的一个版本
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EntryRecord {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// add时间，unix秒
+    #[serde(default)]
+    pub added: u64,
+    /// 按深度优先顺序排列的节点，第一个为根节点
+    pub tree: Vec<TreeRecord>,
+}
+
+/// 条目中的一个节点
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TreeRecord {
+    /// 相对于条目根目录的路径，以 `/` 分隔，根节点为空
+    pub path: String,
+    /// file、inline、symlink 或 directory
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// 对象名，仅file有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// 文件大小，对象不在仓库中时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// 链接目标，仅symlink有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<PathBuf>,
+    /// base64编码的内容，仅inline有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+impl Store {
+    /// 导出所有条目的索引
+    pub fn export_index(&self) -> anyhow::Result<IndexExport> {
+        let mut entries = Vec::new();
+        for root in self.entries() {
+            let mut tree = Vec::new();
+            root.try_walk(&mut |path, node| {
+                let path = path
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let mut record = TreeRecord {
+                    path,
+                    kind: String::new(),
+                    hash: None,
+                    size: None,
+                    target: None,
+                    data: None,
+                };
+                match &node.meta {
+                    FILE(hash) => {
+                        record.kind = String::from("file");
+                        record.size = self.object_path(hash).metadata().ok().map(|m| m.len());
+                        record.hash = Some(hash.clone());
+                    }
+                    INLINE(data) => {
+                        record.kind = String::from("inline");
+                        record.size = node.inline_content()?.map(|d| d.len() as u64);
+                        record.data = Some(data.clone());
+                    }
+                    SYMLINK(target) => {
+                        record.kind = String::from("symlink");
+                        record.target = Some(target.clone());
+                    }
+                    DIRECTORY(_) => record.kind = String::from("directory"),
+                }
+                tree.push(record);
+                Ok(())
+            })?;
+            entries.push(EntryRecord {
+                name: root.name.clone(),
+                version: root.version.clone(),
+                added: root.added(),
+                tree,
+            });
+        }
+        Ok(IndexExport {
+            schema: INDEX_SCHEMA.to_string(),
+            version: INDEX_VERSION,
+            generated: format_time(now()),
+            entries,
+        })
+    }
+
+    /// 导入索引，已存在的条目跳过，引用的对象需要已经在仓库中，返回导入的条目
+    pub fn import_index(&mut self, index: IndexExport) -> anyhow::Result<Vec<String>> {
+        if index.schema != INDEX_SCHEMA {
+            bail!("unknown index schema {:?}", index.schema);
+        }
+        if index.version > INDEX_VERSION {
+            bail!(
+                "index version {} is newer than supported version {}",
+                index.version,
+                INDEX_VERSION
+            );
+        }
+        let mut ans = Vec::new();
+        for entry in index.entries {
+            let mut root = build_tree(&entry.name, entry.tree)?;
+            root.version = entry.version;
+            root.summary = Some(Summary {
+                added: entry.added,
+                ..Default::default()
+            });
+            if self
+                .entry_version(&root.name, root.version.as_deref())
+                .is_ok()
+            {
+                info!("{} already exists", root.label());
+                continue;
+            }
+            ans.push(root.label());
+            self.put_manifest(root)?;
+        }
+        Ok(ans)
+    }
+}
+
+/// 由深度优先排列的节点还原节点树
+fn build_tree(name: &str, records: Vec<TreeRecord>) -> anyhow::Result<Node> {
+    fn attach(stack: &mut Vec<Node>) -> anyhow::Result<()> {
+        let node = stack.pop().ok_or(anyhow!("empty tree"))?;
+        match stack.last_mut().map(|p| &mut p.meta) {
+            Some(DIRECTORY(vec)) => vec.push(node),
+            _ => bail!("{:?} is not under a directory", node.name),
+        }
+        Ok(())
+    }
+
+    let mut stack: Vec<Node> = Vec::new();
+    for r in records {
+        let depth = match r.path.as_str() {
+            "" => 0,
+            p => p.split('/').count(),
+        };
+        if depth == 0 && !stack.is_empty() {
+            bail!("{} has more than one root", name);
+        }
+        while stack.len() > depth {
+            attach(&mut stack)?;
+        }
+        if stack.len() != depth {
+            bail!("{} has no parent directory", r.path);
+        }
+        let meta = match r.kind.as_str() {
+            "file" => FILE(r.hash.ok_or(anyhow!("{} has no hash", r.path))?),
+            "inline" => INLINE(r.data.unwrap_or_default()),
+            "symlink" => SYMLINK(r.target.ok_or(anyhow!("{} has no target", r.path))?),
+            "directory" => DIRECTORY(Vec::new()),
+            kind => bail!("unknown type {:?} of {}", kind, r.path),
+        };
+        let leaf = match depth {
+            0 => name,
+            _ => r.path.rsplit('/').next().unwrap_or_default(),
+        };
+        stack.push(Node::with_meta(leaf.to_string(), meta));
+    }
+    while stack.len() > 1 {
+        attach(&mut stack)?;
+    }
+    stack.pop().ok_or(anyhow!("{} has an empty tree", name))
+}
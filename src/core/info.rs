@@ -0,0 +1,101 @@
+use crate::core::node::Meta::{DIRECTORY, FILE, INLINE, SYMLINK};
+use crate::core::node::{Node, Provenance};
+use crate::core::store::Store;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+
+/// 一组文件的数量和大小
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Bucket {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Bucket {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// 条目的组成
+#[derive(Debug, Default, Serialize)]
+pub struct EntryInfo {
+    pub label: String,
+    /// add时间，unix秒
+    pub added: u64,
+    /// add时记录的文件数和总大小，旧版本添加的条目为空
+    pub files: Option<u64>,
+    pub size: Option<u64>,
+    /// 条目内容的来源
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// 仓库中对象的数量和大小
+    pub total: Bucket,
+    pub directories: u64,
+    pub symlinks: u64,
+    /// 按扩展名统计，没有扩展名的文件为空字符串
+    pub by_extension: BTreeMap<String, Bucket>,
+    /// 按顶层目录统计，根目录下的文件为 `.`
+    pub by_directory: BTreeMap<String, Bucket>,
+}
+
+impl EntryInfo {
+    /// 按大小从大到小排序的前 `n` 项
+    pub fn top(map: &BTreeMap<String, Bucket>, n: usize) -> Vec<(&String, &Bucket)> {
+        let mut ans: Vec<_> = map.iter().collect();
+        ans.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+        ans.truncate(n);
+        ans
+    }
+}
+
+impl Store {
+    /// 统计条目中的文件类型和各顶层目录的大小，不在仓库中的对象按0计算
+    pub fn entry_info(&self, root: &Node) -> anyhow::Result<EntryInfo> {
+        let mut info = EntryInfo {
+            label: root.label(),
+            added: root.added(),
+            files: root.files(),
+            size: root.size(),
+            provenance: root.summary.as_ref().and_then(|s| s.provenance.clone()),
+            ..Default::default()
+        };
+        root.try_walk(&mut |path, node| {
+            let bytes = match &node.meta {
+                FILE(hash) => self
+                    .object_path(hash)
+                    .metadata()
+                    .map(|m| m.len())
+                    .unwrap_or_default(),
+                INLINE(_) => node.inline_content()?.unwrap_or_default().len() as u64,
+                SYMLINK(_) => {
+                    info.symlinks += 1;
+                    return Ok(());
+                }
+                DIRECTORY(_) => {
+                    // 根节点本身不计入
+                    if !path.as_os_str().is_empty() {
+                        info.directories += 1;
+                    }
+                    return Ok(());
+                }
+            };
+            info.total.add(bytes);
+            let ext = Path::new(&node.name)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            info.by_extension.entry(ext).or_default().add(bytes);
+            let mut parts = path.components();
+            let dir = match (parts.next(), parts.next()) {
+                (Some(Component::Normal(dir)), Some(_)) => dir.to_string_lossy().to_string(),
+                _ => String::from("."),
+            };
+            info.by_directory.entry(dir).or_default().add(bytes);
+            Ok(())
+        })?;
+        Ok(info)
+    }
+}
@@ -0,0 +1,104 @@
+use crate::core::store::Store;
+use crate::core::util::is_hash;
+use log::info;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// 对象目录已经使用分目录布局的标记
+pub const SHARDED_MARKER: &str = ".sharded";
+
+/// 对象在对象目录中的相对路径，按十六进制部分的前两位分目录，
+/// 如 `abcdef…` 为 `ab/cdef…`，`sha256-abcdef…` 为 `ab/sha256-cdef…`
+pub fn shard_path(hash: &str) -> PathBuf {
+    let (prefix, hex) = match hash.rsplit_once('-') {
+        Some((p, h)) => (&hash[..p.len() + 1], h),
+        None => ("", hash),
+    };
+    match (hex.get(..2), hex.get(2..)) {
+        (Some(dir), Some(rest)) if !rest.is_empty() => {
+            Path::new(dir).join(format!("{}{}", prefix, rest))
+        }
+        _ => PathBuf::from(hash),
+    }
+}
+
+/// 由分目录中的文件名还原对象名，`shard_path` 的逆运算
+pub fn object_name(dir: &str, file: &str) -> String {
+    match file.rsplit_once('-') {
+        Some((p, rest)) => format!("{}-{}{}", p, dir, rest),
+        None => format!("{}{}", dir, file),
+    }
+}
+
+/// 写入中的临时文件或旧版本留在对象目录中的临时目录，不是对象
+fn is_temp(name: &str) -> bool {
+    name.starts_with(".tmp") || name.starts_with(".hbx-")
+}
+
+impl Store {
+    /// 把旧仓库平铺在对象目录中的对象移到分目录中，每个对象目录只迁移一次，
+    /// 会移动对象，调用前需要持有仓库锁
+    pub fn migrate_layout(&self) -> anyhow::Result<()> {
+        for dir in self.object_dirs() {
+            if !dir.is_dir() || dir.join(SHARDED_MARKER).exists() {
+                continue;
+            }
+            let mut moved = 0;
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !is_hash(&name) || !entry.file_type()?.is_file() {
+                    continue;
+                }
+                let dst = dir.join(shard_path(&name));
+                if let Some(parent) = dst.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                match fs::rename(entry.path(), &dst) {
+                    // 其他进程同时在迁移
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    res => res?,
+                }
+                moved += 1;
+            }
+            if moved > 0 {
+                info!("moved {} objects in {:?} into sub directories", moved, dir);
+            }
+            fs::write(dir.join(SHARDED_MARKER), "")?;
+        }
+        Ok(())
+    }
+
+    /// 对象目录中的所有文件及其对象名，不包括布局标记和临时文件
+    pub fn stored_files(&self) -> anyhow::Result<Vec<(String, PathBuf)>> {
+        let mut ans = Vec::new();
+        for dir in self.object_dirs() {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if is_temp(&name) {
+                    continue;
+                }
+                if !entry.file_type()?.is_dir() {
+                    if name != SHARDED_MARKER {
+                        ans.push((name, entry.path()));
+                    }
+                    continue;
+                }
+                for file in fs::read_dir(entry.path())? {
+                    let file = file?;
+                    let rest = file.file_name().to_string_lossy().to_string();
+                    if is_temp(&rest) {
+                        continue;
+                    }
+                    ans.push((object_name(&name, &rest), file.path()));
+                }
+            }
+        }
+        Ok(ans)
+    }
+}
@@ -0,0 +1,172 @@
+use crate::core::util::{hostname, now};
+use anyhow::bail;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// 锁文件中记录的持有者信息
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LockInfo {
+    pub pid: u32,
+    pub hostname: String,
+    pub operation: String,
+    /// 加锁时间，unix秒
+    pub created: u64,
+}
+
+impl LockInfo {
+    fn current(operation: &str) -> Self {
+        Self {
+            pid: std::process::id(),
+            hostname: hostname(),
+            operation: operation.to_string(),
+            created: now(),
+        }
+    }
+}
+
+impl Display for LockInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let age = Duration::from_secs(now().saturating_sub(self.created));
+        write!(
+            f,
+            "pid {} on {} ({}), {} ago",
+            self.pid,
+            self.hostname,
+            self.operation,
+            humantime::format_duration(age)
+        )
+    }
+}
+
+/// 仓库锁，使用锁文件上的内核建议锁(unix上为flock)，进程退出时自动释放，离开作用域时释放
+#[derive(Debug)]
+pub struct StoreLock {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl StoreLock {
+    /// 获取锁，`wait` 为等待超时时间，为空时不等待
+    pub fn acquire(path: &Path, operation: &str, wait: Option<Duration>) -> anyhow::Result<Self> {
+        let start = Instant::now();
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            match file.try_lock() {
+                Ok(_) if is_current(&file, path) => {
+                    file.set_len(0)?;
+                    file.write_all(to_string(&LockInfo::current(operation))?.as_bytes())?;
+                    return Ok(Self {
+                        path: path.to_path_buf(),
+                        file: Some(file),
+                    });
+                }
+                // 加锁前持有者释放并删除了锁文件，重新打开
+                Ok(_) => continue,
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => {
+                    bail!("failed to lock {:?}: {}", path, e)
+                }
+            }
+            if wait.is_some_and(|w| start.elapsed() < w) {
+                sleep(Duration::from_millis(100));
+                continue;
+            }
+            match read_info(path)? {
+                // 锁文件刚创建还未写入，稍等片刻
+                None if start.elapsed() < wait.unwrap_or_default() + Duration::from_secs(1) => {
+                    sleep(Duration::from_millis(10));
+                    continue;
+                }
+                None => bail!(
+                    "store is locked by an unknown process, lock file {:?} is empty",
+                    path
+                ),
+                Some(info) => bail!("store is locked by {}", info),
+            }
+        }
+    }
+
+    /// 清理崩溃的进程或旧版本留下的锁文件，仍被持有的锁不会被清理，
+    /// `force` 时文件系统不支持加锁也会清理
+    pub fn unlock(path: &Path, force: bool) -> anyhow::Result<Option<LockInfo>> {
+        let file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let info = read_info(path)?;
+        match (file.try_lock(), &info) {
+            (Ok(_), _) => {}
+            (Err(TryLockError::WouldBlock), Some(info)) => {
+                bail!("store is locked by {}, which is still running", info)
+            }
+            (Err(TryLockError::WouldBlock), None) => {
+                bail!("store is locked by an unknown process")
+            }
+            (Err(TryLockError::Error(e)), _) if !force => bail!(
+                "cannot check the lock {:?}: {}, use --force to remove it anyway",
+                path,
+                e
+            ),
+            (Err(TryLockError::Error(_)), _) => {}
+        }
+        match &info {
+            None => warn!("remove empty lock {:?}", path),
+            Some(info) => warn!("remove lock held by {}", info),
+        }
+        fs::remove_file(path)?;
+        Ok(info)
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        // 先删除再解锁，等待中的进程加锁后会发现文件已被删除
+        match fs::remove_file(&self.path) {
+            Ok(_) => info!("release lock {:?}", self.path),
+            // 仓库迁移时锁文件已被清理
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("failed to release lock {:?}: {}", self.path, e),
+        }
+        drop(self.file.take());
+    }
+}
+
+/// 打开的锁文件是否仍是路径上的文件
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(windows)]
+fn is_current(_file: &File, path: &Path) -> bool {
+    // windows上打开的文件无法被删除
+    path.exists()
+}
+
+/// 读取锁信息，锁不存在时返回空
+pub fn read_info(path: &Path) -> anyhow::Result<Option<LockInfo>> {
+    match fs::read_to_string(path) {
+        // 锁文件刚创建还未写入内容
+        Ok(s) if s.trim().is_empty() => Ok(None),
+        Ok(s) => Ok(Some(from_str(&s)?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
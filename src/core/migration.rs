@@ -0,0 +1,113 @@
+use crate::core::backend::{parse_msgpack, parse_nodes};
+use crate::core::node::Node;
+use anyhow::bail;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// 当前索引文件格式的版本
+pub const INDEX_VERSION: u32 = 2;
+
+/// 把索引从 `from` 版本升级到下一个版本
+struct Migration {
+    from: u32,
+    description: &'static str,
+    run: fn(Value) -> anyhow::Result<Value>,
+}
+
+/// 按版本顺序注册的迁移，修改 `Node` 的序列化格式时在这里增加一项并提高 `INDEX_VERSION`
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 1,
+    description: "wrap the entry list in a versioned envelope",
+    run: wrap_entries,
+}];
+
+/// 保存到文件中的索引
+#[derive(Serialize)]
+struct IndexRef<'a> {
+    version: u32,
+    entries: &'a [&'a Node],
+}
+
+#[derive(Deserialize)]
+struct IndexFile {
+    entries: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+/// 序列化为当前版本的索引，`binary` 时使用MessagePack
+pub fn serialize_index(entries: &[&Node], binary: bool) -> anyhow::Result<Vec<u8>> {
+    let index = IndexRef {
+        version: INDEX_VERSION,
+        entries,
+    };
+    Ok(match binary {
+        true => rmp_serde::to_vec_named(&index)?,
+        false => serde_json::to_vec(&index)?,
+    })
+}
+
+/// 解析json或MessagePack格式的索引，旧版本的格式依次迁移到当前版本
+pub fn parse_index(data: &[u8]) -> anyhow::Result<Vec<Node>> {
+    let format = IndexData::detect(data)?;
+    let version = format.version()?;
+    if version > INDEX_VERSION {
+        bail!(
+            "the index has format version {}, this hbx supports up to {}, upgrade hbx",
+            version,
+            INDEX_VERSION
+        );
+    }
+    if version == INDEX_VERSION {
+        return Ok(format.parse::<IndexFile>()?.entries);
+    }
+    let mut value: Value = format.parse()?;
+    for m in MIGRATIONS.iter().filter(|m| m.from >= version) {
+        info!(
+            "migrate index from version {} to {}: {}",
+            m.from,
+            m.from + 1,
+            m.description
+        );
+        value = (m.run)(value)?;
+    }
+    Ok(serde_json::from_value::<IndexFile>(value)?.entries)
+}
+
+/// 索引文件的内容，json以 `{` 或 `[` 开头，MessagePack不会以这两个字节开头
+enum IndexData<'a> {
+    Json(&'a str),
+    Msgpack(&'a [u8]),
+}
+
+impl<'a> IndexData<'a> {
+    fn detect(data: &'a [u8]) -> anyhow::Result<Self> {
+        match data.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'{' | b'[') | None => Ok(Self::Json(std::str::from_utf8(data)?)),
+            Some(_) => Ok(Self::Msgpack(data)),
+        }
+    }
+
+    fn parse<T: for<'de> Deserialize<'de>>(&self) -> anyhow::Result<T> {
+        match self {
+            Self::Json(s) => parse_nodes(s),
+            Self::Msgpack(data) => parse_msgpack(data),
+        }
+    }
+
+    /// 第一版索引是json数组，没有版本号
+    fn version(&self) -> anyhow::Result<u32> {
+        match self {
+            Self::Json(s) if s.trim_start().starts_with('[') => Ok(1),
+            _ => Ok(self.parse::<Header>()?.version),
+        }
+    }
+}
+
+fn wrap_entries(value: Value) -> anyhow::Result<Value> {
+    Ok(json!({"version": 2, "entries": value}))
+}
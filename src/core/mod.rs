@@ -1,4 +1,49 @@
+pub mod adopt;
+pub mod alias;
+pub mod attest;
+pub mod attrs;
+pub mod backend;
+pub mod backup;
 pub mod cli;
+pub mod complete;
+pub mod dedup;
+pub mod download;
+pub mod encryption;
+pub mod export;
+pub mod farm;
+pub mod filter;
+pub mod gc;
+pub mod git;
+pub mod grep;
+pub mod hardlink;
+pub mod hash;
+pub mod hash_cache;
+pub mod index;
+pub mod info;
+pub mod layout;
+pub mod lock;
+pub mod migration;
+pub mod names;
 pub mod node;
+pub mod object_filter;
+pub mod porcelain;
+pub mod project;
+pub mod queue;
+pub mod quota;
+pub mod release;
+pub mod remote;
+pub mod repair;
+pub mod resolve;
+pub mod seal;
+pub mod search;
+pub mod selfcheck;
+pub mod session;
+pub mod settings;
+pub mod snapshot;
 pub mod store;
+pub mod stores;
+pub mod stream;
+pub mod trash;
 pub mod util;
+pub mod verify;
+pub mod webhook;
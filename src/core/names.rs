@@ -0,0 +1,168 @@
+use anyhow::bail;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// 在windows上不能使用的设备名，带扩展名时同样不能使用
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// 恢复时对windows上非法文件名的转义方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NameEscape {
+    /// 不转义
+    None,
+    /// 非法字符转为 `%XX`
+    Percent,
+    /// 非法字符替换为 `_`
+    Underscore,
+}
+
+impl Default for NameEscape {
+    fn default() -> Self {
+        if cfg!(windows) {
+            NameEscape::Percent
+        } else {
+            NameEscape::None
+        }
+    }
+}
+
+impl NameEscape {
+    /// 转义文件名，不需要转义时返回空
+    pub fn escape(&self, name: &str) -> Option<String> {
+        if *self == NameEscape::None || is_portable(name) {
+            return None;
+        }
+        let mut ans = String::with_capacity(name.len());
+        let len = name.chars().count();
+        let trailing = len - name.trim_end_matches(['.', ' ']).chars().count();
+        for (i, c) in name.chars().enumerate() {
+            let bad = is_invalid_char(c) || i >= len - trailing;
+            ans.push_str(&self.replace(c, bad));
+        }
+        // 保留的设备名转义首字母
+        if is_reserved(&ans) {
+            let first = ans.chars().next().unwrap_or('_');
+            ans = format!("{}{}", self.replace(first, true), &ans[first.len_utf8()..]);
+        }
+        Some(ans)
+    }
+
+    fn replace(&self, c: char, bad: bool) -> String {
+        match (self, bad) {
+            (_, false) | (NameEscape::None, _) => c.to_string(),
+            (NameEscape::Underscore, true) => String::from("_"),
+            (NameEscape::Percent, true) => {
+                let mut buf = [0; 4];
+                c.encode_utf8(&mut buf)
+                    .bytes()
+                    .map(|b| format!("%{:02X}", b))
+                    .collect()
+            }
+        }
+    }
+}
+
+fn is_invalid_char(c: char) -> bool {
+    (c as u32) < 32 || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+}
+
+/// 是否为windows保留的设备名
+pub fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem))
+}
+
+/// 文件名在windows上是否合法
+pub fn is_portable(name: &str) -> bool {
+    !name.chars().any(is_invalid_char) && !name.ends_with(['.', ' ']) && !is_reserved(name)
+}
+
+/// 条目名称的校验规则，名称可以用 `/` 分为多级，如 `toolchains/gcc/12.3`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct NameRules {
+    /// 允许的顶级命名空间，为空时不限制
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<String>,
+    /// 名称必须带有命名空间
+    #[serde(default)]
+    pub require_namespace: bool,
+    /// 最大层级数，0表示不限制
+    #[serde(default)]
+    pub max_levels: usize,
+}
+
+/// 校验标签，标签不能为空，不能包含空白、逗号和控制字符
+pub fn validate_tag(tag: &str) -> anyhow::Result<()> {
+    if tag.is_empty()
+        || tag
+            .chars()
+            .any(|c| c.is_whitespace() || c == ',' || c.is_control())
+    {
+        bail!(
+            "invalid tag {:?}, empty or has whitespace, ',' or control characters",
+            tag
+        );
+    }
+    Ok(())
+}
+
+/// 校验版本号，版本号不能为空，不能包含路径分隔符、空白和控制字符
+pub fn validate_version(version: &str) -> anyhow::Result<()> {
+    let bad = |c: char| matches!(c, '/' | '\\') || c.is_whitespace() || c.is_control();
+    if version.is_empty() || version.chars().any(bad) {
+        bail!(
+            "invalid version {:?}, empty or has '/', '\\', whitespace or control characters",
+            version
+        );
+    }
+    Ok(())
+}
+
+impl NameRules {
+    /// 校验条目名称
+    pub fn validate(&self, name: &str) -> anyhow::Result<()> {
+        let parts: Vec<&str> = name.split('/').collect();
+        for part in &parts {
+            if part.is_empty() || *part == "." || *part == ".." {
+                bail!("invalid name {:?}, empty or relative level", name);
+            }
+            if part.contains(['@', '\\']) || part.chars().any(|c| c.is_control()) {
+                bail!(
+                    "invalid name {:?}, '@', '\\' and control characters are not allowed",
+                    name
+                );
+            }
+        }
+        if self.require_namespace && parts.len() < 2 {
+            bail!("name {:?} must have a namespace, e.g. team/{}", name, name);
+        }
+        if parts.len() > 1
+            && !self.namespaces.is_empty()
+            && !self.namespaces.iter().any(|n| n == parts[0])
+        {
+            bail!(
+                "namespace {:?} is not allowed, expected one of {:?}",
+                parts[0],
+                self.namespaces
+            );
+        }
+        if self.max_levels > 0 && parts.len() > self.max_levels {
+            bail!("name {:?} has more than {} levels", name, self.max_levels);
+        }
+        Ok(())
+    }
+}
+
+/// 名称是否在前缀之下，前缀可以带或不带结尾的 `/`
+pub fn has_prefix(name: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty()
+        || name == prefix
+        || name
+            .strip_prefix(prefix)
+            .is_some_and(|s| s.starts_with('/'))
+}
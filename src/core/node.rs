@@ -1,30 +1,114 @@
-use crate::core::node::Meta::{DIRECTORY, FILE, SYMLINK};
-use crate::core::util::md5;
+use crate::core::attrs::Attrs;
+use crate::core::hash::HashAlgorithm;
+use crate::core::node::Meta::{DIRECTORY, FILE, INLINE, SYMLINK};
 use anyhow::anyhow;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::cell::RefCell;
-use std::fs::read_link;
+use std::fs;
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Meta {
     FILE(String),
     SYMLINK(PathBuf),
-    DIRECTORY(Rc<RefCell<Vec<Node>>>),
+    DIRECTORY(Vec<Node>),
+    /// 小文件直接以base64保存在索引中
+    INLINE(String),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Meta {
+    /// 节点类型的名称
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FILE(_) | INLINE(_) => "file",
+            SYMLINK(_) => "symlink",
+            DIRECTORY(_) => "directory",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Node {
     pub name: String,
     pub meta: Meta,
+    /// 顶层节点的版本，同名的多个版本可以同时存在
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// 仅顶层节点有汇总信息
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+    /// 加密的顶层节点，内容为加密后的条目包，解密前不能恢复
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<Sealed>,
+    /// 顶层节点的对象过滤器，对象以编码后的内容保存
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<String>,
+    /// 目录的树哈希，由子节点的名称和内容计算，与目录本身的名称无关
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<String>,
+    /// 需要还原的文件属性，装箱以减小节点，深层目录递归时不会栈溢出
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attrs: Option<Box<Attrs>>,
+    /// 顶层节点的标签，有序且不重复
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// 顶层节点的别名，get时可以代替名称使用
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+/// 加密条目的信息
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Sealed {
+    /// 可以解密的接收者公钥
+    pub recipients: Vec<String>,
+}
+
+/// 顶层节点的汇总信息
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Summary {
+    /// add时间，unix秒
+    #[serde(default)]
+    pub added: u64,
+    /// add时源文件的总大小，不计重复和压缩
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// add时保存的文件数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<u64>,
+    /// add时被过滤掉的路径，相对于根目录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<PathBuf>,
+    /// add期间一直在变化而没有保存的文件，相对于根目录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unstable: Vec<PathBuf>,
+    /// 源目录中互为硬链接的文件，每组为同一个inode的路径，相对于根目录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hardlinks: Vec<Vec<PathBuf>>,
+    /// 条目内容的来源，如 `add-git` 的仓库和提交
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+/// 条目内容的来源
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Provenance {
+    /// 来源的地址，如git仓库的URL
+    pub origin: String,
+    /// 指定的版本，如分支或标签
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// 实际检出的提交哈希
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
 }
 
 impl PartialEq<Self> for Node {
-    /// 判断节点是否相同，在linux中可以通过inode判断,此处可以优化
+    /// 名称和版本相同的顶层节点是同一个条目，内容用 `content_id` 比较
     fn eq(&self, other: &Self) -> bool {
-        self.name == other.name
+        self.name == other.name && self.version == other.version
     }
 }
 
@@ -33,41 +117,127 @@ impl Eq for Node {}
 impl Hash for Node {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.name.hash(state);
+        self.version.hash(state);
     }
 }
 
+/// 按名称排序后对子节点的名称和内容标识计算哈希
+fn tree_hash(children: &[Node]) -> String {
+    let mut sorted: Vec<&Node> = children.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut hasher = blake3::Hasher::new();
+    for x in sorted {
+        hasher.update(x.name.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(x.content_id().as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
 impl TryFrom<&Path> for Node {
     type Error = anyhow::Error;
 
     fn try_from(p: &Path) -> Result<Self, Self::Error> {
-        let name = p
-            .file_name()
-            .ok_or(anyhow!("invalid path"))?
-            .to_string_lossy()
-            .to_string();
-
-        let meta = if p.is_symlink() {
-            SYMLINK(read_link(p)?)
-        } else if p.is_dir() {
-            DIRECTORY(Rc::new(RefCell::new(Vec::new())))
-        } else {
-            FILE(md5(p)?)
-        };
-
-        let n = Self { name, meta };
-        Ok(n)
+        Self::new(p, HashAlgorithm::default())
     }
 }
 
 impl Node {
     pub fn sample(s: &str) -> Self {
+        Self::with_meta(s.to_string(), FILE(String::new()))
+    }
+
+    pub fn with_meta(name: String, meta: Meta) -> Self {
         Self {
-            name: s.to_string(),
-            meta: FILE(String::new()),
+            name,
+            meta,
+            version: None,
+            summary: None,
+            sealed: None,
+            filters: Vec::new(),
+            tree: None,
+            attrs: None,
+            tags: Vec::new(),
+            aliases: Vec::new(),
+        }
+    }
+
+    /// 是否带有全部指定的标签
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|t| self.tags.contains(t))
+    }
+
+    /// 带版本号的名称，如 `tools@1.0`
+    pub fn label(&self) -> String {
+        match &self.version {
+            None => self.name.clone(),
+            Some(v) => format!("{}@{}", self.name, v),
+        }
+    }
+
+    /// 多级名称的最后一级，恢复和导出时作为根目录名
+    pub fn leaf_name(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or(&self.name)
+    }
+
+    /// 内容是否相同，不比较名称、版本和汇总信息
+    pub fn same_content(&self, other: &Node) -> bool {
+        self.content_id() == other.content_id()
+    }
+
+    /// 内容的标识，文件为对象名，目录为树哈希
+    pub fn content_id(&self) -> String {
+        match &self.meta {
+            FILE(hash) => hash.clone(),
+            INLINE(data) => format!("inline-{}", blake3::hash(data.as_bytes()).to_hex()),
+            SYMLINK(target) => format!(
+                "symlink-{}",
+                blake3::hash(target.to_string_lossy().as_bytes()).to_hex()
+            ),
+            DIRECTORY(vec) => match &self.tree {
+                Some(tree) => tree.clone(),
+                None => tree_hash(vec),
+            },
+        }
+    }
+
+    /// 计算所有目录的树哈希
+    pub fn compute_tree(&mut self) {
+        if let DIRECTORY(vec) = &mut self.meta {
+            for x in vec.iter_mut() {
+                x.compute_tree();
+            }
+            self.tree = Some(tree_hash(vec));
         }
     }
 
-    pub fn new(p: &Path) -> anyhow::Result<Node> {
+    /// add时记录的权限位，旧数据和windows上没有记录
+    pub fn mode(&self) -> Option<u32> {
+        self.attrs.as_ref().and_then(|a| a.mode)
+    }
+
+    /// add时记录的修改时间，unix纳秒
+    pub fn mtime(&self) -> Option<i64> {
+        self.attrs.as_ref().and_then(|a| a.mtime)
+    }
+
+    /// add时间，旧数据没有记录时为0
+    pub fn added(&self) -> u64 {
+        self.summary.as_ref().map(|s| s.added).unwrap_or_default()
+    }
+
+    /// add时记录的总大小，旧版本添加的条目为空
+    pub fn size(&self) -> Option<u64> {
+        self.summary.as_ref().and_then(|s| s.size)
+    }
+
+    /// add时记录的文件数，旧版本添加的条目为空
+    pub fn files(&self) -> Option<u64> {
+        self.summary.as_ref().and_then(|s| s.files)
+    }
+
+    pub fn new(p: &Path, algorithm: HashAlgorithm) -> anyhow::Result<Node> {
         let name = p
             .file_name()
             .ok_or(anyhow!("invalidate path"))?
@@ -76,10 +246,115 @@ impl Node {
         let meta = if p.is_symlink() {
             SYMLINK(p.read_link()?)
         } else if p.is_dir() {
-            DIRECTORY(Rc::new(RefCell::new(Vec::new())))
+            DIRECTORY(Vec::new())
         } else {
-            FILE(md5(&p)?)
+            FILE(algorithm.hash_file(p)?)
         };
-        Ok(Node { name, meta })
+        Ok(Node::with_meta(name, meta))
+    }
+
+    /// 按符号链接指向的目标创建节点
+    pub fn dereference(p: &Path, algorithm: HashAlgorithm) -> anyhow::Result<Node> {
+        let name = p
+            .file_name()
+            .ok_or(anyhow!("invalidate path"))?
+            .to_string_lossy()
+            .to_string();
+        let meta = if p.is_dir() {
+            DIRECTORY(Vec::new())
+        } else {
+            FILE(algorithm.hash_file(p)?)
+        };
+        Ok(Node::with_meta(name, meta))
+    }
+
+    /// 相对路径处的子节点，路径为空时为自身
+    pub fn find(&self, rel: &Path) -> Option<&Node> {
+        let mut node = self;
+        for part in rel.components() {
+            let part = part.as_os_str().to_string_lossy();
+            node = match &node.meta {
+                DIRECTORY(vec) => vec.iter().find(|x| x.name == part)?,
+                _ => return None,
+            };
+        }
+        Some(node)
+    }
+
+    /// 只包含相对路径处子树的顶层节点，名称为 `条目/路径`，沿用条目的版本和过滤器，
+    /// 硬链接组只保留子树中的路径
+    pub fn subtree(&self, rel: &Path) -> Option<Node> {
+        let mut ans = self.find(rel)?.clone();
+        let rel_name: Vec<String> = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        ans.name = format!("{}/{}", self.name, rel_name.join("/"));
+        ans.version = self.version.clone();
+        ans.filters = self.filters.clone();
+        ans.summary = self.summary.as_ref().map(|s| Summary {
+            added: s.added,
+            hardlinks: s
+                .hardlinks
+                .iter()
+                .map(|group| {
+                    group
+                        .iter()
+                        .filter_map(|p| p.strip_prefix(rel).ok().map(Path::to_path_buf))
+                        .collect::<Vec<_>>()
+                })
+                .filter(|group| group.len() > 1)
+                .collect(),
+            provenance: s.provenance.clone(),
+            ..Default::default()
+        });
+        Some(ans)
+    }
+
+    /// 深度优先遍历节点树，回调参数为相对于当前节点的路径
+    pub fn walk<F: FnMut(&Path, &Node)>(&self, f: &mut F) {
+        let _ = self.try_walk(&mut |p, n| -> anyhow::Result<()> {
+            f(p, n);
+            Ok(())
+        });
+    }
+
+    /// 同 `walk`，回调出错时停止遍历
+    pub fn try_walk<F>(&self, f: &mut F) -> anyhow::Result<()>
+    where
+        F: FnMut(&Path, &Node) -> anyhow::Result<()>,
+    {
+        fn dfs<F>(node: &Node, path: &Path, f: &mut F) -> anyhow::Result<()>
+        where
+            F: FnMut(&Path, &Node) -> anyhow::Result<()>,
+        {
+            f(path, node)?;
+            if let DIRECTORY(vec) = &node.meta {
+                for x in vec {
+                    dfs(x, &path.join(&x.name), f)?;
+                }
+            }
+            Ok(())
+        }
+        dfs(self, Path::new(""), f)
+    }
+
+    /// 将文件内容内联到节点中
+    pub fn inline(p: &Path) -> anyhow::Result<Node> {
+        let name = p
+            .file_name()
+            .ok_or(anyhow!("invalidate path"))?
+            .to_string_lossy()
+            .to_string();
+        let meta = INLINE(STANDARD.encode(fs::read(p)?));
+        Ok(Node::with_meta(name, meta))
+    }
+
+    /// 解码内联的文件内容
+    pub fn inline_content(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        match &self.meta {
+            INLINE(data) => Ok(Some(STANDARD.decode(data)?)),
+            _ => Ok(None),
+        }
     }
 }
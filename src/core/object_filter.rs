@@ -0,0 +1,205 @@
+use crate::core::encryption::AGE_FILTER;
+use age::x25519::Identity;
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// 对象内容的转换，add时编码后再写入仓库，get时解码
+pub trait ObjectFilter: Debug + Send + Sync {
+    /// 配置中使用的名称
+    fn name(&self) -> &'static str;
+
+    fn encode<'a>(&self, reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>>;
+
+    fn decode<'a>(&self, reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>>;
+}
+
+/// 按名称创建内置的过滤器，`age` 需要仓库的私钥
+pub fn object_filter(
+    name: &str,
+    identity: Option<&Identity>,
+) -> anyhow::Result<Box<dyn ObjectFilter>> {
+    Ok(match (name, identity) {
+        ("zstd", _) => Box::new(Zstd),
+        ("crlf", _) => Box::new(Crlf),
+        (AGE_FILTER, Some(identity)) => Box::new(Age::new(identity.clone())),
+        (AGE_FILTER, None) => bail!("object filter age needs the encryption key"),
+        _ => bail!("unknown object filter {}, expected zstd or crlf", name),
+    })
+}
+
+/// 按条目名称选择过滤器的规则
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FilterRule {
+    /// 条目名称的通配符，如 `logs/*`
+    pub pattern: String,
+    /// 依次应用的过滤器
+    pub filters: Vec<String>,
+}
+
+/// 依次应用的过滤器，解码时顺序相反
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    filters: Vec<Box<dyn ObjectFilter>>,
+}
+
+impl Pipeline {
+    pub fn new(names: &[String]) -> anyhow::Result<Self> {
+        Self::with_identity(names, None)
+    }
+
+    pub fn with_identity(names: &[String], identity: Option<&Identity>) -> anyhow::Result<Self> {
+        let filters = names
+            .iter()
+            .map(|n| object_filter(n, identity))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { filters })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.filters.iter().map(|f| f.name().to_string()).collect()
+    }
+
+    pub fn encode<'a>(&self, mut reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>> {
+        for f in &self.filters {
+            reader = f.encode(reader)?;
+        }
+        Ok(reader)
+    }
+
+    pub fn decode<'a>(&self, mut reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>> {
+        for f in self.filters.iter().rev() {
+            reader = f.decode(reader)?;
+        }
+        Ok(reader)
+    }
+}
+
+/// zstd压缩
+#[derive(Debug)]
+struct Zstd;
+
+impl ObjectFilter for Zstd {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn encode<'a>(&self, reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(zstd::stream::read::Encoder::new(reader, 0)?))
+    }
+
+    fn decode<'a>(&self, reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(zstd::stream::read::Decoder::new(reader)?))
+    }
+}
+
+/// 用仓库的私钥对应的公钥加密，编码结果先写入临时文件
+pub struct Age {
+    identity: Identity,
+}
+
+impl Age {
+    pub fn new(identity: Identity) -> Self {
+        Self { identity }
+    }
+}
+
+impl Debug for Age {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Age({})", self.identity.to_public())
+    }
+}
+
+impl ObjectFilter for Age {
+    fn name(&self) -> &'static str {
+        AGE_FILTER
+    }
+
+    fn encode<'a>(&self, mut reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>> {
+        let recipient = self.identity.to_public();
+        let encryptor =
+            age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient))?;
+        let mut tmp = tempfile::tempfile()?;
+        let mut writer = encryptor.wrap_output(&mut tmp)?;
+        io::copy(&mut reader, &mut writer)?;
+        writer.finish()?;
+        tmp.seek(SeekFrom::Start(0))?;
+        Ok(Box::new(tmp))
+    }
+
+    fn decode<'a>(&self, reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>> {
+        let reader = age::Decryptor::new(reader)?
+            .decrypt(std::iter::once(&self.identity as &dyn age::Identity))
+            .map_err(|e| anyhow!("failed to decrypt object: {}", e))?;
+        Ok(Box::new(reader))
+    }
+}
+
+/// 将 `\r\n` 换行统一为 `\n`，解码时不还原
+#[derive(Debug)]
+struct Crlf;
+
+impl ObjectFilter for Crlf {
+    fn name(&self) -> &'static str {
+        "crlf"
+    }
+
+    fn encode<'a>(&self, reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(CrlfToLf {
+            inner: reader,
+            buf: Vec::new(),
+            pos: 0,
+            cr: false,
+        }))
+    }
+
+    fn decode<'a>(&self, reader: Box<dyn Read + 'a>) -> anyhow::Result<Box<dyn Read + 'a>> {
+        Ok(reader)
+    }
+}
+
+struct CrlfToLf<R> {
+    inner: R,
+    /// 转换后还没有读取的数据
+    buf: Vec<u8>,
+    pos: usize,
+    /// 上一块以 `\r` 结尾
+    cr: bool,
+}
+
+impl<R: Read> Read for CrlfToLf<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0; 64 * 1024];
+        while self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                if !std::mem::take(&mut self.cr) {
+                    return Ok(0);
+                }
+                self.buf.push(b'\r');
+                break;
+            }
+            for &b in &chunk[..n] {
+                if std::mem::take(&mut self.cr) && b != b'\n' {
+                    self.buf.push(b'\r');
+                }
+                if b == b'\r' {
+                    self.cr = true;
+                } else {
+                    self.buf.push(b);
+                }
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,91 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// `--porcelain` 模式，开启后stdout只输出每行一个json的事件
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// 已经输出过result事件
+static RESULTED: AtomicBool = AtomicBool::new(false);
+/// 没有json输出的命令的文本行，命令结束时作为result事件输出
+static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 机器可读的事件，`event` 字段为事件类型
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event<'a> {
+    /// 命令开始执行
+    Start { command: &'a str, pid: u32 },
+    /// 多个条目的处理进度
+    Progress {
+        done: usize,
+        total: usize,
+        item: &'a str,
+    },
+    /// 单个文件的结果，`path` 为相对条目根目录的路径
+    File {
+        action: &'a str,
+        entry: &'a str,
+        path: &'a Path,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hash: Option<&'a str>,
+    },
+    /// 命令的输出数据，与 `--format json` 的内容相同
+    Result { data: Value },
+    /// 命令结束
+    Summary {
+        ok: bool,
+        elapsed_ms: u128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// 输出事件，未开启porcelain模式时忽略
+pub fn emit(event: &Event) {
+    if !enabled() {
+        return;
+    }
+    if matches!(event, Event::Result { .. }) {
+        RESULTED.store(true, Ordering::Relaxed);
+    }
+    if let Ok(s) = serde_json::to_string(event) {
+        // 多线程同时输出时保证每个事件占完整的一行
+        let mut out = std::io::stdout().lock();
+        let _ = writeln!(out, "{}", s);
+        let _ = out.flush();
+    }
+}
+
+/// 记录一行文本输出，porcelain模式下不直接打印
+pub fn line(s: String) {
+    if let Ok(mut lines) = LINES.lock() {
+        lines.push(s);
+    }
+}
+
+/// 命令没有输出过result事件时，把记录的文本行作为result事件输出
+pub fn flush_lines() {
+    if RESULTED.load(Ordering::Relaxed) {
+        return;
+    }
+    let lines = LINES
+        .lock()
+        .map(|mut l| std::mem::take(&mut *l))
+        .unwrap_or_default();
+    if !lines.is_empty() {
+        emit(&Event::Result {
+            data: serde_json::json!({ "lines": lines }),
+        });
+    }
+}
@@ -0,0 +1,153 @@
+use crate::core::store::{GetOptions, Store};
+use anyhow::{anyhow, bail};
+use log::info;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 项目文件 `hbx.toml`，记录项目依赖的条目
+///
+/// ```toml
+/// remote = "build-cache"
+///
+/// [entries]
+/// gcc = "13.2"
+/// "toolchains/llvm" = { version = "17", remote = "other", path = "tools/llvm" }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Project {
+    /// 默认的远程仓库
+    #[serde(default)]
+    pub remote: Option<String>,
+    /// key为条目名称
+    #[serde(default)]
+    pub entries: BTreeMap<String, Pin>,
+}
+
+/// 固定的条目版本
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Pin {
+    /// 只指定版本，`latest` 表示最新版本
+    Version(String),
+    Detailed {
+        #[serde(default)]
+        version: Option<String>,
+        /// 为空时使用项目的默认远程仓库
+        #[serde(default)]
+        remote: Option<String>,
+        /// 恢复的位置，相对于项目文件所在目录，默认为条目名称的最后一级
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
+}
+
+impl Pin {
+    /// 固定的版本，为空时使用最新版本
+    pub fn version(&self) -> Option<&str> {
+        let v = match self {
+            Pin::Version(v) => Some(v.as_str()),
+            Pin::Detailed { version, .. } => version.as_deref(),
+        };
+        v.filter(|v| *v != "latest")
+    }
+
+    fn remote(&self) -> Option<&str> {
+        match self {
+            Pin::Version(_) => None,
+            Pin::Detailed { remote, .. } => remote.as_deref(),
+        }
+    }
+
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Pin::Version(_) => None,
+            Pin::Detailed { path, .. } => path.as_deref(),
+        }
+    }
+}
+
+impl Project {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|e| anyhow!("invalid project file {:?}: {}", path, e))
+    }
+}
+
+/// sync的结果
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// 从远程仓库拉取的条目
+    pub pulled: Vec<String>,
+    /// 本地已有的条目
+    pub cached: Vec<String>,
+    /// 恢复到项目目录中的路径
+    pub restored: Vec<PathBuf>,
+}
+
+impl Store {
+    /// 拉取项目文件中固定的条目，`prefetch_only` 为假时同时恢复到项目目录中已不存在的位置
+    pub fn sync(&mut self, file: &Path, prefetch_only: bool) -> anyhow::Result<SyncReport> {
+        let project = Project::load(file)?;
+        let mut report = SyncReport::default();
+        // 按远程仓库分组，每个远程仓库只连接一次
+        let mut missing: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, pin) in &project.entries {
+            let label = match pin.version() {
+                None => name.clone(),
+                Some(v) => format!("{}@{}", name, v),
+            };
+            let local = match pin.version() {
+                None => self.entry(name).is_ok(),
+                Some(v) => self.entry_version(name, Some(v)).is_ok(),
+            };
+            // 没有固定版本时总是检查远程仓库是否有更新的版本
+            if local && pin.version().is_some() {
+                report.cached.push(label);
+                continue;
+            }
+            let remote = pin.remote().or(project.remote.as_deref()).ok_or(anyhow!(
+                "no remote for {} in {:?}",
+                name,
+                file
+            ))?;
+            missing.entry(remote.to_string()).or_default().push(label);
+        }
+        for (remote, names) in missing {
+            info!("prefetch {:?} from {}", names, remote);
+            let pulled = self.pull(names, remote)?;
+            report.pulled.extend(pulled.pulled);
+            report.cached.extend(pulled.skipped);
+        }
+        if prefetch_only {
+            return Ok(report);
+        }
+
+        let base = file.parent().unwrap_or(Path::new("."));
+        for (name, pin) in &project.entries {
+            let node = match pin.version() {
+                None => self.entry(name)?,
+                Some(v) => self.entry_version(name, Some(v))?,
+            };
+            let target = base.join(pin.path().unwrap_or(Path::new(node.leaf_name())));
+            if target.symlink_metadata().is_ok() {
+                continue;
+            }
+            let parent = target
+                .parent()
+                .ok_or(anyhow!("invalid path {:?}", target))?;
+            if target.file_name() != Some(node.leaf_name().as_ref()) {
+                bail!(
+                    "{:?} must end with {}, the entry is restored under its own name",
+                    target,
+                    node.leaf_name()
+                );
+            }
+            fs::create_dir_all(parent)?;
+            self.get_node(node, Some(parent.to_path_buf()), &GetOptions::default())?;
+            report.restored.push(target);
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,202 @@
+use crate::core::node::Node;
+use crate::core::remote::{PullReport, Source};
+use crate::core::store::Store;
+use crate::core::util::now;
+use crate::QUEUE_NAME;
+use anyhow::bail;
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// 任务失败后的最大尝试次数，超过后保留在队列中等待取消
+const MAX_ATTEMPTS: u32 = 3;
+
+/// 后台pull任务
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PullJob {
+    pub id: u64,
+    pub names: Vec<String>,
+    pub address: String,
+    /// 越大越先执行
+    pub priority: i32,
+    /// 入队时间，unix秒
+    pub created: u64,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl PullJob {
+    /// 是否已经放弃重试
+    pub fn failed(&self) -> bool {
+        self.attempts >= MAX_ATTEMPTS
+    }
+}
+
+/// 后台pull队列，保存在仓库目录中
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PullQueue {
+    #[serde(default)]
+    next_id: u64,
+    pub jobs: Vec<PullJob>,
+}
+
+impl Store {
+    /// 读取后台pull队列
+    pub fn pull_queue(&self) -> anyhow::Result<PullQueue> {
+        match fs::read_to_string(self.path().join(QUEUE_NAME)) {
+            Ok(s) => Ok(serde_json::from_str(&s)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(PullQueue::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save_pull_queue(&self, queue: &PullQueue) -> anyhow::Result<()> {
+        let s = serde_json::to_string_pretty(queue)?;
+        AtomicFile::new(self.path().join(QUEUE_NAME), AllowOverwrite)
+            .write(|f| f.write_all(s.as_bytes()))?;
+        Ok(())
+    }
+
+    /// 添加后台pull任务，调用方需要持有仓库锁，返回任务id
+    pub fn enqueue_pull(
+        &self,
+        names: Vec<String>,
+        address: String,
+        priority: i32,
+    ) -> anyhow::Result<u64> {
+        if names.is_empty() {
+            bail!("nothing to pull");
+        }
+        let mut queue = self.pull_queue()?;
+        queue.next_id += 1;
+        let id = queue.next_id;
+        queue.jobs.push(PullJob {
+            id,
+            names,
+            address,
+            priority,
+            created: now(),
+            attempts: 0,
+            last_error: None,
+        });
+        self.save_pull_queue(&queue)?;
+        Ok(id)
+    }
+
+    /// 取消任务，调用方需要持有仓库锁
+    pub fn cancel_pull(&self, id: u64) -> anyhow::Result<bool> {
+        let mut queue = self.pull_queue()?;
+        let len = queue.jobs.len();
+        queue.jobs.retain(|j| j.id != id);
+        if queue.jobs.len() == len {
+            return Ok(false);
+        }
+        self.save_pull_queue(&queue)?;
+        Ok(true)
+    }
+
+    /// 执行优先级最高的任务，`limit` 为每秒下载的字节数，调用方需要持有仓库锁，队列为空时返回空
+    pub fn run_next_pull(
+        &mut self,
+        limit: Option<u64>,
+    ) -> anyhow::Result<Option<(PullJob, anyhow::Result<PullReport>)>> {
+        let mut queue = self.pull_queue()?;
+        let job = match queue
+            .jobs
+            .iter()
+            .filter(|j| !j.failed())
+            .max_by_key(|j| (j.priority, std::cmp::Reverse(j.id)))
+        {
+            None => return Ok(None),
+            Some(j) => j.clone(),
+        };
+        info!(
+            "run pull job {} {:?} from {}",
+            job.id, job.names, job.address
+        );
+        let res = self.open_source(&job.address).and_then(|mut source| {
+            let mut source = Throttled {
+                inner: source.as_mut(),
+                limit,
+            };
+            self.pull_from(&job.names, &mut source)
+        });
+        match &res {
+            Ok(_) => queue.jobs.retain(|j| j.id != job.id),
+            Err(e) => {
+                warn!("pull job {} failed: {}", job.id, e);
+                if let Some(j) = queue.jobs.iter_mut().find(|j| j.id == job.id) {
+                    j.attempts += 1;
+                    j.last_error = Some(e.to_string());
+                }
+            }
+        }
+        self.save_pull_queue(&queue)?;
+        Ok(Some((job, res)))
+    }
+}
+
+/// 限制下载速度的数据来源
+struct Throttled<'a> {
+    inner: &'a mut dyn Source,
+    limit: Option<u64>,
+}
+
+impl Source for Throttled<'_> {
+    fn manifest(&mut self) -> anyhow::Result<Vec<Node>> {
+        self.inner.manifest()
+    }
+
+    fn object(&mut self, hash: &str) -> anyhow::Result<Box<dyn Read + '_>> {
+        let reader = self.inner.object(hash)?;
+        Ok(match self.limit {
+            None => reader,
+            Some(limit) => Box::new(RateLimited::new(reader, limit)),
+        })
+    }
+
+    fn size(&mut self, hash: &str) -> anyhow::Result<Option<u64>> {
+        self.inner.size(hash)
+    }
+}
+
+/// 读取速度超过限制时等待
+pub struct RateLimited<R> {
+    inner: R,
+    /// 每秒字节数
+    limit: u64,
+    start: Instant,
+    read: u64,
+}
+
+impl<R: Read> RateLimited<R> {
+    pub fn new(inner: R, limit: u64) -> Self {
+        Self {
+            inner,
+            limit: limit.max(1),
+            start: Instant::now(),
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for RateLimited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // 每次最多读取0.1秒的配额，避免突发
+        let max = (self.limit / 10).max(1) as usize;
+        let len = buf.len().min(max);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.read += n as u64;
+        let expected = Duration::from_secs_f64(self.read as f64 / self.limit as f64);
+        if let Some(wait) = expected.checked_sub(self.start.elapsed()) {
+            sleep(wait);
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,230 @@
+use crate::core::node::Meta::FILE;
+use crate::core::node::Node;
+use crate::core::store::Store;
+use crate::core::util::now;
+use crate::core::webhook::entry_hashes;
+use crate::{ACCESS_LOCK_NAME, ACCESS_NAME};
+use anyhow::bail;
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// 仓库对象占用空间的上限
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Quota {
+    /// 最大空间(字节)，按仓库中对象文件的实际大小计算
+    pub max_size: u64,
+    /// add会超出上限时的处理方式
+    #[serde(default)]
+    pub policy: QuotaPolicy,
+}
+
+/// 超出容量时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuotaPolicy {
+    /// 拒绝add
+    #[default]
+    Refuse,
+    /// 删除最久没有get的条目，直到空间足够
+    EvictLru,
+}
+
+impl Store {
+    fn access_path(&self) -> PathBuf {
+        self.path().join(ACCESS_NAME)
+    }
+
+    /// 各条目最后一次get的时间，unix秒
+    pub fn access_times(&self) -> anyhow::Result<BTreeMap<String, u64>> {
+        match fs::read_to_string(self.access_path()) {
+            Ok(s) => Ok(serde_json::from_str(&s)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 记录条目被get的时间，失败时只打印警告，不影响get，
+    /// get不加仓库锁，读写期间锁住单独的锁文件，避免并发的get互相覆盖
+    pub(crate) fn touch(&self, labels: &[String]) {
+        if labels.is_empty() {
+            return;
+        }
+        let lock =
+            File::create(self.path().join(ACCESS_LOCK_NAME)).and_then(|f| f.lock().map(|_| f));
+        let res = lock.map_err(anyhow::Error::from).and_then(|_lock| {
+            let mut times = self.access_times()?;
+            let t = now();
+            for label in labels {
+                times.insert(label.clone(), t);
+            }
+            let s = serde_json::to_string(&times)?;
+            AtomicFile::new(self.access_path(), AllowOverwrite)
+                .write(|f| f.write_all(s.as_bytes()))?;
+            Ok(())
+        });
+        if let Err(e) = res {
+            warn!("failed to record access time: {}", e);
+        }
+    }
+
+    /// 条目最近一次使用的时间，没有get过时为add时间
+    pub fn last_used(&self, root: &Node, times: &BTreeMap<String, u64>) -> u64 {
+        let added = root.summary.as_ref().map_or(0, |s| s.added);
+        times
+            .get(&root.label())
+            .copied()
+            .unwrap_or(added)
+            .max(added)
+    }
+
+    /// 仓库中对象文件的总大小
+    pub fn physical_size(&self) -> anyhow::Result<u64> {
+        let mut size = 0;
+        for (_, path) in self.stored_files()? {
+            size += path.metadata().map_or(0, |m| m.len());
+        }
+        Ok(size)
+    }
+
+    /// 检查加入条目后是否超出容量，`pending` 为还没有写入仓库的新对象的大小，
+    /// 超出时按配置报错，或返回需要淘汰的条目，不做任何修改
+    pub(crate) fn quota_victims(&self, root: &Node, pending: u64) -> anyhow::Result<Vec<Node>> {
+        let Some(quota) = self.settings().quota.clone() else {
+            return Ok(Vec::new());
+        };
+        // 已经写入仓库、还没有被引用的对象也是新条目带来的
+        let keep: HashSet<String> = entry_hashes(root).into_iter().collect();
+        let mut incoming = pending;
+        for hash in &keep {
+            if self.has_object(hash) && self.refcount(hash)? == 0 {
+                incoming += self.object_path(hash).metadata()?.len();
+            }
+        }
+        if incoming == 0 {
+            return Ok(Vec::new());
+        }
+        if incoming > quota.max_size {
+            bail!(
+                "{} needs {} bytes, more than the store quota of {} bytes",
+                root.label(),
+                incoming,
+                quota.max_size
+            );
+        }
+        let used = self.physical_size()? + pending;
+        if used <= quota.max_size {
+            return Ok(Vec::new());
+        }
+        if quota.policy == QuotaPolicy::Refuse {
+            bail!(
+                "store quota exceeded: {} bytes used, {} bytes to add, quota is {} bytes; \
+                 delete entries and run gc, or raise quota.max_size",
+                used - incoming,
+                incoming,
+                quota.max_size
+            );
+        }
+        let times = self.access_times()?;
+        let mut candidates: Vec<(u64, &Node)> = self
+            .entries()
+            .into_iter()
+            .map(|n| (self.last_used(n, &times), n))
+            .collect();
+        candidates.sort_by_key(|(t, _)| *t);
+        let mut over = used - quota.max_size;
+        // 淘汰的条目中引用对象的文件数，等于引用数时对象可以删除
+        let mut dropped: HashMap<String, usize> = HashMap::new();
+        let mut freed = HashSet::new();
+        let mut victims = Vec::new();
+        for (_, node) in candidates {
+            if over == 0 {
+                break;
+            }
+            victims.push(node.clone());
+            node.walk(&mut |_, n| {
+                if let FILE(hash) = &n.meta {
+                    *dropped.entry(hash.clone()).or_default() += 1;
+                }
+            });
+            for hash in entry_hashes(node) {
+                if keep.contains(&hash)
+                    || freed.contains(&hash)
+                    || !self.has_object(&hash)
+                    || self.refcount(&hash)? != dropped[&hash]
+                {
+                    continue;
+                }
+                over = over.saturating_sub(self.object_path(&hash).metadata()?.len());
+                freed.insert(hash);
+            }
+        }
+        if over > 0 {
+            bail!(
+                "store quota exceeded: evicting all other entries frees only {} of {} bytes",
+                used - quota.max_size - over,
+                used - quota.max_size
+            );
+        }
+        Ok(victims)
+    }
+
+    /// 加入新条目，超出容量时按配置拒绝，或在条目加入之后才淘汰最久没有使用的条目，
+    /// 加入失败时不会删除任何条目，返回被淘汰的条目
+    pub(crate) fn insert_with_quota(&mut self, root: Node) -> anyhow::Result<Vec<String>> {
+        let victims = self.quota_victims(&root, 0)?;
+        self.insert_entry(root)?;
+        if victims.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut evicted = Vec::new();
+        let mut freed = Vec::new();
+        for node in victims {
+            self.delete_version(&node.name, node.version.as_deref());
+            evicted.push(node.label());
+            for hash in entry_hashes(&node) {
+                if self.refcount(&hash)? == 0 && self.has_object(&hash) {
+                    freed.push(hash);
+                }
+            }
+        }
+        // 先保存索引再把对象移到回收站，中断时不会留下引用缺失对象的条目
+        self.save()?;
+        for hash in freed {
+            info!("evict object {}", hash);
+            self.trash_object(&self.object_path(&hash), &hash)?;
+            self.pending_garbage_mut().remove(&hash);
+        }
+        warn!(
+            "store quota of {} bytes exceeded, evicted {:?}",
+            self.settings().quota.as_ref().map_or(0, |q| q.max_size),
+            evicted
+        );
+        Ok(evicted)
+    }
+
+    /// 新对象的大小，按源文件计算
+    pub(crate) fn incoming_size(&self, root: &Node, src: &Path) -> anyhow::Result<u64> {
+        let mut seen = HashSet::new();
+        let mut size = 0;
+        root.try_walk(&mut |rel, node| {
+            if let FILE(hash) = &node.meta {
+                if self.has_object(hash) || !seen.insert(hash.clone()) {
+                    return Ok(());
+                }
+                let path = if rel.as_os_str().is_empty() {
+                    src.to_path_buf()
+                } else {
+                    src.join(rel)
+                };
+                size += path.metadata()?.len();
+            }
+            Ok(())
+        })?;
+        Ok(size)
+    }
+}
@@ -0,0 +1,135 @@
+use crate::core::hash::matches;
+use crate::core::node::Meta::{DIRECTORY, FILE, INLINE, SYMLINK};
+use crate::core::node::Node;
+use crate::core::object_filter::Pipeline;
+use crate::core::store::Store;
+use crate::core::util::same_file;
+use anyhow::{anyhow, bail};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+impl Store {
+    /// 删除由get恢复的目录，目录与条目的某个版本一致时才删除，返回匹配的版本，
+    /// `force` 为真时忽略本地修改
+    pub fn release(
+        &self,
+        path: &Path,
+        name: Option<&str>,
+        force: bool,
+    ) -> anyhow::Result<Option<String>> {
+        if !path.exists() && !path.is_symlink() {
+            bail!("{:?} not exists", path);
+        }
+        let name = match name {
+            Some(n) => n.to_string(),
+            None => path
+                .file_name()
+                .ok_or(anyhow!("invalid path {:?}", path))?
+                .to_string_lossy()
+                .to_string(),
+        };
+        let versions = self.versions(&name);
+        if versions.is_empty() {
+            bail!("{} not exists", name);
+        }
+        let mut matched = None;
+        let mut changes = Vec::new();
+        for node in versions.iter().rev() {
+            let diff = self.local_changes(node, path)?;
+            if diff.is_empty() {
+                matched = Some(node.label());
+                break;
+            }
+            if changes.is_empty() || diff.len() < changes.len() {
+                changes = diff;
+            }
+        }
+        if matched.is_none() {
+            if !force {
+                bail!(
+                    "{:?} has {} local changes, e.g. {:?}, use --force to remove it anyway",
+                    path,
+                    changes.len(),
+                    &changes[..changes.len().min(5)]
+                );
+            }
+            warn!("{:?} has local changes {:?}, remove anyway", path, changes);
+        }
+        info!("release {:?}", path);
+        if path.is_dir() && !path.is_symlink() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+        Ok(matched)
+    }
+
+    /// 与节点相比被修改或新增的路径，删除这些路径会丢失数据，已删除的路径不算
+    pub fn local_changes(&self, root: &Node, path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let mut ans = Vec::new();
+        let codec = self.pipeline(&root.filters)?;
+        self.diff_node(root, path, &codec, &mut ans)?;
+        Ok(ans)
+    }
+
+    /// 对象以编码后的内容保存时，解码后与文件比较
+    fn same_decoded(&self, path: &Path, hash: &str, codec: &Pipeline) -> anyhow::Result<bool> {
+        if !self.has_object(hash) {
+            return Ok(false);
+        }
+        let algorithm = self.hash_algorithm();
+        let decoded = codec.decode(Box::new(File::open(self.object_path(hash))?))?;
+        Ok(algorithm.hash_reader(decoded, &mut |_| {})? == algorithm.hash_file(path)?)
+    }
+
+    fn diff_node(
+        &self,
+        node: &Node,
+        path: &Path,
+        codec: &Pipeline,
+        ans: &mut Vec<PathBuf>,
+    ) -> anyhow::Result<()> {
+        if !path.exists() && !path.is_symlink() {
+            return Ok(());
+        }
+        let same = match &node.meta {
+            FILE(hash) if !codec.is_empty() => {
+                !path.is_symlink() && path.is_file() && self.same_decoded(path, hash, codec)?
+            }
+            FILE(hash) => {
+                !path.is_symlink()
+                    && path.is_file()
+                    && (same_file(path, &self.object_path(hash)) || matches(path, hash)?)
+            }
+            INLINE(_) => {
+                !path.is_symlink()
+                    && path.is_file()
+                    && node.inline_content()? == Some(fs::read(path)?)
+            }
+            SYMLINK(target) => path.is_symlink() && fs::read_link(path)? == *target,
+            DIRECTORY(children) => {
+                if path.is_symlink() || !path.is_dir() {
+                    false
+                } else {
+                    let names: HashSet<&str> = children.iter().map(|c| c.name.as_str()).collect();
+                    for entry in fs::read_dir(path)? {
+                        let entry = entry?;
+                        if !names.contains(entry.file_name().to_string_lossy().as_ref()) {
+                            ans.push(entry.path());
+                        }
+                    }
+                    for child in children {
+                        self.diff_node(child, &path.join(&child.name), codec, ans)?;
+                    }
+                    true
+                }
+            }
+        };
+        if !same {
+            ans.push(path.to_path_buf());
+        }
+        Ok(())
+    }
+}
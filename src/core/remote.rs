@@ -0,0 +1,276 @@
+use crate::core::layout::shard_path;
+use crate::core::migration::parse_index;
+use crate::core::names::has_prefix;
+use crate::core::node::Meta::FILE;
+use crate::core::node::Node;
+use crate::core::porcelain::{self, Event};
+use crate::core::resolve::resolve;
+use crate::core::settings::{Remote, Settings};
+use crate::core::store::Store;
+use crate::core::util::{ensure_space, format_time, ingest_expected, ssh_session, username};
+use crate::core::webhook::EventKind;
+use crate::{CONFIG_NAME, INDEX_DB_NAME, SETTINGS_NAME, STORE_DIRECTORY};
+use anyhow::{anyhow, bail};
+use log::info;
+use serde::Serialize;
+use ssh2::Session;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// 远程仓库默认目录，相对于远程用户的家目录
+const DEFAULT_REMOTE_PATH: &str = ".hbx";
+
+/// pull的数据来源
+pub trait Source {
+    /// 读取远程仓库的索引
+    fn manifest(&mut self) -> anyhow::Result<Vec<Node>>;
+
+    /// 读取对象内容
+    fn object(&mut self, hash: &str) -> anyhow::Result<Box<dyn Read + '_>>;
+
+    /// 对象大小，用于检查可用空间，无法获取时为空
+    fn size(&mut self, _hash: &str) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+/// 通过ssh读取远程仓库
+pub struct SshSource {
+    sess: Session,
+    path: PathBuf,
+    /// 远程仓库的大文件目录
+    big_objects: Option<PathBuf>,
+}
+
+impl SshSource {
+    pub fn new(remote: &Remote) -> anyhow::Result<Self> {
+        let username = remote.username.clone().unwrap_or_else(username);
+        let sess = ssh_session(&username, &remote.address)?;
+        let path = PathBuf::from(remote.path.as_deref().unwrap_or(DEFAULT_REMOTE_PATH));
+        let mut source = Self {
+            sess,
+            path,
+            big_objects: None,
+        };
+        // 旧的远程仓库可能没有配置文件
+        if let Ok(s) = source.read_to_string(&source.path.join(SETTINGS_NAME)) {
+            let settings: Settings = serde_json::from_str(&s)?;
+            source.big_objects = settings.big_objects.map(|b| b.path);
+        }
+        Ok(source)
+    }
+
+    /// 对象可能的位置，远程仓库可能还没有迁移到分目录布局
+    fn object_paths(&self, hash: &str) -> Vec<PathBuf> {
+        let mut dirs = vec![self.path.join(STORE_DIRECTORY)];
+        dirs.extend(self.big_objects.clone());
+        let shard = shard_path(hash);
+        let mut ans: Vec<PathBuf> = dirs.iter().map(|d| d.join(&shard)).collect();
+        ans.extend(dirs.iter().map(|d| d.join(hash)));
+        ans
+    }
+
+    fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let (mut channel, _) = self.sess.scp_recv(path)?;
+        let mut data = Vec::new();
+        channel.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_to_string(&self, path: &Path) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.read(path)?)?)
+    }
+}
+
+impl Source for SshSource {
+    fn manifest(&mut self) -> anyhow::Result<Vec<Node>> {
+        let data = self.read(&self.path.join(CONFIG_NAME))?;
+        parse_index(&data)
+    }
+
+    fn object(&mut self, hash: &str) -> anyhow::Result<Box<dyn Read + '_>> {
+        let mut res = Err(anyhow!("{} not found", hash));
+        for path in self.object_paths(hash) {
+            res = self.sess.scp_recv(&path).map_err(|e| e.into());
+            if res.is_ok() {
+                break;
+            }
+        }
+        let (channel, stat) = res.map_err(|e| anyhow!("failed to fetch {}: {}", hash, e))?;
+        info!("fetch {} ({} bytes)", hash, stat.size());
+        Ok(Box::new(channel))
+    }
+
+    fn size(&mut self, hash: &str) -> anyhow::Result<Option<u64>> {
+        let sftp = self.sess.sftp()?;
+        Ok(self
+            .object_paths(hash)
+            .iter()
+            .find_map(|p| sftp.stat(p).ok())
+            .and_then(|s| s.size))
+    }
+}
+
+/// 读取本机上的其他仓库，如挂载的网络磁盘
+pub struct LocalSource {
+    store: Store,
+}
+
+impl LocalSource {
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        if !path.join(CONFIG_NAME).is_file() && !path.join(INDEX_DB_NAME).is_file() {
+            bail!("{:?} is not a store", path);
+        }
+        let mut store = Store::new(path.to_path_buf())?;
+        store.load()?;
+        Ok(Self { store })
+    }
+}
+
+impl Source for LocalSource {
+    fn manifest(&mut self) -> anyhow::Result<Vec<Node>> {
+        // 索引可能保存在SQLite中，使用加载后的条目
+        Ok(self.store.entries().into_iter().cloned().collect())
+    }
+
+    fn object(&mut self, hash: &str) -> anyhow::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(std::fs::File::open(self.store.object_path(hash))?))
+    }
+
+    fn size(&mut self, hash: &str) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .store
+            .object_path(hash)
+            .metadata()
+            .ok()
+            .map(|m| m.len()))
+    }
+}
+
+/// pull的结果
+#[derive(Debug, Default, Serialize)]
+pub struct PullReport {
+    /// 新增的条目
+    pub pulled: Vec<String>,
+    /// 本地已有的条目
+    pub skipped: Vec<String>,
+    /// 下载的对象数
+    pub downloaded: usize,
+    /// 本地已有而无需下载的对象数
+    pub reused: usize,
+}
+
+impl Store {
+    /// 从远程仓库拉取条目，`address` 可以是配置的远程仓库名、本机仓库目录或ssh地址
+    pub fn pull(&mut self, names: Vec<String>, address: String) -> anyhow::Result<PullReport> {
+        info!("pull tools {:?} from {:?}", names, address);
+        let mut source = self.open_source(&address)?;
+        self.pull_from(&names, source.as_mut())
+    }
+
+    /// 按配置的远程仓库名、本机仓库目录、ssh地址的顺序解析地址
+    pub fn open_source(&self, address: &str) -> anyhow::Result<Box<dyn Source>> {
+        Ok(match self.settings().remotes.get(address) {
+            Some(remote) => Box::new(SshSource::new(remote)?),
+            None if Path::new(address).is_dir() => Box::new(LocalSource::new(Path::new(address))?),
+            None => Box::new(SshSource::new(&Remote {
+                address: address.to_string(),
+                ..Default::default()
+            })?),
+        })
+    }
+
+    /// 只下载本地缺少的对象，同名的不同版本会同时保留
+    pub fn pull_from(
+        &mut self,
+        names: &[String],
+        source: &mut dyn Source,
+    ) -> anyhow::Result<PullReport> {
+        let manifest = source.manifest()?;
+        let mut report = PullReport::default();
+        // 以 `/` 结尾的名称表示拉取该前缀下的所有条目
+        let mut expanded = BTreeSet::new();
+        for name in names {
+            if !name.ends_with('/') {
+                expanded.insert(name.clone());
+                continue;
+            }
+            let matched: Vec<&Node> = manifest
+                .iter()
+                .filter(|n| has_prefix(&n.name, name))
+                .collect();
+            if matched.is_empty() {
+                bail!("nothing under {} in remote", name);
+            }
+            expanded.extend(matched.into_iter().map(|n| n.name.clone()));
+        }
+        for (i, name) in expanded.iter().enumerate() {
+            porcelain::emit(&Event::Progress {
+                done: i,
+                total: expanded.len(),
+                item: name,
+            });
+            let mut node = resolve(name, &manifest)
+                .map_err(|e| anyhow!("{} in remote", e))?
+                .clone();
+
+            if let Ok(local) = self.entry_version(&node.name, node.version.as_deref()) {
+                if local.same_content(&node) {
+                    info!("{} is up to date", node.label());
+                    report.skipped.push(node.label());
+                    continue;
+                }
+                // 同名同版本但内容不同，以远程的add时间区分版本
+                node.version = Some(format_time(node.added()));
+                if self
+                    .entry_version(&node.name, node.version.as_deref())
+                    .is_ok()
+                {
+                    report.skipped.push(node.label());
+                    continue;
+                }
+            }
+
+            let mut hashes = HashSet::new();
+            node.walk(&mut |_, n| {
+                if let FILE(hash) = &n.meta {
+                    hashes.insert(hash.clone());
+                }
+            });
+            let missing: Vec<&String> = hashes.iter().filter(|h| !self.has_object(h)).collect();
+            info!(
+                "{}: {} objects to download, {} already present",
+                node.label(),
+                missing.len(),
+                hashes.len() - missing.len()
+            );
+            let mut needed: BTreeMap<PathBuf, (u64, u64)> = BTreeMap::new();
+            for hash in &missing {
+                let size = source.size(hash)?;
+                let (total, count) = needed
+                    .entry(self.object_dir(size.unwrap_or_default())?)
+                    .or_default();
+                *total += size.unwrap_or_default();
+                *count += 1;
+            }
+            // 下载前检查容量，需要淘汰的条目在新条目加入之后才删除
+            self.quota_victims(&node, needed.values().map(|(size, _)| size).sum())?;
+            for (dir, (size, count)) in needed {
+                ensure_space(&dir, size)?;
+                self.selfcheck(&dir)?.ensure_inodes(count)?;
+            }
+            for hash in &missing {
+                ingest_expected(source.object(hash)?, self, hash)?;
+            }
+            report.downloaded += missing.len();
+            report.reused += hashes.len() - missing.len();
+            report.pulled.push(node.label());
+            let mut hashes: Vec<String> = hashes.into_iter().collect();
+            hashes.sort();
+            self.put_manifest(node.clone())?;
+            self.notify(EventKind::Pull, Some(&node), hashes);
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,136 @@
+use crate::core::backend::parse_nodes;
+use crate::core::encryption::{decrypt, encrypt, is_encrypted};
+use crate::core::node::Node;
+use crate::core::store::Store;
+use crate::MANIFEST_DIRECTORY;
+use age::x25519::Identity;
+use anyhow::bail;
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use log::info;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// repair的结果
+#[derive(Debug, Default, Serialize)]
+pub struct RepairReport {
+    /// 从清单恢复的条目
+    pub restored: Vec<String>,
+    /// 索引中已经存在的条目数
+    pub present: usize,
+    /// 无法恢复的清单
+    pub broken: Vec<BrokenManifest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BrokenManifest {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl Store {
+    pub fn manifest_dir(&self) -> PathBuf {
+        self.path().join(MANIFEST_DIRECTORY)
+    }
+
+    /// 每个条目单独保存一份清单，文件名为内容的哈希，只写入新的清单并删除已删除条目的清单
+    pub(crate) fn save_manifests(&self) -> anyhow::Result<()> {
+        let dir = self.manifest_dir();
+        fs::create_dir_all(&dir)?;
+        let identity = self.identity()?;
+        let mut expected = HashSet::new();
+        for root in self.entries() {
+            let mut data = serde_json::to_vec(root)?;
+            let name = format!("{}.json", blake3::hash(&data).to_hex());
+            let path = dir.join(&name);
+            if !path.exists() {
+                if let Some(identity) = &identity {
+                    data = encrypt(identity, &data)?;
+                }
+                AtomicFile::new(&path, AllowOverwrite).write(|f| f.write_all(&data))?;
+            }
+            expected.insert(name);
+        }
+        for path in manifest_files(&dir)? {
+            let stale = path
+                .file_name()
+                .is_some_and(|n| !expected.contains(n.to_string_lossy().as_ref()));
+            if stale {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 删除一个条目的清单，用于只删除部分条目、不重写所有清单的情况
+    pub(crate) fn remove_manifest(&self, root: &Node) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(root)?;
+        let path = self
+            .manifest_dir()
+            .join(format!("{}.json", blake3::hash(&data).to_hex()));
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// 用清单恢复索引中缺少的条目，引用的对象缺失的清单不会恢复
+    pub fn repair(&mut self) -> anyhow::Result<RepairReport> {
+        let dir = self.manifest_dir();
+        if !dir.is_dir() {
+            bail!("{:?} not exists, nothing to repair from", dir);
+        }
+        let identity = self.identity()?;
+        let mut report = RepairReport::default();
+        for path in manifest_files(&dir)? {
+            let res = read_manifest(&path, identity.as_ref()).and_then(|root| {
+                if self.entries().contains(&&root) {
+                    return Ok(None);
+                }
+                let label = root.label();
+                // 恢复已有的条目，不检查容量
+                let root = self.check_manifest(root)?;
+                self.insert_entry(root)?;
+                Ok(Some(label))
+            });
+            match res {
+                Ok(Some(label)) => {
+                    info!("restore {} from {:?}", label, path);
+                    report.restored.push(label);
+                }
+                Ok(None) => report.present += 1,
+                Err(e) => report.broken.push(BrokenManifest {
+                    path,
+                    error: e.to_string(),
+                }),
+            }
+        }
+        report.restored.sort();
+        Ok(report)
+    }
+}
+
+/// 目录中的清单文件，按名称排序
+fn manifest_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut ans = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|e| e == "json") {
+            ans.push(path);
+        }
+    }
+    ans.sort();
+    Ok(ans)
+}
+
+fn read_manifest(path: &Path, identity: Option<&Identity>) -> anyhow::Result<Node> {
+    let data = fs::read(path)?;
+    let data = match (is_encrypted(&data), identity) {
+        (false, _) => data,
+        (true, Some(identity)) => decrypt(identity, &data)?,
+        (true, None) => bail!("{:?} is encrypted, set encryption.key_file", path),
+    };
+    parse_nodes(std::str::from_utf8(&data)?)
+}
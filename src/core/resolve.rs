@@ -0,0 +1,107 @@
+use crate::core::node::Node;
+use anyhow::{anyhow, bail};
+use semver::{Version, VersionReq};
+
+/// `name@spec` 中的版本要求
+#[derive(Debug, Clone, PartialEq)]
+pub enum Spec {
+    /// 没有指定版本，使用最后add的条目
+    Newest,
+    /// `latest`，使用最高的版本号
+    Latest,
+    /// 完全相同的版本号
+    Exact(String),
+    /// semver范围，如 `^12`、`>=1.2, <2`
+    Range(VersionReq),
+}
+
+/// 拆分 `name@spec`
+pub fn parse(spec: &str) -> (&str, Spec) {
+    match spec.split_once('@') {
+        None => (spec, Spec::Newest),
+        Some((name, "latest")) => (name, Spec::Latest),
+        Some((name, v)) => (name, Spec::Exact(v.to_string())),
+    }
+}
+
+/// 条目按约定表示的版本：`name@version`、`name-version` 或 `name/version`
+pub fn version_of<'a>(node: &'a Node, name: &str) -> Option<&'a str> {
+    if node.name == name {
+        return Some(node.version.as_deref().unwrap_or(""));
+    }
+    let rest = node.name.strip_prefix(name)?;
+    let v = rest.strip_prefix('-').or_else(|| rest.strip_prefix('/'))?;
+    // `gcc-arm` 不是gcc的版本
+    let digit = v
+        .trim_start_matches('v')
+        .starts_with(|c: char| c.is_ascii_digit());
+    digit.then_some(v)
+}
+
+/// 宽松地解析版本号，`v12`、`12.2` 补全为 `12.0.0`、`12.2.0`
+pub fn parse_version(s: &str) -> Option<Version> {
+    let s = s.strip_prefix('v').unwrap_or(s);
+    if let Ok(v) = Version::parse(s) {
+        return Some(v);
+    }
+    let end = s.find(['-', '+']).unwrap_or(s.len());
+    let (core, suffix) = s.split_at(end);
+    let parts = core.split('.').count();
+    if parts >= 3 || core.split('.').any(|p| p.parse::<u64>().is_err()) {
+        return None;
+    }
+    let padded = format!("{}{}{}", core, ".0".repeat(3 - parts), suffix);
+    Version::parse(&padded).ok()
+}
+
+/// 按版本要求在 `nodes` 中选择条目
+pub fn resolve<'a, I>(spec: &str, nodes: I) -> anyhow::Result<&'a Node>
+where
+    I: IntoIterator<Item = &'a Node>,
+{
+    let (name, spec) = parse(spec);
+    let nodes: Vec<&Node> = nodes.into_iter().collect();
+    let newest = |n: &&Node| (n.added(), n.version.clone());
+    if spec == Spec::Newest {
+        return nodes
+            .into_iter()
+            .filter(|n| n.name == name)
+            .max_by_key(newest)
+            .ok_or(anyhow!("{} not exists", name));
+    }
+    let candidates: Vec<(&Node, &str)> = nodes
+        .into_iter()
+        .filter_map(|n| version_of(n, name).map(|v| (n, v)))
+        .collect();
+    if candidates.is_empty() {
+        bail!("{} not exists", name);
+    }
+    let spec = match spec {
+        // 不存在这个版本时按semver范围处理，如 `12` 匹配 `12.2.0`
+        Spec::Exact(v) => match candidates.iter().find(|(_, x)| *x == v) {
+            Some((n, _)) => return Ok(n),
+            None => Spec::Range(
+                VersionReq::parse(&v).map_err(|_| anyhow!("{}@{} not exists", name, v))?,
+            ),
+        },
+        spec => spec,
+    };
+    // 版本号相同时使用后add的条目
+    let best = |req: Option<&VersionReq>| {
+        candidates
+            .iter()
+            .filter_map(|(n, v)| parse_version(v).map(|v| (*n, v)))
+            .filter(|(_, v)| req.is_none_or(|r| r.matches(v)))
+            .max_by(|a, b| (&a.1, a.0.added()).cmp(&(&b.1, b.0.added())))
+            .map(|(n, _)| n)
+    };
+    match spec {
+        Spec::Range(req) => {
+            best(Some(&req)).ok_or(anyhow!("no version of {} matches {}", name, req))
+        }
+        // 没有可以比较的版本号时使用最后add的条目
+        _ => best(None)
+            .or_else(|| candidates.iter().map(|(n, _)| *n).max_by_key(newest))
+            .ok_or(anyhow!("{} not exists", name)),
+    }
+}
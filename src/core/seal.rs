@@ -0,0 +1,146 @@
+use crate::core::node::Meta::FILE;
+use crate::core::node::{Node, Sealed};
+use crate::core::store::Store;
+use crate::core::util::ingest_expected;
+use crate::core::webhook::entry_hashes;
+use crate::STORE_DIRECTORY;
+use age::{Identity, IdentityFile, Recipient};
+use anyhow::{anyhow, bail};
+use log::info;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+
+/// 加密条目包中的索引文件名，其余为 `store/<hash>` 对象
+const MANIFEST_NAME: &str = "manifest.json";
+
+impl Store {
+    /// 用接收者的age公钥加密条目，索引和对象打包加密为一个对象，替换原来的条目，版本为空时加密最新的版本，返回加密后的对象名
+    pub fn seal(
+        &mut self,
+        name: &str,
+        version: Option<&str>,
+        recipients: &[String],
+    ) -> anyhow::Result<String> {
+        if recipients.is_empty() {
+            bail!("at least one recipient is required");
+        }
+        let parsed = recipients
+            .iter()
+            .map(|r| {
+                age::x25519::Recipient::from_str(r)
+                    .map_err(|e| anyhow!("invalid recipient {}: {}", r, e))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let root = match version {
+            None => self.entry(name)?,
+            Some(v) => self.entry_version(name, Some(v))?,
+        }
+        .clone();
+        if root.sealed.is_some() {
+            bail!("{} is already sealed", root.label());
+        }
+
+        let mut tmp = tempfile::tempfile_in(self.tmp_dir()?)?;
+        let encryptor =
+            age::Encryptor::with_recipients(parsed.iter().map(|r| r as &dyn Recipient))?;
+        let mut builder = tar::Builder::new(encryptor.wrap_output(&tmp)?);
+        let manifest = serde_json::to_vec(&root)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, MANIFEST_NAME, manifest.as_slice())?;
+        for hash in entry_hashes(&root) {
+            let path = Path::new(STORE_DIRECTORY).join(&hash);
+            builder.append_path_with_name(self.object_path(&hash), path)?;
+        }
+        builder.into_inner()?.finish()?;
+        tmp.seek(SeekFrom::Start(0))?;
+        let hash = self.put_object(&tmp)?;
+
+        let mut sealed = Node::with_meta(root.name.clone(), FILE(hash.clone()));
+        sealed.version = root.version.clone();
+        sealed.summary = root.summary.clone();
+        sealed.sealed = Some(Sealed {
+            recipients: recipients.to_vec(),
+        });
+        info!(
+            "sealed {} for {} recipients",
+            root.label(),
+            recipients.len()
+        );
+        // 原来的对象没有其他条目引用时可以通过clear清理
+        self.replace_manifest(sealed)?;
+        Ok(hash)
+    }
+
+    /// 用身份文件中的私钥解密条目，恢复原来的索引和对象
+    pub fn unseal(
+        &mut self,
+        name: &str,
+        version: Option<&str>,
+        identity: &Path,
+    ) -> anyhow::Result<String> {
+        let sealed = match version {
+            None => self.entry(name)?,
+            Some(v) => self.entry_version(name, Some(v))?,
+        }
+        .clone();
+        let hash = match (&sealed.sealed, &sealed.meta) {
+            (Some(_), FILE(hash)) => hash.clone(),
+            _ => bail!("{} is not sealed", sealed.label()),
+        };
+        let identities = IdentityFile::from_file(identity.to_string_lossy().to_string())?
+            .into_identities()
+            .map_err(|e| anyhow!("invalid identity file {:?}: {}", identity, e))?;
+        let file = BufReader::new(File::open(self.object_path(&hash))?);
+        let reader = age::Decryptor::new(file)?
+            .decrypt(identities.iter().map(|i| i.as_ref() as &dyn Identity))
+            .map_err(|e| anyhow!("failed to unseal {}: {}", sealed.label(), e))?;
+
+        let mut root: Option<Node> = None;
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_path_buf();
+            if path == Path::new(MANIFEST_NAME) {
+                let mut s = String::new();
+                entry.read_to_string(&mut s)?;
+                root = Some(serde_json::from_str(&s)?);
+                continue;
+            }
+            let hash = path
+                .strip_prefix(STORE_DIRECTORY)
+                .ok()
+                .and_then(|p| p.to_str())
+                .ok_or(anyhow!("unexpected path {:?} in sealed entry", path))?
+                .to_string();
+            ingest_expected(&mut entry, self, &hash)?;
+        }
+        let root = root.ok_or(anyhow!("sealed entry has no manifest"))?;
+        if root.name != sealed.name || root.version != sealed.version {
+            bail!(
+                "sealed entry is {}, expected {}",
+                root.label(),
+                sealed.label()
+            );
+        }
+        let missing: Vec<String> = entry_hashes(&root)
+            .into_iter()
+            .filter(|h| !self.has_object(h))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "{} objects missing after unseal: {:?}",
+                missing.len(),
+                missing
+            );
+        }
+        info!("unsealed {}", root.label());
+        let label = root.label();
+        self.replace_manifest(root)?;
+        Ok(label)
+    }
+}
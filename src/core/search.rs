@@ -0,0 +1,136 @@
+use crate::core::store::Store;
+use crate::core::util::now;
+use crate::CATALOG_DIRECTORY;
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// 远程仓库目录的缓存时间，秒
+const CATALOG_TTL: u64 = 10 * 60;
+
+/// 缓存的远程仓库条目列表
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Catalog {
+    /// 获取时间，unix秒
+    pub fetched: u64,
+    pub entries: Vec<CatalogEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CatalogEntry {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// add时间，unix秒
+    pub added: u64,
+}
+
+/// 搜索结果
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub remote: String,
+    pub entry: CatalogEntry,
+}
+
+impl Store {
+    fn catalog_path(&self, address: &str) -> PathBuf {
+        let key = blake3::hash(address.as_bytes()).to_hex();
+        self.path()
+            .join(CATALOG_DIRECTORY)
+            .join(format!("{}.json", &key[..16]))
+    }
+
+    /// 远程仓库的条目列表，缓存未过期时不访问远程仓库，获取失败时使用过期的缓存
+    pub fn catalog(&self, address: &str, refresh: bool) -> anyhow::Result<Catalog> {
+        let path = self.catalog_path(address);
+        let cached: Option<Catalog> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok());
+        let cached = match cached {
+            Some(c) if !refresh && now().saturating_sub(c.fetched) < CATALOG_TTL => return Ok(c),
+            c => c,
+        };
+        let fetched = self
+            .open_source(address)
+            .and_then(|mut source| source.manifest());
+        let manifest = match (fetched, cached) {
+            (Ok(m), _) => m,
+            (Err(e), Some(c)) => {
+                warn!("failed to fetch catalog of {}: {}, use cached", address, e);
+                return Ok(c);
+            }
+            (Err(e), None) => return Err(e),
+        };
+        let catalog = Catalog {
+            fetched: now(),
+            entries: manifest
+                .iter()
+                .map(|n| CatalogEntry {
+                    name: n.name.clone(),
+                    version: n.version.clone(),
+                    added: n.added(),
+                })
+                .collect(),
+        };
+        info!("fetched {} entries from {}", catalog.entries.len(), address);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let s = serde_json::to_string(&catalog)?;
+        AtomicFile::new(&path, AllowOverwrite).write(|f| f.write_all(s.as_bytes()))?;
+        Ok(catalog)
+    }
+
+    /// 在远程仓库中查找名称匹配的条目，`pattern` 含通配符时按通配符匹配，否则按子串匹配，
+    /// `remotes` 为 `all` 时搜索所有配置的远程仓库
+    pub fn search(
+        &self,
+        remotes: &[String],
+        pattern: &str,
+        refresh: bool,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let remotes: Vec<String> = if remotes.iter().any(|r| r == "all") {
+            self.settings().remotes.keys().cloned().collect()
+        } else {
+            remotes.to_vec()
+        };
+        let glob = if pattern.contains(['*', '?', '[']) {
+            Some(glob::Pattern::new(pattern)?)
+        } else {
+            None
+        };
+        let mut hits = Vec::new();
+        for remote in remotes {
+            let catalog = match self.catalog(&remote, refresh) {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("skip {}: {}", remote, e);
+                    continue;
+                }
+            };
+            for entry in catalog.entries {
+                let matched = match &glob {
+                    Some(g) => g.matches(&entry.name),
+                    None => entry.name.contains(pattern),
+                };
+                if matched {
+                    hits.push(SearchHit {
+                        remote: remote.clone(),
+                        entry,
+                    });
+                }
+            }
+        }
+        hits.sort_by(|a, b| {
+            (&a.entry.name, &a.remote, a.entry.added).cmp(&(
+                &b.entry.name,
+                &b.remote,
+                b.entry.added,
+            ))
+        });
+        Ok(hits)
+    }
+}
@@ -0,0 +1,138 @@
+use crate::core::node::Meta::{FILE, SYMLINK};
+use crate::core::node::Node;
+use crate::core::store::Store;
+use crate::core::util::{available_space, free_inodes, path_limits, same_device, symlink};
+use anyhow::bail;
+use log::info;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 两个位置之间实际可用的操作，add、get和pull据此选择硬链接或复制
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub same_device: bool,
+    /// 可以从 `from` 硬链接到 `to`
+    pub hard_link: bool,
+    /// 可以在 `to` 中创建符号链接
+    pub symlink: bool,
+    /// `to` 所在文件系统的路径和文件名长度上限(字节)
+    pub max_path: Option<u64>,
+    pub max_name: Option<u64>,
+    pub free_inodes: Option<u64>,
+    pub available: Option<u64>,
+}
+
+impl Capabilities {
+    /// 在 `to` 中实际创建链接来检查，`from` 为文件时用它测试硬链接，为目录时在其中创建临时文件
+    pub fn probe(from: &Path, to: &Path) -> anyhow::Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix(".hbx-probe-")
+            .tempdir_in(to)?;
+        let tmp;
+        let src = if from.is_dir() {
+            tmp = tempfile::Builder::new()
+                .prefix(".hbx-probe-")
+                .tempfile_in(from)?;
+            tmp.path()
+        } else {
+            from
+        };
+        let hard_link = match fs::hard_link(src, dir.path().join("link")) {
+            Ok(_) => true,
+            Err(e) => {
+                info!("cannot link {:?} into {:?}: {}", from, to, e);
+                false
+            }
+        };
+        let symlink = match symlink(Path::new("link"), &dir.path().join("symlink")) {
+            Ok(_) => true,
+            Err(e) => {
+                info!("cannot create symlinks in {:?}: {}", to, e);
+                false
+            }
+        };
+        let (max_path, max_name) = path_limits(to);
+        Ok(Self {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            same_device: same_device(from, to),
+            hard_link,
+            symlink,
+            max_path,
+            max_name,
+            free_inodes: free_inodes(to),
+            available: available_space(to),
+        })
+    }
+
+    /// 检查要在 `to` 下创建的节点树，路径过长或需要的符号链接无法创建时报错
+    pub fn check_tree(&self, root: &Node, base: &Path) -> anyhow::Result<()> {
+        root.try_walk(&mut |rel, node| {
+            self.check_path(&base.join(rel), matches!(node.meta, SYMLINK(_)))
+        })
+    }
+
+    fn check_path(&self, path: &Path, is_symlink: bool) -> anyhow::Result<()> {
+        if is_symlink && !self.symlink {
+            bail!(
+                "{:?} is a symlink but symlinks cannot be created in {:?}",
+                path,
+                self.to
+            );
+        }
+        let len = path.as_os_str().len() as u64;
+        if self.max_path.is_some_and(|max| len >= max) {
+            bail!("{:?} is longer than the limit of {:?}", path, self.to);
+        }
+        let name = path.file_name().map_or(0, |n| n.len() as u64);
+        if self.max_name.is_some_and(|max| name > max) {
+            bail!(
+                "the name of {:?} is longer than the limit of {:?}",
+                path,
+                self.to
+            );
+        }
+        Ok(())
+    }
+
+    /// 需要创建 `count` 个文件时检查inode是否足够
+    pub fn ensure_inodes(&self, count: u64) -> anyhow::Result<()> {
+        match self.free_inodes {
+            Some(free) if free < count => bail!(
+                "not enough inodes on {:?}: need {}, have {}",
+                self.to,
+                count,
+                free
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 节点树中第一个文件，用于测试硬链接
+pub(crate) fn first_file(root: &Node, base: &Path) -> Option<PathBuf> {
+    let mut ans = None;
+    root.walk(&mut |rel, node| {
+        if ans.is_none() && matches!(node.meta, FILE(_)) {
+            // 根节点是文件时 `join("")` 会在末尾加上分隔符
+            ans = Some(if rel.as_os_str().is_empty() {
+                base.to_path_buf()
+            } else {
+                base.join(rel)
+            });
+        }
+    });
+    ans
+}
+
+impl Store {
+    /// 检查仓库与目录之间的能力
+    pub fn selfcheck(&self, dir: &Path) -> anyhow::Result<Capabilities> {
+        let store = self.store_dir();
+        fs::create_dir_all(&store)?;
+        Capabilities::probe(&store, dir)
+    }
+}
@@ -0,0 +1,102 @@
+use crate::core::hash::HashAlgorithm;
+use crate::core::store::{file_stamp, FileStamp, Store};
+use crate::SESSION_DIRECTORY;
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// 进度的保存间隔
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// add的进度，中断后再次add同一路径时复用已计算的哈希
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Session {
+    /// 已计算哈希的文件
+    files: BTreeMap<PathBuf, Hashed>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    saved: Option<Instant>,
+}
+
+/// 计算哈希时文件的状态
+#[derive(Debug, Deserialize, Serialize)]
+struct Hashed {
+    stamp: FileStamp,
+    hash: String,
+}
+
+impl Session {
+    /// 打开源路径对应的进度文件，文件不存在或损坏时从头开始
+    pub fn open(store: &Store, src: &Path) -> anyhow::Result<Self> {
+        let src = src.canonicalize()?;
+        let mut hasher = HashAlgorithm::Blake3.hasher();
+        hasher.update(src.to_string_lossy().as_bytes());
+        let path = store.path().join(SESSION_DIRECTORY).join(hasher.finalize());
+        let mut session = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                warn!("ignore broken session {:?}: {}", path, e);
+                Session::default()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Session::default(),
+            Err(e) => return Err(e.into()),
+        };
+        if !session.files.is_empty() {
+            info!(
+                "resume add of {:?}, {} files already hashed",
+                src,
+                session.files.len()
+            );
+        }
+        session.path = path;
+        session.saved = Some(Instant::now());
+        Ok(session)
+    }
+
+    /// 上次计算的哈希，文件之后被修改过或算法不同时为空
+    pub fn hash(&self, path: &Path, algorithm: HashAlgorithm) -> Option<String> {
+        let hashed = self.files.get(path)?;
+        let valid = file_stamp(path) == Some(hashed.stamp)
+            && HashAlgorithm::of(&hashed.hash) == Some(algorithm);
+        valid.then(|| hashed.hash.clone())
+    }
+
+    /// 记录文件的哈希，距上次保存超过间隔时写入磁盘
+    pub fn record(&mut self, path: &Path, stamp: FileStamp, hash: &str) -> anyhow::Result<()> {
+        let hashed = Hashed {
+            stamp,
+            hash: hash.to_string(),
+        };
+        self.files.insert(path.to_path_buf(), hashed);
+        if self.saved.is_some_and(|t| t.elapsed() >= SAVE_INTERVAL) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if self.path.as_os_str().is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let s = serde_json::to_string(self)?;
+        AtomicFile::new(&self.path, AllowOverwrite).write(|f| f.write_all(s.as_bytes()))?;
+        self.saved = Some(Instant::now());
+        Ok(())
+    }
+
+    /// add完成后删除进度文件
+    pub fn finish(self) -> anyhow::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
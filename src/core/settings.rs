@@ -0,0 +1,175 @@
+use crate::core::backend::IndexFormat;
+use crate::core::backup::Backup;
+use crate::core::encryption::Encryption;
+use crate::core::hash::HashAlgorithm;
+use crate::core::names::{NameEscape, NameRules};
+use crate::core::object_filter::FilterRule;
+use crate::core::quota::Quota;
+use crate::core::webhook::Webhook;
+use anyhow::{anyhow, bail};
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, from_value, to_string_pretty, to_value, Value};
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// 仓库级别的配置，与索引分开保存
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Settings {
+    /// 命令别名，如 `up = "pull --all origin"`
+    #[serde(default)]
+    pub alias: BTreeMap<String, String>,
+    /// 索引的存储方式
+    #[serde(default)]
+    pub index: IndexFormat,
+    /// 小于该大小(字节)的文件内联到索引中，0表示不内联
+    #[serde(default)]
+    pub inline_threshold: u64,
+    /// 新对象使用的哈希算法，为空时新仓库使用blake3，旧仓库沿用md5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashAlgorithm>,
+    /// 大文件单独存放的目录，为空时所有对象都在仓库中
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub big_objects: Option<BigObjects>,
+    /// 远程仓库
+    #[serde(default)]
+    pub remotes: BTreeMap<String, Remote>,
+    /// 恢复时对windows上非法文件名的转义方式，windows上默认为percent
+    #[serde(default)]
+    pub name_escape: NameEscape,
+    /// 条目名称的校验规则
+    #[serde(default)]
+    pub name_rules: NameRules,
+    /// 定时备份任务，key为条目名称
+    #[serde(default)]
+    pub backups: BTreeMap<String, Backup>,
+    /// 按条目名称选择对象过滤器，使用第一个匹配的规则
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub object_filters: Vec<FilterRule>,
+    /// 源文件或目标目录与仓库不在同一文件系统、无法硬链接时是否复制，为空时复制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy_on_cross_device: Option<bool>,
+    /// 加密保存对象和索引，为空时不加密
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    /// 对象占用空间的上限，为空时不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+    /// 仓库被锁定时默认的等待时间，如 `30s`，为空时不等待
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_wait: Option<String>,
+    /// 接收仓库事件的webhook
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+}
+
+/// 大文件的存放位置，如大文件放在机械硬盘，小文件放在固态硬盘
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BigObjects {
+    /// 对象目录，可以位于其他磁盘
+    pub path: PathBuf,
+    /// 不小于该大小(字节)的文件存放到该目录
+    pub threshold: u64,
+}
+
+/// 远程仓库配置
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Remote {
+    /// ip或者host，如 `10.0.0.1:22`
+    pub address: String,
+    /// ssh用户名，默认为当前用户
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// 远程仓库目录，默认为 `~/.hbx`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// 保存token的环境变量名，配置中只记录引用不保存token本身
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_env: Option<String>,
+}
+
+impl Settings {
+    /// 读取配置，文件不存在时返回默认配置
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = read_to_string(path)?;
+        Ok(from_str(&content)?)
+    }
+
+    /// 条目使用的对象过滤器
+    pub fn filters_for(&self, name: &str) -> anyhow::Result<Vec<String>> {
+        for rule in &self.object_filters {
+            let pattern = glob::Pattern::new(&rule.pattern)
+                .map_err(|e| anyhow!("invalid pattern {:?}: {}", rule.pattern, e))?;
+            if pattern.matches(name) {
+                return Ok(rule.filters.clone());
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    /// 按 `a.b` 形式的路径读取配置项
+    pub fn get(&self, key: &str) -> anyhow::Result<Value> {
+        let mut value = to_value(self)?;
+        for k in key.split('.') {
+            value = match value {
+                Value::Object(mut map) => map.remove(k).ok_or(anyhow!("unknown key {}", key))?,
+                _ => bail!("unknown key {}", key),
+            };
+        }
+        Ok(value)
+    }
+
+    /// 按 `a.b` 形式的路径修改配置项，值优先按json解析
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let value = from_str(value).unwrap_or(Value::String(value.to_string()));
+        let mut root = to_value(&*self)?;
+        let mut keys: Vec<&str> = key.split('.').collect();
+        let last = keys.pop().ok_or(anyhow!("empty key"))?;
+        let mut cur = &mut root;
+        for k in keys {
+            cur = cur
+                .as_object_mut()
+                .and_then(|m| m.get_mut(k))
+                .ok_or(anyhow!("unknown key {}", key))?;
+        }
+        match cur.as_object_mut() {
+            Some(map) => map.insert(last.to_string(), value),
+            None => bail!("unknown key {}", key),
+        };
+        let settings: Settings =
+            from_value(root).map_err(|e| anyhow!("invalid value for {}: {}", key, e))?;
+        // 未知字段在反序列化时会被丢弃
+        settings.get(key)?;
+        *self = settings;
+        Ok(())
+    }
+
+    /// 合并导入的配置，同名的配置项以导入的为准
+    pub fn merge(&mut self, other: Value) -> anyhow::Result<()> {
+        fn merge(a: &mut Value, b: Value) {
+            match (a, b) {
+                (Value::Object(a), Value::Object(b)) => {
+                    for (k, v) in b {
+                        merge(a.entry(k).or_insert(Value::Null), v);
+                    }
+                }
+                (a, b) => *a = b,
+            }
+        }
+        let mut root = to_value(&*self)?;
+        merge(&mut root, other);
+        *self = from_value(root)?;
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let s = to_string_pretty(self)?;
+        AtomicFile::new(path, AllowOverwrite).write(|f| f.write_all(s.as_bytes()))?;
+        Ok(())
+    }
+}
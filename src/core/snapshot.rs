@@ -0,0 +1,154 @@
+use crate::core::layout::{object_name, shard_path};
+use crate::core::migration::{parse_index, serialize_index};
+use crate::core::node::Meta::FILE;
+use crate::core::settings::Settings;
+use crate::core::store::Store;
+use crate::core::util::ingest_expected;
+use crate::{CONFIG_NAME, SETTINGS_NAME, STORE_DIRECTORY};
+use anyhow::bail;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::to_vec_pretty;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path};
+use tempfile::NamedTempFile;
+
+/// import-store的结果
+#[derive(Debug, Default, Serialize)]
+pub struct ImportStoreReport {
+    /// 导入的条目
+    pub imported: Vec<String>,
+    /// 仓库中已有的条目
+    pub skipped: Vec<String>,
+    /// 写入的对象数
+    pub objects: usize,
+    /// 仓库原来没有配置，使用了快照中的配置
+    pub settings: bool,
+}
+
+impl Store {
+    /// 导出整个仓库的快照，扩展名为 `.zst` 时压缩，调用方需要持有仓库锁，返回对象数
+    pub fn snapshot(&self, output: &Path) -> anyhow::Result<usize> {
+        let dir = match output.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        // 写完后再移动到目标位置，不会留下不完整的快照
+        let tmp = NamedTempFile::new_in(dir)?;
+        let count = if output.extension().is_some_and(|e| e == "zst") {
+            let mut encoder = zstd::Encoder::new(tmp.as_file(), 0)?;
+            let count = self.export_store(&mut encoder)?;
+            encoder.finish()?;
+            count
+        } else {
+            self.export_store(tmp.as_file())?
+        };
+        tmp.as_file().sync_all()?;
+        tmp.persist(output)?;
+        Ok(count)
+    }
+
+    /// 以tar格式写入索引、配置和所有被引用的对象，大文件目录中的对象也放在 `store/` 下
+    pub fn export_store<W: Write>(&self, writer: W) -> anyhow::Result<usize> {
+        let mut builder = tar::Builder::new(writer);
+        // 使用内存中的索引，与加锁后读取的状态一致
+        let files = [
+            (CONFIG_NAME, serialize_index(&self.entries(), false)?),
+            (SETTINGS_NAME, to_vec_pretty(self.settings())?),
+        ];
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content.as_slice())?;
+        }
+        let mut objects: Vec<String> = self.objects().into_iter().collect();
+        objects.sort();
+        for hash in &objects {
+            let name = Path::new(STORE_DIRECTORY).join(shard_path(hash));
+            builder.append_path_with_name(self.object_path(hash), name)?;
+        }
+        builder.into_inner()?.flush()?;
+        info!("exported {} objects", objects.len());
+        Ok(objects.len())
+    }
+
+    /// 导入 `export-store` 生成的快照，合并到当前仓库，已有的条目不会被覆盖，扩展名为 `.zst` 时解压
+    pub fn import_store(&mut self, input: &Path) -> anyhow::Result<ImportStoreReport> {
+        let file = File::open(input)?;
+        let reader: Box<dyn Read> = if input.extension().is_some_and(|e| e == "zst") {
+            Box::new(zstd::Decoder::new(file)?)
+        } else {
+            Box::new(file)
+        };
+        let mut report = ImportStoreReport::default();
+        let mut nodes = Vec::new();
+        // 新条目引用的对象，快照中索引和配置在对象之前
+        let mut wanted = HashSet::new();
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            if path == Path::new(CONFIG_NAME) {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                for node in parse_index(&data)? {
+                    if self.entries().contains(&&node) {
+                        report.skipped.push(node.label());
+                        continue;
+                    }
+                    node.walk(&mut |_, n| {
+                        if let FILE(hash) = &n.meta {
+                            wanted.insert(hash.clone());
+                        }
+                    });
+                    nodes.push(node);
+                }
+            } else if path == Path::new(SETTINGS_NAME) {
+                if self.settings_path().exists() {
+                    continue;
+                }
+                let mut settings: Settings = serde_json::from_reader(entry)?;
+                // 大文件目录是导出机器上的路径，快照中的对象都在 `store/` 下
+                if settings.big_objects.take().is_some() {
+                    warn!("big_objects of the snapshot is not imported");
+                }
+                *self.settings_mut() = settings;
+                self.save_settings()?;
+                report.settings = true;
+            } else if path.starts_with(STORE_DIRECTORY) {
+                let parts: Vec<String> = path
+                    .components()
+                    .skip(1)
+                    .filter_map(|c| match c {
+                        Component::Normal(s) => Some(s.to_string_lossy().to_string()),
+                        _ => None,
+                    })
+                    .collect();
+                let hash = match parts.as_slice() {
+                    [dir, file] => object_name(dir, file),
+                    [file] => file.clone(),
+                    _ => bail!("unexpected path {:?} in the snapshot", path),
+                };
+                if wanted.contains(&hash) && !self.has_object(&hash) {
+                    ingest_expected(entry, self, &hash)?;
+                    report.objects += 1;
+                }
+            }
+        }
+        for node in nodes {
+            report.imported.push(node.label());
+            self.put_manifest(node)?;
+        }
+        info!(
+            "imported {} entries and {} objects from {:?}",
+            report.imported.len(),
+            report.objects,
+            input
+        );
+        Ok(report)
+    }
+}
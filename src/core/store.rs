@@ -1,22 +1,307 @@
-use crate::core::node::Meta::{DIRECTORY, FILE, SYMLINK};
-use crate::core::node::Node;
-use crate::{CONFIG_NAME, HBX_HOME_ENV, STORE_DIRECTORY};
-use anyhow::bail;
-use atomicwrites::{AllowOverwrite, AtomicFile};
+use crate::core::attrs::{
+    self, mtime_ns, same_owner, same_xattrs, set_mtime, set_owner, set_xattrs,
+};
+use crate::core::cli::parse_wait;
+use crate::core::export::Exporter;
+use crate::core::filter::{Filter, Ignores, PathFilter};
+use crate::core::hardlink::{inode_key, link_groups, InodeKey};
+use crate::core::hash::{matches, HashAlgorithm};
+use crate::core::hash_cache::HashCache;
+use crate::core::layout::shard_path;
+use crate::core::lock::{read_info, LockInfo, StoreLock};
+use crate::core::names::{has_prefix, validate_tag, validate_version, NameEscape};
+use crate::core::node::Meta::{DIRECTORY, FILE, INLINE, SYMLINK};
+use crate::core::node::{Node, Provenance, Summary};
+use crate::core::object_filter::Pipeline;
+use crate::core::porcelain::{self, Event};
+use crate::core::resolve;
+use crate::core::selfcheck::{first_file, Capabilities};
+use crate::core::session::Session;
+use crate::core::settings::Settings;
+use crate::core::util::{
+    copy_dir, ensure_space, expand_path, file_kind, file_mode, format_time, ingest, is_hash,
+    link_or_copy, now, reflink, remove_path, same_device, same_file, symlink,
+};
+use crate::core::webhook::{entry_hashes, EventKind, Notifier};
+use crate::{
+    CONFIG_NAME, HBX_HOME_ENV, HBX_STORE_ENV, LOCK_NAME, MAX_DEPTH, SETTINGS_NAME, STORE_DIRECTORY,
+    TMP_DIRECTORY,
+};
+use anyhow::{anyhow, bail};
+use clap::ValueEnum;
 use dirs::home_dir;
-use log::info;
+use log::{info, warn};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_string};
-use std::collections::HashSet;
-use std::fs::{create_dir_all, hard_link, read_to_string};
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{create_dir_all, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use std::{env, fs};
 
+/// add的可选参数
+#[derive(Debug, Default, Clone)]
+pub struct AddOptions {
+    pub filter: Filter,
+    /// 存储符号链接指向的内容，而不是链接本身
+    pub dereference: bool,
+    /// 版本号，为空时不区分版本
+    pub version: Option<String>,
+    /// 链接到仓库后重新计算哈希，确认对象内容与名称一致
+    pub rehash: bool,
+    /// 条目名称，可以是 `toolchains/gcc` 这样的多级名称，为空时使用文件名
+    pub name: Option<String>,
+    /// 无法硬链接时是否复制，为空时使用配置
+    pub copy_on_cross_device: Option<bool>,
+    /// 记录windows上文件的DACL
+    pub acl: bool,
+    /// 不记录这些命名空间的扩展属性，如 `security`
+    pub skip_xattrs: Vec<String>,
+    /// 条目的标签
+    pub tags: Vec<String>,
+    /// 记录到汇总信息中的来源
+    pub provenance: Option<Provenance>,
+}
+
+impl AddOptions {
+    /// 条目名称，没有指定时使用文件名
+    pub fn entry_name(&self, path: &Path) -> anyhow::Result<String> {
+        match &self.name {
+            Some(name) => Ok(name.clone()),
+            None => Ok(path
+                .file_name()
+                .ok_or(anyhow!("invalid path {:?}", path))?
+                .to_string_lossy()
+                .to_string()),
+        }
+    }
+}
+
+/// add过程中的状态
+#[derive(Debug, Default)]
+struct BuildContext {
+    /// 被过滤掉的路径
+    skipped: Vec<PathBuf>,
+    /// 当前路径上的各级目录，用于检测符号链接循环
+    ancestors: Vec<PathBuf>,
+    /// 计算哈希时文件的大小和修改时间
+    stamps: HashMap<PathBuf, FileStamp>,
+    /// add期间一直在变化的文件
+    unstable: Vec<PathBuf>,
+    /// 已计算的哈希，用于中断后继续add
+    session: Session,
+    /// 跨多次add的哈希缓存，key为绝对路径
+    cache: HashCache,
+    /// 对象过滤器，为空时对象是源文件的硬链接
+    codec: Pipeline,
+    /// 文件系统不支持硬链接到仓库，只能复制
+    copy: bool,
+    /// 有多个硬链接的文件，key为设备和inode
+    inodes: HashMap<InodeKey, Vec<PathBuf>>,
+    /// `--exclude` 和 `.hbxignore` 中的规则
+    ignores: Ignores,
+    /// add的路径
+    root: PathBuf,
+    /// `--include` 选择的文件
+    include: PathFilter,
+    /// 遍历源目录得到的每个目录下需要保存的子路径
+    children: HashMap<PathBuf, Vec<PathBuf>>,
+    /// 并行计算好的文件哈希，组装目录树时取出
+    hashed: HashMap<PathBuf, anyhow::Result<Hashed>>,
+}
+
+/// get时恢复文件的方式
+struct RestorePlan {
+    /// 恢复的条目，用于输出事件
+    entry: String,
+    codec: Pipeline,
+    /// 可以硬链接到目标目录，否则复制
+    link: bool,
+    /// 需要恢复的文件
+    paths: PathFilter,
+}
+
+/// 文件的大小和修改时间，用于判断文件在add期间是否被修改
+pub(crate) type FileStamp = (u64, Option<SystemTime>);
+
+pub(crate) fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let meta = path.metadata().ok()?;
+    Some((meta.len(), meta.modified().ok()))
+}
+
+/// 条目中文件的总大小和数量，大小按计算哈希时源文件的大小
+fn logical_size(
+    root: &Node,
+    path: &Path,
+    stamps: &HashMap<PathBuf, FileStamp>,
+) -> anyhow::Result<(u64, u64)> {
+    let (mut size, mut files) = (0, 0);
+    root.try_walk(&mut |rel, node| {
+        let bytes = match &node.meta {
+            FILE(_) => {
+                let src = if rel.as_os_str().is_empty() {
+                    path.to_path_buf()
+                } else {
+                    path.join(rel)
+                };
+                match stamps.get(&src) {
+                    Some((len, _)) => *len,
+                    None => src.metadata()?.len(),
+                }
+            }
+            INLINE(_) => node.inline_content()?.unwrap_or_default().len() as u64,
+            _ => return Ok(()),
+        };
+        size += bytes;
+        files += 1;
+        Ok(())
+    })?;
+    Ok((size, files))
+}
+
+/// 计算哈希的结果，文件的大小和修改时间用于add后期检查文件是否被修改
+#[derive(Debug)]
+enum Hashed {
+    Node(Box<Node>, Option<FileStamp>),
+    /// 多次重试后文件仍在变化
+    Unstable,
+}
+
+/// 文件在计算哈希期间被修改时的重试次数
+const STABLE_RETRIES: usize = 3;
+
+/// get的可选参数
+#[derive(Debug, Clone)]
+pub struct GetOptions {
+    /// 遇到已存在的路径时记录冲突并继续，已存在的目录会合并
+    pub collect_conflicts: bool,
+    /// 目标位置已存在时的处理方式
+    pub existing: Existing,
+    /// 非法文件名的转义方式，为空时使用配置中的方式
+    pub name_escape: Option<NameEscape>,
+    /// 先恢复到临时目录再重命名到目标位置，合并到已有目录时不生效
+    pub atomic: bool,
+    /// 无法硬链接时是否复制，为空时使用配置
+    pub copy_on_cross_device: Option<bool>,
+    /// 恢复文件的方式
+    pub link: LinkMode,
+    pub reflink: Reflink,
+    /// 还原add时记录的修改时间
+    pub preserve_times: bool,
+    /// 还原add时记录的属主，需要root权限
+    pub preserve_owner: bool,
+    /// 不还原这些命名空间的扩展属性
+    pub skip_xattrs: Vec<String>,
+    /// 只恢复匹配的文件，通配符可以匹配文件名或相对路径
+    pub include: Vec<String>,
+    /// 不恢复匹配的文件
+    pub exclude: Vec<String>,
+    /// 恢复前重新计算对象的哈希，有损坏的对象时不恢复
+    pub verify: bool,
+}
+
+/// 恢复到已存在的路径时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Existing {
+    /// 报错，指定 `collect_conflicts` 时记录冲突
+    #[default]
+    Fail,
+    /// 删除已有的内容后重新恢复，结果与条目完全一致
+    Overwrite,
+    /// 合并目录，保留已存在的文件
+    SkipExisting,
+    /// 合并目录，替换内容不同的文件，保留条目中没有的文件
+    Merge,
+}
+
+/// 恢复文件的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkMode {
+    /// 硬链接到仓库中的对象，不能硬链接时按配置复制
+    #[default]
+    Hard,
+    /// 复制对象，恢复的文件可以随意修改，不会影响仓库
+    Copy,
+    /// 创建指向仓库中对象的符号链接，可以跨文件系统，只适合只读使用
+    Symlink,
+}
+
+/// 恢复文件时是否使用reflink，reflink得到的文件与仓库共享存储，但修改互不影响
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Reflink {
+    /// 优先reflink，不支持时使用硬链接
+    Auto,
+    /// 只使用reflink，不支持时报错
+    Always,
+    /// 使用硬链接
+    #[default]
+    Never,
+}
+
+impl Default for GetOptions {
+    fn default() -> Self {
+        Self {
+            collect_conflicts: false,
+            existing: Existing::Fail,
+            name_escape: None,
+            atomic: true,
+            copy_on_cross_device: None,
+            link: LinkMode::Hard,
+            reflink: Reflink::Never,
+            preserve_times: false,
+            preserve_owner: false,
+            skip_xattrs: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            verify: false,
+        }
+    }
+}
+
+/// get的结果
+#[derive(Debug, Default, Serialize)]
+pub struct GetReport {
+    /// 跳过的冲突路径
+    pub conflicts: Vec<Conflict>,
+    /// 被转义的文件名，key为转义后相对于恢复根目录的路径，value为原始文件名
+    pub renamed: BTreeMap<PathBuf, String>,
+}
+
+/// 恢复时与目标目录中已有路径的冲突
+#[derive(Debug, Serialize)]
+pub struct Conflict {
+    pub path: PathBuf,
+    /// 仓库中的类型
+    pub expected: &'static str,
+    /// 目标路径上已有的类型
+    pub found: &'static str,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Store {
     path: PathBuf,
     data: HashSet<Node>,
+    #[serde(skip)]
+    settings: Settings,
+    #[serde(skip)]
+    notifier: Notifier,
+    /// 每个对象被多少个文件引用，add和delete时维护
+    #[serde(skip)]
+    refs: HashMap<String, usize>,
+    /// 引用数降为0、等待gc的对象
+    #[serde(skip)]
+    garbage: BTreeSet<String>,
+    /// 条目在用到时才从索引中读取，`data` 中只有读取过的条目
+    #[serde(skip)]
+    lazy: bool,
+    /// 按需加载时已经读取过的名称
+    #[serde(skip)]
+    fetched: HashSet<String>,
+    /// 按需加载时删除、还没有写入索引的条目
+    #[serde(skip)]
+    removed: Vec<Node>,
 }
 
 impl Store {
@@ -25,15 +310,30 @@ impl Store {
         let s = Self {
             path,
             data: HashSet::new(),
+            settings: Settings::default(),
+            notifier: Notifier::default(),
+            refs: HashMap::new(),
+            garbage: BTreeSet::new(),
+            lazy: false,
+            fetched: HashSet::new(),
+            removed: Vec::new(),
         };
         Ok(s)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> anyhow::Result<Self> {
+        // `HBX_STORE` 比 `HBX_HOME` 更明确，配置中的默认仓库优先级最低
+        if let Ok(name) = env::var(HBX_STORE_ENV) {
+            return Store::named(&name);
+        }
         let p = env::var(HBX_HOME_ENV);
         let hbx_home_path: Option<PathBuf> = match p {
-            Ok(p) => Some(p.into()),
-            Err(_) => home_dir().map(|f| f.join(PathBuf::from(".hbx"))),
+            Ok(p) => Some(expand_path(&p)?),
+            Err(_) => match Store::active_name()? {
+                Some(name) => return Store::named(&name),
+                None => home_dir().map(|f| f.join(PathBuf::from(".hbx"))),
+            },
         };
 
         let path = hbx_home_path.unwrap_or(PathBuf::from("~/.hbx"));
@@ -41,6 +341,22 @@ impl Store {
     }
 
     pub fn get(&self, name: &str, dst: Option<PathBuf>) -> anyhow::Result<()> {
+        let root = self.entry(name)?;
+        self.get_node(root, dst, &GetOptions::default())?;
+        self.touch(&[root.label()]);
+        Ok(())
+    }
+
+    /// 恢复指定的顶层节点
+    pub fn get_node(
+        &self,
+        root: &Node,
+        dst: Option<PathBuf>,
+        opts: &GetOptions,
+    ) -> anyhow::Result<GetReport> {
+        if root.sealed.is_some() {
+            bail!("{} is sealed, unseal it first", root.label());
+        }
         let dst = dst.unwrap_or(PathBuf::from("./"));
         if !dst.exists() {
             bail!("{:?} not exits! exit", dst);
@@ -48,72 +364,438 @@ impl Store {
         if dst.is_file() {
             bail!("{:?} is a file, please input a directory path", dst)
         }
-        let root = match self.data.get(&Node::sample(name)) {
-            None => {
-                bail!("{} not exists, exit!", name);
-            }
-            Some(n) => n,
+        let mut report = GetReport::default();
+        let escape = opts.name_escape.unwrap_or(self.settings.name_escape);
+        let leaf = root.leaf_name();
+        let name = escape.escape(leaf).unwrap_or(leaf.to_string());
+        let base = dst.join(&name);
+        let exists = base.symlink_metadata().is_ok();
+        if exists && !opts.collect_conflicts && opts.existing == Existing::Fail {
+            bail!("{:?} already exists", base);
+        }
+        if opts.link == LinkMode::Symlink && !root.filters.is_empty() {
+            bail!(
+                "{} is stored encoded by filters {:?}, it cannot be restored as symlinks",
+                root.label(),
+                root.filters
+            );
+        }
+        let caps = self.preflight_get(root, &dst, &base, opts)?;
+        let plan = RestorePlan {
+            entry: root.label(),
+            codec: self.pipeline(&root.filters)?,
+            link: caps.hard_link || !caps.same_device,
+            paths: PathFilter::new(&opts.include, &opts.exclude)?,
         };
-        self.recover(root, &dst.join(&root.name))?;
-        Ok(())
+        if opts.verify {
+            self.verify_objects(root, &plan)?;
+        }
+        let overwrite = exists && opts.existing == Existing::Overwrite;
+        if opts.atomic && (!exists || overwrite) {
+            // 临时目录与目标在同一目录下，保证可以直接重命名
+            let staging = tempfile::Builder::new()
+                .prefix(".hbx-tmp-")
+                .tempdir_in(&dst)?;
+            let tmp = staging.path().join(&name);
+            self.recover(root, &tmp, &tmp, opts, &plan, &mut report)?;
+            self.relink(root, &tmp, opts, &report)?;
+            if overwrite {
+                info!("remove {:?}", base);
+                remove_path(&base)?;
+            }
+            info!("rename {:?} -> {:?}", tmp, base);
+            fs::rename(&tmp, &base)?;
+        } else {
+            if overwrite {
+                info!("remove {:?}", base);
+                remove_path(&base)?;
+            }
+            self.recover(root, &base, &base, opts, &plan, &mut report)?;
+            self.relink(root, &base, opts, &report)?;
+        }
+        // 保存转义前后的对应关系，便于还原
+        if !report.renamed.is_empty() || name != leaf {
+            let mapping = dst.join(format!("{}.hbx-names.json", name));
+            info!(
+                "{} names escaped, mapping saved to {:?}",
+                report.renamed.len(),
+                mapping
+            );
+            let mut renamed = report.renamed.clone();
+            if name != leaf {
+                renamed.insert(PathBuf::new(), leaf.to_string());
+            }
+            fs::write(mapping, serde_json::to_string_pretty(&renamed)?)?;
+        }
+        Ok(report)
     }
 
-    // 恢复数据
-    #[cfg(unix)]
-    fn recover(&self, node: &Node, dst: &Path) -> anyhow::Result<()> {
-        match &node.meta {
-            FILE(value) => {
-                let src = self.store_dir().join(Path::new(&value));
-                info!("l {:?} -> {:?}", &src, &dst);
-                hard_link(src, dst)?;
+    /// 并行恢复多个顶层节点，结果与输入顺序一致
+    pub fn get_nodes(
+        &self,
+        roots: &[&Node],
+        dst: Option<PathBuf>,
+        opts: &GetOptions,
+    ) -> anyhow::Result<Vec<anyhow::Result<GetReport>>> {
+        let mut leaves = HashSet::new();
+        for root in roots {
+            if !leaves.insert(root.leaf_name()) {
+                bail!(
+                    "more than one entry would be restored to {:?}",
+                    root.leaf_name()
+                );
             }
-            SYMLINK(path) => {
-                std::os::unix::fs::symlink(path, dst)?;
+        }
+        let ans = std::thread::scope(|s| {
+            let handles: Vec<_> = roots
+                .iter()
+                .map(|root| {
+                    let dst = dst.clone();
+                    s.spawn(move || self.get_node(root, dst, opts))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .zip(roots)
+                .enumerate()
+                .map(|(i, (h, root))| {
+                    let res = h
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow!("restore thread panicked")));
+                    porcelain::emit(&Event::Progress {
+                        done: i + 1,
+                        total: roots.len(),
+                        item: &root.label(),
+                    });
+                    res
+                })
+                .collect::<Vec<_>>()
+        });
+        let restored: Vec<String> = roots
+            .iter()
+            .zip(&ans)
+            .filter(|(_, res)| res.is_ok())
+            .map(|(root, _)| root.label())
+            .collect();
+        self.touch(&restored);
+        Ok(ans)
+    }
+
+    /// 按名称查找顶层节点，有多个版本时返回最新的版本
+    pub fn entry(&self, name: &str) -> anyhow::Result<&Node> {
+        match self
+            .versions(name)
+            .pop()
+            .or_else(|| self.alias_target(name))
+        {
+            None => bail!("{} not exists, exit!", name),
+            Some(n) => Ok(n),
+        }
+    }
+
+    /// 按 `name@latest`、`name@^12` 这样的版本要求查找条目，
+    /// 也会匹配 `name-12.2.0`、`name/12.2.0` 这样命名的条目，别名直接指向对应的条目
+    pub fn resolve(&self, spec: &str) -> anyhow::Result<&Node> {
+        if let Some(node) = self.alias_target(spec) {
+            return Ok(node);
+        }
+        resolve::resolve(spec, &self.data)
+    }
+
+    /// 解析 `条目/条目内路径`，整个名称不是条目时从最长的前缀开始依次尝试，
+    /// 返回只包含这个子树的顶层节点
+    pub fn resolve_path(&self, spec: &str) -> anyhow::Result<Cow<'_, Node>> {
+        let err = match self.resolve(spec) {
+            Ok(root) => return Ok(Cow::Borrowed(root)),
+            Err(e) => e,
+        };
+        for (i, _) in spec.rmatch_indices('/') {
+            let Ok(root) = self.resolve(&spec[..i]) else {
+                continue;
+            };
+            if root.sealed.is_some() {
+                bail!("{} is sealed, unseal it first", root.label());
             }
-            DIRECTORY(vec) => {
-                info!("d {:?}", dst);
-                fs::create_dir(&dst)?;
-                for x in vec.borrow().iter() {
-                    self.recover(x, &dst.join(Path::new(&x.name)))?;
+            let rel = Path::new(&spec[i + 1..]);
+            return root.subtree(rel).map(Cow::Owned).ok_or(anyhow!(
+                "{:?} not exists in {}",
+                rel,
+                root.label()
+            ));
+        }
+        Err(err)
+    }
+
+    /// 指定版本的条目
+    pub fn entry_version(&self, name: &str, version: Option<&str>) -> anyhow::Result<&Node> {
+        let mut key = Node::sample(name);
+        key.version = version.map(String::from);
+        self.data
+            .get(&key)
+            .ok_or(anyhow!("{} not exists", key.label()))
+    }
+
+    /// add时间最接近 `t` 的版本
+    pub fn version_at(&self, name: &str, t: u64) -> anyhow::Result<&Node> {
+        match self
+            .versions(name)
+            .into_iter()
+            .min_by_key(|n| n.added().abs_diff(t))
+        {
+            None => bail!("{} not exists, exit!", name),
+            Some(n) => Ok(n),
+        }
+    }
+
+    /// 同名的所有版本，按add时间从旧到新排序
+    pub fn versions(&self, name: &str) -> Vec<&Node> {
+        let mut ans: Vec<&Node> = self.data.iter().filter(|n| n.name == name).collect();
+        ans.sort_by(|a, b| (a.added(), &a.version).cmp(&(b.added(), &b.version)));
+        ans
+    }
+
+    /// 将条目导出为归档，数据直接从仓库对象中读取
+    pub fn export(&self, name: &str, exporter: &mut dyn Exporter) -> anyhow::Result<()> {
+        let root = self.entry(name)?;
+        if root.sealed.is_some() {
+            bail!("{} is sealed, unseal it first", root.label());
+        }
+        let base = Path::new(root.leaf_name());
+        let codec = self.pipeline(&root.filters)?;
+        root.try_walk(&mut |path, node| {
+            let path = base.join(path);
+            match &node.meta {
+                FILE(value) if !codec.is_empty() => {
+                    // 解码后才知道大小
+                    let src = self.object_path(value);
+                    let mut tmp = tempfile::tempfile()?;
+                    io::copy(&mut codec.decode(Box::new(File::open(&src)?))?, &mut tmp)?;
+                    tmp.seek(SeekFrom::Start(0))?;
+                    let size = tmp.metadata()?.len();
+                    let mode = node.mode().unwrap_or(file_mode(&src.metadata()?));
+                    info!("e {:?}", path);
+                    exporter.file(&path, mode, size, &mut tmp)
+                }
+                FILE(value) => {
+                    let src = self.object_path(value);
+                    let meta = src.metadata()?;
+                    info!("e {:?}", path);
+                    let mode = node.mode().unwrap_or(file_mode(&meta));
+                    exporter.file(&path, mode, meta.len(), &mut File::open(src)?)
                 }
+                INLINE(_) => {
+                    let data = node.inline_content()?.unwrap_or_default();
+                    let mode = node.mode().unwrap_or(0o644);
+                    exporter.file(&path, mode, data.len() as u64, &mut data.as_slice())
+                }
+                SYMLINK(target) => exporter.symlink(&path, target),
+                DIRECTORY(_) => exporter.dir(&path, 0o755),
             }
-        }
-        Ok(())
+        })?;
+        exporter.finish()
     }
 
-    #[cfg(windows)]
-    fn recover_windows(
+    // 恢复数据
+    fn recover(
         &self,
         node: &Node,
         dst: &Path,
-        tmp: &HashMap<PathBuf, PathBuf>,
+        base: &Path,
+        opts: &GetOptions,
+        plan: &RestorePlan,
+        report: &mut GetReport,
     ) -> anyhow::Result<()> {
-        // todo 适配windows
+        if let Ok(meta) = dst.symlink_metadata() {
+            let dir = meta.is_dir() && matches!(node.meta, DIRECTORY(_));
+            match opts.existing {
+                // 已存在的目录直接合并
+                Existing::Fail if dir && opts.collect_conflicts => {}
+                Existing::Overwrite | Existing::SkipExisting | Existing::Merge if dir => {}
+                Existing::SkipExisting => {
+                    info!("keep {:?}", dst);
+                    return Ok(());
+                }
+                Existing::Merge | Existing::Overwrite => {
+                    if self.restored(node, dst, opts) {
+                        return Ok(());
+                    }
+                    info!("replace {:?}", dst);
+                    remove_path(dst)?;
+                }
+                Existing::Fail if !opts.collect_conflicts => bail!("{:?} already exists", dst),
+                Existing::Fail => {
+                    info!("conflict {:?}", dst);
+                    report.conflicts.push(Conflict {
+                        path: dst.to_path_buf(),
+                        expected: node.meta.kind(),
+                        found: file_kind(&meta),
+                    });
+                    return Ok(());
+                }
+            }
+        }
         match &node.meta {
+            FILE(value) if !plan.codec.is_empty() => {
+                let src = self.object_path(value);
+                info!("c {:?} -> {:?}", &src, &dst);
+                let mut reader = plan.codec.decode(Box::new(File::open(&src)?))?;
+                io::copy(&mut reader, &mut File::create(dst)?)?;
+                fs::set_permissions(dst, src.metadata()?.permissions())?;
+            }
+            FILE(value) if opts.link == LinkMode::Symlink => {
+                // 使用绝对路径，恢复的位置移动后链接仍然有效
+                let src = std::path::absolute(self.object_path(value))?;
+                info!("s {:?} -> {:?}", dst, src);
+                symlink(&src, dst)?;
+            }
             FILE(value) => {
-                let src = self.store_dir().join(Path::new(&value));
-                info!("l {:?} -> {:?}", &src, &dst);
-                hard_link(src, dst)?;
+                let src = self.object_path(value);
+                let copy = self.copy_on_cross_device(opts.copy_on_cross_device);
+                // 相同内容的文件共用对象，权限或时间不同时修改硬链接会影响其他文件，
+                // windows的属性和DACL属于文件本身，还原时也会修改对象
+                let meta = src.metadata()?;
+                let shareable = node.mode().is_none_or(|m| m == file_mode(&meta))
+                    && node
+                        .attrs
+                        .as_ref()
+                        .is_none_or(|a| a.windows.is_none() && a.acl.is_none())
+                    && (!opts.preserve_times
+                        || node.mtime().is_none_or(|t| Some(t) == mtime_ns(&meta)))
+                    && (!opts.preserve_owner
+                        || node.attrs.as_ref().is_none_or(|a| same_owner(&meta, a)))
+                    && match &node.attrs {
+                        Some(a) => same_xattrs(&src, a, &opts.skip_xattrs)?,
+                        None => true,
+                    };
+                let link = || -> anyhow::Result<()> {
+                    if plan.link && shareable && opts.link == LinkMode::Hard {
+                        info!("l {:?} -> {:?}", &src, &dst);
+                        link_or_copy(&src, dst, copy)
+                    } else {
+                        info!("c {:?} -> {:?}", &src, &dst);
+                        fs::copy(&src, dst)?;
+                        Ok(())
+                    }
+                };
+                match opts.reflink {
+                    Reflink::Never => link()?,
+                    mode => match reflink(&src, dst) {
+                        Ok(_) => info!("r {:?} -> {:?}", &src, &dst),
+                        Err(e) if mode == Reflink::Auto => {
+                            info!("reflink {:?} failed ({}), link instead", &dst, e);
+                            link()?;
+                        }
+                        Err(e) => bail!("failed to reflink {:?} -> {:?}: {}", src, dst, e),
+                    },
+                }
             }
             SYMLINK(path) => {
-                info!("l {:?} -> {:?}", dst, link);
-                if link.is_dir() {
-                    std::os::windows::fs::symlink_dir(dst, link)?;
-                } else {
-                    std::os::windows::fs::symlink_file(dst, link)?;
-                }
+                info!("s {:?} -> {:?}", dst, path);
+                symlink(path, dst)?;
+            }
+            INLINE(_) => {
+                info!("w {:?}", dst);
+                fs::write(dst, node.inline_content()?.unwrap_or_default())?;
             }
             DIRECTORY(vec) => {
                 info!("d {:?}", dst);
-                fs::create_dir(&dst)?;
-                for x in vec.borrow().iter() {
-                    self.recover(x, &dst.join(Path::new(&x.name)))?;
+                if !dst.is_dir() {
+                    fs::create_dir(dst)?;
+                }
+                let escape = opts.name_escape.unwrap_or(self.settings.name_escape);
+                for x in vec.iter() {
+                    let rel = dst.strip_prefix(base)?.join(&x.name);
+                    if !plan.paths.selects(&rel, x) {
+                        info!("skip {:?}: not selected", rel);
+                        continue;
+                    }
+                    let name = match escape.escape(&x.name) {
+                        None => x.name.clone(),
+                        Some(name) => {
+                            let rel = dst.strip_prefix(base)?;
+                            info!("escape {:?} -> {:?}", x.name, name);
+                            report.renamed.insert(rel.join(&name), x.name.clone());
+                            name
+                        }
+                    };
+                    self.recover(x, &dst.join(name), base, opts, plan, report)?;
                 }
             }
         }
+        if !matches!(node.meta, DIRECTORY(_)) {
+            porcelain::emit(&Event::File {
+                action: "restore",
+                entry: &plan.entry,
+                path: dst.strip_prefix(base).unwrap_or(dst),
+                hash: match &node.meta {
+                    FILE(hash) => Some(hash),
+                    _ => None,
+                },
+            });
+        }
+        // 目录的属性在恢复子节点之后设置，避免只读目录无法写入
+        // 指向对象的符号链接不能修改属性，否则会修改仓库中的对象
+        let linked = matches!(node.meta, FILE(_)) && opts.link == LinkMode::Symlink;
+        if let (Some(attrs), false) = (&node.attrs, matches!(node.meta, SYMLINK(_)) || linked) {
+            if opts.preserve_owner {
+                set_owner(dst, attrs)?;
+            }
+            // 修改属主会清除 `security.capability`
+            set_xattrs(dst, attrs, &opts.skip_xattrs)?;
+            attrs::apply(dst, attrs)?;
+            if let (true, Some(t)) = (opts.preserve_times, attrs.mtime) {
+                set_mtime(dst, t)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 重新计算要恢复的对象的哈希，有损坏或缺失的对象时报错并列出这些对象
+    fn verify_objects(&self, root: &Node, plan: &RestorePlan) -> anyhow::Result<()> {
+        let mut hashes = BTreeSet::new();
+        root.walk(&mut |rel, node| {
+            let selected = rel.as_os_str().is_empty() || plan.paths.matches(rel);
+            if let (FILE(hash), true) = (&node.meta, selected) {
+                hashes.insert(hash.clone());
+            }
+        });
+        let total = hashes.len();
+        let bad: Vec<String> = hashes
+            .into_par_iter()
+            .filter(|h| !matches(&self.object_path(h), h).unwrap_or(false))
+            .collect();
+        if !bad.is_empty() {
+            bail!(
+                "{} of {} objects of {} are corrupt or missing:\n{}",
+                bad.len(),
+                total,
+                root.label(),
+                bad.join("\n")
+            );
+        }
+        info!("verified {} objects of {}", total, root.label());
         Ok(())
     }
 
+    /// 已存在的路径是否就是之前从仓库恢复的文件，即硬链接或指向同一个对象的符号链接
+    fn restored(&self, node: &Node, dst: &Path, opts: &GetOptions) -> bool {
+        let FILE(hash) = &node.meta else {
+            return false;
+        };
+        let object = self.object_path(hash);
+        match opts.link {
+            LinkMode::Symlink => {
+                std::path::absolute(&object).is_ok_and(|o| dst.read_link().is_ok_and(|t| t == o))
+            }
+            LinkMode::Hard => !dst.is_symlink() && same_file(&object, dst),
+            LinkMode::Copy => false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     pub fn config_path(&self) -> PathBuf {
         self.path.join(Path::new(CONFIG_NAME))
     }
@@ -122,38 +804,716 @@ impl Store {
         self.path.join(Path::new(STORE_DIRECTORY))
     }
 
+    /// 临时文件目录，与对象目录在同一磁盘上，写好的文件可以直接重命名为对象
+    pub fn tmp_dir(&self) -> anyhow::Result<PathBuf> {
+        let dir = self.path.join(TMP_DIRECTORY);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// 对象的路径，依次在仓库和大文件目录中查找，也会查找迁移前平铺的位置，
+    /// 都不存在时为仓库中的路径
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        let shard = shard_path(hash);
+        let dirs = self.object_dirs();
+        dirs.iter()
+            .map(|d| d.join(&shard))
+            .chain(dirs.iter().map(|d| d.join(hash)))
+            .find(|p| p.exists())
+            .unwrap_or_else(|| self.store_dir().join(shard))
+    }
+
+    /// 新对象的路径，会创建所在的分目录
+    pub(crate) fn new_object_path(&self, hash: &str, size: u64) -> anyhow::Result<PathBuf> {
+        let path = self.object_dir(size)?.join(shard_path(hash));
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        Ok(path)
+    }
+
+    /// 所有存放对象的目录
+    pub fn object_dirs(&self) -> Vec<PathBuf> {
+        let mut ans = vec![self.store_dir()];
+        ans.extend(self.settings.big_objects.iter().map(|b| b.path.clone()));
+        ans
+    }
+
+    /// add的源路径不能是仓库或对象目录本身，也不能包含它们，否则会把仓库链接到自身中
+    pub(crate) fn check_source(&self, path: &Path) -> anyhow::Result<()> {
+        let src = path.canonicalize()?;
+        let mut dirs = vec![self.path.clone()];
+        dirs.extend(self.object_dirs());
+        for dir in dirs {
+            let dir = dir.canonicalize().unwrap_or(dir);
+            if dir.starts_with(&src) {
+                bail!(
+                    "{:?} contains the store {:?}, refusing to add the store into itself",
+                    path,
+                    dir
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// 新对象的存放目录，大文件存放到单独配置的目录
+    pub(crate) fn object_dir(&self, size: u64) -> anyhow::Result<PathBuf> {
+        match &self.settings.big_objects {
+            Some(big) if size >= big.threshold => {
+                create_dir_all(&big.path)?;
+                Ok(big.path.clone())
+            }
+            _ => Ok(self.store_dir()),
+        }
+    }
+
+    /// 对象是否已经在仓库中
+    pub fn has_object(&self, hash: &str) -> bool {
+        is_hash(hash) && self.object_path(hash).is_file()
+    }
+
+    /// 批量查询对象是否在仓库中，结果与输入顺序一致
+    pub fn has_objects<S: AsRef<str>>(&self, hashes: &[S]) -> Vec<bool> {
+        hashes.iter().map(|h| self.has_object(h.as_ref())).collect()
+    }
+
+    /// 写入外部产生的内容，返回对象名，相同内容只保存一份
+    pub fn put_object<R: Read>(&self, reader: R) -> anyhow::Result<String> {
+        ingest(reader, self)
+    }
+
+    /// 写入外部构造的节点树，引用的对象需要先通过 `put_object` 写入，与add一样检查容量
+    pub fn put_manifest(&mut self, root: Node) -> anyhow::Result<()> {
+        let root = self.check_manifest(root)?;
+        self.insert_with_quota(root)?;
+        Ok(())
+    }
+
+    /// 校验外部构造的节点树，补上树哈希和add时间
+    pub(crate) fn check_manifest(&self, mut root: Node) -> anyhow::Result<Node> {
+        self.settings.name_rules.validate(&root.name)?;
+        root.try_walk(&mut |path, node| {
+            if path.components().count() > MAX_DEPTH {
+                bail!(
+                    "directory nesting exceeds {} levels at {:?}",
+                    MAX_DEPTH,
+                    path
+                );
+            }
+            // 根节点的名称可以是多级的，已经单独校验
+            let root = path.as_os_str().is_empty();
+            if !root
+                && (node.name.is_empty()
+                    || node.name == "."
+                    || node.name == ".."
+                    || node.name.contains(['/', '\\']))
+            {
+                bail!("invalid name {:?} at {:?}", node.name, path);
+            }
+            match &node.meta {
+                FILE(hash) if !self.has_object(hash) => {
+                    bail!("object {} of {:?} not exists", hash, path)
+                }
+                _ => Ok(()),
+            }
+        })?;
+        if root.tree.is_none() {
+            root.compute_tree();
+        }
+        let summary = root.summary.get_or_insert_with(Summary::default);
+        if summary.added == 0 {
+            summary.added = now();
+        }
+        Ok(root)
+    }
+
+    /// 替换同名同版本的条目，新条目不合法时保留原来的条目
+    pub(crate) fn replace_manifest(&mut self, root: Node) -> anyhow::Result<()> {
+        let old = self.take_entry(&root);
+        let res = self
+            .check_manifest(root)
+            .and_then(|root| self.insert_entry(root));
+        if let (Err(_), Some(old)) = (&res, old) {
+            self.insert_entry(old)?;
+        }
+        res
+    }
+
+    pub fn settings_path(&self) -> PathBuf {
+        self.path.join(Path::new(SETTINGS_NAME))
+    }
+
+    pub fn lock_path(&self) -> PathBuf {
+        self.path.join(Path::new(LOCK_NAME))
+    }
+
+    /// 获取仓库锁，修改仓库的操作需要先加锁
+    pub fn lock(&self, operation: &str, wait: Option<Duration>) -> anyhow::Result<StoreLock> {
+        StoreLock::acquire(&self.lock_path(), operation, wait)
+    }
+
+    /// 加锁时的等待时间，命令行参数优先于配置，`no_wait` 时不等待
+    pub fn lock_wait(
+        &self,
+        wait: Option<Duration>,
+        no_wait: bool,
+    ) -> anyhow::Result<Option<Duration>> {
+        if no_wait {
+            return Ok(None);
+        }
+        match (wait, &self.settings.lock_wait) {
+            (Some(w), _) => Ok(Some(w)),
+            (None, Some(s)) => {
+                Ok(Some(parse_wait(s).map_err(|e| {
+                    anyhow!("invalid lock_wait {:?}: {}", s, e)
+                })?))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// 清理崩溃后遗留的锁
+    pub fn unlock(&self, force: bool) -> anyhow::Result<Option<LockInfo>> {
+        StoreLock::unlock(&self.lock_path(), force)
+    }
+
+    /// 新对象使用的哈希算法，配置中没有指定时为blake3，已有对象的算法由对象名推断
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.settings.hash.unwrap_or_default()
+    }
+
+    /// 无法硬链接时是否复制，命令行参数优先于配置
+    pub fn copy_on_cross_device(&self, opt: Option<bool>) -> bool {
+        opt.or(self.settings.copy_on_cross_device).unwrap_or(true)
+    }
+
+    pub(crate) fn notifier(&self) -> &Notifier {
+        &self.notifier
+    }
+
+    pub(crate) fn pending_garbage(&self) -> &BTreeSet<String> {
+        &self.garbage
+    }
+
+    pub(crate) fn pending_garbage_mut(&mut self) -> &mut BTreeSet<String> {
+        &mut self.garbage
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    pub fn save_settings(&self) -> anyhow::Result<()> {
+        self.settings.save(&self.settings_path())?;
+        info!("save settings {}", self.settings_path().display());
+        Ok(())
+    }
+
     /// 加载数据
     pub fn load(&mut self) -> anyhow::Result<()> {
-        let config_path = self.config_path();
-        if config_path.exists() {
-            let content = read_to_string(&config_path)?;
-            let tmp: HashSet<Node> = from_str(&content)?;
-            self.data.extend(tmp);
+        self.settings = Settings::load(&self.settings_path())?;
+        self.lazy = true;
+        self.load_all()
+    }
+
+    /// 只加载配置，索引支持按需读取时条目在用到时才读取，否则和 `load` 相同
+    pub fn load_lazy(&mut self) -> anyhow::Result<()> {
+        self.settings = Settings::load(&self.settings_path())?;
+        self.lazy = true;
+        if !self.index_backend()?.lazy() {
+            self.load_all()?;
+        }
+        Ok(())
+    }
+
+    /// 读取索引中其余的条目，之后和完整加载时相同
+    pub fn load_all(&mut self) -> anyhow::Result<()> {
+        if !self.lazy {
+            return Ok(());
         }
+        // 索引无法加载时repair仍然在完整加载的状态下执行
+        self.lazy = false;
+        self.fetched.clear();
+        let removed = std::mem::take(&mut self.removed);
+        let fetched: Vec<Node> = self.data.drain().collect();
+        // 旧版本的索引没有树哈希
+        for mut n in self.index_backend()?.load()? {
+            if n.tree.is_none() {
+                n.compute_tree();
+            }
+            // 重复的条目只保留一份，内容不同时报错
+            if self.data.get(&n).is_some_and(|old| old.same_content(&n)) {
+                warn!("{} appears more than once in the index, ignored", n.label());
+                continue;
+            }
+            self.insert_entry(n)?;
+        }
+        // 已经读取的条目可能在内存中被修改过
+        for n in fetched {
+            self.take_entry(&n);
+            self.insert_entry(n)?;
+        }
+        for n in removed {
+            self.take_entry(&n);
+        }
+        Ok(())
+    }
+
+    /// 按需加载时读取同名的所有版本，没有这个名称时可能是别名，读取整个索引
+    pub fn fetch(&mut self, name: &str) -> anyhow::Result<()> {
+        if !self.lazy || self.fetched.contains(name) {
+            return Ok(());
+        }
+        let found = self.index_backend()?.find(name)?;
+        if found.is_empty() {
+            return self.load_all();
+        }
+        for mut n in found {
+            if self.removed.contains(&n) {
+                continue;
+            }
+            if n.tree.is_none() {
+                n.compute_tree();
+            }
+            self.data.insert(n);
+        }
+        self.fetched.insert(name.to_string());
         Ok(())
     }
 
+    /// 丢弃内存中的数据重新加载，保持原来的加载方式
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        self.data.clear();
+        self.refs.clear();
+        self.fetched.clear();
+        self.removed.clear();
+        match self.lazy {
+            true => self.load_lazy(),
+            false => self.load(),
+        }
+    }
+
     pub fn save(&self) -> anyhow::Result<()> {
-        let s = to_string(&self.data)?;
-        AtomicFile::new(self.config_path(), AllowOverwrite).write(|f| f.write_all(s.as_bytes()))?;
-        info!("save path is {}", self.config_path().display());
+        if self.lazy {
+            return self.save_removed();
+        }
+        // 按名称排序，保证同样的数据序列化结果完全一致
+        self.index_backend()?.save(&self.entries())?;
+        self.save_manifests()?;
+        self.save_garbage()?;
         Ok(())
     }
 
     pub fn add(&mut self, path: &Path) -> anyhow::Result<()> {
-        if path.exists() {
-            if !self.data.contains(&Node::try_from(path)?) {
-                let root = self.build(path)?;
-                self.links(&root, path)?;
-                self.data.insert(root);
+        self.add_with(path, &AddOptions::default())
+    }
+
+    /// 已保存的条目不会被修改，再次add同名条目时创建新版本，内容与最新版本相同时不做任何操作
+    pub fn add_with(&mut self, path: &Path, opts: &AddOptions) -> anyhow::Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        self.check_source(path)?;
+        let name = opts.entry_name(path)?;
+        let (version, tags) = self.add_target(&name, opts)?;
+        let mut ctx = BuildContext {
+            session: Session::open(self, path)?,
+            cache: HashCache::open(self)?,
+            codec: self.pipeline(&self.filters_for(&name)?)?,
+            ..Default::default()
+        };
+        let built = self.build(path, opts, &mut ctx);
+        // 失败时保留进度，再次add时不必重新计算哈希
+        ctx.session.save()?;
+        ctx.cache.save()?;
+        let mut root = built?.ok_or(anyhow!("{:?} keeps changing, add it again later", path))?;
+        self.preflight_add(&root, path, opts, &mut ctx)?;
+        // 只检查容量，需要淘汰的条目在新条目加入之后才删除
+        self.quota_victims(&root, self.incoming_size(&root, path)?)?;
+        if !self.links(&mut root, path, opts, &mut ctx)? {
+            bail!("{:?} changed during add, add it again later", path);
+        }
+        std::mem::take(&mut ctx.session).finish()?;
+        let relative = |paths: Vec<PathBuf>| {
+            let mut ans: Vec<PathBuf> = paths
+                .into_iter()
+                .map(|p| p.strip_prefix(path).map(Path::to_path_buf).unwrap_or(p))
+                .collect();
+            ans.sort();
+            ans
+        };
+        let unstable = relative(ctx.unstable);
+        if !unstable.is_empty() {
+            warn!(
+                "{} files changed during add and were not stored: {:?}",
+                unstable.len(),
+                unstable
+            );
+        }
+        root.name = name;
+        root.version = version;
+        root.filters = ctx.codec.names();
+        root.tags = tags;
+        let (size, files) = logical_size(&root, path, &ctx.stamps)?;
+        let summary = Summary {
+            added: now(),
+            size: Some(size),
+            files: Some(files),
+            skipped: relative(ctx.skipped),
+            unstable,
+            hardlinks: link_groups(ctx.inodes, path),
+            ..Default::default()
+        };
+        self.commit_add(root, opts, summary)
+    }
+
+    /// 校验条目名称和标签，确定新条目的版本，返回版本和排序去重后的标签
+    pub(crate) fn add_target(
+        &self,
+        name: &str,
+        opts: &AddOptions,
+    ) -> anyhow::Result<(Option<String>, Vec<String>)> {
+        self.settings.name_rules.validate(name)?;
+        let mut tags = opts.tags.clone();
+        for tag in &tags {
+            validate_tag(tag)?;
+        }
+        tags.sort();
+        tags.dedup();
+        let version = match &opts.version {
+            Some(v) if self.entry_version(name, Some(v)).is_ok() => {
+                bail!(
+                    "{}@{} already exists, stored entries are immutable",
+                    name,
+                    v
+                )
+            }
+            Some(v) => {
+                validate_version(v)?;
+                Some(v.clone())
+            }
+            None if self.versions(name).is_empty() => None,
+            None => Some(self.next_version(name)),
+        };
+        Ok((version, tags))
+    }
+
+    /// 保存add的结果，没有指定版本且内容和标签与最新版本相同时不做任何操作
+    pub(crate) fn commit_add(
+        &mut self,
+        mut root: Node,
+        opts: &AddOptions,
+        summary: Summary,
+    ) -> anyhow::Result<()> {
+        root.compute_tree();
+        if let Some(other) = self
+            .find_same_content(&root)
+            .into_iter()
+            .find(|n| n.name != root.name)
+        {
+            info!("{} has the same content as {}", root.name, other.label());
+        }
+        if opts.version.is_none() {
+            if let Ok(latest) = self.entry(&root.name) {
+                if latest.same_content(&root) && latest.tags == root.tags {
+                    info!("{} is unchanged", latest.label());
+                    return Ok(());
+                }
             }
         }
+        if porcelain::enabled() {
+            let label = root.label();
+            root.walk(&mut |rel, node| {
+                if let FILE(hash) = &node.meta {
+                    porcelain::emit(&Event::File {
+                        action: "add",
+                        entry: &label,
+                        path: rel,
+                        hash: Some(hash),
+                    });
+                }
+            });
+        }
+        root.summary = Some(Summary {
+            provenance: opts.provenance.clone(),
+            ..summary
+        });
+        self.notify(EventKind::Add, Some(&root), entry_hashes(&root));
+        self.insert_with_quota(root)?;
         Ok(())
     }
 
-    fn build(&self, path: &Path) -> anyhow::Result<Node> {
+    /// 只计算哈希不写入仓库，目录会计算树哈希
+    pub fn scan(&self, path: &Path, opts: &AddOptions) -> anyhow::Result<Node> {
+        let mut ctx = BuildContext::default();
+        let mut root = self
+            .build(path, opts, &mut ctx)?
+            .ok_or(anyhow!("{:?} keeps changing, try again later", path))?;
+        root.compute_tree();
+        Ok(root)
+    }
+
+    /// add前检查空间和inode，只有跨设备复制的新对象需要占用空间，文件系统不支持硬链接时改为复制
+    fn preflight_add(
+        &self,
+        root: &Node,
+        src: &Path,
+        opts: &AddOptions,
+        ctx: &mut BuildContext,
+    ) -> anyhow::Result<()> {
+        let copy = self.copy_on_cross_device(opts.copy_on_cross_device);
+        let mut needed: BTreeMap<PathBuf, u64> = BTreeMap::new();
+        let mut seen = HashSet::new();
+        root.try_walk(&mut |rel, node| {
+            if let FILE(hash) = &node.meta {
+                if self.has_object(hash) || !seen.insert(hash.clone()) {
+                    return Ok(());
+                }
+                // 根节点是文件时 `join("")` 会在末尾加上分隔符
+                let path = if rel.as_os_str().is_empty() {
+                    src.to_path_buf()
+                } else {
+                    src.join(rel)
+                };
+                let size = path.metadata()?.len();
+                let dir = self.object_dir(size)?;
+                if !same_device(&path, &dir) {
+                    if !copy {
+                        bail!("{:?} is not on the same filesystem as {:?}", path, dir);
+                    }
+                    *needed.entry(dir).or_default() += size;
+                }
+            }
+            Ok(())
+        })?;
+        for (dir, size) in needed {
+            ensure_space(&dir, size)?;
+        }
+        if let Some(file) = first_file(root, src) {
+            let file = file.canonicalize()?;
+            let caps = self.selfcheck_between(&file, file.metadata()?.len())?;
+            caps.ensure_inodes(seen.len() as u64)?;
+            if caps.same_device && !caps.hard_link {
+                if !copy {
+                    bail!("{:?} does not support hard links to the store", src);
+                }
+                info!(
+                    "hard links to {:?} are not supported, copy instead",
+                    caps.to
+                );
+                ctx.copy = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// 测试文件能否硬链接到对应的对象目录
+    fn selfcheck_between(&self, file: &Path, size: u64) -> anyhow::Result<Capabilities> {
+        let dir = self.object_dir(size)?;
+        create_dir_all(&dir)?;
+        Capabilities::probe(file, &dir)
+    }
+
+    /// get前检查空间、inode、路径长度和符号链接，内联的文件和跨设备复制的对象需要占用空间
+    fn preflight_get(
+        &self,
+        root: &Node,
+        dst: &Path,
+        base: &Path,
+        opts: &GetOptions,
+    ) -> anyhow::Result<Capabilities> {
+        let copy = self.copy_on_cross_device(opts.copy_on_cross_device);
+        let mut first = None;
+        let mut needed = 0;
+        let mut created = 0;
+        // 同一设备上硬链接的文件，不支持硬链接时也要复制
+        let (mut linked, mut linked_size) = (0, 0);
+        root.try_walk(&mut |_, node| {
+            match &node.meta {
+                // base64编码前的大小
+                INLINE(data) => {
+                    needed += data.len() as u64 / 4 * 3;
+                    created += 1;
+                }
+                FILE(_) if opts.link == LinkMode::Symlink => created += 1,
+                FILE(hash) => {
+                    let src = self.object_path(hash);
+                    let size = src.metadata().map(|m| m.len()).unwrap_or_default();
+                    if opts.link == LinkMode::Copy {
+                        needed += size;
+                        created += 1;
+                    } else if same_device(&src, dst) {
+                        linked += 1;
+                        linked_size += size;
+                    } else {
+                        if !copy {
+                            bail!("{:?} is not on the same filesystem as {:?}", dst, src);
+                        }
+                        needed += size;
+                        created += 1;
+                    }
+                    if first.is_none() && src.is_file() {
+                        first = Some(src);
+                    }
+                }
+                _ => created += 1,
+            }
+            Ok(())
+        })?;
+        // 用第一个对象实际测试能否硬链接到目标目录
+        let caps = match first {
+            Some(src) => Capabilities::probe(&src, dst)?,
+            None => self.selfcheck(dst)?,
+        };
+        caps.check_tree(root, base)?;
+        if caps.same_device && !caps.hard_link && opts.link == LinkMode::Hard {
+            if !copy {
+                bail!("{:?} does not support hard links from the store", dst);
+            }
+            info!("hard links to {:?} are not supported, copy instead", dst);
+            needed += linked_size;
+            created += linked;
+        }
+        caps.ensure_inodes(created)?;
+        ensure_space(dst, needed)?;
+        Ok(caps)
+    }
+
+    /// 自动生成的版本号为add时间，同一秒内多次add时加上序号
+    fn next_version(&self, name: &str) -> String {
+        let base = format_time(now());
+        let mut version = base.clone();
+        let mut i = 1;
+        while self.entry_version(name, Some(&version)).is_ok() {
+            version = format!("{}.{}", base, i);
+            i += 1;
+        }
+        version
+    }
+
+    fn build(
+        &self,
+        path: &Path,
+        opts: &AddOptions,
+        ctx: &mut BuildContext,
+    ) -> anyhow::Result<Option<Node>> {
         info!("build {:?}", path);
-        let root = Node::new(path)?;
+        ctx.ignores = Ignores::new(path, &opts.filter.exclude)?;
+        ctx.include = PathFilter::new(&opts.filter.include, &[])?;
+        ctx.root = path.to_path_buf();
+        let mut files = Vec::new();
+        self.walk_source(path, opts, ctx, &mut files)?;
+        // 整个目录树中的文件一起并行计算哈希，不受每个目录中文件数的限制
+        let hashed: Vec<_> = files
+            .into_par_iter()
+            .map(|p| {
+                let hashed = self.hash_node(&p, opts, &*ctx);
+                (p, hashed)
+            })
+            .collect();
+        ctx.hashed = hashed.into_iter().collect();
+        let hashed = self.take_hashed(path, opts, ctx)?;
+        match self.accept(path, hashed, ctx)? {
+            None => Ok(None),
+            Some(root) => self.expand(root, path, opts, ctx).map(Some),
+        }
+    }
+
+    /// 遍历目录树，记录每个目录下需要保存的子路径，收集需要计算哈希的文件
+    fn walk_source(
+        &self,
+        path: &Path,
+        opts: &AddOptions,
+        ctx: &mut BuildContext,
+        files: &mut Vec<PathBuf>,
+    ) -> anyhow::Result<()> {
+        // 与 `new_node` 一致，解引用时指向目录的符号链接也是目录
+        if !path.is_dir() || (path.is_symlink() && !opts.dereference) {
+            files.push(path.to_path_buf());
+            return Ok(());
+        }
+        if ctx.ancestors.len() >= MAX_DEPTH {
+            bail!(
+                "directory nesting exceeds {} levels at {:?}",
+                MAX_DEPTH,
+                path
+            );
+        }
+        // 解引用时通过真实路径检测符号链接循环
+        let real = if opts.dereference {
+            path.canonicalize()?
+        } else {
+            path.to_path_buf()
+        };
+        if ctx.ancestors.contains(&real) {
+            bail!("symlink cycle at {:?}, it points to {:?}", path, real);
+        }
+        ctx.ancestors.push(real);
+        ctx.ignores.push(path)?;
+        let children = self.children(path, opts, ctx)?;
+        for child in &children {
+            self.walk_source(child, opts, ctx, files)?;
+        }
+        ctx.ancestors.pop();
+        ctx.ignores.pop();
+        ctx.children.insert(path.to_path_buf(), children);
+        Ok(())
+    }
+
+    /// 取出已经计算好的哈希，目录只需要创建节点
+    fn take_hashed(
+        &self,
+        path: &Path,
+        opts: &AddOptions,
+        ctx: &mut BuildContext,
+    ) -> anyhow::Result<Hashed> {
+        match ctx.hashed.remove(path) {
+            Some(hashed) => hashed,
+            None => self.hash_node(path, opts, ctx),
+        }
+    }
+
+    /// 按遍历的结果组装目录的子节点
+    fn expand(
+        &self,
+        mut root: Node,
+        path: &Path,
+        opts: &AddOptions,
+        ctx: &mut BuildContext,
+    ) -> anyhow::Result<Node> {
+        if !matches!(root.meta, DIRECTORY(_)) {
+            return Ok(root);
+        }
+        for child in ctx.children.remove(path).unwrap_or_default() {
+            info!("build {:?}", child);
+            let hashed = self.take_hashed(&child, opts, ctx)?;
+            let node = match self.accept(&child, hashed, ctx)? {
+                None => continue,
+                Some(n) => self.expand(n, &child, opts, ctx)?,
+            };
+            if let DIRECTORY(vec) = &mut root.meta {
+                vec.push(node);
+            }
+        }
+        if let DIRECTORY(vec) = &mut root.meta {
+            vec.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        Ok(root)
+    }
+
+    /// 目录下需要保存的子路径，被过滤的路径记录在 `ctx.skipped` 中
+    fn children(
+        &self,
+        path: &Path,
+        opts: &AddOptions,
+        ctx: &mut BuildContext,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let mut children = Vec::new();
         for entry in walkdir::WalkDir::new(path)
             .follow_links(false)
             .sort_by_file_name()
@@ -162,97 +1522,460 @@ impl Store {
             .filter_map(|f| f.ok())
             .filter(|f| f.path() != path)
         {
-            let node = if entry.path().is_dir() {
-                self.build(entry.path())?
+            let is_dir = entry.file_type().is_dir() || (opts.dereference && entry.path().is_dir());
+            let rel = entry.path().strip_prefix(&ctx.root)?;
+            let reason = if ctx.ignores.ignored(entry.path(), is_dir) {
+                Some(String::from("ignored"))
+            } else if let Some(max) = opts
+                .filter
+                .max_depth
+                .filter(|m| rel.components().count() > *m)
+            {
+                Some(format!("deeper than {}", max))
+            } else if !is_dir && !ctx.include.matches(rel) {
+                Some(String::from("not included"))
             } else {
-                Node::new(entry.path())?
+                opts.filter.skip(entry.path(), opts.dereference)?
             };
+            if let Some(reason) = reason {
+                info!("skip {:?}: {}", entry.path(), reason);
+                ctx.skipped.push(entry.path().to_path_buf());
+                continue;
+            }
+            children.push(entry.into_path());
+        }
+        Ok(children)
+    }
 
-            match &root.meta {
-                DIRECTORY(vec) => {
-                    vec.borrow_mut().push(node);
+    /// 创建节点，文件在计算哈希期间被修改时重试
+    fn hash_node(
+        &self,
+        path: &Path,
+        opts: &AddOptions,
+        ctx: &BuildContext,
+    ) -> anyhow::Result<Hashed> {
+        for _ in 0..STABLE_RETRIES {
+            let before = file_stamp(path);
+            let mut node = self.new_node(path, opts, ctx)?;
+            if !path.is_symlink() || opts.dereference {
+                node.attrs = attrs::capture(path, opts.acl, &opts.skip_xattrs)?.map(Box::new);
+            }
+            if !matches!(node.meta, FILE(_)) {
+                return Ok(Hashed::Node(Box::new(node), None));
+            }
+            let after = file_stamp(path);
+            if let Some(stamp) = after.filter(|_| before == after) {
+                return Ok(Hashed::Node(Box::new(node), Some(stamp)));
+            }
+            warn!("{:?} changed while hashing, retry", path);
+        }
+        Ok(Hashed::Unstable)
+    }
+
+    /// 记录计算哈希的结果，一直在变化的文件返回空
+    fn accept(
+        &self,
+        path: &Path,
+        hashed: Hashed,
+        ctx: &mut BuildContext,
+    ) -> anyhow::Result<Option<Node>> {
+        match hashed {
+            Hashed::Unstable => {
+                ctx.unstable.push(path.to_path_buf());
+                Ok(None)
+            }
+            Hashed::Node(node, None) => Ok(Some(*node)),
+            Hashed::Node(node, Some(stamp)) => {
+                ctx.stamps.insert(path.to_path_buf(), stamp);
+                if let Some(key) = inode_key(path) {
+                    ctx.inodes.entry(key).or_default().push(path.to_path_buf());
+                }
+                if let FILE(hash) = &node.meta {
+                    ctx.session.record(path, stamp, hash)?;
+                    ctx.cache.insert(&std::path::absolute(path)?, hash);
                 }
-                _ => {}
+                Ok(Some(*node))
             }
         }
-        Ok(root)
     }
 
-    fn links(&self, root: &Node, src: &Path) -> anyhow::Result<()> {
-        match &root.meta {
+    /// 创建节点，小于阈值的文件直接内联，没有变化的文件使用上次计算的哈希
+    fn new_node(&self, path: &Path, opts: &AddOptions, ctx: &BuildContext) -> anyhow::Result<Node> {
+        // 解引用时按链接目标处理，悬空的链接仍然记录为链接
+        let follow = opts.dereference && path.is_symlink() && path.exists();
+        if path.is_symlink() && !follow {
+            return Node::new(path, self.hash_algorithm());
+        }
+        let threshold = self.settings.inline_threshold;
+        if threshold > 0 && path.is_file() && path.metadata()?.len() < threshold {
+            return Node::inline(path);
+        }
+        let algorithm = self.hash_algorithm();
+        let cached = ctx.session.hash(path, algorithm).or_else(|| {
+            let path = std::path::absolute(path).ok()?;
+            ctx.cache.hash(&path, algorithm)
+        });
+        if let Some(hash) = cached {
+            let name = path.file_name().ok_or(anyhow!("invalid path {:?}", path))?;
+            return Ok(Node::with_meta(
+                name.to_string_lossy().to_string(),
+                FILE(hash),
+            ));
+        }
+        if follow {
+            return Node::dereference(path, self.hash_algorithm());
+        }
+        Node::new(path, self.hash_algorithm())
+    }
+
+    /// 将文件链接到仓库中，文件在计算哈希后被修改时返回false，此时不会留下对象
+    fn links(
+        &self,
+        root: &mut Node,
+        src: &Path,
+        opts: &AddOptions,
+        ctx: &mut BuildContext,
+    ) -> anyhow::Result<bool> {
+        match &mut root.meta {
+            FILE(value) if !ctx.codec.is_empty() => {
+                // 编码后的内容与源文件不同，只能复制
+                let real = src.canonicalize()?;
+                let hash = ingest(ctx.codec.encode(Box::new(File::open(&real)?))?, self)?;
+                if file_stamp(&real) != ctx.stamps.get(src).copied() {
+                    warn!("{:?} changed after hashing, not stored", src);
+                    ctx.unstable.push(src.to_path_buf());
+                    return Ok(false);
+                }
+                fs::set_permissions(self.object_path(&hash), real.metadata()?.permissions())?;
+                info!("e {:?} -> {}", src, hash);
+                *value = hash;
+            }
             FILE(value) => {
-                let dst = self.store_dir().join(Path::new(value));
-                info!("l {:?} -> {:?}", &src, &dst);
-                hard_link(src, dst)?;
+                // 相同内容的文件已经在仓库中
+                if self.has_object(value) {
+                    return Ok(true);
+                }
+                // 解引用时链接到真实文件，而不是符号链接本身
+                let real = if src.is_symlink() {
+                    src.canonicalize()?
+                } else {
+                    src.to_path_buf()
+                };
+                let dst = self.new_object_path(value, real.metadata()?.len())?;
+                // 预检发现不支持硬链接时直接复制
+                let linked = if ctx.copy {
+                    None
+                } else {
+                    info!("l {:?} -> {:?}", &real, &dst);
+                    Some(fs::hard_link(&real, &dst))
+                };
+                match linked {
+                    Some(Ok(_)) => {}
+                    // 其他进程同时写入了同一对象
+                    Some(Err(e))
+                        if e.kind() == ErrorKind::AlreadyExists && matches(&dst, value)? =>
+                    {
+                        return Ok(true);
+                    }
+                    Some(Err(e)) if e.kind() != ErrorKind::CrossesDevices => return Err(e.into()),
+                    _ => {
+                        if !self.copy_on_cross_device(opts.copy_on_cross_device) {
+                            bail!("{:?} is not on the same filesystem as {:?}", real, dst);
+                        }
+                        // 通过临时文件复制，复制的同时校验内容
+                        info!("{:?} cannot be linked, copy instead", real);
+                        let hash = ingest(File::open(&real)?, self)?;
+                        if hash != *value {
+                            warn!("{:?} changed after hashing, not stored", src);
+                            ctx.unstable.push(src.to_path_buf());
+                            return Ok(false);
+                        }
+                        return Ok(true);
+                    }
+                }
+                // 硬链接与源文件是同一个文件
+                let changed = file_stamp(&real) != ctx.stamps.get(src).copied()
+                    || (opts.rehash && !matches(&dst, value)?);
+                if changed {
+                    warn!("{:?} changed after hashing, not stored", src);
+                    fs::remove_file(&dst)?;
+                    ctx.unstable.push(src.to_path_buf());
+                    return Ok(false);
+                }
             }
-            SYMLINK(_) => {}
+            SYMLINK(_) | INLINE(_) => {}
             DIRECTORY(vec) => {
-                for node in vec.borrow().iter() {
-                    self.links(node, &src.join(Path::new(&node.name)))?;
+                let mut keep = Vec::with_capacity(vec.len());
+                for mut node in std::mem::take(vec) {
+                    let path = src.join(Path::new(&node.name));
+                    if self.links(&mut node, &path, opts, ctx)? {
+                        keep.push(node);
+                    }
                 }
+                *vec = keep;
             }
         }
-        Ok(())
+        Ok(true)
+    }
+
+    pub fn list(&self) -> anyhow::Result<Vec<String>> {
+        self.list_prefix("")
     }
 
-    pub fn list(&self) -> Vec<&str> {
+    /// 名称在前缀之下的条目，如 `toolchains/` 下的 `toolchains/gcc`，
+    /// 按需加载时只读取索引中的名称和版本，不解析条目
+    pub fn list_prefix(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        if !self.lazy {
+            return Ok(self
+                .entries_prefix(prefix)
+                .into_iter()
+                .map(Node::label)
+                .collect());
+        }
         let mut ans = Vec::new();
-        for x in &self.data {
-            ans.push(x.name.as_str());
+        for (name, version) in self.index_backend()?.labels()? {
+            let mut key = Node::sample(&name);
+            key.version = version;
+            if has_prefix(&name, prefix) && !self.removed.contains(&key) {
+                ans.push(key.label());
+            }
         }
-        ans
+        Ok(ans)
     }
 
-    pub fn delete(&mut self, name: &str) {
-        self.data.remove(&Node::sample(name));
+    /// 名称在前缀之下的顶层节点
+    pub fn entries_prefix(&self, prefix: &str) -> Vec<&Node> {
+        self.entries()
+            .into_iter()
+            .filter(|n| has_prefix(&n.name, prefix))
+            .collect()
     }
 
-    pub fn clear(&self) -> anyhow::Result<()> {
-        let names = walkdir::WalkDir::new(self.store_dir())
-            .follow_links(false)
+    /// 按名称和版本排序的顶层节点
+    pub fn entries(&self) -> Vec<&Node> {
+        let mut ans: Vec<&Node> = self.data.iter().collect();
+        ans.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        ans
+    }
+
+    /// 内容与节点相同的条目，目录按树哈希比较
+    pub fn find_same_content(&self, node: &Node) -> Vec<&Node> {
+        let id = node.content_id();
+        self.entries()
             .into_iter()
-            .filter_map(|f| f.ok())
-            .filter(|p| p.path() != self.store_dir())
-            .map(|p| p.file_name().to_string_lossy().to_string())
-            .collect::<HashSet<String>>();
-        let mut tmp = HashSet::new();
+            .filter(|n| n.content_id() == id)
+            .collect()
+    }
 
-        fn dfs(node: &Node, tmp: &mut HashSet<String>) {
-            match &node.meta {
-                FILE(x) => {
-                    tmp.insert(x.to_owned());
-                }
-                DIRECTORY(nodes) => {
-                    for x in nodes.borrow().iter() {
-                        dfs(x, tmp);
+    /// 加入顶层节点，增加其对象的引用数，同名同版本的条目已存在时报错，不会覆盖
+    pub(crate) fn insert_entry(&mut self, root: Node) -> anyhow::Result<()> {
+        // 引用数和冲突检查需要完整的索引
+        self.load_all()?;
+        if let Some(old) = self.data.get(&root) {
+            if old.same_content(&root) {
+                bail!("{} already exists", root.label());
+            }
+            bail!(
+                "conflict: {} already exists with different content ({} != {}), \
+                 store it under another name or version",
+                root.label(),
+                old.content_id(),
+                root.content_id()
+            );
+        }
+        root.walk(&mut |_, n| {
+            if let FILE(x) = &n.meta {
+                *self.refs.entry(x.to_owned()).or_default() += 1;
+                self.garbage.remove(x);
+            }
+        });
+        self.data.insert(root);
+        Ok(())
+    }
+
+    /// 移除顶层节点，引用数降为0的对象等待gc
+    pub(crate) fn take_entry(&mut self, key: &Node) -> Option<Node> {
+        let root = self.data.take(key)?;
+        // 按需加载时保存时才从索引中删除并统计引用数
+        if self.lazy {
+            self.removed.push(root.clone());
+            return Some(root);
+        }
+        root.walk(&mut |_, n| {
+            if let FILE(x) = &n.meta {
+                if let Some(count) = self.refs.get_mut(x) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.refs.remove(x);
+                        self.garbage.insert(x.to_owned());
                     }
                 }
-                _ => {}
-            };
+            }
+        });
+        Some(root)
+    }
+
+    /// 对象被多少个文件引用，按需加载时从索引中查询
+    pub fn refcount(&self, hash: &str) -> anyhow::Result<usize> {
+        if !self.lazy {
+            return Ok(self.refs.get(hash).copied().unwrap_or_default());
+        }
+        let mut removed = 0;
+        for root in &self.removed {
+            root.walk(&mut |_, n| {
+                if matches!(&n.meta, FILE(x) if x == hash) {
+                    removed += 1;
+                }
+            });
+        }
+        Ok(self
+            .index_backend()?
+            .refcount(hash)?
+            .saturating_sub(removed))
+    }
+
+    /// 按需加载时只把删除写入索引，引用数降为0的对象等待gc
+    fn save_removed(&self) -> anyhow::Result<()> {
+        let backend = self.index_backend()?;
+        let mut garbage = BTreeSet::new();
+        for root in &self.removed {
+            if backend
+                .remove(&root.name, root.version.as_deref())?
+                .is_none()
+            {
+                continue;
+            }
+            for hash in entry_hashes(root) {
+                if backend.refcount(&hash)? == 0 {
+                    garbage.insert(hash);
+                }
+            }
+            self.remove_manifest(root)?;
         }
+        self.merge_garbage(&garbage)
+    }
 
+    /// 所有节点引用的对象
+    pub fn objects(&self) -> HashSet<String> {
+        let mut ans = HashSet::new();
         for node in &self.data {
-            dfs(&node, &mut tmp);
+            node.walk(&mut |_, n| {
+                if let FILE(x) = &n.meta {
+                    ans.insert(x.to_owned());
+                }
+            });
         }
+        ans
+    }
 
-        let res: HashSet<_> = names
-            .difference(&tmp)
-            .map(|name| self.store_dir().join(PathBuf::from(name)))
+    /// 被引用但不在仓库中的对象
+    pub fn missing_objects(&self) -> Vec<String> {
+        let mut ans: Vec<String> = self
+            .objects()
+            .into_iter()
+            .filter(|x| !self.has_object(x))
             .collect();
+        ans.sort();
+        ans
+    }
 
-        for path in res {
-            info!("delete {:?}", path);
-            fs::remove_file(path)?;
+    /// 将整个仓库迁移到新的目录
+    pub fn relocate(&mut self, dst: &Path) -> anyhow::Result<()> {
+        let dst = std::path::absolute(dst)?;
+        // 解析符号链接和 `..` 后再比较，目标还不存在时解析最近的已有上级目录
+        let existing = dst.ancestors().find(|p| p.exists()).unwrap_or(&dst);
+        let rest = dst.strip_prefix(existing)?;
+        if rest.components().any(|c| c == Component::ParentDir) {
+            bail!("{:?} contains .. after a missing directory", dst);
+        }
+        let real = existing.canonicalize()?.join(rest);
+        if real.starts_with(self.path.canonicalize()?) {
+            bail!("{:?} is inside the store {:?}", dst, self.path);
+        }
+        if dst.exists() {
+            if fs::read_dir(&dst)?.next().is_some() {
+                bail!("{:?} is not empty", dst);
+            }
+            fs::remove_dir(&dst)?;
+        }
+        if let Some(parent) = dst.parent() {
+            create_dir_all(parent)?;
         }
 
+        let src = self.path.clone();
+        info!("move {:?} -> {:?}", src, dst);
+        let copied = match fs::rename(&src, &dst) {
+            Ok(_) => false,
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                info!("{:?} is on another device, copy instead", dst);
+                copy_dir(&src, &dst)?;
+                true
+            }
+            Err(e) => return Err(e.into()),
+        };
+        self.path = dst;
+        // 位于仓库内的大文件目录随仓库一起移动了
+        if let Some(big) = &mut self.settings.big_objects {
+            if let Ok(rel) = big.path.strip_prefix(&src) {
+                big.path = self.path.join(rel);
+                self.save_settings()?;
+            }
+        }
+
+        let missing = self.missing_objects();
+        if !missing.is_empty() {
+            bail!(
+                "{} objects missing after relocation: {:?}",
+                missing.len(),
+                missing
+            );
+        }
+        // 校验通过后再删除原目录
+        if copied {
+            fs::remove_dir_all(&src)?;
+        }
+        // 当前进程持有的锁随仓库一起移动了，直接清理
+        if read_info(&self.lock_path())?.is_some_and(|i| i.pid == std::process::id()) {
+            fs::remove_file(self.lock_path())?;
+        }
         Ok(())
     }
-}
 
-impl Store {
-    pub fn pull(&self, names: Vec<String>, address: String) -> anyhow::Result<()> {
-        info!("pull tools {:?} from {:?}", names, address);
-        // todo: implement
+    /// 删除条目的所有版本
+    pub fn delete(&mut self, name: &str) {
+        let versions: Vec<Node> = self.versions(name).into_iter().cloned().collect();
+        for node in versions {
+            self.delete_version(&node.name, node.version.as_deref());
+        }
+    }
+
+    /// 删除条目的指定版本
+    pub fn delete_version(&mut self, name: &str, version: Option<&str>) -> bool {
+        let mut key = Node::sample(name);
+        key.version = version.map(String::from);
+        match self.take_entry(&key) {
+            None => false,
+            Some(node) => {
+                self.notify(EventKind::Delete, Some(&node), entry_hashes(&node));
+                true
+            }
+        }
+    }
+
+    /// 把所有没有被引用的对象移到回收站
+    pub fn clear(&self) -> anyhow::Result<()> {
+        let tmp = self.objects();
+        let mut deleted = Vec::new();
+        for (hash, path) in self.stored_files()? {
+            if !tmp.contains(&hash) {
+                self.trash_object(&path, &hash)?;
+                deleted.push(hash);
+            }
+        }
+        if !deleted.is_empty() {
+            deleted.sort();
+            self.notify(EventKind::Clear, None, deleted);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,114 @@
+use crate::core::store::Store;
+use crate::core::util::expand_path;
+use crate::{HBX_STORES_ENV, HBX_STORE_ENV};
+use anyhow::{anyhow, bail};
+use atomicwrites::{AllowOverwrite, AtomicFile};
+use dirs::config_dir;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// 多个命名仓库的配置，默认位于 `~/.config/hbx/stores.toml`
+///
+/// ```toml
+/// default = "toolchains"
+///
+/// [stores.toolchains]
+/// path = "/data/hbx/toolchains"
+///
+/// [stores.datasets]
+/// path = "~/datasets/{user}"
+/// ```
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StoresConfig {
+    /// 没有指定 `--store` 和 `HBX_HOME` 时使用的仓库
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub stores: BTreeMap<String, NamedStore>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NamedStore {
+    /// 仓库目录，支持与 `HBX_HOME` 相同的变量
+    pub path: String,
+}
+
+impl StoresConfig {
+    /// 配置文件路径，可以通过 `HBX_STORES` 指定
+    pub fn path() -> Option<PathBuf> {
+        match env::var(HBX_STORES_ENV) {
+            Ok(p) => Some(PathBuf::from(p)),
+            Err(_) => config_dir().map(|d| d.join("hbx").join("stores.toml")),
+        }
+    }
+
+    /// 读取配置，文件不存在时为空
+    pub fn load() -> anyhow::Result<Self> {
+        match Self::path() {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(&path)?;
+                toml::from_str(&content)
+                    .map_err(|e| anyhow!("invalid stores file {:?}: {}", path, e))
+            }
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = Self::path().ok_or(anyhow!("no config directory for the stores file"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let s = toml::to_string_pretty(self)?;
+        AtomicFile::new(&path, AllowOverwrite).write(|f| f.write_all(s.as_bytes()))?;
+        Ok(())
+    }
+
+    /// 仓库迁移后把指向原目录的命名仓库改为新目录，返回修改的仓库名称
+    pub fn relocate(&mut self, from: &Path, to: &Path) -> anyhow::Result<Vec<String>> {
+        let mut ans = Vec::new();
+        for (name, store) in self.stores.iter_mut() {
+            if expand_path(&store.path)? == from {
+                store.path = to.to_string_lossy().to_string();
+                ans.push(name.clone());
+            }
+        }
+        Ok(ans)
+    }
+
+    /// 命名仓库的目录
+    pub fn resolve(&self, name: &str) -> anyhow::Result<PathBuf> {
+        match self.stores.get(name) {
+            Some(s) => expand_path(&s.path),
+            None if self.stores.is_empty() => bail!(
+                "unknown store {}, declare it in {:?}",
+                name,
+                Self::path().unwrap_or_default()
+            ),
+            None => bail!(
+                "unknown store {}, expected one of {}",
+                name,
+                self.stores.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+impl Store {
+    /// 打开配置中的命名仓库
+    pub fn named(name: &str) -> anyhow::Result<Self> {
+        Store::new(StoresConfig::load()?.resolve(name)?)
+    }
+
+    /// 当前使用的命名仓库，由 `HBX_STORE` 或配置中的默认仓库决定，`HBX_HOME` 优先
+    pub fn active_name() -> anyhow::Result<Option<String>> {
+        if let Ok(name) = env::var(HBX_STORE_ENV) {
+            return Ok(Some(name));
+        }
+        Ok(StoresConfig::load()?.default)
+    }
+}
@@ -0,0 +1,265 @@
+use crate::core::attrs::Attrs;
+use crate::core::filter::{Ignores, PathFilter};
+use crate::core::node::Meta::{DIRECTORY, FILE, INLINE, SYMLINK};
+use crate::core::node::{Node, Summary};
+use crate::core::store::{AddOptions, Store};
+use crate::core::util::{ensure_space, free_inodes, ingest, now};
+use anyhow::{anyhow, bail};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use clap::ValueEnum;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tar::EntryType;
+
+/// `add --stdin` 读取的流格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum StreamFormat {
+    #[default]
+    Tar,
+    /// zstd压缩的tar
+    TarZst,
+}
+
+/// tar中的路径转为相对路径，去掉 `./`，不接受绝对路径和 `..`
+fn entry_path(path: &Path) -> anyhow::Result<PathBuf> {
+    let mut ans = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(s) => ans.push(s),
+            Component::CurDir => {}
+            _ => bail!("unsafe path {:?} in the archive", path),
+        }
+    }
+    Ok(ans)
+}
+
+/// 把节点放到目录树中的相对路径处，缺少的中间目录自动创建，已有的目录只更新属性
+fn insert(dir: &mut Node, rel: &Path, node: Node) {
+    let DIRECTORY(children) = &mut dir.meta else {
+        return;
+    };
+    let mut parts = rel.components();
+    let Some(first) = parts.next() else {
+        return;
+    };
+    let name = first.as_os_str().to_string_lossy().to_string();
+    let rest = parts.as_path();
+    let pos = children.iter().position(|n| n.name == name);
+    if rest.as_os_str().is_empty() {
+        match pos {
+            Some(i)
+                if matches!(children[i].meta, DIRECTORY(_))
+                    && matches!(node.meta, DIRECTORY(_)) =>
+            {
+                children[i].attrs = node.attrs;
+            }
+            Some(i) => children[i] = node,
+            None => children.push(node),
+        }
+        return;
+    }
+    let i = match pos {
+        Some(i) if matches!(children[i].meta, DIRECTORY(_)) => i,
+        Some(i) => {
+            children[i] = Node::with_meta(name, DIRECTORY(Vec::new()));
+            i
+        }
+        None => {
+            children.push(Node::with_meta(name, DIRECTORY(Vec::new())));
+            children.len() - 1
+        }
+    };
+    insert(&mut children[i], rest, node);
+}
+
+/// 按名称排序所有目录的子节点，与从磁盘add的顺序一致
+fn sort_tree(node: &mut Node) {
+    if let DIRECTORY(children) = &mut node.meta {
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+        children.iter_mut().for_each(sort_tree);
+    }
+}
+
+impl Store {
+    /// 从tar流add条目，文件直接写入仓库，不需要先解包到磁盘，必须指定 `--name`，
+    /// 失败时删除这次写入、没有被引用的对象
+    pub fn add_tar<R: Read>(&mut self, reader: R, opts: &AddOptions) -> anyhow::Result<()> {
+        let mut written = Vec::new();
+        let res = self
+            .read_tar(reader, opts, &mut written)
+            .and_then(|(root, summary)| self.commit_add(root, opts, summary));
+        if res.is_err() {
+            for hash in written {
+                if !matches!(self.refcount(&hash), Ok(0)) || !self.has_object(&hash) {
+                    continue;
+                }
+                info!("remove object {}", hash);
+                if let Err(e) = fs::remove_file(self.object_path(&hash)) {
+                    warn!("failed to remove object {}: {}", hash, e);
+                }
+            }
+        }
+        res
+    }
+
+    /// 写入一个对象前检查对象目录的剩余空间和inode，流的总大小事先不知道，只能逐个检查
+    fn ensure_room(&self, size: u64) -> anyhow::Result<()> {
+        let dir = self.object_dir(size)?;
+        ensure_space(&dir, size)?;
+        if free_inodes(&dir) == Some(0) {
+            bail!("not enough inodes on {:?}", dir);
+        }
+        Ok(())
+    }
+
+    /// 读取tar流并写入对象，返回节点树和汇总信息，写入的对象记录在 `written` 中
+    fn read_tar<R: Read>(
+        &self,
+        reader: R,
+        opts: &AddOptions,
+        written: &mut Vec<String>,
+    ) -> anyhow::Result<(Node, Summary)> {
+        let name = opts
+            .name
+            .clone()
+            .ok_or(anyhow!("--name is required when adding from a stream"))?;
+        let (version, tags) = self.add_target(&name, opts)?;
+        let filters = self.filters_for(&name)?;
+        let codec = self.pipeline(&filters)?;
+        let include = PathFilter::new(&opts.filter.include, &[])?;
+        let ignores = Ignores::new(Path::new(""), &opts.filter.exclude)?;
+        let threshold = self.settings().inline_threshold;
+        let mut root = Node::with_meta(name, DIRECTORY(Vec::new()));
+        let mut files: BTreeMap<PathBuf, (Node, u64)> = BTreeMap::new();
+        let mut links: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+        let mut skipped = Vec::new();
+        let (mut size, mut count) = (0, 0);
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let rel = entry_path(&entry.path()?)?;
+            if rel.as_os_str().is_empty() {
+                continue;
+            }
+            let kind = entry.header().entry_type();
+            let excluded = rel
+                .ancestors()
+                .filter(|p| !p.as_os_str().is_empty())
+                .any(|p| ignores.ignored(p, p != rel || kind.is_dir()))
+                || opts
+                    .filter
+                    .max_depth
+                    .is_some_and(|d| rel.components().count() > d);
+            if excluded {
+                info!("s {:?}", rel);
+                skipped.push(rel);
+                continue;
+            }
+            let leaf = rel
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let meta = match kind {
+                EntryType::Directory => DIRECTORY(Vec::new()),
+                EntryType::Link => {
+                    let target = entry_path(
+                        &entry
+                            .link_name()?
+                            .ok_or(anyhow!("hard link {:?} has no target", rel))?,
+                    )?;
+                    let Some((node, len)) = files.get(&target) else {
+                        info!("s {:?}, link target {:?} not stored", rel, target);
+                        skipped.push(rel);
+                        continue;
+                    };
+                    let mut node = node.clone();
+                    node.name = leaf;
+                    size += len;
+                    count += 1;
+                    links.entry(target).or_default().push(rel.clone());
+                    insert(&mut root, &rel, node);
+                    continue;
+                }
+                EntryType::Symlink => SYMLINK(
+                    entry
+                        .link_name()?
+                        .ok_or(anyhow!("symlink {:?} has no target", rel))?
+                        .to_path_buf(),
+                ),
+                EntryType::Regular | EntryType::Continuous => {
+                    let len = entry.size();
+                    let reason = opts
+                        .filter
+                        .skip_size(len)
+                        .or_else(|| (!include.matches(&rel)).then(|| "not included".to_string()));
+                    if let Some(reason) = reason {
+                        info!("s {:?}, {}", rel, reason);
+                        skipped.push(rel);
+                        continue;
+                    }
+                    size += len;
+                    count += 1;
+                    if threshold > 0 && len < threshold {
+                        let mut data = Vec::new();
+                        entry.read_to_end(&mut data)?;
+                        INLINE(STANDARD.encode(data))
+                    } else {
+                        self.ensure_room(len)?;
+                        let hash = ingest(codec.encode(Box::new(&mut entry))?, self)?;
+                        info!("e {:?} -> {}", rel, hash);
+                        written.push(hash.clone());
+                        FILE(hash)
+                    }
+                }
+                _ => {
+                    info!("s {:?}, unsupported entry type {:?}", rel, kind);
+                    skipped.push(rel);
+                    continue;
+                }
+            };
+            let header = entry.header();
+            let attrs = Attrs {
+                mode: matches!(meta, FILE(_) | INLINE(_))
+                    .then(|| header.mode().map(|m| m & 0o7777))
+                    .transpose()?,
+                mtime: header.mtime().ok().map(|t| t as i64 * 1_000_000_000),
+                uid: header.uid().ok().map(|u| u as u32),
+                gid: header.gid().ok().map(|g| g as u32),
+                ..Default::default()
+            };
+            let mut node = Node::with_meta(leaf, meta);
+            node.attrs = (!attrs.is_empty()).then(|| Box::new(attrs));
+            if matches!(node.meta, FILE(_) | INLINE(_)) {
+                files.insert(rel.clone(), (node.clone(), entry.size()));
+            }
+            insert(&mut root, &rel, node);
+        }
+        sort_tree(&mut root);
+        root.version = version;
+        root.filters = codec.names();
+        root.tags = tags;
+        let hardlinks = links
+            .into_iter()
+            .map(|(target, mut paths)| {
+                paths.push(target);
+                paths.sort();
+                paths
+            })
+            .collect();
+        let summary = Summary {
+            added: now(),
+            size: Some(size),
+            files: Some(count),
+            skipped,
+            unstable: Vec::new(),
+            hardlinks,
+            ..Default::default()
+        };
+        Ok((root, summary))
+    }
+}
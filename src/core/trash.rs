@@ -0,0 +1,113 @@
+use crate::core::store::Store;
+use crate::core::util::{move_file, now};
+use crate::TRASH_DIRECTORY;
+use log::info;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 回收站中的对象，按删除时间分目录存放：`trash/<删除时间>/<hash>`
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashedObject {
+    pub hash: String,
+    /// 删除时间
+    pub deleted: u64,
+    pub size: u64,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Store {
+    pub fn trash_dir(&self) -> PathBuf {
+        self.path().join(TRASH_DIRECTORY)
+    }
+
+    /// 把对象移到回收站
+    pub(crate) fn trash_object(&self, path: &Path, hash: &str) -> anyhow::Result<()> {
+        let dir = self.trash_dir().join(now().to_string());
+        fs::create_dir_all(&dir)?;
+        info!("trash {:?}", path);
+        move_file(path, &dir.join(hash))?;
+        // 删除空的分目录
+        if let Some(parent) = path.parent() {
+            if !self.object_dirs().iter().any(|d| d == parent) {
+                let _ = fs::remove_dir(parent);
+            }
+        }
+        Ok(())
+    }
+
+    /// 回收站中的所有对象，按删除时间从旧到新排序
+    pub fn trashed(&self) -> anyhow::Result<Vec<TrashedObject>> {
+        let mut ans = Vec::new();
+        if !self.trash_dir().exists() {
+            return Ok(ans);
+        }
+        for dir in fs::read_dir(self.trash_dir())? {
+            let dir = dir?;
+            let deleted = match dir.file_name().to_string_lossy().parse() {
+                Ok(t) => t,
+                Err(_) => continue,
+            };
+            for file in fs::read_dir(dir.path())? {
+                let file = file?;
+                ans.push(TrashedObject {
+                    hash: file.file_name().to_string_lossy().to_string(),
+                    deleted,
+                    size: file.metadata()?.len(),
+                    path: file.path(),
+                });
+            }
+        }
+        ans.sort_by(|a, b| (a.deleted, &a.hash).cmp(&(b.deleted, &b.hash)));
+        Ok(ans)
+    }
+
+    /// 从回收站恢复对象，有多份时使用最近删除的一份，回收站中没有时返回false
+    pub fn restore_blob(&self, hash: &str) -> anyhow::Result<bool> {
+        let item = match self.trashed()?.into_iter().rev().find(|t| t.hash == hash) {
+            None => return Ok(false),
+            Some(t) => t,
+        };
+        if self.has_object(hash) {
+            info!("{} is already in the store", hash);
+        } else {
+            let dst = self.new_object_path(hash, item.size)?;
+            info!("restore {:?} -> {:?}", item.path, dst);
+            move_file(&item.path, &dst)?;
+        }
+        self.remove_trashed(&item)?;
+        Ok(true)
+    }
+
+    /// 清除删除时间超过 `age` 的对象，`dry_run` 时只返回这些对象
+    pub fn expire_trash(&self, age: Duration, dry_run: bool) -> anyhow::Result<Vec<TrashedObject>> {
+        let deadline = now().saturating_sub(age.as_secs());
+        let expired: Vec<TrashedObject> = self
+            .trashed()?
+            .into_iter()
+            .filter(|t| t.deleted <= deadline)
+            .collect();
+        if !dry_run {
+            for item in &expired {
+                info!("purge {:?}", item.path);
+                self.remove_trashed(item)?;
+            }
+        }
+        Ok(expired)
+    }
+
+    /// 删除回收站中的文件，目录空了也一起删除
+    fn remove_trashed(&self, item: &TrashedObject) -> anyhow::Result<()> {
+        if item.path.exists() {
+            fs::remove_file(&item.path)?;
+        }
+        if let Some(dir) = item.path.parent() {
+            if fs::read_dir(dir)?.next().is_none() {
+                fs::remove_dir(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
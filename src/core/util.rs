@@ -1,19 +1,97 @@
-use anyhow::bail;
-use log::info;
-use std::fs;
+use crate::core::hash::{matches, HashAlgorithm};
+use crate::core::store::Store;
+use anyhow::{anyhow, bail};
+use dirs::home_dir;
+use log::{info, warn};
 use std::io::{Read, Write};
 use std::net::TcpStream;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fs};
 
-use md5::Digest;
 use ssh2::Session;
+use tempfile::NamedTempFile;
 
-pub fn md5(path: &Path) -> anyhow::Result<String> {
-    let content = fs::read(path)?;
-    let mut hasher = md5::Md5::default();
-    hasher.update(content);
+/// 是否为合法的对象名，对象名由哈希算法生成
+pub fn is_hash(s: &str) -> bool {
+    HashAlgorithm::of(s).is_some()
+}
+
+/// 边复制边计算哈希，写入完成后原子地移动到仓库中，返回对象名
+pub fn ingest<R: Read>(reader: R, store: &Store) -> anyhow::Result<String> {
+    ingest_inner(reader, store, None)
+}
+
+/// 同 `ingest`，内容与期望的哈希不一致时报错且不写入仓库
+pub fn ingest_expected<R: Read>(reader: R, store: &Store, expected: &str) -> anyhow::Result<()> {
+    ingest_inner(reader, store, Some(expected))?;
+    Ok(())
+}
+
+fn ingest_inner<R: Read>(
+    mut reader: R,
+    store: &Store,
+    expected: Option<&str>,
+) -> anyhow::Result<String> {
+    // 远程仓库可能使用不同的算法，按期望的对象名选择算法
+    let algorithm = match expected {
+        None => store.hash_algorithm(),
+        Some(name) => HashAlgorithm::of(name).ok_or(anyhow!("invalid object name {}", name))?,
+    };
+    let mut tmp = NamedTempFile::new_in(store.tmp_dir()?)?;
+    let mut hasher = algorithm.hasher();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hasher.update(&buf[..n]);
+        tmp.write_all(&buf[..n])?;
+        size += n as u64;
+    }
     let hash = hasher.finalize();
-    Ok(format!("{:x}", hash))
+    if let Some(expected) = expected {
+        if hash != expected {
+            bail!("checksum mismatch, expected {} but got {}", expected, hash);
+        }
+    }
+    if store.has_object(&hash) {
+        return Ok(hash);
+    }
+    tmp.as_file().sync_all()?;
+    let dst = store.new_object_path(&hash, size)?;
+    if !dst.starts_with(store.store_dir()) {
+        // 大文件目录可能在其他磁盘上，不能直接重命名
+        let big = NamedTempFile::new_in(dst.parent().unwrap_or(&dst))?;
+        fs::copy(tmp.path(), big.path())?;
+        big.as_file().sync_all()?;
+        tmp = big;
+    }
+    persist_object(tmp, &dst, &hash)?;
+    info!("put {}", hash);
+    Ok(hash)
+}
+
+/// 把写好的临时文件原子地重命名为对象，其他进程同时写入了同一对象时保留先写入的，
+/// 已有的对象损坏时才替换
+pub fn persist_object(tmp: NamedTempFile, dst: &Path, hash: &str) -> anyhow::Result<()> {
+    match tmp.persist_noclobber(dst) {
+        Ok(_) => Ok(()),
+        Err(e) if e.error.kind() == std::io::ErrorKind::AlreadyExists => {
+            if matches(dst, hash)? {
+                info!("{} was written by another process", hash);
+                return Ok(());
+            }
+            warn!("replace corrupt object {:?}", dst);
+            e.file.persist(dst)?;
+            Ok(())
+        }
+        Err(e) => Err(e.error.into()),
+    }
 }
 
 /// 从服务器上下载文件到本地
@@ -56,7 +134,7 @@ pub fn upload(
 
     let size = local_path.metadata()?.len();
     let mut remote_file = sess.scp_send(remote_path, 0o644, size, None)?;
-    remote_file.write(&fs::read(local_path)?)?;
+    remote_file.write_all(&fs::read(local_path)?)?;
     // Close the channel and wait for the whole content to be transferred
     remote_file.send_eof()?;
     remote_file.wait_eof()?;
@@ -66,7 +144,7 @@ pub fn upload(
 }
 
 /// 获取ssh session
-fn ssh_session(username: &str, address: &str) -> anyhow::Result<Session> {
+pub fn ssh_session(username: &str, address: &str) -> anyhow::Result<Session> {
     let tcp = TcpStream::connect(address)?;
     let mut sess = Session::new()?;
     sess.set_tcp_stream(tcp);
@@ -89,3 +167,384 @@ pub fn execute(cmd: &str, username: &str, address: &str) -> anyhow::Result<Strin
     channel.wait_close()?;
     Ok(s)
 }
+
+/// 当前时间，unix秒
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// 将unix秒格式化为UTC时间
+pub fn format_time(t: u64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(t)).to_string()
+}
+
+/// 解析 `2024-05-01` 或 `2024-05-01 12:00:00` 形式的UTC时间，返回unix秒
+pub fn parse_time(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let full = if s.len() == 10 {
+        format!("{}T00:00:00Z", s)
+    } else {
+        s.to_string()
+    };
+    let t =
+        humantime::parse_rfc3339_weak(&full).map_err(|e| anyhow!("invalid time {:?}: {}", s, e))?;
+    Ok(t.duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// 当前主机名
+pub fn hostname() -> String {
+    gethostname::gethostname().to_string_lossy().to_string()
+}
+
+/// 当前用户名
+pub fn username() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| String::from("unknown"))
+}
+
+/// 当前项目名，取最近的git仓库根目录名，否则为当前目录名
+pub fn project() -> anyhow::Result<String> {
+    let cwd = env::current_dir()?;
+    let root = cwd
+        .ancestors()
+        .find(|p| p.join(".git").exists())
+        .unwrap_or(&cwd);
+    Ok(root
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_else(|| String::from("root")))
+}
+
+/// 展开路径中的 `~` 以及 `{user}`、`{hostname}`、`{project}` 变量
+pub fn expand_path(template: &str) -> anyhow::Result<PathBuf> {
+    let mut s = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        s.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or(anyhow!("unclosed variable in {:?}", template))?
+            + start;
+        let value = match &rest[start + 1..end] {
+            "user" => username(),
+            "hostname" => hostname(),
+            "project" => project()?,
+            var => bail!("unknown variable {{{}}} in {:?}", var, template),
+        };
+        s.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    s.push_str(rest);
+
+    let path = match s.strip_prefix('~') {
+        Some(tail) if tail.is_empty() || tail.starts_with(['/', '\\']) => match home_dir() {
+            None => PathBuf::from(&s),
+            Some(home) => home.join(tail.trim_start_matches(['/', '\\'])),
+        },
+        _ => PathBuf::from(&s),
+    };
+    Ok(path)
+}
+
+/// 解析 `512`、`10K`、`2G` 形式的大小，单位为1024进制
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        None => (s, ""),
+        Some(i) => s.split_at(i),
+    };
+    let num: u64 = num.parse().map_err(|_| anyhow!("invalid size {:?}", s))?;
+    let shift = match unit
+        .trim()
+        .to_ascii_uppercase()
+        .trim_end_matches(['B', 'I'])
+    {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => bail!("invalid size unit {:?}", unit),
+    };
+    num.checked_mul(1 << shift)
+        .ok_or(anyhow!("size {:?} is too large", s))
+}
+
+/// 将字节数格式化为 `1.5M` 这样的形式，与 `parse_size` 相对应
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", size)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+/// 递归复制目录，符号链接保持为链接
+pub fn copy_dir(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    for entry in walkdir::WalkDir::new(src).follow_links(false) {
+        let entry = entry?;
+        let target = dst.join(entry.path().strip_prefix(src)?);
+        let t = entry.file_type();
+        if t.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if t.is_symlink() {
+            symlink(&fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// 创建硬链接，跨设备时根据 `copy` 改为复制或报错
+pub fn link_or_copy(src: &Path, dst: &Path, copy: bool) -> anyhow::Result<()> {
+    match fs::hard_link(src, dst) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices && !copy => {
+            bail!(
+                "{:?} and {:?} are on different filesystems and copy_on_cross_device is off",
+                src,
+                dst
+            )
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            info!(
+                "{:?} is on another filesystem, copy instead of linking",
+                src
+            );
+            fs::copy(src, dst)?;
+            Ok(())
+        }
+        res => Ok(res?),
+    }
+}
+
+/// 创建共享数据块的副本(btrfs/XFS的 `FICLONE`)，文件系统不支持时返回错误
+#[cfg(target_os = "linux")]
+pub fn reflink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let from = fs::File::open(src)?;
+    let to = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dst)?;
+    if unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE as _, from.as_raw_fd()) } != 0 {
+        let e = std::io::Error::last_os_error();
+        drop(to);
+        let _ = fs::remove_file(dst);
+        return Err(e);
+    }
+    fs::set_permissions(dst, from.metadata()?.permissions())
+}
+
+/// APFS的 `clonefile`
+#[cfg(target_os = "macos")]
+pub fn reflink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let from = std::ffi::CString::new(src.as_os_str().as_bytes())?;
+    let to = std::ffi::CString::new(dst.as_os_str().as_bytes())?;
+    if unsafe { libc::clonefile(from.as_ptr(), to.as_ptr(), 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn reflink(_src: &Path, _dst: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// 移动文件，跨设备时复制后删除原文件
+pub fn move_file(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    match fs::rename(src, dst) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            info!("{:?} is on another device, copy instead", dst);
+            fs::copy(src, dst)?;
+            fs::remove_file(src)?;
+            Ok(())
+        }
+        res => Ok(res?),
+    }
+}
+
+#[cfg(unix)]
+pub fn symlink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(src, dst)
+}
+
+#[cfg(windows)]
+pub fn symlink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    if src.is_dir() {
+        std::os::windows::fs::symlink_dir(src, dst)
+    } else {
+        std::os::windows::fs::symlink_file(src, dst)
+    }
+}
+
+/// 文件权限位
+#[cfg(unix)]
+pub fn file_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(windows)]
+pub fn file_mode(meta: &fs::Metadata) -> u32 {
+    if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+/// 路径所在文件系统的可用空间，无法获取时为空
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn available_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let c = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(windows)]
+pub fn available_space(_path: &Path) -> Option<u64> {
+    // todo 适配windows
+    None
+}
+
+/// 路径所在文件系统的可用inode数，文件系统不限制inode时为空
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn free_inodes(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let c = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c.as_ptr(), &mut stat) } != 0 || stat.f_files == 0 {
+        return None;
+    }
+    Some(stat.f_favail as u64)
+}
+
+#[cfg(windows)]
+pub fn free_inodes(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// 路径所在文件系统的路径和文件名长度上限，无法获取时为空
+#[cfg(unix)]
+pub fn path_limits(path: &Path) -> (Option<u64>, Option<u64>) {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(c) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return (None, None);
+    };
+    let limit = |name| match unsafe { libc::pathconf(c.as_ptr(), name) } {
+        n if n > 0 => Some(n as u64),
+        _ => None,
+    };
+    (limit(libc::_PC_PATH_MAX), limit(libc::_PC_NAME_MAX))
+}
+
+#[cfg(windows)]
+pub fn path_limits(_path: &Path) -> (Option<u64>, Option<u64>) {
+    // 未启用长路径时为MAX_PATH
+    (Some(260), Some(255))
+}
+
+/// 两个路径是否在同一设备上，无法判断时认为在同一设备上
+#[cfg(unix)]
+pub fn same_device(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => true,
+    }
+}
+
+#[cfg(windows)]
+pub fn same_device(a: &Path, b: &Path) -> bool {
+    a.components().next() == b.components().next()
+}
+
+/// 删除文件、符号链接或整个目录，路径不存在时什么也不做
+pub fn remove_path(path: &Path) -> anyhow::Result<()> {
+    match path.symlink_metadata() {
+        Ok(m) if m.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+/// 两个路径是否为同一个文件，如互为硬链接，无法判断时认为不是
+#[cfg(unix)]
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(windows)]
+pub fn same_file(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+/// 两个文件的权限和属主是否相同，硬链接后只能保留其中一份
+#[cfg(unix)]
+pub fn same_ownership(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.mode() == b.mode() && a.uid() == b.uid() && a.gid() == b.gid(),
+        _ => false,
+    }
+}
+
+#[cfg(windows)]
+pub fn same_ownership(a: &Path, b: &Path) -> bool {
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.permissions().readonly() == b.permissions().readonly(),
+        _ => false,
+    }
+}
+
+/// 检查可用空间是否足够，无法获取可用空间时不检查
+pub fn ensure_space(path: &Path, needed: u64) -> anyhow::Result<()> {
+    if needed == 0 {
+        return Ok(());
+    }
+    match available_space(path) {
+        Some(available) if available < needed => bail!(
+            "not enough space on {:?}: need ~{}, have {}",
+            path,
+            format_size(needed),
+            format_size(available)
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// 文件类型的名称
+pub fn file_kind(meta: &fs::Metadata) -> &'static str {
+    if meta.is_symlink() {
+        "symlink"
+    } else if meta.is_dir() {
+        "directory"
+    } else {
+        "file"
+    }
+}
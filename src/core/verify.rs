@@ -0,0 +1,162 @@
+use crate::core::hash::matches;
+use crate::core::node::Meta::FILE;
+use crate::core::node::Node;
+use crate::core::store::Store;
+use crate::core::util::is_hash;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// 对象的校验结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// 对象不在仓库中
+    Missing,
+    /// 对象内容与哈希不一致
+    Corrupt,
+    /// add期间一直在变化而没有保存的文件，没有对象
+    Unstable,
+}
+
+/// 单个文件的校验结果
+#[derive(Debug, Serialize)]
+pub struct FileCheck {
+    /// 相对于条目根目录的路径
+    pub path: PathBuf,
+    pub hash: String,
+    pub status: Status,
+}
+
+/// 整个仓库的校验结果
+#[derive(Debug, Default, Serialize)]
+pub struct StoreCheck {
+    /// 校验的对象数
+    pub objects: usize,
+    /// 内容与对象名不一致的对象
+    pub corrupt: Vec<String>,
+    /// 被条目引用但不在仓库中的对象
+    pub missing: Vec<MissingObject>,
+    /// 有文件在add期间变化而没有保存的条目，不影响校验结果
+    pub unstable: Vec<UnstableEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnstableEntry {
+    pub entry: String,
+    /// 没有保存的文件，相对于条目根目录
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingObject {
+    /// 引用该对象的条目，带版本号
+    pub entry: String,
+    pub hash: String,
+}
+
+impl Status {
+    /// 文件的内容缺失或损坏，add时没有保存的文件不算
+    pub fn is_bad(&self) -> bool {
+        matches!(self, Status::Missing | Status::Corrupt)
+    }
+}
+
+impl StoreCheck {
+    pub fn is_ok(&self) -> bool {
+        self.corrupt.is_empty() && self.missing.is_empty()
+    }
+}
+
+impl Store {
+    /// 重新计算所有对象的哈希，并检查条目引用的对象是否存在
+    pub fn verify_store(&self) -> anyhow::Result<StoreCheck> {
+        // 跳过写入中的临时文件
+        let objects: Vec<(String, PathBuf)> = self
+            .stored_files()?
+            .into_iter()
+            .filter(|(name, _)| is_hash(name))
+            .collect();
+        let checked: Vec<anyhow::Result<Option<String>>> = objects
+            .par_iter()
+            .map(|(hash, path)| Ok((!matches(path, hash)?).then(|| hash.clone())))
+            .collect();
+        let mut ans = StoreCheck {
+            objects: objects.len(),
+            ..Default::default()
+        };
+        for res in checked {
+            ans.corrupt.extend(res?);
+        }
+        ans.corrupt.sort();
+        for node in self.entries() {
+            let mut hashes = BTreeSet::new();
+            node.walk(&mut |_, n| {
+                if let FILE(hash) = &n.meta {
+                    hashes.insert(hash.clone());
+                }
+            });
+            for hash in hashes.into_iter().filter(|h| !self.has_object(h)) {
+                ans.missing.push(MissingObject {
+                    entry: node.label(),
+                    hash,
+                });
+            }
+            let unstable = node.summary.as_ref().map(|s| &s.unstable);
+            if let Some(paths) = unstable.filter(|u| !u.is_empty()) {
+                ans.unstable.push(UnstableEntry {
+                    entry: node.label(),
+                    paths: paths.clone(),
+                });
+            }
+        }
+        Ok(ans)
+    }
+
+    /// 校验条目引用的对象是否存在且内容完整，内联的文件不需要校验，add时没有保存的文件标记为unstable
+    pub fn verify_entry(&self, root: &Node) -> anyhow::Result<Vec<FileCheck>> {
+        let base = Path::new(root.leaf_name());
+        // 同一对象只计算一次哈希
+        let mut cache: HashMap<String, Status> = HashMap::new();
+        let mut ans = Vec::new();
+        root.try_walk(&mut |path, node| {
+            if let FILE(hash) = &node.meta {
+                let status = match cache.get(hash) {
+                    Some(s) => *s,
+                    None => {
+                        let s = self.check_object(hash)?;
+                        cache.insert(hash.clone(), s);
+                        s
+                    }
+                };
+                ans.push(FileCheck {
+                    path: base.join(path),
+                    hash: hash.clone(),
+                    status,
+                });
+            }
+            Ok(())
+        })?;
+        for path in root.summary.iter().flat_map(|s| &s.unstable) {
+            ans.push(FileCheck {
+                path: base.join(path),
+                hash: String::new(),
+                status: Status::Unstable,
+            });
+        }
+        Ok(ans)
+    }
+
+    /// 校验单个对象
+    pub fn check_object(&self, hash: &str) -> anyhow::Result<Status> {
+        if !self.has_object(hash) {
+            return Ok(Status::Missing);
+        }
+        if !matches(&self.object_path(hash), hash)? {
+            return Ok(Status::Corrupt);
+        }
+        Ok(Status::Ok)
+    }
+}
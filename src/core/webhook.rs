@@ -0,0 +1,137 @@
+use crate::core::node::Meta::FILE;
+use crate::core::node::Node;
+use crate::core::store::Store;
+use crate::core::util::{hostname, now, username};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::thread::{sleep, JoinHandle};
+use std::time::Duration;
+
+/// 发送失败时的最大尝试次数
+const ATTEMPTS: u32 = 3;
+/// 单次请求的超时时间
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 接收仓库事件的地址，事件以json的形式post过去
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Webhook {
+    pub url: String,
+    /// 订阅的事件，为空时接收所有事件
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<EventKind>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Add,
+    Delete,
+    Pull,
+    /// 清理未引用的对象
+    Clear,
+}
+
+/// 发送给webhook的事件
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Event {
+    pub event: EventKind,
+    /// 条目名称，clear时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// 涉及的对象
+    pub hashes: Vec<String>,
+    /// 对象的总大小(字节)
+    pub size: u64,
+    /// 操作者，`用户名@主机名`
+    pub actor: String,
+    /// 事件时间，unix秒
+    pub time: u64,
+}
+
+/// 后台发送中的事件，drop时等待发送完成
+#[derive(Debug, Default)]
+pub struct Notifier {
+    pending: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        let pending = std::mem::take(self.pending.get_mut().unwrap_or_else(|e| e.into_inner()));
+        for handle in pending {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Notifier {
+    /// 在后台线程发送，失败时重试，不影响当前操作
+    fn send(&self, url: String, event: Event) {
+        let handle = std::thread::spawn(move || {
+            for attempt in 1..=ATTEMPTS {
+                let res = ureq::post(&url).timeout(TIMEOUT).send_json(&event);
+                match res {
+                    Ok(_) => {
+                        info!("notified {} of {:?}", url, event.event);
+                        return;
+                    }
+                    Err(e) if attempt < ATTEMPTS => {
+                        warn!("failed to notify {}: {}, retry", url, e);
+                        sleep(Duration::from_secs(1 << attempt));
+                    }
+                    Err(e) => warn!("failed to notify {}: {}", url, e),
+                }
+            }
+        });
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(handle);
+    }
+}
+
+impl Store {
+    /// 通知订阅了该事件的webhook
+    pub(crate) fn notify(&self, kind: EventKind, entry: Option<&Node>, hashes: Vec<String>) {
+        let hooks: Vec<&Webhook> = self
+            .settings()
+            .webhooks
+            .iter()
+            .filter(|w| w.events.is_empty() || w.events.contains(&kind))
+            .collect();
+        if hooks.is_empty() {
+            return;
+        }
+        let size = hashes
+            .iter()
+            .filter_map(|h| self.object_path(h).metadata().ok())
+            .map(|m| m.len())
+            .sum();
+        let event = Event {
+            event: kind,
+            entry: entry.map(|n| n.name.clone()),
+            version: entry.and_then(|n| n.version.clone()),
+            hashes,
+            size,
+            actor: format!("{}@{}", username(), hostname()),
+            time: now(),
+        };
+        for hook in hooks {
+            self.notifier().send(hook.url.clone(), event.clone());
+        }
+    }
+}
+
+/// 条目引用的对象
+pub(crate) fn entry_hashes(node: &Node) -> Vec<String> {
+    let mut hashes = BTreeSet::new();
+    node.walk(&mut |_, n| {
+        if let FILE(hash) = &n.meta {
+            hashes.insert(hash.clone());
+        }
+    });
+    hashes.into_iter().collect()
+}
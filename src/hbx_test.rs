@@ -0,0 +1,221 @@
+//! 集成测试工具，通过 `test-utils` feature 开启：临时仓库、生成目录树和破坏仓库内容
+
+use crate::core::store::Store;
+use crate::LOCK_NAME;
+use anyhow::anyhow;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// 临时目录中的仓库，离开作用域时删除
+pub struct TempStore {
+    pub store: Store,
+    home: TempDir,
+}
+
+impl TempStore {
+    pub fn new() -> anyhow::Result<Self> {
+        let home = tempfile::tempdir()?;
+        let mut store = Store::new(home.path().to_path_buf())?;
+        store.load()?;
+        Ok(Self { store, home })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.home.path()
+    }
+
+    /// 保存后重新打开仓库，模拟另一个进程
+    pub fn reopen(&mut self) -> anyhow::Result<&mut Store> {
+        self.store.save()?;
+        self.store.save_settings()?;
+        let mut store = Store::new(self.home.path().to_path_buf())?;
+        store.load()?;
+        self.store = store;
+        Ok(&mut self.store)
+    }
+
+    /// 生成目录树并add到仓库中，返回条目名称
+    pub fn add_tree(&mut self, spec: &TreeSpec) -> anyhow::Result<String> {
+        let src = tempfile::tempdir()?;
+        let root = src.path().join(&spec.name);
+        generate_tree(&root, spec)?;
+        self.store.add(&root)?;
+        Ok(spec.name.clone())
+    }
+}
+
+/// 生成的目录树的形状，同样的参数总是生成同样的内容
+#[derive(Debug, Clone)]
+pub struct TreeSpec {
+    /// 根目录名，也是add后的条目名称
+    pub name: String,
+    pub seed: u64,
+    /// 目录层级
+    pub depth: usize,
+    /// 每个目录中的子目录数
+    pub dirs: usize,
+    /// 每个目录中的文件数
+    pub files: usize,
+    /// 文件大小范围(字节)
+    pub min_size: usize,
+    pub max_size: usize,
+    /// 内容与之前的文件重复的比例，0到1
+    pub duplicates: f64,
+    /// 每个目录中指向同目录文件的符号链接数
+    pub symlinks: usize,
+}
+
+impl Default for TreeSpec {
+    fn default() -> Self {
+        Self {
+            name: "tree".to_string(),
+            seed: 1,
+            depth: 2,
+            dirs: 2,
+            files: 3,
+            min_size: 0,
+            max_size: 4096,
+            duplicates: 0.0,
+            symlinks: 0,
+        }
+    }
+}
+
+/// xorshift伪随机数，保证测试数据可以复现
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            return 0;
+        }
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// 按 `spec` 在 `root` 生成目录树，返回生成的文件路径
+pub fn generate_tree(root: &Path, spec: &TreeSpec) -> anyhow::Result<Vec<PathBuf>> {
+    let mut rng = Rng(spec.seed.max(1));
+    let mut contents: Vec<Vec<u8>> = Vec::new();
+    let mut files = Vec::new();
+    fill(root, spec, spec.depth, &mut rng, &mut contents, &mut files)?;
+    Ok(files)
+}
+
+fn fill(
+    dir: &Path,
+    spec: &TreeSpec,
+    depth: usize,
+    rng: &mut Rng,
+    contents: &mut Vec<Vec<u8>>,
+    files: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(dir)?;
+    for i in 0..spec.files {
+        let data = if !contents.is_empty() && rng.chance(spec.duplicates) {
+            contents[rng.below(contents.len())].clone()
+        } else {
+            let size = spec.min_size + rng.below(spec.max_size.saturating_sub(spec.min_size) + 1);
+            let data: Vec<u8> = (0..size).map(|_| rng.next() as u8).collect();
+            contents.push(data.clone());
+            data
+        };
+        let path = dir.join(format!("file{}.bin", i));
+        fs::write(&path, data)?;
+        files.push(path);
+    }
+    for i in 0..spec.symlinks.min(spec.files) {
+        let target = PathBuf::from(format!("file{}.bin", i));
+        crate::core::util::symlink(&target, &dir.join(format!("link{}", i)))?;
+    }
+    if depth > 0 {
+        for i in 0..spec.dirs {
+            fill(
+                &dir.join(format!("dir{}", i)),
+                spec,
+                depth - 1,
+                rng,
+                contents,
+                files,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// 翻转对象中间的一个字节，大小不变
+pub fn corrupt_object(store: &Store, hash: &str) -> anyhow::Result<()> {
+    let path = existing_object(store, hash)?;
+    let mut data = fs::read(&path)?;
+    if data.is_empty() {
+        data.push(0);
+    } else {
+        let i = data.len() / 2;
+        data[i] ^= 0xff;
+    }
+    rewrite(&path, &data)
+}
+
+/// 截断对象
+pub fn truncate_object(store: &Store, hash: &str, len: u64) -> anyhow::Result<()> {
+    let path = existing_object(store, hash)?;
+    let file = writable(&path)?;
+    file.set_len(len)?;
+    Ok(())
+}
+
+/// 删除对象，模拟丢失
+pub fn remove_object(store: &Store, hash: &str) -> anyhow::Result<()> {
+    Ok(fs::remove_file(existing_object(store, hash)?)?)
+}
+
+/// 用无法解析的内容覆盖索引
+pub fn corrupt_index(store: &Store) -> anyhow::Result<()> {
+    fs::write(store.config_path(), b"{\"truncated\": [")?;
+    Ok(())
+}
+
+/// 留下一个没有被持有的锁文件，模拟崩溃的进程
+pub fn leave_lock(store: &Store, operation: &str) -> anyhow::Result<()> {
+    let info = serde_json::json!({
+        "pid": u32::MAX,
+        "hostname": "crashed",
+        "operation": operation,
+        "created": 0,
+    });
+    fs::write(store.path().join(LOCK_NAME), info.to_string())?;
+    Ok(())
+}
+
+fn existing_object(store: &Store, hash: &str) -> anyhow::Result<PathBuf> {
+    let path = store.object_path(hash);
+    if !path.is_file() {
+        return Err(anyhow!("object {} not found", hash));
+    }
+    Ok(path)
+}
+
+/// 对象是源文件的硬链接，先断开链接再修改，避免改动源文件
+fn writable(path: &Path) -> anyhow::Result<fs::File> {
+    let data = fs::read(path)?;
+    rewrite(path, &data)?;
+    Ok(OpenOptions::new().write(true).open(path)?)
+}
+
+fn rewrite(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    fs::remove_file(path)?;
+    fs::write(path, data)?;
+    Ok(())
+}
@@ -1,44 +1,1222 @@
 pub mod core;
+#[cfg(feature = "test-utils")]
+pub mod hbx_test;
 
-use crate::core::cli::Commands;
-use clap::Parser;
+use crate::core::cli::{
+    AttestCommands, BackupCommands, Cli, Commands, ConfigCommands, EntryAliasCommands,
+    OutputFormat, QueueCommands,
+};
+use crate::core::download::is_url;
+use crate::core::export::Format;
+use crate::core::filter::Filter;
+use crate::core::info::EntryInfo;
+use crate::core::porcelain::{self, Event};
+use crate::core::store::{AddOptions, Existing, GetOptions, LinkMode, Store};
+use crate::core::stores::StoresConfig;
+use crate::core::stream::StreamFormat;
+use crate::core::util::{format_size, format_time, parse_time};
+use anyhow::{anyhow, bail};
+use clap::{CommandFactory, FromArgMatches};
+use log::{info, warn};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::env;
+use std::fs::{self, read_to_string, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 pub const HBX_HOME_ENV: &str = "HBX_HOME";
+/// 使用的命名仓库，与 `--store` 相同
+pub const HBX_STORE_ENV: &str = "HBX_STORE";
+/// 命名仓库的配置文件
+pub const HBX_STORES_ENV: &str = "HBX_STORES";
 pub const CONFIG_NAME: &str = "config";
 pub const STORE_DIRECTORY: &str = "store";
+pub const SETTINGS_NAME: &str = "settings";
+pub const LOCK_NAME: &str = "lock";
+/// 远程仓库条目列表的缓存目录
+pub const CATALOG_DIRECTORY: &str = "catalogs";
+/// 后台pull队列
+pub const QUEUE_NAME: &str = "queue";
+/// 文件哈希缓存
+pub const HASH_CACHE_NAME: &str = "hash-cache";
+/// 引用数降为0、等待gc的对象列表
+pub const GARBAGE_NAME: &str = "garbage";
+/// 被删除的对象，过期前可以恢复
+pub const TRASH_DIRECTORY: &str = "trash";
+/// 仓库的唯一标识，第一次使用时生成
+pub const STORE_ID_NAME: &str = "id";
+/// 签名attestation的私钥
+pub const ATTEST_KEY_NAME: &str = "attest-key";
+/// 每个条目一个文件的索引目录
+pub const ENTRIES_DIRECTORY: &str = "entries";
+/// SQLite索引
+pub const INDEX_DB_NAME: &str = "index.db";
+/// 条目最后一次get的时间
+pub const ACCESS_NAME: &str = "access";
+pub const ACCESS_LOCK_NAME: &str = "access.lock";
+/// 每个条目单独保存的清单，索引损坏时用来重建
+pub const MANIFEST_DIRECTORY: &str = "manifests";
+/// 未完成的add的进度目录
+pub const SESSION_DIRECTORY: &str = "sessions";
+/// 写入中的临时文件和下载、解包的临时目录，不放在对象目录中
+pub const TMP_DIRECTORY: &str = "tmp";
+/// 目录的最大嵌套层级
+pub const MAX_DEPTH: usize = 256;
+/// 源目录中的忽略规则文件，语法与 `.gitignore` 相同
+pub const IGNORE_NAME: &str = ".hbxignore";
+
+/// 人类可读的输出，porcelain模式下记录下来，命令没有json结果时作为result事件输出
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if porcelain::enabled() {
+            porcelain::line(format!($($arg)*));
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// 输出json结果，porcelain模式下作为result事件输出
+fn print_json<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<()> {
+    if porcelain::enabled() {
+        porcelain::emit(&Event::Result {
+            data: serde_json::to_value(value)?,
+        });
+    } else {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    }
+    Ok(())
+}
 
 pub fn run() -> anyhow::Result<()> {
-    let mut store = core::store::Store::default()?;
-    store.load()?;
-    let cli = core::cli::Cli::parse();
+    let args: Vec<_> = env::args_os().collect();
+    let mut store = match core::cli::store_arg(&args) {
+        Some(name) => Store::named(&name)?,
+        None => Store::default()?,
+    };
+    // 索引损坏时配置已经加载，repair仍然可以执行
+    let loaded = store.load_lazy();
+    let args = core::cli::expand_alias(args, &store.settings().alias)?;
+    let matches = Cli::command().get_matches_from(args);
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    tolerate_broken_index(&cli, loaded)?;
+    if !cli.porcelain {
+        return execute(store, cli);
+    }
+    porcelain::enable();
+    cli.command.set_format(OutputFormat::Json);
+    let start = Instant::now();
+    porcelain::emit(&Event::Start {
+        command: matches.subcommand_name().unwrap_or_default(),
+        pid: std::process::id(),
+    });
+    let res = execute(store, cli);
+    porcelain::flush_lines();
+    porcelain::emit(&Event::Summary {
+        ok: res.is_ok(),
+        elapsed_ms: start.elapsed().as_millis(),
+        error: res.as_ref().err().map(|e| e.to_string()),
+    });
+    res
+}
+
+/// 只有repair可以在索引无法加载时继续
+fn tolerate_broken_index(cli: &Cli, loaded: anyhow::Result<()>) -> anyhow::Result<()> {
+    match (loaded, &cli.command) {
+        (Err(e), Commands::Repair { .. }) => {
+            warn!("the index can not be loaded: {}", e);
+            Ok(())
+        }
+        (res, _) => res,
+    }
+}
+
+fn execute(mut store: Store, cli: Cli) -> anyhow::Result<()> {
+    let wait = store.lock_wait(cli.wait, cli.no_wait)?;
+    let _lock = match cli.command.lock_name() {
+        None => None,
+        Some(operation) => {
+            let lock = store.lock(operation, wait)?;
+            // 加锁期间其他进程可能修改了索引，重新加载
+            tolerate_broken_index(&cli, store.reload())?;
+            store.migrate_layout()?;
+            Some(lock)
+        }
+    };
+    if !cli.command.lazy_index() {
+        tolerate_broken_index(&cli, store.load_all())?;
+    }
     match cli.command {
-        Commands::Add { path } => {
-            store.add(&path)?;
+        Commands::Complete { words } => {
+            for x in core::complete::complete(&store, &words) {
+                say!("{}", x);
+            }
+        }
+        Commands::Add {
+            path,
+            checksum,
+            stdin,
+            format,
+            version,
+            max_file_size,
+            min_file_size,
+            max_depth,
+            exclude_type,
+            exclude,
+            include,
+            dereference,
+            rehash,
+            name,
+            copy_on_cross_device,
+            acl,
+            skip_xattr,
+            tag,
+        } => {
+            let opts = AddOptions {
+                filter: Filter {
+                    max_file_size,
+                    min_file_size,
+                    exclude_types: exclude_type,
+                    exclude,
+                    include,
+                    max_depth,
+                },
+                dereference,
+                rehash,
+                name,
+                copy_on_cross_device,
+                acl,
+                skip_xattrs: skip_xattr,
+                tags: tag,
+                version,
+                provenance: None,
+            };
+            let name = match path {
+                Some(path) if !stdin => match path.to_str().filter(|s| is_url(s)) {
+                    Some(url) => store.add_url(url, checksum.as_deref(), &opts)?,
+                    None if checksum.is_some() => bail!("--checksum only applies to URLs"),
+                    None => {
+                        store.add_with(&path, &opts)?;
+                        opts.entry_name(&path)?
+                    }
+                },
+                _ => {
+                    let input = io::stdin().lock();
+                    match format {
+                        StreamFormat::Tar => store.add_tar(input, &opts)?,
+                        StreamFormat::TarZst => store.add_tar(zstd::Decoder::new(input)?, &opts)?,
+                    }
+                    opts.name.clone().unwrap_or_default()
+                }
+            };
+            store.save()?;
+            if let Some(summary) = store
+                .versions(&name)
+                .last()
+                .and_then(|n| n.summary.as_ref())
+                .filter(|s| !s.skipped.is_empty())
+            {
+                say!("skipped {} paths:", summary.skipped.len());
+                for p in &summary.skipped {
+                    say!("  {}", p.display());
+                }
+            }
+        }
+        Commands::AddGit {
+            url,
+            rev,
+            name,
+            version,
+            tag,
+        } => {
+            let opts = AddOptions {
+                name,
+                version,
+                tags: tag,
+                ..Default::default()
+            };
+            let name = store.add_git(&url, rev.as_deref(), &opts)?;
+            store.save()?;
+            if let Some(p) = store
+                .versions(&name)
+                .last()
+                .and_then(|n| n.summary.as_ref())
+                .and_then(|s| s.provenance.as_ref())
+            {
+                say!(
+                    "{} from {} at {}",
+                    name,
+                    p.origin,
+                    p.commit.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        Commands::Adopt {
+            path,
+            name,
+            replace,
+        } => {
+            let opts = AddOptions {
+                name,
+                ..Default::default()
+            };
+            let report = store.adopt(&path, &opts, replace)?;
+            store.save()?;
+            say!("adopted {:?} as {}", path, report.name);
+            if replace {
+                say!("replaced {} files with links", report.replaced.len());
+            }
+            for rel in &report.skipped {
+                say!(
+                    "kept {:?}, its mode or owner differs from the stored object",
+                    rel
+                );
+            }
+        }
+        Commands::Release { path, name, force } => {
+            match store.release(&path, name.as_deref(), force)? {
+                Some(label) => say!("released {:?}, it matched {}", path, label),
+                None => say!("released {:?} with local changes", path),
+            }
+        }
+        Commands::Get {
+            names,
+            dst,
+            output,
+            at,
+            report,
+            overwrite,
+            skip_existing,
+            merge,
+            name_escape,
+            no_atomic,
+            copy_on_cross_device,
+            copy,
+            symlink,
+            reflink,
+            preserve_times,
+            preserve_owner,
+            skip_xattr,
+            include,
+            exclude,
+            verify,
+        } => {
+            // 兼容 `hbx get <name> <path>` 的旧用法
+            let (names, dst) = match (dst, names.as_slice()) {
+                (None, [name, path])
+                    if store.versions(path).is_empty() && Path::new(path).is_dir() =>
+                {
+                    (vec![name.clone()], Some(PathBuf::from(path)))
+                }
+                (dst, _) => (names, dst),
+            };
+            let mut roots = Vec::new();
+            for name in &names {
+                roots.push(match &at {
+                    None => store.resolve_path(name)?,
+                    Some(at) => Cow::Borrowed(store.version_at(name, parse_time(at)?)?),
+                });
+            }
+            // 恢复到指定的路径，用目标的文件名作为根节点的名称
+            let dst = match (&output, roots.as_mut_slice()) {
+                (None, _) => dst,
+                (Some(output), [root]) => {
+                    let name = output
+                        .file_name()
+                        .ok_or(anyhow!("invalid output path {:?}", output))?;
+                    root.to_mut().name = name.to_string_lossy().to_string();
+                    output
+                        .parent()
+                        .filter(|p| !p.as_os_str().is_empty())
+                        .map(Path::to_path_buf)
+                }
+                (Some(_), _) => bail!("--output takes exactly one entry"),
+            };
+            let roots: Vec<&core::node::Node> = roots.iter().map(|r| r.as_ref()).collect();
+            let opts = GetOptions {
+                collect_conflicts: report.is_some(),
+                existing: match (overwrite, skip_existing, merge) {
+                    (true, _, _) => Existing::Overwrite,
+                    (_, true, _) => Existing::SkipExisting,
+                    (_, _, true) => Existing::Merge,
+                    _ => Existing::Fail,
+                },
+                name_escape,
+                atomic: !no_atomic,
+                copy_on_cross_device,
+                link: match (copy, symlink) {
+                    (true, _) => LinkMode::Copy,
+                    (_, true) => LinkMode::Symlink,
+                    _ => LinkMode::Hard,
+                },
+                reflink,
+                preserve_times,
+                preserve_owner: preserve_owner && core::attrs::can_chown(),
+                skip_xattrs: skip_xattr,
+                include,
+                exclude,
+                verify,
+            };
+            if preserve_owner && !opts.preserve_owner {
+                warn!("only root can restore owners, --preserve-owner is ignored");
+            }
+            let mut conflicts = Vec::new();
+            let mut failed = 0;
+            for (root, res) in roots.iter().zip(store.get_nodes(&roots, dst, &opts)?) {
+                match res {
+                    Ok(r) => {
+                        info!("restored {}", root.label());
+                        conflicts.extend(r.conflicts);
+                    }
+                    Err(e) => {
+                        failed += 1;
+                        warn!("failed to restore {}: {}", root.label(), e);
+                    }
+                }
+            }
+            if let Some(report) = report {
+                fs::write(&report, serde_json::to_string_pretty(&conflicts)?)?;
+                if !conflicts.is_empty() {
+                    bail!("{} conflicts, see {:?}", conflicts.len(), report);
+                }
+            }
+            if failed > 0 {
+                bail!("{} of {} entries failed to restore", failed, roots.len());
+            }
+        }
+        Commands::Has { mut hashes } => {
+            if hashes.is_empty() {
+                for line in io::stdin().lines() {
+                    let line = line?;
+                    hashes.extend(line.split_whitespace().map(String::from));
+                }
+            }
+            let res = store.has_objects(&hashes);
+            for (hash, has) in hashes.iter().zip(&res) {
+                say!("{}\t{}", hash, if *has { "present" } else { "missing" });
+            }
+            if porcelain::enabled() {
+                let ans: Vec<_> = hashes
+                    .iter()
+                    .zip(&res)
+                    .map(|(hash, has)| serde_json::json!({ "hash": hash, "present": has }))
+                    .collect();
+                print_json(&ans)?;
+            }
+            let missing = res.iter().filter(|x| !**x).count();
+            if missing > 0 {
+                bail!("{} of {} objects missing", missing, hashes.len());
+            }
+        }
+        Commands::Versions { name, format } => {
+            store.fetch(&name)?;
+            let versions = store.versions(&name);
+            if versions.is_empty() {
+                bail!("{} not exists, exit!", name);
+            }
+            match format {
+                OutputFormat::Text => {
+                    for n in versions {
+                        say!("{}\t{}", n.label(), format_time(n.added()));
+                    }
+                }
+                OutputFormat::Json => {
+                    let ans: Vec<_> = versions
+                        .iter()
+                        .map(|n| {
+                            serde_json::json!({
+                                "name": n.name,
+                                "version": n.version,
+                                "added": format_time(n.added()),
+                                "added_unix": n.added(),
+                            })
+                        })
+                        .collect();
+                    print_json(&ans)?;
+                }
+            }
+        }
+        Commands::Verify { name: None, format } => {
+            let check = store.verify_store()?;
+            match format {
+                OutputFormat::Text => {
+                    for hash in &check.corrupt {
+                        say!("corrupt\t{}", hash);
+                    }
+                    for m in &check.missing {
+                        say!("missing\t{}\t{}", m.hash, m.entry);
+                    }
+                    for u in &check.unstable {
+                        say!(
+                            "unstable\t{}\t{} files changed during add and were not stored",
+                            u.entry,
+                            u.paths.len()
+                        );
+                    }
+                    say!("checked {} objects", check.objects);
+                }
+                OutputFormat::Json => print_json(&check)?,
+            }
+            if !check.is_ok() {
+                bail!(
+                    "{} corrupt objects, {} missing objects",
+                    check.corrupt.len(),
+                    check.missing.len()
+                );
+            }
+        }
+        Commands::Verify {
+            name: Some(name),
+            format,
+        } => {
+            let checks = store.verify_entry(store.entry(&name)?)?;
+            match format {
+                OutputFormat::Text => {
+                    for c in &checks {
+                        say!("{:?}\t{}\t{}", c.status, c.hash, c.path.display());
+                    }
+                }
+                OutputFormat::Json => print_json(&checks)?,
+            }
+            let bad = checks.iter().filter(|c| c.status.is_bad()).count();
+            if bad > 0 {
+                bail!("{} of {} files failed verification", bad, checks.len());
+            }
+        }
+        Commands::Seal { name, recipient } => {
+            let (name, version) = match name.split_once('@') {
+                None => (name.as_str(), None),
+                Some((n, v)) => (n, Some(v)),
+            };
+            store.seal(name, version, &recipient)?;
             store.save()?;
+            say!("sealed {} for {} recipients", name, recipient.len());
         }
-        Commands::Get { name, path } => {
-            store.get(&name, path)?;
+        Commands::Unseal { name, identity } => {
+            let (name, version) = match name.split_once('@') {
+                None => (name.as_str(), None),
+                Some((n, v)) => (n, Some(v)),
+            };
+            let label = store.unseal(name, version, &identity)?;
+            store.save()?;
+            say!("unsealed {}", label);
         }
         Commands::Delete { name } => {
-            store.delete(&name);
+            store.fetch(name.split_once('@').map_or(name.as_str(), |(n, _)| n))?;
+            match name.split_once('@') {
+                None => store.delete(&name),
+                Some((name, version)) => {
+                    if !store.delete_version(name, Some(version)) {
+                        bail!("{}@{} not exists", name, version);
+                    }
+                }
+            }
+            store.save()?;
+        }
+        Commands::Rename { old, new } => {
+            store.rename(&old, &new)?;
             store.save()?;
         }
-        Commands::List { .. } => {
-            let ans = store.list();
-            for item in ans {
-                println!("{}", item);
+        Commands::EntryAlias { command } => match command {
+            EntryAliasCommands::Add { entry, alias } => {
+                store.add_alias(&entry, &alias)?;
+                store.save()?;
+            }
+            EntryAliasCommands::Remove { alias } => {
+                if !store.remove_alias(&alias)? {
+                    bail!("alias {} not exists", alias);
+                }
+                store.save()?;
+            }
+            EntryAliasCommands::List {} => {
+                for (alias, n) in store.entry_aliases() {
+                    say!("{}\t{}", alias, n.label());
+                }
+            }
+        },
+        Commands::List {
+            prefix,
+            long,
+            tag,
+            count,
+        } => {
+            // 只列出名称时不解析条目
+            if !long && !count && tag.is_empty() && !porcelain::enabled() {
+                for label in store.list_prefix(prefix.as_deref().unwrap_or_default())? {
+                    say!("{}", label);
+                }
+                return Ok(());
+            }
+            store.load_all()?;
+            let ans = match prefix {
+                None => store.entries(),
+                Some(prefix) => store.entries_prefix(&prefix),
+            };
+            let ans: Vec<&core::node::Node> =
+                ans.into_iter().filter(|n| n.has_tags(&tag)).collect();
+            if porcelain::enabled() {
+                let entries: Vec<_> = ans
+                    .iter()
+                    .map(|n| {
+                        serde_json::json!({
+                            "name": n.name,
+                            "version": n.version,
+                            "label": n.label(),
+                            "files": n.files(),
+                            "size": n.size(),
+                            "added": format_time(n.added()),
+                            "added_unix": n.added(),
+                            "tags": n.tags,
+                        })
+                    })
+                    .collect();
+                return print_json(&entries);
+            }
+            if count {
+                let mut names: Vec<&str> = ans.iter().map(|n| n.name.as_str()).collect();
+                names.dedup();
+                for name in names {
+                    let versions = store.versions(name);
+                    let newest = versions.last().map(|n| n.label()).unwrap_or_default();
+                    say!("{}\t{} versions\t{}", name, versions.len(), newest);
+                }
+            } else {
+                for n in ans {
+                    if !long {
+                        say!("{}", n.label());
+                        continue;
+                    }
+                    // 旧版本添加的条目没有记录大小
+                    let files = n.files().map_or(String::from("-"), |f| f.to_string());
+                    let size = n.size().map_or(String::from("-"), format_size);
+                    say!(
+                        "{}\t{}\t{}\t{}\t{}\t{}",
+                        n.label(),
+                        files,
+                        size,
+                        format_time(n.added()),
+                        n.content_id(),
+                        n.tags.join(",")
+                    );
+                }
+            }
+        }
+        Commands::Info { name, top, format } => {
+            store.fetch(&name)?;
+            let info = store.entry_info(store.entry(&name)?)?;
+            match format {
+                OutputFormat::Text => {
+                    say!("{}\tadded {}", info.label, format_time(info.added));
+                    say!(
+                        "{} files, {} directories, {} symlinks, {}",
+                        info.total.files,
+                        info.directories,
+                        info.symlinks,
+                        format_size(info.total.bytes)
+                    );
+                    if let (Some(files), Some(size)) = (info.files, info.size) {
+                        say!("{} files, {} at add", files, format_size(size));
+                    }
+                    if let Some(p) = &info.provenance {
+                        let rev = p.rev.as_deref().unwrap_or("HEAD");
+                        let commit = p.commit.as_deref().unwrap_or("-");
+                        say!("from {} {} ({})", p.origin, rev, commit);
+                    }
+                    for (title, map) in [
+                        ("extension", &info.by_extension),
+                        ("directory", &info.by_directory),
+                    ] {
+                        say!("\nsize\tfiles\t{}", title);
+                        for (k, b) in EntryInfo::top(map, top) {
+                            let k = if k.is_empty() { "(none)" } else { k.as_str() };
+                            say!("{}\t{}\t{}", format_size(b.bytes), b.files, k);
+                        }
+                    }
+                }
+                OutputFormat::Json => print_json(&info)?,
+            }
+        }
+        Commands::DedupReport { top, format } => {
+            let mut report = store.dedup_report()?;
+            report.pairs.truncate(top);
+            match format {
+                OutputFormat::Text => {
+                    say!(
+                        "logical {}, physical {}",
+                        format_size(report.logical),
+                        format_size(report.physical)
+                    );
+                    say!("unique\ttotal\tobjects\tentry");
+                    for u in &report.entries {
+                        say!(
+                            "{}\t{}\t{}\t{}",
+                            format_size(u.unique),
+                            format_size(u.total),
+                            u.objects,
+                            u.label
+                        );
+                    }
+                    if !report.pairs.is_empty() {
+                        say!("shared\tentries");
+                    }
+                    for p in &report.pairs {
+                        say!("{}\t{} {}", format_size(p.shared), p.a, p.b);
+                    }
+                }
+                OutputFormat::Json => print_json(&report)?,
+            }
+        }
+        Commands::Stats { format } => {
+            let stats = store.stats()?;
+            match format {
+                OutputFormat::Text => {
+                    say!("entries\t{}", stats.entries);
+                    say!("files\t{}", stats.files);
+                    say!("blobs\t{}", stats.blobs);
+                    say!("logical\t{}", format_size(stats.logical));
+                    say!("physical\t{}", format_size(stats.physical));
+                    say!("inline\t{}", format_size(stats.inline));
+                    say!("dedup ratio\t{:.2}x", stats.ratio);
+                }
+                OutputFormat::Json => print_json(&stats)?,
+            }
+        }
+        Commands::Hash { path, dereference } => {
+            // 单个文件不内联，直接输出对象名
+            let id = if path.is_dir() {
+                let opts = AddOptions {
+                    dereference,
+                    ..Default::default()
+                };
+                store.scan(&path, &opts)?.content_id()
+            } else {
+                store.hash_algorithm().hash_file(&path)?
+            };
+            say!("{}\t{}", id, path.display());
+            let same: Vec<String> = store
+                .entries()
+                .into_iter()
+                .filter(|n| n.content_id() == id)
+                .map(|n| n.label())
+                .collect();
+            if !same.is_empty() {
+                say!("same as {}", same.join(" "));
+            } else if store.has_object(&id) {
+                say!("stored as an object");
+            }
+        }
+        Commands::Grep {
+            pattern,
+            name,
+            include,
+            ignore_case,
+        } => {
+            let pattern = regex::bytes::RegexBuilder::new(&pattern)
+                .case_insensitive(ignore_case)
+                .build()?;
+            let mut matches = Vec::new();
+            let report = store.grep(&name, &pattern, &include, &mut |m| {
+                if porcelain::enabled() {
+                    matches.extend(serde_json::to_value(m).ok());
+                } else {
+                    say!("{}:{}:{}", m.path.display(), m.line, m.text);
+                }
+            })?;
+            if porcelain::enabled() {
+                print_json(&serde_json::json!({ "matches": matches, "report": report }))?;
+            }
+            for path in &report.binary {
+                info!("skip binary file {:?}", path);
+            }
+            if report.matches == 0 {
+                bail!("no match in {} files", report.files);
+            }
+        }
+        Commands::Why { hash, format } => {
+            let usage = store.why(&hash);
+            match format {
+                OutputFormat::Text => {
+                    for r in &usage.retained_by {
+                        say!("{}\t{}", r.entry, r.path.display());
+                    }
+                    match (usage.present, usage.size) {
+                        (false, _) => say!("{} is not in the store", hash),
+                        (true, size) if usage.collectable() => say!(
+                            "{} ({}) is not referenced, clear will remove it",
+                            hash,
+                            format_size(size.unwrap_or_default())
+                        ),
+                        (true, size) => say!(
+                            "{} ({}) is kept by {} files",
+                            hash,
+                            format_size(size.unwrap_or_default()),
+                            usage.retained_by.len()
+                        ),
+                    }
+                }
+                OutputFormat::Json => print_json(&usage)?,
+            }
+        }
+        Commands::Search {
+            pattern,
+            remote,
+            refresh,
+            format,
+        } => {
+            let hits = store.search(&remote, &pattern, refresh)?;
+            match format {
+                OutputFormat::Text => {
+                    for h in &hits {
+                        let label = match &h.entry.version {
+                            None => h.entry.name.clone(),
+                            Some(v) => format!("{}@{}", h.entry.name, v),
+                        };
+                        say!("{}\t{}\t{}", h.remote, label, format_time(h.entry.added));
+                    }
+                }
+                OutputFormat::Json => print_json(&hits)?,
             }
         }
         Commands::About { .. } => {
-            println!("config {:?}", store.config_path());
-            println!("storage {:?}", store.store_dir());
+            say!("config {:?}", store.config_path());
+            say!("storage {:?}", store.store_dir());
+            if let Some(big) = &store.settings().big_objects {
+                say!("big objects {:?} (>= {} bytes)", big.path, big.threshold);
+            }
+            say!("settings {:?}", store.settings_path());
+            if let Some(e) = &store.settings().encryption {
+                say!("encryption key {:?}", e.key_file);
+            }
+        }
+        Commands::Selfcheck { path, format } => {
+            let caps = store.selfcheck(&path.unwrap_or(PathBuf::from(".")))?;
+            match format {
+                OutputFormat::Text => {
+                    let show = |v: Option<u64>| v.map_or("unknown".to_string(), |v| v.to_string());
+                    say!("path {:?}", caps.to);
+                    say!("same device\t{}", caps.same_device);
+                    say!("hard links\t{}", caps.hard_link);
+                    say!("symlinks\t{}", caps.symlink);
+                    say!("max path\t{}", show(caps.max_path));
+                    say!("max name\t{}", show(caps.max_name));
+                    say!("free inodes\t{}", show(caps.free_inodes));
+                    say!(
+                        "available\t{}",
+                        caps.available.map_or("unknown".to_string(), format_size)
+                    );
+                }
+                OutputFormat::Json => print_json(&caps)?,
+            }
         }
         Commands::Clear { .. } => {
             store.clear()?;
         }
-        Commands::Pull { names, address } => {
-            store.pull(names, address)?;
+        Commands::Gc { dry_run, expire } => {
+            let report = store.gc(dry_run, expire)?;
+            if porcelain::enabled() {
+                return print_json(&report);
+            }
+            for hash in &report.removed {
+                say!("trash\t{}", hash);
+            }
+            for t in &report.expired {
+                say!("purge\t{}", t.hash);
+            }
+            let verb = if dry_run { "would move" } else { "moved" };
+            say!(
+                "{} {} objects ({}) to the trash",
+                verb,
+                report.removed.len(),
+                format_size(report.size)
+            );
+            if expire.is_some() {
+                let verb = if dry_run { "would free" } else { "freed" };
+                say!("{} {} from the trash", verb, format_size(report.freed));
+            }
+        }
+        Commands::RestoreBlob { hashes } => {
+            if hashes.is_empty() {
+                for t in store.trashed()? {
+                    say!(
+                        "{}\t{}\t{}",
+                        t.hash,
+                        format_size(t.size),
+                        format_time(t.deleted)
+                    );
+                }
+            }
+            let mut missing = 0;
+            for hash in &hashes {
+                if store.restore_blob(hash)? {
+                    say!("restored {}", hash);
+                } else {
+                    missing += 1;
+                    warn!("{} is not in the trash", hash);
+                }
+            }
+            if missing > 0 {
+                bail!("{} of {} objects not in the trash", missing, hashes.len());
+            }
+        }
+        Commands::Pull {
+            names,
+            address,
+            background: true,
+            priority,
+        } => {
+            let id = store.enqueue_pull(names, address, priority)?;
+            say!("queued pull job {}, run it with `hbx queue run`", id);
+        }
+        Commands::Pull { names, address, .. } => {
+            let report = store.pull(names, address)?;
+            store.save()?;
+            if porcelain::enabled() {
+                return print_json(&report);
+            }
+            for name in &report.pulled {
+                say!("pulled {}", name);
+            }
+            for name in &report.skipped {
+                say!("{} is up to date", name);
+            }
+            say!(
+                "{} objects downloaded, {} reused",
+                report.downloaded,
+                report.reused
+            );
+        }
+        Commands::Export { name, zip, output } => {
+            let format = if zip { Format::Zip } else { Format::Tar };
+            let output =
+                output.unwrap_or_else(|| PathBuf::from(format!("{}.{}", name, format.extension())));
+            let mut exporter = format.exporter(File::create(&output)?);
+            store.export(&name, exporter.as_mut())?;
+            say!("exported {} to {:?}", name, output);
+        }
+        Commands::ExportStore { output } => {
+            let count = store.snapshot(&output)?;
+            say!("exported store with {} objects to {:?}", count, output);
+        }
+        Commands::ImportStore { input } => {
+            let report = store.import_store(&input)?;
+            store.save()?;
+            for label in &report.skipped {
+                warn!("{} already exists, skip", label);
+            }
+            say!(
+                "imported {} entries and {} objects from {:?}",
+                report.imported.len(),
+                report.objects,
+                input
+            );
+        }
+        Commands::ExportIndex { file } => {
+            let index = store.export_index()?;
+            match file {
+                None => print_json(&index)?,
+                Some(file) => fs::write(file, serde_json::to_string_pretty(&index)?)?,
+            }
+        }
+        Commands::ImportIndex { file } => {
+            let index = serde_json::from_str(&read_to_string(file)?)?;
+            for name in store.import_index(index)? {
+                say!("imported {}", name);
+            }
+            store.save()?;
+        }
+        Commands::Unlock { force } => match store.unlock(force)? {
+            None => say!("store is not locked"),
+            Some(info) => say!("removed lock held by {}", info),
+        },
+        Commands::Farm {
+            entries,
+            dst,
+            format,
+        } => {
+            let report = store.farm(&entries, &dst, &GetOptions::default())?;
+            match format {
+                OutputFormat::Text => {
+                    for (kind, labels) in [
+                        ("added", &report.added),
+                        ("updated", &report.updated),
+                        ("removed", &report.removed),
+                    ] {
+                        for label in labels {
+                            say!("{}\t{}", kind, label);
+                        }
+                    }
+                    say!("{} unchanged", report.unchanged.len());
+                }
+                OutputFormat::Json => print_json(&report)?,
+            }
+        }
+        Commands::Stores {} => {
+            let config = StoresConfig::load()?;
+            for (name, s) in &config.stores {
+                let path = config.resolve(name)?;
+                let active = if path == store.path() { "*" } else { " " };
+                say!("{} {}\t{:?}\t{}", active, name, path, s.path);
+            }
+            if config.stores.is_empty() {
+                say!(
+                    "no named stores, declare them in {:?}",
+                    StoresConfig::path()
+                );
+            }
+        }
+        Commands::Encrypt { key_file } => {
+            let count = store.encrypt_store(&key_file)?;
+            store.save_settings()?;
+            store.save()?;
+            say!("encrypted {} entries", count);
+            if count > 0 {
+                say!("run `hbx gc --expire 0s` to purge the unencrypted objects");
+            }
+        }
+        Commands::Repair {} => {
+            let report = store.repair()?;
+            for label in &report.restored {
+                say!("restored\t{}", label);
+            }
+            for broken in &report.broken {
+                warn!("skip {:?}: {}", broken.path, broken.error);
+            }
+            store.save()?;
+            say!(
+                "restored {} entries, {} already in the index, {} broken manifests",
+                report.restored.len(),
+                report.present,
+                report.broken.len()
+            );
+        }
+        Commands::Relocate { path } => {
+            let from = store.path().to_path_buf();
+            store.relocate(&path)?;
+            say!("store moved to {:?}", store.path());
+            let mut stores = StoresConfig::load()?;
+            let renamed = stores.relocate(&from, store.path())?;
+            if renamed.is_empty() {
+                say!("set {}={:?} to keep using it", HBX_HOME_ENV, store.path());
+            } else {
+                stores.save()?;
+                say!("updated named stores {}", renamed.join(", "));
+            }
+        }
+        Commands::Attest {
+            output,
+            command: None,
+        } => {
+            let attestation = store.attest()?;
+            match output {
+                None => print_json(&attestation)?,
+                Some(file) => fs::write(file, serde_json::to_string_pretty(&attestation)?)?,
+            }
+        }
+        Commands::Attest {
+            command: Some(AttestCommands::Verify { file, format }),
+            ..
+        } => {
+            let old = serde_json::from_str(&read_to_string(&file)?)?;
+            let diff = store.verify_attestation(&old)?;
+            match format {
+                OutputFormat::Text => {
+                    if !diff.same_store {
+                        warn!("{:?} was made for another store", file);
+                    }
+                    if !diff.own_key {
+                        warn!("{:?} was not signed by this store's key", file);
+                    }
+                    for (kind, labels) in [
+                        ("added", &diff.added),
+                        ("removed", &diff.removed),
+                        ("changed", &diff.changed),
+                    ] {
+                        for label in labels {
+                            say!("{}\t{}", kind, label);
+                        }
+                    }
+                }
+                OutputFormat::Json => print_json(&diff)?,
+            }
+            if !diff.unchanged() {
+                bail!(
+                    "{} added, {} removed, {} changed since the attestation",
+                    diff.added.len(),
+                    diff.removed.len(),
+                    diff.changed.len()
+                );
+            }
+        }
+        Commands::Backup { command } => match command {
+            BackupCommands::Add {
+                path,
+                schedule,
+                keep,
+            } => {
+                let name = store.add_backup(&path, &schedule, keep)?;
+                store.save_settings()?;
+                say!("backup {} {} keep {}", name, schedule, keep);
+            }
+            BackupCommands::Remove { name } => {
+                if store.settings_mut().backups.remove(&name).is_none() {
+                    bail!("backup {} not exists", name);
+                }
+                store.save_settings()?;
+            }
+            BackupCommands::List {} => {
+                for (name, b) in &store.settings().backups {
+                    let last = match b.last_run {
+                        0 => String::from("never"),
+                        t => format_time(t),
+                    };
+                    say!(
+                        "{}\t{:?}\t{}\tkeep {}\tlast {}\tversions {}",
+                        name,
+                        b.path,
+                        b.schedule,
+                        b.keep,
+                        last,
+                        store.versions(name).len()
+                    );
+                }
+            }
+            BackupCommands::Run { mut force, watch } => loop {
+                {
+                    let _lock = store.lock("backup", wait)?;
+                    store.reload()?;
+                    let names = store.run_backups(force)?;
+                    if !names.is_empty() {
+                        store.save()?;
+                        store.save_settings()?;
+                    }
+                }
+                force = false;
+                if !watch {
+                    break;
+                }
+                // 最多等待一分钟，以便感知新增的任务
+                let next = store.next_backup().unwrap_or(u64::MAX);
+                let wait = next.saturating_sub(core::util::now()).clamp(1, 60);
+                std::thread::sleep(std::time::Duration::from_secs(wait));
+            },
+        },
+        Commands::Sync {
+            mut files,
+            prefetch_only,
+        } => {
+            if files.is_empty() {
+                files.push(PathBuf::from("hbx.toml"));
+            }
+            for file in files {
+                let report = store.sync(&file, prefetch_only)?;
+                store.save()?;
+                say!(
+                    "{:?}: {} pulled, {} already local, {} restored",
+                    file,
+                    report.pulled.len(),
+                    report.cached.len(),
+                    report.restored.len()
+                );
+            }
         }
+        Commands::Queue { command } => match command {
+            QueueCommands::List {} => {
+                for j in store.pull_queue()?.jobs {
+                    let state = match (&j.last_error, j.failed()) {
+                        (None, _) => String::from("pending"),
+                        (Some(e), false) => format!("retrying: {}", e),
+                        (Some(e), true) => format!("failed: {}", e),
+                    };
+                    say!(
+                        "{}\tpriority {}\t{}\t{}\t{}",
+                        j.id,
+                        j.priority,
+                        j.address,
+                        j.names.join(" "),
+                        state
+                    );
+                }
+            }
+            QueueCommands::Cancel { id } => {
+                if !store.cancel_pull(id)? {
+                    bail!("job {} not exists", id);
+                }
+            }
+            QueueCommands::Run { limit, watch } => loop {
+                let next = {
+                    let _lock = store.lock("pull", wait)?;
+                    store.reload()?;
+                    let next = store.run_next_pull(limit)?;
+                    if let Some((job, Ok(report))) = &next {
+                        store.save()?;
+                        say!("job {} pulled {:?}", job.id, report.pulled);
+                    }
+                    next
+                };
+                // 任务之间释放锁，不阻塞其他命令
+                if next.is_none() {
+                    if !watch {
+                        break;
+                    }
+                    std::thread::sleep(std::time::Duration::from_secs(10));
+                }
+            },
+        },
+        Commands::Alias {
+            name,
+            command,
+            unset,
+        } => match (name, command) {
+            (None, _) => {
+                for (k, v) in &store.settings().alias {
+                    say!("{} = {}", k, v);
+                }
+            }
+            (Some(name), _) if unset => {
+                store.settings_mut().alias.remove(&name);
+                store.save_settings()?;
+            }
+            (Some(name), None) => match store.settings().alias.get(&name) {
+                None => bail!("alias {} not exists", name),
+                Some(v) => say!("{}", v),
+            },
+            (Some(name), Some(command)) => {
+                if core::cli::is_builtin(&name) {
+                    bail!("{} is a builtin command", name);
+                }
+                core::cli::split_alias(&command)?;
+                store.settings_mut().alias.insert(name, command);
+                store.save_settings()?;
+            }
+        },
+        Commands::Config { command } => match command {
+            ConfigCommands::Get { key } => {
+                let value = match key {
+                    None => serde_json::to_value(store.settings())?,
+                    Some(key) => store.settings().get(&key)?,
+                };
+                print_json(&value)?;
+            }
+            ConfigCommands::Set { key, value } => {
+                store.settings_mut().set(&key, &value)?;
+                store.save_settings()?;
+            }
+            ConfigCommands::Export { file } => match file {
+                None => print_json(store.settings())?,
+                Some(file) => store.settings().save(&file)?,
+            },
+            ConfigCommands::Import { file, replace } => {
+                let value: serde_json::Value = serde_json::from_str(&read_to_string(file)?)?;
+                if replace {
+                    *store.settings_mut() = serde_json::from_value(value)?;
+                } else {
+                    store.settings_mut().merge(value)?;
+                }
+                store.save_settings()?;
+            }
+        },
     }
     Ok(())
 }
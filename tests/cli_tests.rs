@@ -0,0 +1,442 @@
+use hbx::core::backend::IndexFormat;
+use hbx::core::cli::{expand_alias, store_arg};
+use hbx::core::complete::complete;
+use hbx::core::store::Store;
+use hbx::core::util::{format_size, format_time};
+use hbx::hbx_test::TempStore;
+use hbx::ENTRIES_DIRECTORY;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use tempfile::tempdir;
+
+fn args(s: &[&str]) -> Vec<OsString> {
+    s.iter().map(OsString::from).collect()
+}
+
+#[test]
+fn test_expand_alias() -> anyhow::Result<()> {
+    let mut alias = BTreeMap::new();
+    alias.insert("up".to_string(), "pull tools 'my host'".to_string());
+    alias.insert("list".to_string(), "about".to_string());
+
+    let res = expand_alias(args(&["hbx", "up", "extra"]), &alias)?;
+    assert_eq!(res, args(&["hbx", "pull", "tools", "my host", "extra"]));
+
+    // 内置命令不会被别名覆盖
+    let res = expand_alias(args(&["hbx", "list"]), &alias)?;
+    assert_eq!(res, args(&["hbx", "list"]));
+
+    let res = expand_alias(args(&["hbx", "unknown"]), &alias)?;
+    assert_eq!(res, args(&["hbx", "unknown"]));
+
+    // 全局选项的值不是子命令
+    let res = expand_alias(args(&["hbx", "--wait", "5s", "up"]), &alias)?;
+    assert_eq!(
+        res,
+        args(&["hbx", "--wait", "5s", "pull", "tools", "my host"])
+    );
+    let res = expand_alias(args(&["hbx", "--no-wait", "up"]), &alias)?;
+    assert_eq!(res, args(&["hbx", "--no-wait", "pull", "tools", "my host"]));
+
+    // 只处理引号，不做shell展开，未加单引号的 `$`、反引号报错
+    alias.insert(
+        "echo".to_string(),
+        "about '$(touch pwned)' \\$HOME".to_string(),
+    );
+    let res = expand_alias(args(&["hbx", "echo"]), &alias)?;
+    assert_eq!(res, args(&["hbx", "about", "$(touch pwned)", "$HOME"]));
+    for value in ["add --name auto-$(date)", "about \"$HOME\"", "about `date`"] {
+        alias.insert("snap".to_string(), value.to_string());
+        assert!(expand_alias(args(&["hbx", "snap"]), &alias).is_err());
+    }
+    alias.insert("broken".to_string(), "about 'open".to_string());
+    assert!(expand_alias(args(&["hbx", "broken"]), &alias).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_complete() -> anyhow::Result<()> {
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("tools");
+    fs::create_dir_all(root.join("bin"))?;
+    fs::write(root.join("bin/run"), "run")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+
+    let words = |s: &[&str]| s.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    assert!(complete(&store, &words(&["ge"])).contains(&"get".to_string()));
+    assert_eq!(complete(&store, &words(&["get", "t"])), vec!["tools"]);
+    assert_eq!(
+        complete(&store, &words(&["get", "tools/"])),
+        vec!["tools/bin/"]
+    );
+    assert_eq!(
+        complete(&store, &words(&["get", "tools/bin/"])),
+        vec!["tools/bin/run"]
+    );
+    assert!(complete(&store, &words(&["get", "--re"])).contains(&"--report".to_string()));
+    assert!(complete(&store, &words(&[""]))
+        .iter()
+        .all(|x| x != "__complete"));
+    Ok(())
+}
+
+#[test]
+fn test_porcelain() -> anyhow::Result<()> {
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let file = src.path().join("tool");
+    fs::write(&file, "porcelain")?;
+    let hbx = |args: &[&str]| -> anyhow::Result<Vec<serde_json::Value>> {
+        let out = std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+            .env("HBX_HOME", home.path())
+            .arg("--porcelain")
+            .args(args)
+            .output()?;
+        let events = String::from_utf8(out.stdout)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        Ok(events)
+    };
+    let events = hbx(&["add", file.to_str().unwrap_or_default()])?;
+    let kinds: Vec<&str> = events.iter().filter_map(|e| e["event"].as_str()).collect();
+    assert_eq!(kinds, vec!["start", "file", "summary"]);
+    assert_eq!(events[0]["command"], "add");
+    assert_eq!(events[1]["entry"], "tool");
+    assert_eq!(events[2]["ok"], true);
+
+    let events = hbx(&["versions", "tool"])?;
+    assert_eq!(events[1]["event"], "result");
+    assert_eq!(events[1]["data"][0]["name"], "tool");
+
+    let events = hbx(&["get", "missing"])?;
+    let last = events.last().cloned().unwrap_or_default();
+    assert_eq!(last["ok"], false);
+    assert!(last["error"].is_string());
+    Ok(())
+}
+
+#[test]
+fn test_porcelain_results() -> anyhow::Result<()> {
+    let home = tempdir()?;
+    let src = tempdir()?;
+    fs::write(src.path().join("kept"), "kept by an entry")?;
+    fs::write(src.path().join("dropped"), "dropped later")?;
+    let hbx = |args: &[&str]| -> anyhow::Result<Vec<serde_json::Value>> {
+        let out = std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+            .env("HBX_HOME", home.path())
+            .arg("--porcelain")
+            .args(args)
+            .output()?;
+        let events = String::from_utf8(out.stdout)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        Ok(events)
+    };
+    let result = |events: &[serde_json::Value]| {
+        events
+            .iter()
+            .find(|e| e["event"] == "result")
+            .map(|e| e["data"].clone())
+            .unwrap_or_default()
+    };
+    for name in ["kept", "dropped"] {
+        hbx(&["add", src.path().join(name).to_str().unwrap_or_default()])?;
+    }
+    hbx(&["delete", "dropped"])?;
+
+    let data = result(&hbx(&["list"])?);
+    assert_eq!(data.as_array().map(Vec::len), Some(1));
+    assert_eq!(data[0]["name"], "kept");
+    assert_eq!(data[0]["files"], 1);
+
+    let data = result(&hbx(&["gc", "--dry-run"])?);
+    assert_eq!(data["removed"].as_array().map(Vec::len), Some(1));
+    assert!(data["size"].as_u64().unwrap_or_default() > 0);
+
+    // 没有json输出的命令，文本行作为result事件输出
+    let data = result(&hbx(&["about"])?);
+    assert!(data["lines"][0]
+        .as_str()
+        .unwrap_or_default()
+        .starts_with("config"));
+    Ok(())
+}
+
+#[test]
+fn test_named_stores() -> anyhow::Result<()> {
+    let res = expand_alias(
+        args(&["hbx", "--store", "up", "up"]),
+        &BTreeMap::from([("up".to_string(), "about".to_string())]),
+    )?;
+    assert_eq!(res, args(&["hbx", "--store", "up", "about"]));
+    assert_eq!(
+        store_arg(&args(&["hbx", "--store=data", "list"])).as_deref(),
+        Some("data")
+    );
+    assert_eq!(store_arg(&args(&["hbx", "get", "--", "--store"])), None);
+
+    let base = tempdir()?;
+    let config = base.path().join("stores.toml");
+    let tools = base.path().join("tools");
+    let data = base.path().join("data");
+    fs::write(
+        &config,
+        format!(
+            "default = \"tools\"\n[stores.tools]\npath = {:?}\n[stores.data]\npath = {:?}\n",
+            tools, data
+        ),
+    )?;
+    let file = base.path().join("model");
+    fs::write(&file, "weights")?;
+    let hbx = |extra: &[&str], store: Option<&str>| -> anyhow::Result<bool> {
+        let mut cmd = std::process::Command::new(env!("CARGO_BIN_EXE_hbx"));
+        cmd.env("HBX_STORES", &config)
+            .env_remove("HBX_HOME")
+            .args(extra);
+        match store {
+            Some(s) => cmd.env("HBX_STORE", s),
+            None => cmd.env_remove("HBX_STORE"),
+        };
+        Ok(cmd.status()?.success())
+    };
+    assert!(hbx(
+        &["--store", "data", "add", file.to_str().unwrap_or_default()],
+        None
+    )?);
+    let mut store = Store::new(data.clone())?;
+    store.load()?;
+    assert!(store.entry("model").is_ok());
+    // 默认仓库中没有
+    assert!(!hbx(&["versions", "model"], None)?);
+    assert!(hbx(&["versions", "model"], Some("data"))?);
+    assert!(!hbx(&["--store", "missing", "about"], None)?);
+
+    // 迁移后stores.toml指向新目录
+    let moved = base.path().join("moved");
+    assert!(hbx(
+        &[
+            "--store",
+            "data",
+            "relocate",
+            moved.to_str().unwrap_or_default()
+        ],
+        None
+    )?);
+    assert!(fs::read_to_string(&config)?.contains(moved.to_str().unwrap_or_default()));
+    assert!(hbx(&["versions", "model"], Some("data"))?);
+    Ok(())
+}
+
+#[test]
+fn test_add_version() -> anyhow::Result<()> {
+    let mut tmp = TempStore::new()?;
+    let src = tempdir()?;
+    let foo = src.path().join("foo");
+    fs::create_dir(&foo)?;
+    let hbx = |extra: &[&str]| -> anyhow::Result<std::process::Output> {
+        Ok(std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+            .env("HBX_HOME", tmp.path())
+            .env_remove("HBX_STORE")
+            .args(extra)
+            .output()?)
+    };
+    let path = foo.to_str().unwrap_or_default();
+    for v in ["1.2.2", "1.2.3"] {
+        fs::write(foo.join("bin"), v)?;
+        assert!(hbx(&["add", path, "--version", v])?.status.success());
+    }
+    assert!(!hbx(&["add", path, "--version", "1 2"])?.status.success());
+    let out = hbx(&["list", "--count"])?;
+    assert_eq!(
+        String::from_utf8(out.stdout)?,
+        "foo\t2 versions\tfoo@1.2.3\n"
+    );
+
+    tmp.store.reload()?;
+    assert_eq!(tmp.store.resolve("foo")?.label(), "foo@1.2.3");
+    assert_eq!(tmp.store.resolve("foo@1.2.2")?.label(), "foo@1.2.2");
+    Ok(())
+}
+
+#[test]
+fn test_list_long() -> anyhow::Result<()> {
+    let mut tmp = TempStore::new()?;
+    let src = tempdir()?;
+    let tool = src.path().join("tool");
+    fs::create_dir(&tool)?;
+    fs::write(tool.join("a"), "a".repeat(2048))?;
+    fs::write(tool.join("b"), "b".repeat(1024))?;
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+        .env("HBX_HOME", tmp.path())
+        .env_remove("HBX_STORE")
+        .args(["add", tool.to_str().unwrap_or_default(), "--tag", "cli"])
+        .status()?;
+    assert!(status.success());
+    let out = std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+        .env("HBX_HOME", tmp.path())
+        .env_remove("HBX_STORE")
+        .args(["list", "--long"])
+        .output()?;
+    let stdout = String::from_utf8(out.stdout)?;
+    let columns: Vec<&str> = stdout.trim_end().split('\t').collect();
+
+    tmp.store.reload()?;
+    let entry = tmp.store.entry("tool")?;
+    assert_eq!(
+        columns,
+        vec![
+            "tool".to_string(),
+            "2".to_string(),
+            format_size(3072),
+            format_time(entry.added()),
+            entry.content_id(),
+            "cli".to_string(),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_list_corrupt_entry() -> anyhow::Result<()> {
+    let mut tmp = TempStore::new()?;
+    let src = tempdir()?;
+    for name in ["a", "b"] {
+        fs::write(src.path().join(name), name)?;
+    }
+    tmp.store.settings_mut().index = IndexFormat::Entries;
+    tmp.store.save_settings()?;
+    for name in ["a", "b"] {
+        tmp.store.add(&src.path().join(name))?;
+    }
+    tmp.store.save()?;
+
+    // 只列出名称时只读取文件名，条目内容损坏也不影响
+    let dir = tmp.path().join(ENTRIES_DIRECTORY);
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with("b."))
+        {
+            fs::write(path, "{")?;
+        }
+    }
+    let hbx = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+            .env("HBX_HOME", tmp.path())
+            .env_remove("HBX_STORE")
+            .args(args)
+            .output()
+    };
+    let out = hbx(&["list"])?;
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(String::from_utf8(out.stdout)?, "a\nb\n");
+    assert!(!hbx(&["list", "--long"])?.status.success());
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_get_access_times() -> anyhow::Result<()> {
+    let mut tmp = TempStore::new()?;
+    let src = tempdir()?;
+    let names: Vec<String> = (0..8).map(|i| format!("tool{}", i)).collect();
+    for name in &names {
+        fs::write(src.path().join(name), name)?;
+        tmp.store.add(&src.path().join(name))?;
+    }
+    tmp.store.save()?;
+
+    // get不加仓库锁，并发记录访问时间时不能互相覆盖
+    let dst = tempdir()?;
+    let children = names
+        .iter()
+        .map(|name| {
+            std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+                .env("HBX_HOME", tmp.path())
+                .env_remove("HBX_STORE")
+                .args(["get", name, "-d", dst.path().to_str().unwrap_or_default()])
+                .spawn()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for mut child in children {
+        assert!(child.wait()?.success());
+    }
+    let times = tmp.store.access_times()?;
+    assert_eq!(times.keys().cloned().collect::<Vec<_>>(), names);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_follow_symlinks() -> anyhow::Result<()> {
+    let mut tmp = TempStore::new()?;
+    let src = tempdir()?;
+    let target = src.path().join("target.txt");
+    fs::write(&target, "content")?;
+    let farm = src.path().join("farm");
+    fs::create_dir(&farm)?;
+    std::os::unix::fs::symlink(&target, farm.join("link"))?;
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+        .env("HBX_HOME", tmp.path())
+        .env_remove("HBX_STORE")
+        .args([
+            "add",
+            "--follow-symlinks",
+            farm.to_str().unwrap_or_default(),
+        ])
+        .status()?;
+    assert!(status.success());
+    tmp.store.reload()?;
+    let dst = tempdir()?;
+    tmp.store.get("farm", Some(dst.path().to_path_buf()))?;
+    let link = dst.path().join("farm/link");
+    assert!(!link.is_symlink());
+    assert_eq!(fs::read_to_string(link)?, "content");
+    Ok(())
+}
+
+#[test]
+fn test_get_subpath() -> anyhow::Result<()> {
+    let src = tempdir()?;
+    let root = src.path().join("toolchain");
+    fs::create_dir_all(root.join("bin"))?;
+    fs::write(root.join("bin/gcc"), "gcc")?;
+    fs::write(root.join("bin/ld"), "ld")?;
+    let mut tmp = TempStore::new()?;
+    tmp.store.add(&root)?;
+    tmp.store.save()?;
+
+    let dst = tempdir()?;
+    let out = dst.path().join("gcc");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+        .env("HBX_HOME", tmp.path())
+        .env_remove("HBX_STORE")
+        .args([
+            "get",
+            "toolchain/bin/gcc",
+            "-o",
+            out.to_str().unwrap_or_default(),
+        ])
+        .status()?;
+    assert!(status.success());
+    assert_eq!(fs::read_to_string(&out)?, "gcc");
+    assert!(!dst.path().join("ld").exists());
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_hbx"))
+        .env("HBX_HOME", tmp.path())
+        .env_remove("HBX_STORE")
+        .args([
+            "get",
+            "toolchain/bin/cc",
+            "-d",
+            dst.path().to_str().unwrap_or_default(),
+        ])
+        .status()?;
+    assert!(!status.success());
+    Ok(())
+}
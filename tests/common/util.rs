@@ -1,7 +1,19 @@
 use std::env::set_var;
+use std::path::Path;
 
 pub(crate) fn set_log() -> anyhow::Result<()> {
     set_var("RUST_LOG", "DEBUG");
-    env_logger::try_init()?;
+    // 同一个测试进程内可能多次调用，重复初始化时忽略
+    let _ = env_logger::builder().is_test(true).try_init();
     Ok(())
 }
+
+/// 对象目录中的对象数，不包括分目录和布局标记
+#[allow(dead_code)]
+pub(crate) fn count_objects(dir: &Path) -> usize {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && e.file_name() != ".sharded")
+        .count()
+}
@@ -1,5 +1,7 @@
 mod common;
 
+use common::util::set_log;
+use hbx::core::util::execute;
 use log::debug;
 use ssh2::Session;
 
@@ -8,7 +10,7 @@ use ssh2::Session;
 /// 使用winget install Microsoft.OpenSSH.Beta
 #[test]
 fn test_ssh_session() -> anyhow::Result<()> {
-    common::util::set_log()?;
+    set_log()?;
     let sess = Session::new()?;
     let mut agent = sess.agent()?;
     agent.connect()?;
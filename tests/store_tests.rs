@@ -0,0 +1,3313 @@
+mod common;
+
+use age::secrecy::ExposeSecret;
+use common::util::{count_objects, set_log};
+use hbx::core::attrs;
+use hbx::core::backend::{IndexFormat, SqliteIndex};
+use hbx::core::export::{TarExporter, ZipExporter};
+use hbx::core::filter::Filter;
+use hbx::core::hash::HashAlgorithm;
+use hbx::core::migration::INDEX_VERSION;
+use hbx::core::names::NameEscape;
+use hbx::core::node::{Meta, Node, Summary};
+use hbx::core::object_filter::FilterRule;
+use hbx::core::quota::{Quota, QuotaPolicy};
+use hbx::core::remote::LocalSource;
+use hbx::core::session::Session;
+use hbx::core::settings::BigObjects;
+use hbx::core::store::{AddOptions, Existing, GetOptions, LinkMode, Reflink, Store};
+use hbx::core::verify::Status;
+use hbx::core::webhook::{Event, EventKind, Webhook};
+use hbx::hbx_test::{
+    corrupt_index, corrupt_object, generate_tree, leave_lock, remove_object, TempStore, TreeSpec,
+};
+use hbx::{ACCESS_NAME, ENTRIES_DIRECTORY, INDEX_DB_NAME};
+use std::fs;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::tempdir;
+
+#[test]
+fn test_inline_small_files() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("tools");
+    fs::create_dir(&root)?;
+    fs::write(root.join("small"), "tiny")?;
+    fs::write(root.join("large"), "x".repeat(1024))?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.settings_mut().inline_threshold = 16;
+    store.add(&root)?;
+    assert_eq!(count_objects(&store.store_dir()), 1);
+
+    store.get("tools", Some(dst.path().to_path_buf()))?;
+    assert_eq!(fs::read_to_string(dst.path().join("tools/small"))?, "tiny");
+    assert_eq!(
+        fs::read_to_string(dst.path().join("tools/large"))?,
+        "x".repeat(1024)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_add_size_filter() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("data");
+    fs::create_dir_all(root.join("out"))?;
+    fs::write(root.join("keep"), "keep")?;
+    fs::write(root.join("out/huge"), "x".repeat(4096))?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    let opts = AddOptions {
+        filter: Filter {
+            max_file_size: Some(1024),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    store.get("data", Some(dst.path().to_path_buf()))?;
+    assert!(dst.path().join("data/keep").exists());
+    assert!(dst.path().join("data/out").is_dir());
+    assert!(!dst.path().join("data/out/huge").exists());
+    Ok(())
+}
+
+#[test]
+fn test_canonical_manifest() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    for name in ["b", "a", "c"] {
+        let root = src.path().join(name);
+        fs::create_dir_all(root.join("z/y"))?;
+        fs::write(root.join("z/y/file"), name)?;
+        fs::write(root.join("m"), "m")?;
+    }
+
+    let mut configs = Vec::new();
+    for order in [["a", "b", "c"], ["c", "a", "b"]] {
+        let home = tempdir()?;
+        let mut store = Store::new(home.path().to_path_buf())?;
+        for name in order {
+            store.add(&src.path().join(name))?;
+        }
+        store.save()?;
+        configs.push(fs::read(store.config_path())?);
+    }
+    assert_eq!(configs[0], configs[1]);
+    Ok(())
+}
+
+#[test]
+fn test_relocate() -> anyhow::Result<()> {
+    set_log()?;
+    let base = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("tools");
+    fs::create_dir(&root)?;
+    fs::write(root.join("a"), "a")?;
+
+    let mut store = Store::new(base.path().join("old"))?;
+    store.settings_mut().big_objects = Some(BigObjects {
+        path: base.path().join("old/big"),
+        threshold: 1,
+    });
+    store.save_settings()?;
+    store.add(&root)?;
+    store.save()?;
+    // 经过符号链接或 `..` 指向仓库内部的目标也要拒绝
+    std::os::unix::fs::symlink(base.path().join("old"), base.path().join("link"))?;
+    for dst in ["link/inner", "link/a/b", "old/../link/inner"] {
+        let err = store.relocate(&base.path().join(dst)).unwrap_err();
+        assert!(err.to_string().contains("inside the store"), "{}", err);
+    }
+    assert!(store
+        .relocate(&base.path().join("missing/../old/inner"))
+        .is_err());
+    assert!(!base.path().join("old/inner").exists());
+    fs::remove_file(base.path().join("link"))?;
+    store.relocate(&base.path().join("new"))?;
+    assert!(!base.path().join("old").exists());
+    assert!(store.config_path().starts_with(base.path().join("new")));
+    assert_eq!(
+        store
+            .settings()
+            .big_objects
+            .as_ref()
+            .map(|b| b.path.clone()),
+        Some(base.path().join("new/big"))
+    );
+
+    let mut store = Store::new(base.path().join("new"))?;
+    store.load()?;
+    store.get("tools", Some(dst.path().to_path_buf()))?;
+    assert_eq!(fs::read_to_string(dst.path().join("tools/a"))?, "a");
+    Ok(())
+}
+
+#[test]
+fn test_store_lock() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let store = Store::new(home.path().to_path_buf())?;
+    let lock = store.lock("add", None)?;
+
+    let err = store.lock("delete", None).unwrap_err().to_string();
+    assert!(err.contains(&format!("pid {}", std::process::id())));
+    assert!(err.contains("(add)"));
+    // 持有锁的进程仍然存活，不能强制清理
+    assert!(store.unlock(true).is_err());
+
+    drop(lock);
+    assert!(!store.lock_path().exists());
+    let _lock = store.lock("delete", Some(Duration::from_millis(200)))?;
+    Ok(())
+}
+
+#[test]
+fn test_store_lock_contention() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let store = Store::new(home.path().to_path_buf())?;
+    // 崩溃的进程留下的锁文件没有被加锁，可以直接获取
+    fs::write(
+        store.lock_path(),
+        r#"{"pid":1,"hostname":"gone","operation":"add","created":0}"#,
+    )?;
+    drop(store.lock("add", None)?);
+
+    let counter = home.path().join("counter");
+    fs::write(&counter, "0")?;
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| -> anyhow::Result<()> {
+                let _lock = store.lock("add", Some(Duration::from_secs(30)))?;
+                let n: u32 = fs::read_to_string(&counter)?.parse()?;
+                std::thread::sleep(Duration::from_millis(5));
+                fs::write(&counter, (n + 1).to_string())?;
+                Ok(())
+            });
+        }
+    });
+    assert_eq!(fs::read_to_string(&counter)?, "8");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_export_archives() -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("tools");
+    fs::create_dir_all(root.join("bin"))?;
+    fs::write(root.join("bin/run"), "#!/bin/sh")?;
+    fs::set_permissions(root.join("bin/run"), fs::Permissions::from_mode(0o755))?;
+    std::os::unix::fs::symlink("bin/run", root.join("run"))?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+
+    let mut buf = Cursor::new(Vec::new());
+    store.export("tools", &mut ZipExporter::new(&mut buf))?;
+    let mut zip = zip::ZipArchive::new(buf)?;
+    let mut file = zip.by_name("tools/bin/run")?;
+    assert_eq!(file.unix_mode().map(|m| m & 0o777), Some(0o755));
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    assert_eq!(content, "#!/bin/sh");
+    drop(file);
+    let mut link = zip.by_name("tools/run")?;
+    let mut target = String::new();
+    link.read_to_string(&mut target)?;
+    assert_eq!(target, "bin/run");
+    drop(link);
+
+    let mut buf = Vec::new();
+    store.export("tools", &mut TarExporter::new(&mut buf))?;
+    let mut names = Vec::new();
+    for entry in tar::Archive::new(buf.as_slice()).entries()? {
+        names.push(entry?.path()?.to_string_lossy().to_string());
+    }
+    assert_eq!(names, ["tools", "tools/bin", "tools/bin/run", "tools/run"]);
+    Ok(())
+}
+
+#[test]
+fn test_backup_retention() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("docs");
+    fs::create_dir(&root)?;
+    fs::write(root.join("a"), "a")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    let name = store.add_backup(&root, "daily", 2)?;
+    assert_eq!(name, "docs");
+    assert_eq!(store.run_backups(false)?, ["docs"]);
+    // 刚执行过，未到期
+    assert!(store.run_backups(false)?.is_empty());
+
+    for v in ["v1", "v2", "v3"] {
+        let opts = AddOptions {
+            version: Some(v.to_string()),
+            ..Default::default()
+        };
+        store.add_with(&root, &opts)?;
+    }
+    assert_eq!(store.versions("docs").len(), 4);
+    assert_eq!(store.prune_versions("docs", 2).len(), 2);
+    let left: Vec<_> = store.versions("docs").iter().map(|n| n.label()).collect();
+    assert_eq!(left.len(), 2);
+    assert_eq!(store.entry("docs")?.label(), left[1]);
+    Ok(())
+}
+
+#[test]
+fn test_has_object() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let file = src.path().join("a");
+    fs::write(&file, "a")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&file)?;
+    let hash = HashAlgorithm::Blake3.hash_file(&file)?;
+    assert!(store.has_object(&hash));
+    assert!(!store.has_object("../config"));
+    assert_eq!(
+        store.has_objects(&[hash.as_str(), "0123abcd"]),
+        [true, false]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_put_object_and_manifest() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let dst = tempdir()?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+
+    let hash = store.put_object("hello".as_bytes())?;
+    assert_eq!(store.put_object(Cursor::new("hello"))?, hash);
+    assert_eq!(count_objects(&store.store_dir()), 1);
+
+    let file = Node::with_meta("hello.txt".to_string(), Meta::FILE(hash));
+    let root = Node::with_meta("generated".to_string(), Meta::DIRECTORY(vec![file]));
+    store.put_manifest(root)?;
+    store.get("generated", Some(dst.path().to_path_buf()))?;
+    assert_eq!(
+        fs::read_to_string(dst.path().join("generated/hello.txt"))?,
+        "hello"
+    );
+
+    let bad = Node::with_meta("bad".to_string(), Meta::FILE("0123".to_string()));
+    assert!(store.put_manifest(bad).is_err());
+    let bad = Node::with_meta("..".to_string(), Meta::DIRECTORY(vec![]));
+    assert!(store.put_manifest(bad).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_get_conflict_report() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(root.join("conf"))?;
+    fs::write(root.join("conf/app.ini"), "new")?;
+    fs::write(root.join("run"), "run")?;
+    fs::create_dir_all(dst.path().join("app/conf"))?;
+    fs::write(dst.path().join("app/conf/app.ini"), "local")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    assert!(store.get("app", Some(dst.path().to_path_buf())).is_err());
+
+    let opts = GetOptions {
+        collect_conflicts: true,
+        ..Default::default()
+    };
+    let conflicts = store
+        .get_node(store.entry("app")?, Some(dst.path().to_path_buf()), &opts)?
+        .conflicts;
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].path, dst.path().join("app/conf/app.ini"));
+    assert_eq!(
+        fs::read_to_string(dst.path().join("app/conf/app.ini"))?,
+        "local"
+    );
+    assert_eq!(fs::read_to_string(dst.path().join("app/run"))?, "run");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_get_escape_names() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(root.join("con"))?;
+    fs::write(root.join("con/a:b"), "x")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    let opts = GetOptions {
+        name_escape: Some(NameEscape::Percent),
+        ..Default::default()
+    };
+    let report = store.get_node(store.entry("app")?, Some(dst.path().to_path_buf()), &opts)?;
+    assert_eq!(report.renamed.len(), 2);
+    assert_eq!(fs::read_to_string(dst.path().join("app/%63on/a%3Ab"))?, "x");
+    let mapping = fs::read_to_string(dst.path().join("app.hbx-names.json"))?;
+    assert!(mapping.contains(r#""%63on/a%3Ab": "a:b""#));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_cycle() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let outside = src.path().join("outside");
+    fs::write(&outside, "outside")?;
+    let root = src.path().join("tree");
+    fs::create_dir_all(root.join("sub"))?;
+    std::os::unix::fs::symlink("..", root.join("sub/loop"))?;
+    std::os::unix::fs::symlink(&outside, root.join("ext"))?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    store.get("tree", Some(dst.path().to_path_buf()))?;
+    assert!(dst.path().join("tree/sub/loop").is_symlink());
+    assert!(dst.path().join("tree/ext").is_symlink());
+
+    let opts = AddOptions {
+        dereference: true,
+        version: Some("deref".to_string()),
+        ..Default::default()
+    };
+    let err = store.add_with(&root, &opts).unwrap_err().to_string();
+    assert!(err.contains("symlink cycle"), "{}", err);
+
+    fs::remove_file(root.join("sub/loop"))?;
+    store.add_with(&root, &opts)?;
+    let dst = tempdir()?;
+    store.get("tree", Some(dst.path().to_path_buf()))?;
+    assert!(!dst.path().join("tree/ext").is_symlink());
+    assert_eq!(fs::read_to_string(dst.path().join("tree/ext"))?, "outside");
+    Ok(())
+}
+
+#[test]
+fn test_depth_limit() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let mut deep = src.path().join("deep");
+    for _ in 0..100 {
+        deep = deep.join("d");
+    }
+    fs::create_dir_all(&deep)?;
+    fs::write(deep.join("f"), "f")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&src.path().join("deep"))?;
+    store.save()?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.load()?;
+    assert_eq!(store.list()?, ["deep"]);
+
+    let mut deeper = src.path().join("deeper");
+    for _ in 0..hbx::MAX_DEPTH + 1 {
+        deeper = deeper.join("d");
+    }
+    fs::create_dir_all(&deeper)?;
+    let err = store
+        .add(&src.path().join("deeper"))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("nesting exceeds"), "{}", err);
+    Ok(())
+}
+
+#[test]
+fn test_big_objects() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let big = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("data");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("small"), "s")?;
+    fs::write(root.join("large"), "x".repeat(100))?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.settings_mut().big_objects = Some(BigObjects {
+        path: big.path().to_path_buf(),
+        threshold: 64,
+    });
+    store.add(&root)?;
+    let large = store.put_object("y".repeat(100).as_bytes())?;
+    assert!(store.object_path(&large).starts_with(big.path()));
+    assert_eq!(count_objects(big.path()), 2);
+    assert_eq!(count_objects(&store.store_dir()), 1);
+    assert!(store.missing_objects().is_empty());
+
+    store.get("data", Some(dst.path().to_path_buf()))?;
+    assert_eq!(
+        fs::read_to_string(dst.path().join("data/large"))?,
+        "x".repeat(100)
+    );
+    store.clear()?;
+    assert_eq!(count_objects(big.path()), 1);
+    Ok(())
+}
+
+#[test]
+fn test_pull_diff() -> anyhow::Result<()> {
+    set_log()?;
+    let remote_home = tempdir()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("tools");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+    fs::write(root.join("b"), "b")?;
+
+    let mut remote = Store::new(remote_home.path().to_path_buf())?;
+    let v1 = AddOptions {
+        version: Some("1".to_string()),
+        ..Default::default()
+    };
+    remote.add_with(&root, &v1)?;
+    remote.save()?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    let names = vec!["tools".to_string()];
+    let report = store.pull_from(&names, &mut LocalSource::new(remote_home.path())?)?;
+    assert_eq!(report.pulled, vec!["tools@1"]);
+    assert_eq!(report.downloaded, 2);
+
+    fs::write(root.join("b"), "b2")?;
+    let v2 = AddOptions {
+        version: Some("2".to_string()),
+        ..Default::default()
+    };
+    remote.add_with(&root, &v2)?;
+    remote.save()?;
+    let report = store.pull_from(&names, &mut LocalSource::new(remote_home.path())?)?;
+    assert_eq!(report.pulled, vec!["tools@2"]);
+    assert_eq!((report.downloaded, report.reused), (1, 1));
+    assert_eq!(store.versions("tools").len(), 2);
+
+    let report = store.pull_from(&names, &mut LocalSource::new(remote_home.path())?)?;
+    assert_eq!(report.skipped, vec!["tools@2"]);
+    assert!(store.missing_objects().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_atomic_get() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+    fs::write(root.join("b"), "b")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    let hash = match &store.entry("app")?.meta {
+        Meta::DIRECTORY(vec) => match &vec[1].meta {
+            Meta::FILE(hash) => hash.clone(),
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+    let object = store.object_path(&hash);
+    fs::rename(&object, home.path().join("moved"))?;
+
+    // 中途失败时目标目录中不会留下任何内容
+    assert!(store.get("app", Some(dst.path().to_path_buf())).is_err());
+    assert_eq!(fs::read_dir(dst.path())?.count(), 0);
+
+    fs::rename(home.path().join("moved"), &object)?;
+    store.get("app", Some(dst.path().to_path_buf()))?;
+    assert_eq!(fs::read_dir(dst.path())?.count(), 1);
+    assert_eq!(fs::read_to_string(dst.path().join("app/b"))?, "b");
+    Ok(())
+}
+
+#[test]
+fn test_atomic_overwrite() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+    fs::write(root.join("b"), "b")?;
+    let mut tmp = TempStore::new()?;
+    tmp.store.settings_mut().inline_threshold = 0;
+    tmp.store.add(&root)?;
+    let copy = GetOptions {
+        link: LinkMode::Copy,
+        ..Default::default()
+    };
+    let entry = tmp.store.entry("app")?.clone();
+    tmp.store
+        .get_node(&entry, Some(dst.path().to_path_buf()), &copy)?;
+    let restored = dst.path().join("app");
+    fs::write(restored.join("a"), "local change")?;
+    fs::write(restored.join("extra"), "local file")?;
+
+    // b的对象缺失，a已经恢复到临时目录后失败
+    let hash = match entry.find(Path::new("b")).map(|n| &n.meta) {
+        Some(Meta::FILE(hash)) => hash.clone(),
+        _ => unreachable!(),
+    };
+    let object = tmp.store.object_path(&hash);
+    let moved = tmp.path().join("moved");
+    fs::rename(&object, &moved)?;
+    let overwrite = GetOptions {
+        existing: Existing::Overwrite,
+        ..copy.clone()
+    };
+    assert!(tmp
+        .store
+        .get_node(&entry, Some(dst.path().to_path_buf()), &overwrite)
+        .is_err());
+    // 已有的目录保持原样，也没有留下临时目录
+    assert_eq!(fs::read_to_string(restored.join("a"))?, "local change");
+    assert_eq!(fs::read_to_string(restored.join("extra"))?, "local file");
+    assert_eq!(fs::read_to_string(restored.join("b"))?, "b");
+    assert_eq!(fs::read_dir(dst.path())?.count(), 1);
+
+    fs::rename(&moved, &object)?;
+    tmp.store
+        .get_node(&entry, Some(dst.path().to_path_buf()), &overwrite)?;
+    assert_eq!(fs::read_to_string(restored.join("a"))?, "a");
+    assert!(!restored.join("extra").exists());
+    Ok(())
+}
+
+#[test]
+fn test_verify_entry() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+    fs::write(root.join("b"), "b")?;
+    fs::write(root.join("c"), "c")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    // 对象与源文件是硬链接，先断开再修改
+    fs::remove_file(root.join("b"))?;
+    fs::remove_file(root.join("c"))?;
+    let checks = store.verify_entry(store.entry("app")?)?;
+    assert!(checks.iter().all(|c| c.status == Status::Ok));
+
+    fs::write(store.object_path(&checks[1].hash), "x")?;
+    fs::remove_file(store.object_path(&checks[2].hash))?;
+    let status: Vec<Status> = store
+        .verify_entry(store.entry("app")?)?
+        .iter()
+        .map(|c| c.status)
+        .collect();
+    assert_eq!(status, vec![Status::Ok, Status::Corrupt, Status::Missing]);
+
+    // add期间变化而没有保存的文件
+    let hash = store.put_object("d".as_bytes())?;
+    let file = Node::with_meta("d".to_string(), Meta::FILE(hash));
+    let mut partial = Node::with_meta("partial".to_string(), Meta::DIRECTORY(vec![file]));
+    partial.summary = Some(Summary {
+        unstable: vec![PathBuf::from("log")],
+        ..Default::default()
+    });
+    store.put_manifest(partial)?;
+    let checks = store.verify_entry(store.entry("partial")?)?;
+    assert_eq!(checks[1].path, PathBuf::from("partial/log"));
+    assert_eq!(checks[1].status, Status::Unstable);
+    assert!(!checks.iter().any(|c| c.status.is_bad()));
+    let check = store.verify_store()?;
+    assert_eq!(check.unstable.len(), 1);
+    assert_eq!(check.unstable[0].entry, "partial");
+    Ok(())
+}
+
+#[test]
+fn test_dedup_report() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("lib"), "x".repeat(100))?;
+    fs::write(root.join("conf"), "v1")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    let version = |v: &str| AddOptions {
+        version: Some(v.to_string()),
+        ..Default::default()
+    };
+    store.add_with(&root, &version("1"))?;
+    fs::remove_file(root.join("conf"))?;
+    fs::write(root.join("conf"), "v2.0")?;
+    store.add_with(&root, &version("2"))?;
+
+    let report = store.dedup_report()?;
+    assert_eq!((report.logical, report.physical), (206, 106));
+    assert_eq!(report.entries[0].label, "app@2");
+    assert_eq!(report.entries[0].unique, 4);
+    assert_eq!(report.pairs.len(), 1);
+    assert_eq!(report.pairs[0].shared, 100);
+
+    let stats = store.stats()?;
+    assert_eq!((stats.entries, stats.files, stats.blobs), (2, 4, 3));
+    assert_eq!((stats.logical, stats.physical), (206, 106));
+    assert!((stats.ratio - 206.0 / 106.0).abs() < 1e-9);
+    Ok(())
+}
+
+#[test]
+fn test_stats() -> anyhow::Result<()> {
+    set_log()?;
+    let mut tmp = TempStore::new()?;
+    tmp.store.settings_mut().inline_threshold = 16;
+    let src = tempdir()?;
+    let files: [(&str, &[(&str, String)]); 3] = [
+        (
+            "a",
+            &[
+                ("shared", "s".repeat(100)),
+                ("only", "a".repeat(50)),
+                ("tiny", "t".to_string()),
+            ],
+        ),
+        (
+            "b",
+            &[("shared", "s".repeat(100)), ("only", "b".repeat(30))],
+        ),
+        ("c", &[("gone", "g".repeat(20))]),
+    ];
+    for (name, content) in files {
+        let root = src.path().join(name);
+        fs::create_dir_all(&root)?;
+        for (file, data) in content {
+            fs::write(root.join(file), data)?;
+        }
+        tmp.store.add(&root)?;
+    }
+    // 删除的条目的对象还没有清理
+    tmp.store.delete("c");
+
+    let stats = tmp.store.stats()?;
+    assert_eq!((stats.entries, stats.files, stats.blobs), (2, 5, 3));
+    assert_eq!(stats.logical, 100 + 50 + 1 + 100 + 30);
+    assert_eq!(stats.inline, 1);
+    assert_eq!(stats.physical, 100 + 50 + 30 + 20);
+    assert!((stats.ratio - 281.0 / 200.0).abs() < 1e-9);
+
+    tmp.store.clear()?;
+    let stats = tmp.store.stats()?;
+    assert_eq!((stats.entries, stats.blobs, stats.physical), (2, 3, 180));
+    Ok(())
+}
+
+#[test]
+fn test_add_rehash() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    let opts = AddOptions {
+        rehash: true,
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    let summary = store.entry("app")?.summary.as_ref().unwrap();
+    assert!(summary.unstable.is_empty());
+    assert!(store.missing_objects().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_readd_creates_version() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    // 内容没有变化时不产生新版本
+    store.add(&root)?;
+    assert_eq!(store.versions("app").len(), 1);
+
+    fs::write(root.join("b"), "b")?;
+    store.add(&root)?;
+    let versions = store.versions("app");
+    assert_eq!(versions.len(), 2);
+    assert!(versions[0].version.is_none());
+    assert!(versions[1].version.is_some());
+
+    let opts = AddOptions {
+        version: Some("1.0".to_string()),
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    assert!(store.add_with(&root, &opts).is_err());
+    assert_eq!(store.versions("app").len(), 3);
+    Ok(())
+}
+
+#[test]
+fn test_hierarchical_names() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("gcc");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("cc"), "cc")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    for name in ["toolchains/gcc/12.3", "toolchains/gcc/13.1", "libs/zlib"] {
+        let opts = AddOptions {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        store.add_with(&root, &opts)?;
+    }
+    assert_eq!(
+        store.list_prefix("toolchains/gcc")?,
+        vec!["toolchains/gcc/12.3", "toolchains/gcc/13.1"]
+    );
+    assert_eq!(store.list_prefix("libs/")?.len(), 1);
+
+    store.get("toolchains/gcc/12.3", Some(dst.path().to_path_buf()))?;
+    assert_eq!(fs::read_to_string(dst.path().join("12.3/cc"))?, "cc");
+
+    let opts = AddOptions {
+        name: Some("bad/../name".to_string()),
+        ..Default::default()
+    };
+    assert!(store.add_with(&root, &opts).is_err());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_export_import_index() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("tools");
+    fs::create_dir_all(root.join("bin"))?;
+    fs::write(root.join("bin/run"), "hello")?;
+    fs::write(root.join("small"), "hi")?;
+    std::os::unix::fs::symlink("bin/run", root.join("link"))?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.settings_mut().inline_threshold = 4;
+    store.add(&root)?;
+    let index = store.export_index()?;
+    let tree = &index.entries[0].tree;
+    assert_eq!(tree.len(), 5);
+    assert_eq!(tree[2].path, "bin/run");
+    assert_eq!(tree[2].size, Some(5));
+    assert_eq!(tree[4].kind, "inline");
+
+    // 导入到共享对象目录的另一个仓库
+    let json = serde_json::to_string(&index)?;
+    let other_home = tempdir()?;
+    let mut other = Store::new(other_home.path().to_path_buf())?;
+    fs::remove_dir(other.store_dir())?;
+    std::os::unix::fs::symlink(store.store_dir(), other.store_dir())?;
+    assert_eq!(
+        other.import_index(serde_json::from_str(&json)?)?,
+        vec!["tools"]
+    );
+    assert!(other.entry("tools")?.same_content(store.entry("tools")?));
+    assert!(other.import_index(serde_json::from_str(&json)?)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_entry_info() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(root.join("lib/deep"))?;
+    fs::create_dir_all(root.join("doc"))?;
+    fs::write(root.join("lib/a.so"), "x".repeat(100))?;
+    fs::write(root.join("lib/deep/b.SO"), "x".repeat(50))?;
+    fs::write(root.join("doc/readme.md"), "readme")?;
+    fs::write(root.join("Makefile"), "all:")?;
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    let info = store.entry_info(store.entry("app")?)?;
+    assert_eq!((info.total.files, info.total.bytes), (4, 160));
+    assert_eq!((info.files, info.size), (Some(4), Some(160)));
+    assert_eq!(info.directories, 3);
+    assert_eq!(info.by_extension["so"].files, 2);
+    assert_eq!(info.by_extension[""].bytes, 4);
+    assert_eq!(info.by_directory["lib"].bytes, 150);
+    assert_eq!(info.by_directory["."].files, 1);
+    Ok(())
+}
+
+#[test]
+fn test_get_many() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    for name in ["a", "b", "c"] {
+        let root = src.path().join(name);
+        fs::create_dir_all(&root)?;
+        fs::write(root.join("file"), name)?;
+        store.add(&root)?;
+    }
+    fs::create_dir(dst.path().join("c"))?;
+
+    let roots = vec![store.entry("a")?, store.entry("b")?, store.entry("c")?];
+    let res = store.get_nodes(
+        &roots,
+        Some(dst.path().to_path_buf()),
+        &GetOptions::default(),
+    )?;
+    assert!(res[0].is_ok() && res[1].is_ok());
+    assert!(res[2].is_err());
+    assert_eq!(fs::read_to_string(dst.path().join("b/file"))?, "b");
+
+    let twice = vec![store.entry("a")?, store.entry("a")?];
+    assert!(store
+        .get_nodes(&twice, None, &GetOptions::default())
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_legacy_md5_store() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+
+    // 模拟旧版本写入的仓库，配置中没有记录算法
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.settings_mut().hash = Some(HashAlgorithm::Md5);
+    store.add(&root)?;
+    store.save()?;
+    assert_eq!(store.objects().iter().next().unwrap().len(), 32);
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.load()?;
+    let checks = store.verify_entry(store.entry("app")?)?;
+    assert_eq!(checks[0].status, Status::Ok);
+
+    // 已有的md5对象照常读取，新对象使用blake3
+    assert_eq!(store.hash_algorithm(), HashAlgorithm::Blake3);
+    fs::write(root.join("b"), "b")?;
+    store.add(&root)?;
+    store.save()?;
+    let b = HashAlgorithm::Blake3.hash_file(&root.join("b"))?;
+    assert!(store.has_object(&b));
+    assert!(store.objects().iter().any(|x| x.len() == 32));
+    let dst = tempdir()?;
+    store.get("app", Some(dst.path().to_path_buf()))?;
+    assert_eq!(fs::read_to_string(dst.path().join("app/a"))?, "a");
+    assert_eq!(fs::read_to_string(dst.path().join("app/b"))?, "b");
+
+    let mut fresh = Store::new(tempdir()?.path().to_path_buf())?;
+    fresh.load()?;
+    assert_eq!(fresh.hash_algorithm(), HashAlgorithm::Blake3);
+    Ok(())
+}
+
+#[test]
+fn test_resume_add() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+    fs::write(root.join("b"), "b")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+
+    // 模拟中断前已计算过a的哈希
+    let a = root.join("a").canonicalize()?;
+    let b = root.join("b").canonicalize()?;
+    let stamp = (1, fs::metadata(&a)?.modified().ok());
+    let fake = "f".repeat(64);
+    let mut session = Session::open(&store, &root)?;
+    session.record(&a, stamp, &fake)?;
+    session.record(&b, (9, None), &fake)?;
+    session.save()?;
+
+    store.add(&root)?;
+    let mut hashes = Vec::new();
+    store.entry("app")?.walk(&mut |_, n| {
+        if let Meta::FILE(hash) = &n.meta {
+            hashes.push(hash.clone());
+        }
+    });
+    // b在中断后被修改，需要重新计算
+    assert_eq!(hashes[0], fake);
+    assert_eq!(hashes[1], HashAlgorithm::Blake3.hash_file(&b)?);
+    // 完成后不再保留进度
+    assert!(fs::read_dir(home.path().join("sessions"))?.next().is_none());
+    Ok(())
+}
+
+#[test]
+fn test_hash_algorithms() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+
+    let expected = [
+        (
+            HashAlgorithm::Md5,
+            "0cc175b9c0f1b6a831c399e269772661".to_string(),
+        ),
+        (
+            HashAlgorithm::Sha256,
+            "sha256-ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb".to_string(),
+        ),
+        (
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Blake3.hash_file(&root.join("a"))?,
+        ),
+        (
+            HashAlgorithm::Xxh3,
+            HashAlgorithm::Xxh3.hash_file(&root.join("a"))?,
+        ),
+    ];
+    for (algorithm, hash) in expected {
+        let home = tempdir()?;
+        let mut store = Store::new(home.path().to_path_buf())?;
+        store
+            .settings_mut()
+            .set("hash", &format!("{:?}", algorithm).to_lowercase())?;
+        store.add(&root)?;
+        assert_eq!(HashAlgorithm::of(&hash), Some(algorithm));
+        assert!(store.has_object(&hash));
+        let checks = store.verify_entry(store.entry("app")?)?;
+        assert_eq!(checks[0].status, Status::Ok);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_webhook() -> anyhow::Result<()> {
+    set_log()?;
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/hook", listener.local_addr()?);
+    let server = std::thread::spawn(move || -> anyhow::Result<String> {
+        let (mut stream, _) = listener.accept()?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            if let Some(v) = line.to_lowercase().strip_prefix("content-length:") {
+                len = v.trim().parse()?;
+            }
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")?;
+        Ok(String::from_utf8(body)?)
+    });
+
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.settings_mut().webhooks.push(Webhook {
+        url,
+        events: vec![EventKind::Add],
+    });
+    store.add(&root)?;
+    // 没有订阅delete
+    store.delete("app");
+    drop(store);
+
+    let event: Event = serde_json::from_str(&server.join().unwrap()?)?;
+    assert_eq!(event.event, EventKind::Add);
+    assert_eq!(event.entry.as_deref(), Some("app"));
+    assert_eq!(event.hashes.len(), 1);
+    assert_eq!(event.size, 1);
+    Ok(())
+}
+
+#[test]
+fn test_snapshot() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    // 没有被引用的对象不导出
+    store.put_object("orphan".as_bytes())?;
+
+    let out = tempdir()?;
+    let file = out.path().join("store-backup.tar.zst");
+    assert_eq!(store.snapshot(&file)?, 1);
+
+    let restored = tempdir()?;
+    let decoder = zstd::Decoder::new(fs::File::open(&file)?)?;
+    tar::Archive::new(decoder).unpack(restored.path())?;
+    let mut copy = Store::new(restored.path().to_path_buf())?;
+    copy.load()?;
+    assert_eq!(copy.list()?, vec!["app"]);
+    assert_eq!(count_objects(&copy.store_dir()), 1);
+    let checks = copy.verify_entry(copy.entry("app")?)?;
+    assert_eq!(checks[0].status, Status::Ok);
+
+    // 合并到另一个仓库，已有的条目不覆盖
+    let other = tempdir()?;
+    let mut imported = Store::new(other.path().to_path_buf())?;
+    let report = imported.import_store(&file)?;
+    assert_eq!(report.imported, vec!["app"]);
+    assert_eq!((report.objects, report.settings), (1, true));
+    imported.save()?;
+    let report = imported.import_store(&file)?;
+    assert_eq!((report.skipped.len(), report.objects), (1, 0));
+    let mut imported = Store::new(other.path().to_path_buf())?;
+    imported.load()?;
+    assert!(imported.verify_store()?.is_ok());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_adopt() -> anyhow::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let old = src.path().join("old");
+    let root = src.path().join("app");
+    fs::create_dir_all(&old)?;
+    fs::create_dir_all(&root)?;
+    fs::write(old.join("a"), "x")?;
+    fs::write(root.join("b"), "x")?;
+    fs::write(root.join("c"), "y")?;
+    // 内容相同但权限不同
+    fs::write(root.join("d"), "x")?;
+    fs::set_permissions(root.join("d"), fs::Permissions::from_mode(0o600))?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&old)?;
+
+    let opts = AddOptions {
+        name: Some("tools/app".to_string()),
+        ..Default::default()
+    };
+    let report = store.adopt(&root, &opts, true)?;
+    assert_eq!(report.name, "tools/app");
+    // c在add时已经链接到仓库，只有b需要替换
+    assert_eq!(report.replaced, vec![Path::new("b")]);
+    assert_eq!(
+        fs::metadata(root.join("b"))?.ino(),
+        fs::metadata(old.join("a"))?.ino()
+    );
+    assert_eq!(fs::read_to_string(root.join("b"))?, "x");
+    assert_eq!(report.skipped, vec![Path::new("d")]);
+    assert_eq!(fs::metadata(root.join("d"))?.mode() & 0o777, 0o600);
+    assert_eq!(fs::metadata(root.join("d"))?.nlink(), 1);
+    assert_eq!(fs::read_dir(&root)?.count(), 3);
+    Ok(())
+}
+
+#[test]
+fn test_release() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(root.join("sub"))?;
+    fs::write(root.join("a"), "a")?;
+    fs::write(root.join("sub/b"), "b")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+
+    let dst = tempdir()?;
+    let restored = dst.path().join("app");
+    store.get("app", Some(dst.path().to_path_buf()))?;
+    assert_eq!(
+        store.release(&restored, None, false)?,
+        Some("app".to_string())
+    );
+    assert!(!restored.exists());
+
+    store.get("app", Some(dst.path().to_path_buf()))?;
+    fs::write(restored.join("new"), "new")?;
+    fs::remove_file(restored.join("a"))?;
+    assert_eq!(
+        store.local_changes(store.entry("app")?, &restored)?,
+        vec![restored.join("new")]
+    );
+    assert!(store.release(&restored, None, false).is_err());
+    assert!(restored.exists());
+    assert_eq!(store.release(&restored, None, true)?, None);
+    assert!(!restored.exists());
+    // 对象仍在仓库中
+    assert!(store.missing_objects().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_release_filtered() -> anyhow::Result<()> {
+    set_log()?;
+    let mut tmp = TempStore::new()?;
+    tmp.store.settings_mut().object_filters.push(FilterRule {
+        pattern: "logs*".to_string(),
+        filters: vec!["crlf".to_string(), "zstd".to_string()],
+    });
+    let src = tempdir()?;
+    let root = src.path().join("logs");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a.log"), "line\r\n".repeat(100))?;
+    tmp.store.add(&root)?;
+
+    // 对象是编码后的内容，与恢复的文件不同
+    let dst = tempdir()?;
+    let restored = dst.path().join("logs");
+    tmp.store.get("logs", Some(dst.path().to_path_buf()))?;
+    assert_eq!(
+        tmp.store.release(&restored, None, false)?,
+        Some("logs".to_string())
+    );
+    assert!(!restored.exists());
+
+    tmp.store.get("logs", Some(dst.path().to_path_buf()))?;
+    fs::write(restored.join("a.log"), "changed")?;
+    assert_eq!(
+        tmp.store
+            .local_changes(tmp.store.entry("logs")?, &restored)?,
+        vec![restored.join("a.log")]
+    );
+    assert!(tmp.store.release(&restored, None, false).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_verify_store() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+    fs::write(root.join("b"), "b")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    assert!(store.verify_store()?.is_ok());
+
+    let mut hashes: Vec<String> = store.objects().into_iter().collect();
+    hashes.sort();
+    // 对象是源文件的硬链接，先断开再修改
+    let corrupt = store.object_path(&hashes[0]);
+    fs::remove_file(&corrupt)?;
+    fs::write(&corrupt, "broken")?;
+    fs::remove_file(store.object_path(&hashes[1]))?;
+
+    let check = store.verify_store()?;
+    assert_eq!(check.objects, 1);
+    assert_eq!(check.corrupt, vec![hashes[0].clone()]);
+    assert_eq!(check.missing.len(), 1);
+    assert_eq!(check.missing[0].hash, hashes[1]);
+    assert_eq!(check.missing[0].entry, "app");
+    assert!(!check.is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_seal() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "secret")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    let plain = store.objects();
+
+    let key = age::x25519::Identity::generate();
+    let identity = src.path().join("key.txt");
+    fs::write(&identity, key.to_string().expose_secret())?;
+    store.seal("app", None, &[key.to_public().to_string()])?;
+    assert!(store.entry("app")?.sealed.is_some());
+    assert!(store
+        .get("app", Some(tempdir()?.path().to_path_buf()))
+        .is_err());
+    // 明文对象不再被引用
+    store.clear()?;
+    assert!(plain.iter().all(|h| !store.has_object(h)));
+
+    let other = age::x25519::Identity::generate();
+    let wrong = src.path().join("other.txt");
+    fs::write(&wrong, other.to_string().expose_secret())?;
+    assert!(store.unseal("app", None, &wrong).is_err());
+
+    assert_eq!(store.unseal("app", None, &identity)?, "app");
+    assert!(store.entry("app")?.sealed.is_none());
+    let dst = tempdir()?;
+    store.get("app", Some(dst.path().to_path_buf()))?;
+    assert_eq!(fs::read_to_string(dst.path().join("app/a"))?, "secret");
+    Ok(())
+}
+
+#[test]
+fn test_object_filters() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("logs");
+    fs::create_dir_all(&root)?;
+    let text = "line\r\n".repeat(1000);
+    fs::write(root.join("a.log"), &text)?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.settings_mut().object_filters.push(FilterRule {
+        pattern: "log*".to_string(),
+        filters: vec!["crlf".to_string(), "zstd".to_string()],
+    });
+    store.add(&root)?;
+    let entry = store.entry("logs")?;
+    assert_eq!(entry.filters, vec!["crlf", "zstd"]);
+    let hash = store.objects().into_iter().next().unwrap();
+    assert!(fs::metadata(store.object_path(&hash))?.len() < text.len() as u64 / 10);
+    assert!(store.verify_store()?.is_ok());
+
+    let dst = tempdir()?;
+    store.get("logs", Some(dst.path().to_path_buf()))?;
+    assert_eq!(
+        fs::read_to_string(dst.path().join("logs/a.log"))?,
+        "line\n".repeat(1000)
+    );
+
+    let mut tar = Vec::new();
+    store.export("logs", &mut TarExporter::new(&mut tar))?;
+    let mut archive = tar::Archive::new(tar.as_slice());
+    let mut file = archive
+        .entries()?
+        .filter_map(|e| e.ok())
+        .find(|e| e.path().map(|p| p.ends_with("a.log")).unwrap_or(false))
+        .unwrap();
+    let mut s = String::new();
+    file.read_to_string(&mut s)?;
+    assert_eq!(s, "line\n".repeat(1000));
+    Ok(())
+}
+
+#[test]
+fn test_hash_cache() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "a")?;
+    fs::write(root.join("b"), "b")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+
+    // 用伪造的哈希确认第二次add没有重新计算
+    let a = HashAlgorithm::Blake3.hash_file(&root.join("a"))?;
+    let fake = "e".repeat(64);
+    let cache = home.path().join("hash-cache");
+    fs::write(&cache, fs::read_to_string(&cache)?.replace(&a, &fake))?;
+    fs::write(root.join("b"), "changed")?;
+    let opts = AddOptions {
+        version: Some("2".to_string()),
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    let mut hashes = Vec::new();
+    store.entry_version("app", Some("2"))?.walk(&mut |_, n| {
+        if let Meta::FILE(hash) = &n.meta {
+            hashes.push(hash.clone());
+        }
+    });
+    assert_eq!(hashes[0], fake);
+    assert_eq!(hashes[1], HashAlgorithm::Blake3.hash_file(&root.join("b"))?);
+    Ok(())
+}
+
+#[test]
+fn test_tree_hash() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    for dir in ["one", "two", "three"] {
+        let root = src.path().join(dir);
+        fs::create_dir_all(root.join("sub"))?;
+        fs::write(root.join("a"), "a")?;
+        fs::write(root.join("sub/b"), "b")?;
+    }
+    fs::rename(src.path().join("three/a"), src.path().join("three/c"))?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    for dir in ["one", "two", "three"] {
+        store.add(&src.path().join(dir))?;
+    }
+    let one = store.entry("one")?;
+    let two = store.entry("two")?;
+    let three = store.entry("three")?;
+    assert!(one.tree.is_some());
+    // 顶层目录名不影响树哈希，子节点的名称会影响
+    assert_eq!(one.tree, two.tree);
+    assert_ne!(one.tree, three.tree);
+    let same: Vec<String> = store
+        .find_same_content(one)
+        .iter()
+        .map(|n| n.label())
+        .collect();
+    assert_eq!(same, vec!["one", "two"]);
+
+    store.save()?;
+    let mut loaded = Store::new(home.path().to_path_buf())?;
+    loaded.load()?;
+    assert_eq!(loaded.entry("one")?.tree, one.tree);
+    Ok(())
+}
+
+#[test]
+fn test_why() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(root.join("sub"))?;
+    fs::write(root.join("a"), "x")?;
+    fs::write(root.join("sub/b"), "x")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    let hash = store.objects().into_iter().next().unwrap();
+
+    let usage = store.why(&hash);
+    let paths: Vec<PathBuf> = usage.retained_by.iter().map(|r| r.path.clone()).collect();
+    assert_eq!(
+        paths,
+        vec![PathBuf::from("app/a"), PathBuf::from("app/sub/b")]
+    );
+    assert_eq!(usage.size, Some(1));
+    assert!(!usage.collectable());
+
+    store.delete("app");
+    assert!(store.why(&hash).collectable());
+    store.clear()?;
+    assert!(!store.why(&hash).present);
+    Ok(())
+}
+
+#[test]
+fn test_search() -> anyhow::Result<()> {
+    set_log()?;
+    let remote_home = tempdir()?;
+    let src = tempdir()?;
+    for dir in ["gcc", "clang", "gcc-docs"] {
+        fs::create_dir_all(src.path().join(dir))?;
+        fs::write(src.path().join(dir).join("a"), dir)?;
+    }
+    let mut remote = Store::new(remote_home.path().to_path_buf())?;
+    remote.add(&src.path().join("gcc"))?;
+    remote.add(&src.path().join("clang"))?;
+    remote.save()?;
+
+    let home = tempdir()?;
+    let store = Store::new(home.path().to_path_buf())?;
+    let address = vec![remote_home.path().to_string_lossy().to_string()];
+    let names = |hits: Vec<hbx::core::search::SearchHit>| -> Vec<String> {
+        hits.into_iter().map(|h| h.entry.name).collect()
+    };
+    assert_eq!(names(store.search(&address, "cc", false)?), vec!["gcc"]);
+
+    // 缓存未过期时看不到远程仓库的新条目
+    remote.add(&src.path().join("gcc-docs"))?;
+    remote.save()?;
+    assert_eq!(names(store.search(&address, "gcc*", false)?), vec!["gcc"]);
+    assert_eq!(
+        names(store.search(&address, "gcc*", true)?),
+        vec!["gcc", "gcc-docs"]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_pull_queue() -> anyhow::Result<()> {
+    set_log()?;
+    let remote_home = tempdir()?;
+    let src = tempdir()?;
+    for dir in ["low", "high"] {
+        fs::create_dir_all(src.path().join(dir))?;
+        fs::write(src.path().join(dir).join("a"), dir)?;
+    }
+    let mut remote = Store::new(remote_home.path().to_path_buf())?;
+    remote.add(&src.path().join("low"))?;
+    remote.add(&src.path().join("high"))?;
+    remote.save()?;
+
+    let home = tempdir()?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    let address = remote_home.path().to_string_lossy().to_string();
+    let low = store.enqueue_pull(vec!["low".to_string()], address.clone(), 0)?;
+    let high = store.enqueue_pull(vec!["high".to_string()], address.clone(), 5)?;
+    let missing = store.enqueue_pull(vec!["missing".to_string()], address, 1)?;
+    assert_eq!(store.pull_queue()?.jobs.len(), 3);
+
+    let (job, res) = store.run_next_pull(Some(1024 * 1024))?.unwrap();
+    assert_eq!(job.id, high);
+    assert_eq!(res?.pulled, vec!["high"]);
+    let (job, res) = store.run_next_pull(None)?.unwrap();
+    assert_eq!(job.id, missing);
+    assert!(res.is_err());
+    assert_eq!(
+        store
+            .pull_queue()?
+            .jobs
+            .iter()
+            .find(|j| j.id == missing)
+            .unwrap()
+            .attempts,
+        1
+    );
+
+    assert!(store.cancel_pull(missing)?);
+    assert!(!store.cancel_pull(missing)?);
+    let (job, _) = store.run_next_pull(None)?.unwrap();
+    assert_eq!(job.id, low);
+    assert!(store.run_next_pull(None)?.is_none());
+    assert_eq!(store.list()?, vec!["high", "low"]);
+    Ok(())
+}
+
+#[test]
+fn test_scan() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(root.join("sub"))?;
+    fs::write(root.join("sub/a"), "a")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+
+    let scanned = store.scan(&root, &AddOptions::default())?;
+    assert!(store.objects().is_empty());
+    assert!(!home.path().join("store").exists() || count_objects(&store.store_dir()) == 0);
+    store.add(&root)?;
+    assert_eq!(scanned.tree, store.entry("app")?.tree);
+    assert_eq!(store.find_same_content(&scanned).len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_sync() -> anyhow::Result<()> {
+    set_log()?;
+    let remote_home = tempdir()?;
+    let src = tempdir()?;
+    let gcc = src.path().join("gcc");
+    fs::create_dir_all(&gcc)?;
+    fs::write(gcc.join("a"), "a")?;
+    let mut remote = Store::new(remote_home.path().to_path_buf())?;
+    let opts = AddOptions {
+        version: Some("13".to_string()),
+        name: Some("toolchains/gcc".to_string()),
+        ..Default::default()
+    };
+    remote.add_with(&gcc, &opts)?;
+    remote.save()?;
+
+    let project = tempdir()?;
+    let file = project.path().join("hbx.toml");
+    fs::write(
+        &file,
+        format!(
+            "remote = {:?}\n\n[entries]\n\"toolchains/gcc\" = {{ version = \"13\", path = \"tools/gcc\" }}\n",
+            remote_home.path()
+        ),
+    )?;
+    let home = tempdir()?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    let report = store.sync(&file, true)?;
+    assert_eq!(report.pulled, vec!["toolchains/gcc@13"]);
+    assert!(report.restored.is_empty());
+    assert!(!project.path().join("tools").exists());
+
+    let report = store.sync(&file, false)?;
+    assert_eq!(report.cached, vec!["toolchains/gcc@13"]);
+    assert_eq!(report.restored, vec![project.path().join("tools/gcc")]);
+    assert_eq!(fs::read_to_string(project.path().join("tools/gcc/a"))?, "a");
+    Ok(())
+}
+
+#[test]
+fn test_gc() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("a"), "shared")?;
+    fs::write(root.join("b"), "only app")?;
+    fs::write(root.join("c"), "shared")?;
+    let other = src.path().join("other");
+    fs::create_dir_all(&other)?;
+    fs::write(other.join("a"), "shared")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+    store.add(&other)?;
+    let shared = HashAlgorithm::default().hash_file(&root.join("a"))?;
+    let only = HashAlgorithm::default().hash_file(&root.join("b"))?;
+    assert_eq!(store.refcount(&shared)?, 3);
+
+    store.delete("app");
+    store.save()?;
+    assert_eq!(store.refcount(&shared)?, 1);
+    assert_eq!(store.refcount(&only)?, 0);
+
+    // 重新加载后仍然知道哪些对象等待gc
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.load()?;
+    let report = store.gc(true, None)?;
+    assert_eq!(report.removed, vec![only.clone()]);
+    assert_eq!(report.size, 8);
+    assert!(store.has_object(&only));
+
+    let report = store.gc(false, None)?;
+    assert_eq!(report.removed, vec![only.clone()]);
+    assert!(!store.has_object(&only));
+    assert!(store.has_object(&shared));
+    assert!(store.gc(false, None)?.removed.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_trash() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let file = src.path().join("tool");
+    fs::write(&file, "precious")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&file)?;
+    let hash = store.entry("tool")?.content_id();
+
+    store.delete("tool");
+    store.clear()?;
+    assert!(!store.has_object(&hash));
+    let trashed = store.trashed()?;
+    assert_eq!(trashed.len(), 1);
+    assert_eq!(
+        (trashed[0].hash.as_str(), trashed[0].size),
+        (hash.as_str(), 8)
+    );
+
+    assert!(store.restore_blob(&hash)?);
+    assert!(store.has_object(&hash));
+    assert!(store.trashed()?.is_empty());
+    assert!(!store.restore_blob(&hash)?);
+
+    store.clear()?;
+    let report = store.gc(false, Some(Duration::from_secs(3600)))?;
+    assert!(report.expired.is_empty());
+    let report = store.gc(true, Some(Duration::ZERO))?;
+    assert_eq!(report.freed, 8);
+    assert_eq!(store.trashed()?.len(), 1);
+    store.gc(false, Some(Duration::ZERO))?;
+    assert!(store.trashed()?.is_empty());
+    assert!(!store.trash_dir().read_dir()?.any(|_| true));
+    Ok(())
+}
+
+#[test]
+fn test_grep() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let root = src.path().join("sdk");
+    fs::create_dir_all(root.join("include"))?;
+    fs::write(
+        root.join("include/sdk.h"),
+        "#pragma once\r\nint sdk_init(void);\n",
+    )?;
+    fs::write(root.join("notes.txt"), "call sdk_init first\n")?;
+    fs::write(root.join("libsdk.so"), b"\x7fELF\0sdk_init")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&root)?;
+
+    let pattern = regex::bytes::Regex::new("sdk_init")?;
+    let mut found = Vec::new();
+    let report = store.grep("sdk", &pattern, &[], &mut |m| {
+        found.push((m.path.clone(), m.line, m.text.clone()))
+    })?;
+    assert_eq!(report.binary, vec![PathBuf::from("libsdk.so")]);
+    assert_eq!(report.files, 2);
+    assert_eq!(
+        found,
+        vec![
+            (
+                PathBuf::from("include/sdk.h"),
+                2,
+                String::from("int sdk_init(void);")
+            ),
+            (
+                PathBuf::from("notes.txt"),
+                1,
+                String::from("call sdk_init first")
+            ),
+        ]
+    );
+
+    let report = store.grep("sdk", &pattern, &[String::from("*.h")], &mut |_| {})?;
+    assert_eq!((report.files, report.matches), (1, 1));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_copy_on_cross_device() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    // 需要另一个文件系统，没有时跳过
+    let shm = Path::new("/dev/shm");
+    if !shm.is_dir() || hbx::core::util::same_device(shm, home.path()) {
+        return Ok(());
+    }
+    let src = tempfile::tempdir_in(shm)?;
+    let file = src.path().join("tool");
+    fs::write(&file, "far away")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    let opts = AddOptions {
+        copy_on_cross_device: Some(false),
+        ..Default::default()
+    };
+    assert!(store.add_with(&file, &opts).is_err());
+    assert!(store.objects().is_empty());
+
+    store.settings_mut().copy_on_cross_device = Some(false);
+    assert!(store.add(&file).is_err());
+    let opts = AddOptions {
+        copy_on_cross_device: Some(true),
+        ..Default::default()
+    };
+    store.add_with(&file, &opts)?;
+    let hash = store.entry("tool")?.content_id();
+    assert!(!hbx::core::util::same_file(
+        &file,
+        &store.object_path(&hash)
+    ));
+
+    let dst = tempfile::tempdir_in(shm)?;
+    assert!(store.get("tool", Some(dst.path().to_path_buf())).is_err());
+    let opts = GetOptions {
+        copy_on_cross_device: Some(true),
+        ..Default::default()
+    };
+    store.get_node(store.entry("tool")?, Some(dst.path().to_path_buf()), &opts)?;
+    assert_eq!(fs::read_to_string(dst.path().join("tool"))?, "far away");
+    Ok(())
+}
+
+#[test]
+fn test_attest() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    for name in ["a", "b", "c"] {
+        fs::write(src.path().join(name), name)?;
+    }
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&src.path().join("a"))?;
+    store.add(&src.path().join("b"))?;
+    let old = store.attest()?;
+    old.check()?;
+    assert_eq!(old.statement.store, store.store_id()?);
+    assert_eq!(old.statement.entries.len(), 2);
+    let diff = store.verify_attestation(&old)?;
+    assert!(diff.unchanged() && diff.same_store && diff.own_key);
+
+    store.delete("a");
+    fs::write(src.path().join("b"), "changed")?;
+    store.add(&src.path().join("b"))?;
+    store.add(&src.path().join("c"))?;
+    let diff = store.verify_attestation(&old)?;
+    // 修改后的b是新版本
+    let latest = store.entry("b")?.label();
+    assert_eq!(diff.added, vec![latest.as_str(), "c"]);
+    assert_eq!(diff.removed, vec!["a"]);
+    assert!(diff.changed.is_empty());
+
+    let mut forged = old.clone();
+    forged.statement.entries.remove("a");
+    assert!(store.verify_attestation(&forged).is_err());
+
+    let other = tempdir()?;
+    let diff = Store::new(other.path().to_path_buf())?.verify_attestation(&old)?;
+    assert!(!diff.same_store && !diff.own_key);
+    Ok(())
+}
+
+#[test]
+fn test_reflink() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let file = src.path().join("tool");
+    fs::write(&file, "cow")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.add(&file)?;
+    let object = store.object_path(&store.entry("tool")?.content_id());
+    // 先确认临时目录的文件系统是否支持reflink
+    let probe = tempdir()?;
+    let supported = hbx::core::util::reflink(&object, &probe.path().join("probe")).is_ok();
+
+    // 不支持reflink时退回硬链接
+    let dst = tempdir()?;
+    let opts = GetOptions {
+        reflink: Reflink::Auto,
+        ..Default::default()
+    };
+    store.get_node(store.entry("tool")?, Some(dst.path().to_path_buf()), &opts)?;
+    let restored = dst.path().join("tool");
+    assert_eq!(fs::read_to_string(&restored)?, "cow");
+    assert_eq!(
+        hbx::core::util::same_file(&object, &restored),
+        !supported && cfg!(unix)
+    );
+
+    // 不支持reflink时报错，不会留下文件
+    let dst = tempdir()?;
+    let opts = GetOptions {
+        reflink: Reflink::Always,
+        ..Default::default()
+    };
+    let res = store.get_node(store.entry("tool")?, Some(dst.path().to_path_buf()), &opts);
+    let restored = dst.path().join("tool");
+    if supported {
+        res?;
+        assert_eq!(fs::read_to_string(&restored)?, "cow");
+        assert!(!hbx::core::util::same_file(&object, &restored));
+    } else {
+        assert!(res.is_err());
+        assert!(!restored.exists());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+#[test]
+fn test_windows_attributes() -> anyhow::Result<()> {
+    use std::os::windows::fs::MetadataExt;
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let dst = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("secret"), "x")?;
+    std::process::Command::new("attrib")
+        .arg("+h")
+        .arg(root.join("secret"))
+        .status()?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    let opts = AddOptions {
+        acl: true,
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    let hash = store.objects().into_iter().next().unwrap();
+    let stored = store.object_path(&hash).metadata()?.file_attributes();
+    store.get("app", Some(dst.path().to_path_buf()))?;
+    let restored = dst.path().join("app/secret");
+    let attrs = restored.metadata()?.file_attributes();
+    assert_ne!(attrs & 0x2, 0);
+    // 有windows属性的文件复制而不是硬链接，修改恢复的文件不影响对象
+    std::process::Command::new("attrib")
+        .arg("-h")
+        .arg(&restored)
+        .status()?;
+    let attrs = store.object_path(&hash).metadata()?.file_attributes();
+    assert_eq!(attrs, stored);
+    Ok(())
+}
+
+#[test]
+fn test_sharded_layout() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let file = src.path().join("tool");
+    fs::write(&file, "sharded")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&file)?;
+    store.save()?;
+    let hash = store.entry("tool")?.content_id();
+    let sharded = store.store_dir().join(&hash[..2]).join(&hash[2..]);
+    assert_eq!(store.object_path(&hash), sharded);
+
+    // 旧仓库的对象平铺在store目录中
+    let flat = store.store_dir().join(&hash);
+    fs::rename(&sharded, &flat)?;
+    assert!(store.has_object(&hash));
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.load()?;
+    // 只有持有锁时才迁移
+    assert!(flat.is_file() && store.has_object(&hash));
+    let lock = store.lock("test", None)?;
+    store.migrate_layout()?;
+    drop(lock);
+    assert!(!flat.exists() && sharded.is_file());
+    assert_eq!(store.stored_files()?, vec![(hash.clone(), sharded)]);
+
+    store.delete("tool");
+    store.clear()?;
+    assert_eq!(count_objects(&store.store_dir()), 0);
+    Ok(())
+}
+
+#[test]
+fn test_parallel_hash() -> anyhow::Result<()> {
+    let src = tempdir()?;
+    let spec = TreeSpec {
+        depth: 4,
+        dirs: 2,
+        files: 5,
+        duplicates: 0.3,
+        symlinks: 1,
+        ..Default::default()
+    };
+    let root = src.path().join(&spec.name);
+    generate_tree(&root, &spec)?;
+    let mut parallel = TempStore::new()?;
+    parallel.store.add(&root)?;
+    // 只有一个线程时按顺序计算哈希
+    let mut sequential = TempStore::new()?;
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()?
+        .install(|| sequential.store.add(&root))?;
+    let flatten = |store: &Store| -> anyhow::Result<Vec<(PathBuf, String, Option<String>)>> {
+        let mut ans = Vec::new();
+        store.entry(&spec.name)?.walk(&mut |rel, n| {
+            ans.push((rel.to_path_buf(), format!("{:?}", n.meta), n.tree.clone()))
+        });
+        Ok(ans)
+    };
+    let expected = flatten(&sequential.store)?;
+    assert!(expected.len() > 50);
+    assert_eq!(flatten(&parallel.store)?, expected);
+    assert_eq!(
+        parallel.store.entry(&spec.name)?.tree,
+        sequential.store.entry(&spec.name)?.tree
+    );
+    Ok(())
+}
+
+#[test]
+fn test_temp_files_not_objects() -> anyhow::Result<()> {
+    let mut t = TempStore::new()?;
+    t.add_tree(&TreeSpec::default())?;
+    t.store.save()?;
+    let stored = t.store.stored_files()?;
+    let shard = stored[0]
+        .1
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    // 其他进程写入中的临时文件
+    let temps = [
+        t.store.store_dir().join(".tmpAbc123"),
+        shard.join(".tmpXyz789"),
+    ];
+    for temp in &temps {
+        fs::write(temp, "partial")?;
+    }
+    assert_eq!(t.store.stored_files()?, stored);
+    assert!(t.store.verify_store()?.is_ok());
+    t.store.clear()?;
+    assert!(temps.iter().all(|p| p.is_file()));
+    assert_eq!(t.store.stored_files()?, stored);
+    Ok(())
+}
+
+#[test]
+fn test_sqlite_index() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let (a, b) = (src.path().join("a"), src.path().join("b"));
+    fs::write(&a, "first")?;
+    fs::write(&b, "second")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&a)?;
+    store.save()?;
+
+    // 切换后从json索引导入
+    store.settings_mut().index = IndexFormat::Sqlite;
+    store.save_settings()?;
+    store.add(&b)?;
+    store.save()?;
+    assert!(home.path().join(INDEX_DB_NAME).is_file());
+    assert!(!store.config_path().exists());
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.load()?;
+    assert_eq!(store.entries().len(), 2);
+    let labels = store.index_backend()?.labels()?;
+    assert_eq!(
+        labels,
+        vec![("a".to_string(), None), ("b".to_string(), None)]
+    );
+    let db = SqliteIndex::new(home.path().join(INDEX_DB_NAME), store.config_path());
+    let hash = store.entry("a")?.content_id();
+    assert_eq!(db.refcount(&hash)?, 1);
+
+    store.delete("a");
+    store.save()?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.load()?;
+    assert_eq!(store.entries().len(), 1);
+    assert_eq!(db.refcount(&hash)?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_sqlite_lazy() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let (app, other) = (src.path().join("app"), src.path().join("other"));
+    fs::create_dir_all(&app)?;
+    fs::create_dir_all(&other)?;
+    fs::write(app.join("a"), "shared")?;
+    fs::write(app.join("b"), "only app")?;
+    fs::write(other.join("a"), "shared")?;
+    let mut tmp = TempStore::new()?;
+    tmp.store.settings_mut().index = IndexFormat::Sqlite;
+    tmp.store.save_settings()?;
+    tmp.store.add(&app)?;
+    tmp.store.add(&other)?;
+    tmp.store.save()?;
+    let shared = HashAlgorithm::default().hash_file(&app.join("a"))?;
+    let only = HashAlgorithm::default().hash_file(&app.join("b"))?;
+
+    // 其他条目损坏也不影响，说明没有解析整个索引
+    let db = rusqlite::Connection::open(tmp.path().join(INDEX_DB_NAME))?;
+    db.execute("UPDATE nodes SET manifest = '{' WHERE name = 'other'", [])?;
+    let mut store = Store::new(tmp.path().to_path_buf())?;
+    store.load_lazy()?;
+    assert!(store.entries().is_empty());
+    assert_eq!(store.list()?, vec!["app", "other"]);
+    store.fetch("app")?;
+    assert_eq!(store.entry("app")?.files(), Some(2));
+    assert_eq!(store.refcount(&shared)?, 2);
+
+    store.delete("app");
+    assert_eq!(store.list()?, vec!["other"]);
+    assert_eq!(store.refcount(&shared)?, 1);
+    store.save()?;
+    assert!(store.load().is_err());
+
+    let labels: Vec<String> = db
+        .prepare("SELECT name FROM nodes")?
+        .query_map([], |r| r.get(0))?
+        .collect::<Result<_, _>>()?;
+    assert_eq!(labels, vec!["other"]);
+    let mut store = Store::new(tmp.path().to_path_buf())?;
+    store.load_lazy()?;
+    assert_eq!(store.refcount(&shared)?, 1);
+    assert_eq!(store.refcount(&only)?, 0);
+    assert_eq!(store.gc(true, None)?.removed, vec![only]);
+    Ok(())
+}
+
+#[test]
+fn test_selfcheck() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    fs::create_dir(src.path().join("pkg"))?;
+    fs::write(src.path().join("pkg/file"), "selfcheck")?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&src.path().join("pkg"))?;
+
+    let dst = tempdir()?;
+    let caps = store.selfcheck(dst.path())?;
+    assert!(caps.same_device && caps.hard_link && caps.symlink);
+    assert!(caps.max_name.is_some());
+    // 探测不会留下文件
+    assert_eq!(fs::read_dir(dst.path())?.count(), 0);
+
+    let mut short = caps.clone();
+    short.max_name = Some(3);
+    let root = store.entry("pkg")?;
+    assert!(short.check_tree(root, &dst.path().join("pkg")).is_err());
+    assert!(caps.check_tree(root, &dst.path().join("pkg")).is_ok());
+
+    // /dev/shm 通常是另一个文件系统
+    let shm = Path::new("/dev/shm");
+    if shm.is_dir() && !hbx::core::util::same_device(shm, home.path()) {
+        let other = tempfile::tempdir_in(shm)?;
+        let caps = store.selfcheck(other.path())?;
+        assert!(!caps.same_device && !caps.hard_link);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_encryption() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let keys = tempdir()?;
+    let secret = "proprietary binary";
+    fs::write(src.path().join("tool"), secret)?;
+    fs::write(src.path().join("later"), secret.repeat(2))?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.add(&src.path().join("tool"))?;
+    store.save()?;
+    let plain = store.entry("tool")?.content_id();
+
+    let key = keys.path().join("key");
+    assert!(store.encrypt_store(&home.path().join("key")).is_err());
+    assert_eq!(store.encrypt_store(&key)?, 1);
+    store.save_settings()?;
+    store.add(&src.path().join("later"))?;
+    store.save()?;
+    store.gc(false, Some(Duration::ZERO))?;
+    assert!(!store.has_object(&plain));
+    for (_, path) in store.stored_files()? {
+        assert!(!fs::read_to_string(path)
+            .unwrap_or_default()
+            .contains(secret));
+    }
+    assert!(!fs::read(store.config_path())?
+        .windows(secret.len())
+        .any(|w| w == secret.as_bytes()));
+
+    let mut store = Store::new(home.path().to_path_buf())?;
+    store.load()?;
+    assert_eq!(store.entry("later")?.filters, vec!["age"]);
+    let dst = tempdir()?;
+    store.get("tool", Some(dst.path().to_path_buf()))?;
+    store.get("later", Some(dst.path().to_path_buf()))?;
+    assert_eq!(fs::read_to_string(dst.path().join("tool"))?, secret);
+    assert_eq!(
+        fs::read_to_string(dst.path().join("later"))?,
+        secret.repeat(2)
+    );
+    assert!(store.verify_store()?.is_ok());
+
+    // 没有私钥时无法读取索引
+    fs::remove_file(&key)?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    assert!(store.load().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_farm() -> anyhow::Result<()> {
+    set_log()?;
+    let home = tempdir()?;
+    let src = tempdir()?;
+    let mut store = Store::new(home.path().to_path_buf())?;
+    for (name, content) in [("gcc", "v1"), ("make", "make")] {
+        let dir = src.path().join(name);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("bin"), content)?;
+        store.add(&dir)?;
+    }
+    let farm = tempdir()?;
+    let dst = farm.path().join("tools");
+    let opts = GetOptions::default();
+    let names = vec!["gcc".to_string(), "make".to_string()];
+    let report = store.farm(&names, &dst, &opts)?;
+    assert_eq!(report.added, names);
+    assert_eq!(fs::read_to_string(dst.join("gcc/bin"))?, "v1");
+
+    // 只替换变化的条目
+    fs::write(src.path().join("gcc/bin"), "v2")?;
+    store.add(&src.path().join("gcc"))?;
+    let report = store.farm(&[], &dst, &opts)?;
+    assert!(report.updated.len() == 1 && report.updated[0].starts_with("gcc@"));
+    assert_eq!(report.unchanged, vec!["make"]);
+    assert_eq!(fs::read_to_string(dst.join("gcc/bin"))?, "v2");
+
+    let report = store.farm(&["make".to_string()], &dst, &opts)?;
+    assert_eq!(report.removed, vec!["gcc"]);
+    assert!(!dst.join("gcc").exists() && dst.join("make/bin").is_file());
+    let mut names: Vec<_> = fs::read_dir(&dst)?
+        .map(|e| e.map(|e| e.file_name()))
+        .collect::<Result<_, _>>()?;
+    names.sort();
+    assert_eq!(names, vec![".hbx-farm.json", "make"]);
+    Ok(())
+}
+
+#[test]
+fn test_harness() -> anyhow::Result<()> {
+    set_log()?;
+    let mut tmp = TempStore::new()?;
+    let spec = TreeSpec {
+        duplicates: 0.5,
+        symlinks: 1,
+        min_size: 1,
+        ..Default::default()
+    };
+    let name = tmp.add_tree(&spec)?;
+    let store = tmp.reopen()?;
+    let entry = store.entry(&name)?;
+    let mut files = 0;
+    entry.walk(&mut |_, n| files += matches!(n.meta, Meta::FILE(_)) as usize);
+    // 2层目录，每层2个子目录，共7个目录，每个目录3个文件
+    assert_eq!(files, 21);
+    assert!(store.objects().len() < files);
+    assert!(store.verify_store()?.is_ok());
+
+    // 同样的参数生成同样的内容
+    let a = tempdir()?;
+    let b = tempdir()?;
+    let files = generate_tree(a.path(), &spec)?;
+    generate_tree(b.path(), &spec)?;
+    for f in &files {
+        let rel = f.strip_prefix(a.path())?;
+        assert_eq!(fs::read(f)?, fs::read(b.path().join(rel))?);
+    }
+
+    let mut objects: Vec<String> = store.objects().into_iter().collect();
+    objects.sort();
+    corrupt_object(store, &objects[0])?;
+    remove_object(store, &objects[1])?;
+    let check = store.verify_store()?;
+    assert_eq!(check.corrupt, vec![objects[0].clone()]);
+    assert_eq!(check.missing.len(), 1);
+
+    leave_lock(store, "add")?;
+    drop(store.lock("delete", None)?);
+    corrupt_index(store)?;
+    assert!(Store::new(tmp.path().to_path_buf())?.load().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_quota() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    for name in ["a", "b", "c"] {
+        fs::write(src.path().join(name), name.repeat(100))?;
+    }
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.settings_mut().quota = Some(Quota {
+        max_size: 250,
+        policy: QuotaPolicy::Refuse,
+    });
+    store.add(&src.path().join("a"))?;
+    store.add(&src.path().join("b"))?;
+    store.save()?;
+    let err = store.add(&src.path().join("c")).unwrap_err();
+    assert!(err.to_string().contains("quota exceeded"));
+    assert!(store.entry("c").is_err());
+
+    // a最近get过，淘汰b
+    store.get("a", Some(tempdir()?.path().to_path_buf()))?;
+    assert!(store.access_times()?.contains_key("a"));
+    // 与add在同一秒内，改为更晚的时间
+    fs::write(store.path().join(ACCESS_NAME), r#"{"a":4102444800}"#)?;
+    store.settings_mut().quota.as_mut().unwrap().policy = QuotaPolicy::EvictLru;
+    let evicted = store.entry("b")?.content_id();
+    store.add(&src.path().join("c"))?;
+    store.save()?;
+    assert_eq!(store.list()?, vec!["a", "c"]);
+    assert_eq!(store.physical_size()?, 200);
+    assert!(store.missing_objects().is_empty());
+    // 淘汰的对象与gc一样移到回收站，可以恢复
+    let trashed: Vec<String> = store.trashed()?.into_iter().map(|t| t.hash).collect();
+    assert_eq!(trashed, vec![evicted.clone()]);
+    assert!(store.restore_blob(&evicted)?);
+
+    // pull同样检查容量，超出时不下载对象
+    let mut remote = TempStore::new()?;
+    fs::write(src.path().join("e"), "e".repeat(100))?;
+    remote.store.add(&src.path().join("e"))?;
+    remote.store.save()?;
+    let e = remote.store.entry("e")?.content_id();
+    store.settings_mut().quota.as_mut().unwrap().policy = QuotaPolicy::Refuse;
+    let names = vec!["e".to_string()];
+    let err = store
+        .pull_from(&names, &mut LocalSource::new(remote.path())?)
+        .unwrap_err();
+    assert!(err.to_string().contains("quota exceeded"));
+    assert!(!store.has_object(&e));
+
+    // 新条目加入失败时不淘汰任何条目
+    store.settings_mut().quota.as_mut().unwrap().policy = QuotaPolicy::EvictLru;
+    let d = store.put_object(Cursor::new("d".repeat(100)))?;
+    let conflict = Node::with_meta("a".to_string(), Meta::FILE(d));
+    let err = store.put_manifest(conflict).unwrap_err();
+    assert!(err.to_string().contains("conflict"));
+    assert_eq!(store.list()?, vec!["a", "c"]);
+    assert!(store.missing_objects().is_empty());
+    assert!(store.trashed()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_repair() -> anyhow::Result<()> {
+    set_log()?;
+    let mut tmp = TempStore::new()?;
+    let name = tmp.add_tree(&TreeSpec::default())?;
+    let src = tempdir()?;
+    fs::write(src.path().join("gone"), "gone")?;
+    let store = tmp.reopen()?;
+    store.add(&src.path().join("gone"))?;
+    store.save()?;
+    store.delete("gone");
+    store.save()?;
+    assert_eq!(fs::read_dir(store.manifest_dir())?.count(), 1);
+
+    corrupt_index(store)?;
+    let mut store = Store::new(tmp.path().to_path_buf())?;
+    assert!(store.load().is_err());
+    let report = store.repair()?;
+    assert_eq!(report.restored, vec![name.clone()]);
+    assert!(report.broken.is_empty());
+    store.save()?;
+
+    let mut store = Store::new(tmp.path().to_path_buf())?;
+    store.load()?;
+    assert_eq!(store.list()?, vec![name.clone()]);
+    assert!(store.verify_store()?.is_ok());
+    let report = store.repair()?;
+    assert_eq!((report.restored.len(), report.present), (0, 1));
+    Ok(())
+}
+
+#[test]
+fn test_index_migration() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    fs::write(src.path().join("tool"), "tool")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.add(&src.path().join("tool"))?;
+    store.save()?;
+    let saved: serde_json::Value = serde_json::from_slice(&fs::read(store.config_path())?)?;
+    assert_eq!(saved["version"], INDEX_VERSION);
+
+    // 第一版的索引是条目数组
+    fs::write(store.config_path(), serde_json::to_vec(&saved["entries"])?)?;
+    let mut store = Store::new(tmp.path().to_path_buf())?;
+    store.load()?;
+    assert!(store.entry("tool").is_ok());
+    store.save()?;
+    let saved: serde_json::Value = serde_json::from_slice(&fs::read(store.config_path())?)?;
+    assert_eq!(saved["version"], INDEX_VERSION);
+
+    fs::write(store.config_path(), r#"{"version": 999, "entries": []}"#)?;
+    let err = Store::new(tmp.path().to_path_buf())?.load().unwrap_err();
+    assert!(err.to_string().contains("upgrade hbx"));
+    Ok(())
+}
+
+#[test]
+fn test_msgpack_index() -> anyhow::Result<()> {
+    set_log()?;
+    let mut tmp = TempStore::new()?;
+    let name = tmp.add_tree(&TreeSpec {
+        symlinks: 1,
+        ..Default::default()
+    })?;
+    let store = tmp.reopen()?;
+    let json = fs::metadata(store.config_path())?.len();
+    let entry = store.entry(&name)?.clone();
+
+    store.settings_mut().index = IndexFormat::Msgpack;
+    store.save_settings()?;
+    store.save()?;
+    let data = fs::read(store.config_path())?;
+    assert!(serde_json::from_slice::<serde_json::Value>(&data).is_err());
+    assert!((data.len() as u64) < json);
+
+    // 读取时自动识别格式，与配置无关
+    for format in [IndexFormat::Msgpack, IndexFormat::Json] {
+        let store = tmp.reopen()?;
+        assert_eq!(store.settings().index, format);
+        let loaded = store.entry(&name)?;
+        assert_eq!(loaded.content_id(), entry.content_id());
+        assert_eq!(loaded.added(), entry.added());
+        store.settings_mut().index = IndexFormat::Json;
+        store.save_settings()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_entries_index() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let (a, b) = (src.path().join("a"), src.path().join("b"));
+    fs::write(&a, "first")?;
+    fs::write(&b, "second")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.add(&a)?;
+    store.save()?;
+
+    // 切换后从单文件索引导入
+    store.settings_mut().index = IndexFormat::Entries;
+    store.save_settings()?;
+    let opts = AddOptions {
+        name: Some("tools/b c".to_string()),
+        version: Some("1.0@rc".to_string()),
+        ..Default::default()
+    };
+    store.add_with(&b, &opts)?;
+    store.save()?;
+    assert!(!store.config_path().exists());
+    let dir = store.path().join(ENTRIES_DIRECTORY);
+    let files = || -> anyhow::Result<Vec<PathBuf>> {
+        let mut ans: Vec<_> = fs::read_dir(&dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        ans.sort();
+        Ok(ans)
+    };
+    assert_eq!(files()?.len(), 2);
+
+    let store = tmp.reopen()?;
+    assert!(store.entry_version("tools/b c", Some("1.0@rc")).is_ok());
+    assert_eq!(
+        store.index_backend()?.labels()?,
+        vec![
+            ("a".to_string(), None),
+            ("tools/b c".to_string(), Some("1.0@rc".to_string()))
+        ]
+    );
+
+    // 只有变化的条目被重写
+    let before = files()?;
+    store.delete("a");
+    store.save()?;
+    assert_eq!(files()?, before[1..]);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_preserve_mode() -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("bin");
+    fs::create_dir(&root)?;
+    let mode = |p: &Path| -> anyhow::Result<u32> { Ok(p.metadata()?.permissions().mode() & 0o777) };
+    // 内容相同、权限不同的文件共用一个对象
+    for (name, content, bits) in [
+        ("run", "#!/bin/sh", 0o755),
+        ("doc", "#!/bin/sh", 0o640),
+        ("s", "x", 0o700),
+    ] {
+        fs::write(root.join(name), content)?;
+        fs::set_permissions(root.join(name), fs::Permissions::from_mode(bits))?;
+    }
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.settings_mut().inline_threshold = 2;
+    store.add(&root)?;
+    assert_eq!(store.objects().len(), 1);
+
+    let dst = tempdir()?;
+    store.get("bin", Some(dst.path().to_path_buf()))?;
+    let out = dst.path().join("bin");
+    assert_eq!(mode(&out.join("run"))?, 0o755);
+    assert_eq!(mode(&out.join("doc"))?, 0o640);
+    assert_eq!(mode(&out.join("s"))?, 0o700);
+    // 对象是doc的硬链接，恢复run时没有修改它的权限
+    let object = store.object_path(&store.objects().into_iter().next().unwrap_or_default());
+    assert_eq!(mode(&object)?, 0o640);
+    Ok(())
+}
+
+#[test]
+fn test_preserve_times() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("build");
+    fs::create_dir_all(root.join("obj"))?;
+    fs::write(root.join("obj/a.o"), "same")?;
+    fs::write(root.join("b.o"), "same")?;
+    let old = |days: u64| std::time::SystemTime::now() - Duration::from_secs(days * 86400);
+    fs::File::options()
+        .write(true)
+        .open(root.join("obj/a.o"))?
+        .set_modified(old(10))?;
+    fs::File::options()
+        .write(true)
+        .open(root.join("b.o"))?
+        .set_modified(old(20))?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.add(&root)?;
+    let mtime =
+        |p: &Path| -> anyhow::Result<std::time::SystemTime> { Ok(p.metadata()?.modified()?) };
+    let expected = [
+        (PathBuf::from("obj/a.o"), mtime(&root.join("obj/a.o"))?),
+        (PathBuf::from("b.o"), mtime(&root.join("b.o"))?),
+        (PathBuf::from("obj"), mtime(&root.join("obj"))?),
+    ];
+
+    let dst = tempdir()?;
+    let opts = GetOptions {
+        preserve_times: true,
+        ..Default::default()
+    };
+    store.get_node(store.entry("build")?, Some(dst.path().to_path_buf()), &opts)?;
+    for (rel, t) in &expected {
+        assert_eq!(mtime(&dst.path().join("build").join(rel))?, *t, "{:?}", rel);
+    }
+    // 共用的对象没有被修改
+    assert_eq!(mtime(&root.join("obj/a.o"))?, expected[0].1);
+    assert_eq!(mtime(&root.join("b.o"))?, expected[1].1);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_preserve_owner() -> anyhow::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    set_log()?;
+    if !attrs::can_chown() {
+        return Ok(());
+    }
+    let src = tempdir()?;
+    let root = src.path().join("image");
+    fs::create_dir(&root)?;
+    fs::write(root.join("owned"), "x")?;
+    fs::write(root.join("same"), "x")?;
+    std::os::unix::fs::chown(root.join("owned"), Some(1234), Some(5678))?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.add(&root)?;
+    assert_eq!(store.objects().len(), 1);
+
+    let dst = tempdir()?;
+    let opts = GetOptions {
+        preserve_owner: true,
+        ..Default::default()
+    };
+    store.get_node(store.entry("image")?, Some(dst.path().to_path_buf()), &opts)?;
+    let meta = dst.path().join("image/owned").metadata()?;
+    assert_eq!((meta.uid(), meta.gid()), (1234, 5678));
+    let same = dst.path().join("image/same").metadata()?;
+    let expected = root.join("same").metadata()?;
+    assert_eq!((same.uid(), same.gid()), (expected.uid(), expected.gid()));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_preserve_xattrs() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("image");
+    fs::create_dir(&root)?;
+    fs::write(root.join("tagged"), "x")?;
+    fs::write(root.join("plain"), "x")?;
+    if xattr::set(root.join("tagged"), "user.hbx.tag", b"v1").is_err() {
+        // 临时目录的文件系统不支持扩展属性
+        return Ok(());
+    }
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.add(&root)?;
+    let dst = tempdir()?;
+    store.get_node(
+        store.entry("image")?,
+        Some(dst.path().to_path_buf()),
+        &GetOptions::default(),
+    )?;
+    let tagged = dst.path().join("image/tagged");
+    assert_eq!(xattr::get(&tagged, "user.hbx.tag")?, Some(b"v1".to_vec()));
+    assert_eq!(
+        xattr::get(dst.path().join("image/plain"), "user.hbx.tag")?,
+        None
+    );
+
+    let opts = AddOptions {
+        name: Some("skipped".to_string()),
+        skip_xattrs: vec!["user".to_string()],
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    let mut recorded = 0;
+    store.entry("skipped")?.walk(&mut |_, n| {
+        recorded += n.attrs.as_ref().map_or(0, |a| a.xattrs.len());
+    });
+    assert_eq!(recorded, 0);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hardlink_groups() -> anyhow::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("tree");
+    fs::create_dir_all(root.join("sub"))?;
+    fs::write(root.join("a"), "same")?;
+    fs::hard_link(root.join("a"), root.join("sub/b"))?;
+    fs::write(root.join("c"), "same")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    // 编码后的对象只能复制到目标目录
+    store.settings_mut().object_filters.push(FilterRule {
+        pattern: "*".to_string(),
+        filters: vec!["zstd".to_string()],
+    });
+    store.add(&root)?;
+    let summary = store.entry("tree")?.summary.clone().unwrap();
+    assert_eq!(
+        summary.hardlinks,
+        vec![vec![PathBuf::from("a"), PathBuf::from("sub/b")]]
+    );
+
+    let dst = tempdir()?;
+    store.get("tree", Some(dst.path().to_path_buf()))?;
+    let ino = |p: &str| dst.path().join("tree").join(p).metadata().map(|m| m.ino());
+    assert_eq!(ino("a")?, ino("sub/b")?);
+    assert_ne!(ino("a")?, ino("c")?);
+    assert_eq!(fs::read_to_string(dst.path().join("tree/sub/b"))?, "same");
+    Ok(())
+}
+
+#[test]
+fn test_tags() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("gcc-13");
+    fs::create_dir(&root)?;
+    fs::write(root.join("gcc"), "gcc")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    let opts = AddOptions {
+        tags: vec!["linux-x64".to_string(), "compiler".to_string()],
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    let entry = store.entry("gcc-13")?;
+    assert_eq!(entry.tags, vec!["compiler", "linux-x64"]);
+    assert!(entry.has_tags(&["compiler".to_string()]));
+    assert!(!entry.has_tags(&["compiler".to_string(), "arm64".to_string()]));
+
+    // 内容相同但标签不同时创建新版本
+    store.add(&root)?;
+    assert_eq!(store.versions("gcc-13").len(), 2);
+    assert!(store.entry("gcc-13")?.tags.is_empty());
+
+    let opts = AddOptions {
+        tags: vec!["has space".to_string()],
+        ..Default::default()
+    };
+    assert!(store.add_with(&root, &opts).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_entry_identity() -> anyhow::Result<()> {
+    set_log()?;
+    let first = tempdir()?;
+    let second = tempdir()?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    for (dir, content) in [(&first, "one"), (&second, "two")] {
+        let root = dir.path().join("tools");
+        fs::create_dir(&root)?;
+        fs::write(root.join("bin"), content)?;
+        store.add(&root)?;
+    }
+    // 同名但内容不同的目录成为新版本，不会被丢弃
+    let versions = store.versions("tools");
+    assert_eq!(versions.len(), 2);
+    assert!(!versions[0].same_content(versions[1]));
+
+    let hash = store.put_object("three".as_bytes())?;
+    let mut root = Node::with_meta("tools".to_string(), Meta::FILE(hash));
+    root.version = versions[1].version.clone();
+    let err = store.put_manifest(root).unwrap_err();
+    assert!(err.to_string().starts_with("conflict"), "{}", err);
+    assert_eq!(store.versions("tools").len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_rename_and_aliases() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("temurin");
+    fs::create_dir(&root)?;
+    fs::write(root.join("java"), "java")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.add(&root)?;
+    fs::write(root.join("javac"), "javac")?;
+    store.add(&root)?;
+    let objects = store.objects();
+
+    store.rename("temurin", "temurin-17.0.9")?;
+    assert!(store.versions("temurin").is_empty());
+    assert_eq!(store.versions("temurin-17.0.9").len(), 2);
+    assert_eq!(store.objects(), objects);
+    assert!(store.rename("temurin", "other").is_err());
+
+    store.add_alias("temurin-17.0.9", "jdk17")?;
+    assert_eq!(store.entry("jdk17")?.name, "temurin-17.0.9");
+    assert_eq!(
+        store.resolve("jdk17")?.label(),
+        store.entry("temurin-17.0.9")?.label()
+    );
+    assert!(store.add_alias("temurin-17.0.9", "jdk17").is_err());
+    assert!(store.add_alias("temurin-17.0.9", "temurin-17.0.9").is_err());
+
+    let dst = tempdir()?;
+    store.get("jdk17", Some(dst.path().to_path_buf()))?;
+    assert!(dst.path().join("temurin-17.0.9/javac").exists());
+
+    let store = tmp.reopen()?;
+    assert_eq!(store.entry_aliases().len(), 1);
+    assert!(store.remove_alias("jdk17")?);
+    assert!(!store.remove_alias("jdk17")?);
+    assert!(store.entry("jdk17").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_ignore_file() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("project");
+    for dir in [".git", "target/debug", "src/cache", "logs"] {
+        fs::create_dir_all(root.join(dir))?;
+    }
+    fs::write(root.join(".git/HEAD"), "ref")?;
+    fs::write(root.join("target/debug/app"), "bin")?;
+    fs::write(root.join("src/main.rs"), "fn main() {}")?;
+    fs::write(root.join("src/cache/x"), "x")?;
+    fs::write(root.join("logs/a.log"), "a")?;
+    fs::write(root.join("logs/keep.log"), "keep")?;
+    fs::write(root.join(".hbxignore"), ".git/\ntarget/\n*.log\n")?;
+    fs::write(root.join("logs/.hbxignore"), "!keep.log\n")?;
+
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    let opts = AddOptions {
+        filter: Filter {
+            exclude: vec!["cache/".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    let mut files = Vec::new();
+    let entry = store.entry("project")?;
+    entry.walk(&mut |rel, n| {
+        if !matches!(n.meta, Meta::DIRECTORY(_)) {
+            files.push(rel.to_path_buf());
+        }
+    });
+    files.sort();
+    let expected: Vec<PathBuf> = [
+        ".hbxignore",
+        "logs/.hbxignore",
+        "logs/keep.log",
+        "src/main.rs",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(files, expected);
+    let skipped = &entry.summary.as_ref().unwrap().skipped;
+    assert!(skipped.contains(&PathBuf::from("target")));
+    assert!(skipped.contains(&PathBuf::from("src/cache")));
+    Ok(())
+}
+
+#[test]
+fn test_include_exclude_globs() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("dataset");
+    fs::create_dir_all(root.join("raw/2024"))?;
+    fs::create_dir_all(root.join("clean"))?;
+    fs::write(root.join("raw/2024/a.parquet"), "a")?;
+    fs::write(root.join("raw/notes.txt"), "notes")?;
+    fs::write(root.join("clean/b.parquet"), "b")?;
+    fs::write(root.join("readme.md"), "readme")?;
+
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    let opts = AddOptions {
+        filter: Filter {
+            include: vec!["**/*.parquet".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    let mut files = Vec::new();
+    store.entry("dataset")?.walk(&mut |rel, n| {
+        if !matches!(n.meta, Meta::DIRECTORY(_)) {
+            files.push(rel.to_path_buf());
+        }
+    });
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            PathBuf::from("clean/b.parquet"),
+            PathBuf::from("raw/2024/a.parquet")
+        ]
+    );
+
+    let dst = tempdir()?;
+    let opts = GetOptions {
+        exclude: vec!["raw/**".to_string()],
+        ..Default::default()
+    };
+    store.get_node(
+        store.entry("dataset")?,
+        Some(dst.path().to_path_buf()),
+        &opts,
+    )?;
+    assert!(dst.path().join("dataset/clean/b.parquet").exists());
+    assert!(!dst.path().join("dataset/raw").exists());
+    Ok(())
+}
+
+#[test]
+fn test_max_depth() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("tree");
+    fs::create_dir_all(root.join("a/b/c"))?;
+    fs::write(root.join("top"), "top")?;
+    fs::write(root.join("a/mid"), "mid")?;
+    fs::write(root.join("a/b/deep"), "deep")?;
+    fs::write(root.join("scratch"), "x".repeat(100))?;
+
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    let opts = AddOptions {
+        filter: Filter {
+            max_depth: Some(2),
+            max_file_size: Some(10),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+    let mut paths = Vec::new();
+    store
+        .entry("tree")?
+        .walk(&mut |rel, _| paths.push(rel.to_path_buf()));
+    paths.sort();
+    let expected: Vec<PathBuf> = ["", "a", "a/b", "a/mid", "top"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(paths, expected);
+    let summary = store.entry("tree")?.summary.clone().unwrap();
+    let expected: Vec<PathBuf> = ["a/b/c", "a/b/deep", "scratch"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(summary.skipped, expected);
+    Ok(())
+}
+
+#[test]
+fn test_add_tar_stream() -> anyhow::Result<()> {
+    set_log()?;
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut append = |path: &str, data: &[u8], mode: u32| -> anyhow::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(mode);
+        header.set_mtime(1_700_000_000);
+        header.set_cksum();
+        builder.append_data(&mut header, path, data)?;
+        Ok(())
+    };
+    append("./bin/tool", b"#!/bin/sh\necho nightly\n", 0o755)?;
+    append("./lib/data.txt", b"data", 0o644)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Link);
+    header.set_size(0);
+    builder.append_link(&mut header, "lib/copy.txt", "lib/data.txt")?;
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    builder.append_link(&mut header, "bin/current", "tool")?;
+    let data = builder.into_inner()?;
+
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    let opts = AddOptions {
+        name: Some("nightly-build".to_string()),
+        ..Default::default()
+    };
+    store.add_tar(Cursor::new(&data), &opts)?;
+    let root = store.entry("nightly-build")?;
+    let summary = root.summary.clone().unwrap();
+    assert_eq!(summary.files, Some(3));
+    assert_eq!(
+        summary.hardlinks,
+        vec![vec![
+            PathBuf::from("lib/copy.txt"),
+            PathBuf::from("lib/data.txt")
+        ]]
+    );
+
+    let dst = tempdir()?;
+    store.get("nightly-build", Some(dst.path().to_path_buf()))?;
+    let out = dst.path().join("nightly-build");
+    assert_eq!(
+        fs::read_to_string(out.join("bin/tool"))?,
+        "#!/bin/sh\necho nightly\n"
+    );
+    assert_eq!(fs::read_to_string(out.join("lib/copy.txt"))?, "data");
+    assert_eq!(
+        fs::read_link(out.join("bin/current"))?,
+        PathBuf::from("tool")
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = out.join("bin/tool").metadata()?.permissions().mode();
+        assert_eq!(mode & 0o111, 0o111);
+    }
+
+    // 同样的流不会产生新版本
+    store.add_tar(Cursor::new(&data), &opts)?;
+    assert_eq!(store.versions("nightly-build").len(), 1);
+
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(1);
+    // Builder拒绝 `..`，直接写入路径
+    header.as_old_mut().name[..9].copy_from_slice(b"../escape");
+    header.set_cksum();
+    builder.append(&header, &b"x"[..])?;
+    let res = store.add_tar(
+        Cursor::new(builder.into_inner()?),
+        &AddOptions {
+            name: Some("bad".to_string()),
+            ..Default::default()
+        },
+    );
+    assert!(res.is_err());
+
+    // 和add一样检查容量，失败时删除已经写入的对象
+    let stored = store.stored_files()?;
+    store.settings_mut().quota = Some(Quota {
+        max_size: store.physical_size()? + 50,
+        policy: QuotaPolicy::Refuse,
+    });
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(100);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, "big", &[b'x'; 100][..])?;
+    let err = store
+        .add_tar(
+            Cursor::new(builder.into_inner()?),
+            &AddOptions {
+                name: Some("big".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("quota"), "{}", err);
+    assert!(store.entry("big").is_err());
+    assert_eq!(store.stored_files()?, stored);
+    Ok(())
+}
+
+#[test]
+fn test_add_url() -> anyhow::Result<()> {
+    set_log()?;
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::default(),
+    ));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(2);
+    header.set_mode(0o755);
+    header.set_cksum();
+    builder.append_data(&mut header, "bin/cc", &b"cc"[..])?;
+    let body = builder.into_inner()?.finish()?;
+    let checksum = HashAlgorithm::Sha256.hash_reader(&body[..], &mut |_| {})?;
+    let checksum = format!("sha256:{}", checksum.trim_start_matches("sha256-"));
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/toolchain.tar.gz", listener.local_addr()?);
+    let server = std::thread::spawn(move || -> anyhow::Result<()> {
+        for _ in 0..2 {
+            let (mut stream, _) = listener.accept()?;
+            let mut reader = BufReader::new(stream.try_clone()?);
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )?;
+            stream.write_all(&body)?;
+        }
+        Ok(())
+    });
+
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    let wrong = format!("sha256:{}", "0".repeat(64));
+    assert!(store
+        .add_url(&url, Some(&wrong), &AddOptions::default())
+        .is_err());
+    assert!(store.entries().is_empty());
+    let name = store.add_url(&url, Some(&checksum), &AddOptions::default())?;
+    assert_eq!(name, "toolchain");
+    server.join().unwrap()?;
+
+    let dst = tempdir()?;
+    store.get("toolchain", Some(dst.path().to_path_buf()))?;
+    assert_eq!(
+        fs::read_to_string(dst.path().join("toolchain/bin/cc"))?,
+        "cc"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_add_git() -> anyhow::Result<()> {
+    set_log()?;
+    let repo = tempdir()?;
+    let git = |args: &[&str]| -> anyhow::Result<String> {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(repo.path())
+            .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+            .args(args)
+            .output()?;
+        assert!(output.status.success(), "{:?}", output);
+        Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    };
+    git(&["init", "--quiet"])?;
+    fs::write(repo.path().join("main.rs"), "fn main() {}")?;
+    git(&["add", "."])?;
+    git(&["commit", "--quiet", "-m", "one"])?;
+    git(&["tag", "v1.4.0"])?;
+    let tagged = git(&["rev-parse", "HEAD"])?;
+    fs::write(repo.path().join("main.rs"), "fn main() { todo!() }")?;
+    git(&["commit", "--quiet", "-am", "two"])?;
+
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    let url = format!("file://{}", repo.path().display());
+    let opts = AddOptions {
+        name: Some("mytool-src".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(store.add_git(&url, Some("v1.4.0"), &opts)?, "mytool-src");
+    let summary = store.entry("mytool-src")?.summary.clone().unwrap();
+    let provenance = summary.provenance.unwrap();
+    assert_eq!(provenance.origin, url);
+    assert_eq!(provenance.rev.as_deref(), Some("v1.4.0"));
+    assert_eq!(provenance.commit, Some(tagged));
+
+    let dst = tempdir()?;
+    store.get("mytool-src", Some(dst.path().to_path_buf()))?;
+    let out = dst.path().join("mytool-src");
+    assert_eq!(fs::read_to_string(out.join("main.rs"))?, "fn main() {}");
+    assert!(!out.join(".git").exists());
+    assert!(store.add_git(&url, Some("no-such-rev"), &opts).is_err());
+
+    // 以 `-` 开头的url和版本不能被当成git的选项
+    let marker = repo.path().join("pwned");
+    let upload_pack = format!("--upload-pack=touch {}", marker.display());
+    assert!(store.add_git(&url, Some(&upload_pack), &opts).is_err());
+    assert!(store.add_git(&upload_pack, None, &opts).is_err());
+    assert!(!marker.exists());
+    Ok(())
+}
+
+#[test]
+fn test_refuse_adding_store() -> anyhow::Result<()> {
+    set_log()?;
+    let parent = tempdir()?;
+    let home = parent.path().join("home");
+    fs::create_dir_all(&home)?;
+    let mut store = Store::new(home.clone())?;
+    fs::write(parent.path().join("a"), "a")?;
+    let err = store.add(&home).unwrap_err();
+    assert!(err.to_string().contains("refusing"), "{}", err);
+    assert!(store.add(parent.path()).is_err());
+    assert!(store.add(&store.store_dir()).is_err());
+    assert!(store.entries().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_get_copy() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("config"), "original")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.settings_mut().inline_threshold = 0;
+    store.add(&root)?;
+    fs::remove_dir_all(&root)?;
+
+    let dst = tempdir()?;
+    let opts = GetOptions {
+        link: LinkMode::Copy,
+        ..Default::default()
+    };
+    store.get_node(store.entry("app")?, Some(dst.path().to_path_buf()), &opts)?;
+    let restored = dst.path().join("app/config");
+    let mut object = None;
+    store.entry("app")?.walk(&mut |_, n| {
+        if let Meta::FILE(h) = &n.meta {
+            object = Some(store.object_path(h));
+        }
+    });
+    let object = object.unwrap();
+    assert!(!hbx::core::util::same_file(&object, &restored));
+    fs::write(&restored, "edited")?;
+    assert_eq!(fs::read_to_string(&object)?, "original");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_get_symlink() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("toolchain");
+    fs::create_dir_all(root.join("bin"))?;
+    fs::write(root.join("bin/cc"), "compiler")?;
+    fs::write(root.join("VERSION"), "1")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.settings_mut().inline_threshold = 4;
+    store.add(&root)?;
+
+    let dst = tempdir()?;
+    let opts = GetOptions {
+        link: LinkMode::Symlink,
+        ..Default::default()
+    };
+    store.get_node(
+        store.entry("toolchain")?,
+        Some(dst.path().to_path_buf()),
+        &opts,
+    )?;
+    let out = dst.path().join("toolchain");
+    let target = fs::read_link(out.join("bin/cc"))?;
+    assert!(target.is_absolute());
+    assert!(target.starts_with(std::path::absolute(store.store_dir())?));
+    assert_eq!(fs::read_to_string(out.join("bin/cc"))?, "compiler");
+    // 内联的小文件直接写入
+    assert!(!out.join("VERSION").is_symlink());
+    assert_eq!(fs::read_to_string(out.join("VERSION"))?, "1");
+    Ok(())
+}
+
+#[test]
+fn test_resolve_subpath() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("gcc");
+    fs::create_dir_all(root.join("bin"))?;
+    fs::write(root.join("bin/gcc"), "gcc")?;
+    fs::write(root.join("bin/cc"), "cc")?;
+    fs::write(root.join("README"), "readme")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    let opts = AddOptions {
+        name: Some("toolchains/gcc".to_string()),
+        version: Some("13".to_string()),
+        ..Default::default()
+    };
+    store.add_with(&root, &opts)?;
+
+    // 多级名称中的 `/` 不会被当作条目内的路径
+    assert_eq!(
+        store.resolve_path("toolchains/gcc")?.label(),
+        "toolchains/gcc@13"
+    );
+    let node = store.resolve_path("toolchains/gcc@13/bin")?;
+    assert_eq!(node.label(), "toolchains/gcc/bin@13");
+    assert!(store.resolve_path("toolchains/gcc/lib").is_err());
+
+    let dst = tempdir()?;
+    store.get_node(
+        &node,
+        Some(dst.path().to_path_buf()),
+        &GetOptions::default(),
+    )?;
+    assert_eq!(fs::read_to_string(dst.path().join("bin/gcc"))?, "gcc");
+    assert!(!dst.path().join("README").exists());
+    Ok(())
+}
+
+#[test]
+fn test_get_existing_policies() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("site");
+    fs::create_dir_all(root.join("css"))?;
+    fs::write(root.join("index.html"), "index")?;
+    fs::write(root.join("css/main.css"), "main")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.settings_mut().inline_threshold = 0;
+    store.add(&root)?;
+
+    let dst = tempdir()?;
+    let base = dst.path().join("site");
+    let get = |existing: Existing| -> anyhow::Result<()> {
+        let opts = GetOptions {
+            existing,
+            ..Default::default()
+        };
+        store.get_node(store.entry("site")?, Some(dst.path().to_path_buf()), &opts)?;
+        Ok(())
+    };
+    let tamper = || -> anyhow::Result<()> {
+        // 先删除再写入，不修改仓库中的对象
+        fs::remove_file(base.join("index.html"))?;
+        fs::write(base.join("index.html"), "local")?;
+        fs::remove_file(base.join("css/main.css"))?;
+        fs::write(base.join("extra"), "extra")?;
+        Ok(())
+    };
+    get(Existing::Fail)?;
+    tamper()?;
+    assert!(get(Existing::Fail).is_err());
+
+    get(Existing::SkipExisting)?;
+    assert_eq!(fs::read_to_string(base.join("index.html"))?, "local");
+    assert_eq!(fs::read_to_string(base.join("css/main.css"))?, "main");
+
+    get(Existing::Merge)?;
+    assert_eq!(fs::read_to_string(base.join("index.html"))?, "index");
+    assert!(base.join("extra").exists());
+
+    get(Existing::Overwrite)?;
+    assert_eq!(fs::read_to_string(base.join("index.html"))?, "index");
+    assert!(!base.join("extra").exists());
+    Ok(())
+}
+
+#[test]
+fn test_get_verify() -> anyhow::Result<()> {
+    set_log()?;
+    let src = tempdir()?;
+    let root = src.path().join("app");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("good"), "good content")?;
+    fs::write(root.join("bad"), "bad content")?;
+    let mut tmp = TempStore::new()?;
+    let store = &mut tmp.store;
+    store.settings_mut().inline_threshold = 0;
+    store.add(&root)?;
+    fs::remove_dir_all(&root)?;
+    let mut bad = None;
+    store.entry("app")?.walk(&mut |rel, n| {
+        if let (Meta::FILE(hash), true) = (&n.meta, rel == Path::new("bad")) {
+            bad = Some(hash.clone());
+        }
+    });
+    let bad = bad.unwrap();
+
+    let opts = GetOptions {
+        verify: true,
+        ..Default::default()
+    };
+    let dst = tempdir()?;
+    store.get_node(store.entry("app")?, Some(dst.path().to_path_buf()), &opts)?;
+    corrupt_object(store, &bad)?;
+
+    let dst = tempdir()?;
+    let err = store
+        .get_node(store.entry("app")?, Some(dst.path().to_path_buf()), &opts)
+        .unwrap_err();
+    assert!(err.to_string().contains(&bad), "{}", err);
+    assert_eq!(fs::read_dir(dst.path())?.count(), 0);
+    // 不恢复损坏的文件时不受影响
+    let opts = GetOptions {
+        exclude: vec!["bad".to_string()],
+        ..opts
+    };
+    store.get_node(store.entry("app")?, Some(dst.path().to_path_buf()), &opts)?;
+    Ok(())
+}
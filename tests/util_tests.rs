@@ -0,0 +1,239 @@
+use hbx::core::hash::HashAlgorithm;
+use hbx::core::layout::{object_name, shard_path};
+use hbx::core::names::{has_prefix, is_portable, NameEscape, NameRules};
+use hbx::core::node::{Node, Summary};
+use hbx::core::queue::RateLimited;
+use hbx::core::resolve::{parse_version, resolve};
+use hbx::core::settings::Settings;
+use hbx::core::store::Store;
+use hbx::core::util::{
+    ensure_space, expand_path, format_size, format_time, hostname, ingest, ingest_expected,
+    is_hash, parse_size, parse_time, persist_object, username,
+};
+use std::path::PathBuf;
+
+#[test]
+fn test_expand_path() -> anyhow::Result<()> {
+    let p = expand_path("/data/{user}/{hostname}/hbx")?;
+    assert_eq!(
+        p,
+        PathBuf::from(format!("/data/{}/{}/hbx", username(), hostname()))
+    );
+    assert_eq!(expand_path("/plain/path")?, PathBuf::from("/plain/path"));
+    assert!(expand_path("/data/{unknown}").is_err());
+    assert!(expand_path("/data/{user").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_parse_size() -> anyhow::Result<()> {
+    assert_eq!(parse_size("512")?, 512);
+    assert_eq!(parse_size("10K")?, 10 * 1024);
+    assert_eq!(parse_size("2G")?, 2 << 30);
+    assert_eq!(parse_size("3MiB")?, 3 << 20);
+    assert!(parse_size("1X").is_err());
+    assert!(parse_size("G").is_err());
+    assert_eq!(format_size(512), "512B");
+    assert_eq!(format_size(3 << 19), "1.5M");
+    Ok(())
+}
+
+#[test]
+fn test_settings_merge() -> anyhow::Result<()> {
+    let mut settings = Settings::default();
+    settings.set("alias.up", "pull tools origin")?;
+    settings.set("inline_threshold", "64")?;
+    assert!(settings.set("no_such_key", "1").is_err());
+
+    settings.merge(serde_json::json!({
+        "alias": {"st": "about"},
+        "remotes": {"origin": {"address": "10.0.0.1:22", "token_env": "HBX_TOKEN"}}
+    }))?;
+    assert_eq!(settings.alias.len(), 2);
+    assert_eq!(settings.inline_threshold, 64);
+    assert_eq!(settings.remotes["origin"].address, "10.0.0.1:22");
+    Ok(())
+}
+
+#[test]
+fn test_parse_time() -> anyhow::Result<()> {
+    assert_eq!(parse_time("1970-01-02")?, 86400);
+    assert_eq!(parse_time("2024-05-01 12:00:00")?, 1714564800);
+    assert_eq!(format_time(1714564800), "2024-05-01T12:00:00Z");
+    assert!(parse_time("yesterday").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_name_escape() {
+    assert!(is_portable("readme.md"));
+    assert!(!is_portable("Aux.txt"));
+    assert_eq!(NameEscape::Percent.escape("a?b"), Some("a%3Fb".to_string()));
+    assert_eq!(NameEscape::Percent.escape("x."), Some("x%2E".to_string()));
+    assert_eq!(NameEscape::Percent.escape("aux"), Some("%61ux".to_string()));
+    assert_eq!(
+        NameEscape::Underscore.escape("a:b*"),
+        Some("a_b_".to_string())
+    );
+    assert_eq!(NameEscape::None.escape("a?b"), None);
+    assert_eq!(NameEscape::Percent.escape("plain"), None);
+}
+
+#[test]
+fn test_ingest() -> anyhow::Result<()> {
+    let home = tempfile::tempdir()?;
+    let store = Store::new(home.path().to_path_buf())?;
+    let hash = ingest("hello".as_bytes(), &store)?;
+    assert_eq!(hash.len(), 64);
+    assert_eq!(std::fs::read_to_string(store.object_path(&hash))?, "hello");
+    assert_eq!(ingest("hello".as_bytes(), &store)?, hash);
+
+    ingest_expected(
+        "world".as_bytes(),
+        &store,
+        "7d793037a0760186574b0282f2f435e7",
+    )?;
+    assert!(ingest_expected(
+        "w0rld".as_bytes(),
+        &store,
+        "7d793037a0760186574b0282f2f435e7"
+    )
+    .is_err());
+    // 校验失败时不会留下临时文件
+    assert!(store.stored_files()?.iter().all(|(name, _)| is_hash(name)));
+    assert_eq!(store.stored_files()?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_name_rules() {
+    let mut rules = NameRules::default();
+    assert!(rules.validate("toolchains/gcc/12.3").is_ok());
+    assert!(rules.validate("a//b").is_err());
+    assert!(rules.validate("../b").is_err());
+    assert!(rules.validate("a@1").is_err());
+
+    rules.namespaces = vec!["toolchains".to_string()];
+    rules.require_namespace = true;
+    assert!(rules.validate("gcc").is_err());
+    assert!(rules.validate("libs/gcc").is_err());
+    assert!(rules.validate("toolchains/gcc").is_ok());
+
+    assert!(has_prefix("toolchains/gcc", "toolchains/"));
+    assert!(has_prefix("toolchains/gcc", "toolchains"));
+    assert!(!has_prefix("toolchains2/gcc", "toolchains"));
+}
+
+#[test]
+fn test_ensure_space() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    ensure_space(dir.path(), 1)?;
+    let err = ensure_space(dir.path(), u64::MAX).unwrap_err().to_string();
+    assert!(err.contains("need ~"), "{}", err);
+    Ok(())
+}
+
+#[test]
+fn test_hash_reader() -> anyhow::Result<()> {
+    let data = vec![7u8; 3 * 1024 * 1024 + 1];
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("big");
+    std::fs::write(&file, &data)?;
+    let mut steps = Vec::new();
+    let hash = HashAlgorithm::Blake3.hash_reader(data.as_slice(), &mut |done| steps.push(done))?;
+    assert_eq!(hash, HashAlgorithm::Blake3.hash_file(&file)?);
+    assert_eq!(steps.len(), 4);
+    assert_eq!(steps.last(), Some(&(data.len() as u64)));
+    Ok(())
+}
+
+#[test]
+fn test_rate_limited() -> anyhow::Result<()> {
+    let data = vec![1u8; 300];
+    let start = std::time::Instant::now();
+    let mut reader = RateLimited::new(data.as_slice(), 1000);
+    let mut out = Vec::new();
+    std::io::Read::read_to_end(&mut reader, &mut out)?;
+    assert_eq!(out, data);
+    assert!(start.elapsed() >= std::time::Duration::from_millis(250));
+    Ok(())
+}
+
+#[test]
+fn test_resolve() -> anyhow::Result<()> {
+    let mut nodes = Vec::new();
+    for (i, name) in ["gcc-11.4.0", "gcc-12.2.0", "gcc/12.1", "gcc-arm-13", "gcc"]
+        .iter()
+        .enumerate()
+    {
+        let mut node = Node::sample(name);
+        node.summary = Some(Summary {
+            added: i as u64,
+            ..Default::default()
+        });
+        nodes.push(node);
+    }
+    nodes[4].version = Some(String::from("v13.1"));
+    let pick = |spec: &str| resolve(spec, &nodes).map(|n| n.label());
+    assert_eq!(pick("gcc")?, "gcc@v13.1");
+    assert_eq!(pick("gcc@latest")?, "gcc@v13.1");
+    assert_eq!(pick("gcc@^12")?, "gcc-12.2.0");
+    assert_eq!(pick("gcc@~12.1")?, "gcc/12.1");
+    assert_eq!(pick("gcc@11.4.0")?, "gcc-11.4.0");
+    assert_eq!(pick("gcc@12")?, "gcc-12.2.0");
+    assert!(pick("gcc@^14").is_err());
+    assert!(pick("clang@latest").is_err());
+    assert_eq!(
+        parse_version("v12").map(|v| v.to_string()),
+        Some("12.0.0".into())
+    );
+    assert_eq!(
+        parse_version("1.2-rc1").map(|v| v.to_string()),
+        Some("1.2.0-rc1".into())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_shard_path() {
+    for name in [
+        "d41d8cd98f00b204e9800998ecf8427e",
+        "sha256-e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+    ] {
+        let path = shard_path(name);
+        let dir = path.parent().unwrap().to_string_lossy();
+        let file = path.file_name().unwrap().to_string_lossy();
+        assert_eq!(dir.len(), 2);
+        assert_eq!(object_name(&dir, &file), name);
+    }
+    assert_eq!(shard_path("sha256-e3b0c4"), PathBuf::from("e3/sha256-b0c4"));
+}
+
+#[test]
+fn test_concurrent_ingest() -> anyhow::Result<()> {
+    let home = tempfile::tempdir()?;
+    let store = Store::new(home.path().to_path_buf())?;
+    let data = "same".repeat(64 * 1024);
+    let hashes = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| s.spawn(|| ingest(data.as_bytes(), &store)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<anyhow::Result<Vec<String>>>()
+    })?;
+    assert!(hashes.iter().all(|h| *h == hashes[0]));
+    assert_eq!(store.stored_files()?.len(), 1);
+
+    // 已有的对象损坏时被替换
+    let path = store.object_path(&hashes[0]);
+    std::fs::remove_file(&path)?;
+    std::fs::write(&path, "broken")?;
+    let tmp = tempfile::NamedTempFile::new_in(store.store_dir())?;
+    std::fs::write(tmp.path(), &data)?;
+    persist_object(tmp, &path, &hashes[0])?;
+    assert_eq!(std::fs::read_to_string(&path)?, data);
+    assert_eq!(store.stored_files()?.len(), 1);
+    Ok(())
+}